@@ -0,0 +1,628 @@
+//! Serve an easy-fs image on the host through `fuser`.
+//!
+//! Requests are answered one by one through the `Inode` API of easy-fs, the
+//! FUSE inode number of an inode is its easy-fs inode number plus one so
+//! that the root directory gets `INodeNo::ROOT`.
+
+use easy_fs::{block_cache_sync_all, Inode, InodeOwner, IoError, BLOCK_SZ, NAME_LENGTH_LIMIT};
+use fuser::{
+    Config, Errno, FileAttr, FileHandle, FileType, Filesystem, FopenFlags, Generation, INodeNo,
+    LockOwner, MountOption, OpenFlags, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory,
+    ReplyEmpty, ReplyEntry, ReplyWrite, Request, Session, TimeOrNow, WriteFlags,
+};
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long the kernel may keep names and attributes, other processes can
+/// only change the image through this server.
+const TTL: Duration = Duration::from_secs(1);
+
+/// Blocks failing their checksum are reported as I/O errors.
+fn eio(_: IoError) -> Errno {
+    Errno::EIO
+}
+
+/// Names must be UTF-8 and fit in a directory entry.
+fn name_str(name: &OsStr) -> Result<&str, Errno> {
+    let name = name.to_str().ok_or(Errno::EINVAL)?;
+    if name.len() > NAME_LENGTH_LIMIT {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    Ok(name)
+}
+
+/// Kind of `inode` as FUSE reports it.
+fn file_type(inode: &Inode) -> Result<FileType, IoError> {
+    Ok(if inode.is_dir()? {
+        FileType::Directory
+    } else if inode.is_fifo()? {
+        FileType::NamedPipe
+    } else {
+        FileType::RegularFile
+    })
+}
+
+/// What a new inode is made as by `EasyFuse::make`.
+#[derive(Clone, Copy)]
+enum NewInode {
+    File,
+    Dir,
+    Fifo,
+}
+
+struct EasyFuse {
+    root_inode: Arc<Inode>,
+    uid: u32,
+    gid: u32,
+}
+
+impl EasyFuse {
+    fn inode(&self, ino: INodeNo) -> Arc<Inode> {
+        if ino == INodeNo::ROOT {
+            Arc::clone(&self.root_inode)
+        } else {
+            self.root_inode.get_inode((ino.0 - 1) as u32)
+        }
+    }
+
+    fn ino(inode: &Inode) -> INodeNo {
+        INodeNo(inode.inode_id() as u64 + 1)
+    }
+
+    fn attr(&self, inode: &Inode) -> Result<FileAttr, Errno> {
+        let kind = file_type(inode).map_err(eio)?;
+        let is_dir = kind == FileType::Directory;
+        // images made before owners belong to whoever mounted them
+        let owner = inode.owner().map_err(eio)?.unwrap_or(InodeOwner {
+            uid: self.uid,
+            gid: self.gid,
+            mode: if is_dir { 0o755 } else { 0o644 },
+        });
+        Ok(FileAttr {
+            ino: Self::ino(inode),
+            size: inode.size().map_err(eio)? as u64,
+            blocks: inode.blocks().map_err(eio)? as u64 * (BLOCK_SZ as u64 / 512),
+            // times are not stored by easy-fs
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm: owner.mode as u16,
+            nlink: if is_dir { 2 } else { 1 },
+            uid: owner.uid,
+            gid: owner.gid,
+            rdev: 0,
+            blksize: BLOCK_SZ as u32,
+            flags: 0,
+        })
+    }
+
+    fn dir(&self, ino: INodeNo) -> Result<Arc<Inode>, Errno> {
+        let inode = self.inode(ino);
+        if !inode.is_dir().map_err(eio)? {
+            return Err(Errno::ENOTDIR);
+        }
+        Ok(inode)
+    }
+
+    fn lookup_child(&self, parent: INodeNo, name: &OsStr) -> Result<FileAttr, Errno> {
+        let inode = self
+            .dir(parent)?
+            .find(name_str(name)?)
+            .map_err(eio)?
+            .ok_or(Errno::ENOENT)?;
+        self.attr(&inode)
+    }
+
+    fn set_attr(
+        &self,
+        ino: INodeNo,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+    ) -> Result<FileAttr, Errno> {
+        let inode = self.inode(ino);
+        if let Some(size) = size {
+            if inode.is_dir().map_err(eio)? {
+                return Err(Errno::EISDIR);
+            }
+            if !inode.set_size(size as usize).map_err(eio)? {
+                return Err(Errno::EFBIG);
+            }
+        }
+        if mode.is_some() || uid.is_some() || gid.is_some() {
+            let mut owner = inode.owner().map_err(eio)?.ok_or(Errno::EPERM)?;
+            if let Some(mode) = mode {
+                owner.mode = mode & 0o7777;
+            }
+            owner.uid = uid.unwrap_or(owner.uid);
+            owner.gid = gid.unwrap_or(owner.gid);
+            inode.set_owner(owner).map_err(eio)?;
+        }
+        self.attr(&inode)
+    }
+
+    /// Create `name` in `parent` for `uid`:`gid`, the kernel has already
+    /// applied the umask of the creator to `mode`.
+    fn make(
+        &self,
+        parent: INodeNo,
+        name: &OsStr,
+        kind: NewInode,
+        (uid, gid): (u32, u32),
+        mode: u32,
+    ) -> Result<FileAttr, Errno> {
+        let dir = self.dir(parent)?;
+        let name = name_str(name)?;
+        let inode = match kind {
+            NewInode::File => dir.create(name),
+            NewInode::Dir => dir.create_dir(name),
+            NewInode::Fifo => dir.create_fifo(name),
+        }
+        .map_err(eio)?
+        .ok_or(Errno::EEXIST)?;
+        inode
+            .set_owner(InodeOwner {
+                uid,
+                gid,
+                mode: mode & 0o7777,
+            })
+            .map_err(eio)?;
+        self.attr(&inode)
+    }
+
+    /// Remove `name` from `parent`, `is_dir` tells rmdir from unlink.
+    fn remove(&self, parent: INodeNo, name: &OsStr, is_dir: bool) -> Result<(), Errno> {
+        let dir = self.dir(parent)?;
+        let name = name_str(name)?;
+        let inode = dir.find(name).map_err(eio)?.ok_or(Errno::ENOENT)?;
+        match (is_dir, inode.is_dir().map_err(eio)?) {
+            (false, true) => return Err(Errno::EISDIR),
+            (true, false) => return Err(Errno::ENOTDIR),
+            _ => {}
+        }
+        if !dir.unlink(name).map_err(eio)? {
+            return Err(Errno::ENOTEMPTY);
+        }
+        Ok(())
+    }
+
+    fn read_data(&self, ino: INodeNo, offset: u64, size: u32) -> Result<Vec<u8>, Errno> {
+        let mut data = vec![0u8; size as usize];
+        let len = self
+            .inode(ino)
+            .read_at(offset as usize, &mut data)
+            .map_err(eio)?;
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Every entry of the directory with "." and ".." first, the offset of
+    /// an entry is its index plus one.
+    fn dir_entries(&self, ino: INodeNo) -> Result<Vec<(INodeNo, FileType, String)>, Errno> {
+        let dir = self.dir(ino)?;
+        let mut entries = vec![
+            (Self::ino(&dir), FileType::Directory, String::from(".")),
+            // the parent is not recorded, the kernel fixes it for the root
+            (Self::ino(&dir), FileType::Directory, String::from("..")),
+        ];
+        for (name, inode) in dir.read_dir().map_err(eio)? {
+            let kind = file_type(&inode).map_err(eio)?;
+            entries.push((Self::ino(&inode), kind, name));
+        }
+        Ok(entries)
+    }
+
+    fn punch(&self, ino: INodeNo, offset: u64, length: u64, mode: i32) -> Result<(), Errno> {
+        // blocks are allocated when written, only holes can be made
+        if mode != libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE {
+            return Err(Errno::EOPNOTSUPP);
+        }
+        self.inode(ino)
+            .punch_hole(offset as usize, length as usize)
+            .map_err(eio)
+    }
+}
+
+impl Filesystem for EasyFuse {
+    fn destroy(&mut self) {
+        block_cache_sync_all();
+    }
+
+    fn lookup(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEntry) {
+        match self.lookup_child(parent, name) {
+            Ok(attr) => reply.entry(&TTL, &attr, Generation(0)),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getattr(&self, _req: &Request, ino: INodeNo, _fh: Option<FileHandle>, reply: ReplyAttr) {
+        match self.attr(&self.inode(ino)) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn setattr(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<FileHandle>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<fuser::BsdFileFlags>,
+        reply: ReplyAttr,
+    ) {
+        match self.set_attr(ino, mode, uid, gid, size) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn mknod(
+        &self,
+        req: &Request,
+        parent: INodeNo,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        _rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        // FIFOs are the only special files easy-fs stores
+        if mode & libc::S_IFMT != libc::S_IFIFO {
+            return reply.error(Errno::EPERM);
+        }
+        let creator = (req.uid(), req.gid());
+        match self.make(parent, name, NewInode::Fifo, creator, mode) {
+            Ok(attr) => reply.entry(&TTL, &attr, Generation(0)),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn mkdir(
+        &self,
+        req: &Request,
+        parent: INodeNo,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let creator = (req.uid(), req.gid());
+        match self.make(parent, name, NewInode::Dir, creator, mode) {
+            Ok(attr) => reply.entry(&TTL, &attr, Generation(0)),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn create(
+        &self,
+        req: &Request,
+        parent: INodeNo,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let creator = (req.uid(), req.gid());
+        match self.make(parent, name, NewInode::File, creator, mode) {
+            Ok(attr) => reply.created(
+                &TTL,
+                &attr,
+                Generation(0),
+                FileHandle(0),
+                FopenFlags::empty(),
+            ),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn unlink(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEmpty) {
+        match self.remove(parent, name, false) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn rmdir(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEmpty) {
+        match self.remove(parent, name, true) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn read(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        size: u32,
+        _flags: OpenFlags,
+        _lock_owner: Option<LockOwner>,
+        reply: ReplyData,
+    ) {
+        match self.read_data(ino, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn write(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        data: &[u8],
+        _write_flags: WriteFlags,
+        _flags: OpenFlags,
+        _lock_owner: Option<LockOwner>,
+        reply: ReplyWrite,
+    ) {
+        match self.inode(ino).write_at(offset as usize, data) {
+            Ok(size) => reply.written(size as u32),
+            Err(err) => reply.error(eio(err)),
+        }
+    }
+
+    fn flush(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        _fh: FileHandle,
+        _lock_owner: LockOwner,
+        reply: ReplyEmpty,
+    ) {
+        block_cache_sync_all();
+        reply.ok();
+    }
+
+    fn fsync(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        _fh: FileHandle,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        block_cache_sync_all();
+        reply.ok();
+    }
+
+    fn readdir(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        mut reply: ReplyDirectory,
+    ) {
+        let entries = match self.dir_entries(ino) {
+            Ok(entries) => entries,
+            Err(errno) => return reply.error(errno),
+        };
+        for (index, (ino, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            // only whole entries fit in the buffer
+            if reply.add(ino, index as u64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn fsyncdir(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        _fh: FileHandle,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        block_cache_sync_all();
+        reply.ok();
+    }
+
+    fn fallocate(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        length: u64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        match self.punch(ino, offset, length, mode) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+}
+
+/// Serve the file system of `root_inode` at `mountpoint` until it is
+/// unmounted with `umount` or `fusermount -u`.
+pub fn mount(root_inode: Arc<Inode>, mountpoint: &Path) -> std::io::Result<()> {
+    let mut config = Config::default();
+    config.mount_options = vec![
+        MountOption::FSName(String::from("easy-fs")),
+        MountOption::Subtype(String::from("easy-fs")),
+        MountOption::NoSuid,
+        MountOption::NoDev,
+    ];
+    let server = EasyFuse {
+        root_inode,
+        uid: unsafe { libc::getuid() },
+        gid: unsafe { libc::getgid() },
+    };
+    let session = Session::new(server, mountpoint, &config)?;
+    println!("easy-fs mounted at {}", mountpoint.display());
+    let result = session.run();
+    block_cache_sync_all();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use easy_fs::{EasyFileSystem, InodeFormat};
+
+    fn names(entries: &[(INodeNo, FileType, String)]) -> Vec<&str> {
+        entries.iter().map(|(_, _, name)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn fuse_ops_test() -> std::io::Result<()> {
+        let (_guard, _block_file, efs) =
+            crate::new_test_image("fuse_test.img", 8192, InodeFormat::Indirect)?;
+        let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+        let file = root_inode.create("hello.txt").unwrap().unwrap();
+        file.write_at(0, b"hello fuse").unwrap();
+        root_inode.create_dir("dir").unwrap().unwrap();
+        let server = EasyFuse {
+            root_inode,
+            uid: 1000,
+            gid: 1000,
+        };
+
+        // lookup answers with the inode number and attributes of the child
+        let attr = server
+            .lookup_child(INodeNo::ROOT, OsStr::new("hello.txt"))
+            .unwrap();
+        assert_eq!(attr.ino, INodeNo(file.inode_id() as u64 + 1));
+        assert_eq!(attr.size, 10);
+        assert_eq!(attr.kind, FileType::RegularFile);
+        assert_eq!(
+            server
+                .lookup_child(INodeNo::ROOT, OsStr::new("missing"))
+                .unwrap_err(),
+            Errno::ENOENT
+        );
+        assert_eq!(
+            server.lookup_child(attr.ino, OsStr::new("x")).unwrap_err(),
+            Errno::ENOTDIR
+        );
+        let long_name = "n".repeat(NAME_LENGTH_LIMIT + 1);
+        assert_eq!(
+            server
+                .lookup_child(INodeNo::ROOT, OsStr::new(&long_name))
+                .unwrap_err(),
+            Errno::ENAMETOOLONG
+        );
+
+        // reads stop at the end of the file
+        assert_eq!(server.read_data(attr.ino, 6, 100).unwrap(), b"fuse");
+
+        // "." and ".." come first
+        let entries = server.dir_entries(INodeNo::ROOT).unwrap();
+        assert_eq!(names(&entries), [".", "..", "hello.txt", "dir"]);
+        assert_eq!(entries[3].1, FileType::Directory);
+
+        // new inodes belong to their creator
+        let creator = (1001, 1002);
+        let fifo = server
+            .make(
+                INodeNo::ROOT,
+                OsStr::new("fifo"),
+                NewInode::Fifo,
+                creator,
+                0o640,
+            )
+            .unwrap();
+        assert_eq!(fifo.kind, FileType::NamedPipe);
+        assert_eq!((fifo.uid, fifo.gid, fifo.perm), (1001, 1002, 0o640));
+        assert_eq!(
+            server
+                .make(
+                    INodeNo::ROOT,
+                    OsStr::new("dir"),
+                    NewInode::File,
+                    creator,
+                    0o644
+                )
+                .unwrap_err(),
+            Errno::EEXIST
+        );
+        assert_eq!(
+            server.remove(INodeNo::ROOT, OsStr::new("dir"), false),
+            Err(Errno::EISDIR)
+        );
+        assert_eq!(
+            server.remove(INodeNo::ROOT, OsStr::new("fifo"), true),
+            Err(Errno::ENOTDIR)
+        );
+        assert_eq!(
+            server.remove(INodeNo::ROOT, OsStr::new("fifo"), false),
+            Ok(())
+        );
+        assert_eq!(
+            server.remove(INodeNo::ROOT, OsStr::new("dir"), true),
+            Ok(())
+        );
+        assert_eq!(
+            names(&server.dir_entries(INodeNo::ROOT).unwrap()),
+            [".", "..", "hello.txt"]
+        );
+        Ok(())
+    }
+
+    /// Truncating punches the tail out instead of rewriting the head.
+    #[test]
+    fn fuse_truncate_test() -> std::io::Result<()> {
+        let (_guard, _block_file, efs) =
+            crate::new_test_image("fuse_truncate.img", 8192, InodeFormat::Extents)?;
+        let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+        let file = root_inode.create("file").unwrap().unwrap();
+        let data: Vec<u8> = (0..40 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+        file.write_at(0, &data).unwrap();
+        let server = EasyFuse {
+            root_inode,
+            uid: 1000,
+            gid: 1000,
+        };
+        let ino = INodeNo(file.inode_id() as u64 + 1);
+
+        // shrinking frees the tail and keeps the head
+        let size = 3 * BLOCK_SZ + 100;
+        let attr = server
+            .set_attr(ino, None, None, None, Some(size as u64))
+            .unwrap();
+        assert_eq!(attr.size as usize, size);
+        assert!(file.blocks().unwrap() <= 5);
+        assert_eq!(server.read_data(ino, 0, 8192).unwrap(), &data[..size]);
+
+        // growing again reads zeros past the old end, not the cut data
+        server
+            .set_attr(ino, None, None, None, Some(8 * BLOCK_SZ as u64))
+            .unwrap();
+        let grown = server.read_data(ino, 0, 8192).unwrap();
+        assert_eq!(grown.len(), 8 * BLOCK_SZ);
+        assert_eq!(&grown[..size], &data[..size]);
+        assert!(grown[size..].iter().all(|b| *b == 0));
+        assert!(file.blocks().unwrap() <= 5);
+        assert!(efs.lock().fsck(false).is_empty());
+
+        // directories have no size to set
+        assert_eq!(
+            server
+                .set_attr(INodeNo::ROOT, None, None, None, Some(0))
+                .unwrap_err(),
+            Errno::EISDIR
+        );
+        Ok(())
+    }
+}
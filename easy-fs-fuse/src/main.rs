@@ -1,7 +1,23 @@
-use clap::{App, Arg};
-use easy_fs::{BlockDevice, EasyFileSystem};
-use std::fs::{read_dir, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+#[macro_use]
+extern crate clap;
+
+mod fuse;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+#[cfg(test)]
+use easy_fs::{
+    block_cache_clear, block_cache_sync, block_cache_try_sync_all, set_block_cache_capacity,
+    FsckProblem, InodeOwner, IoErrorKind, JOURNAL_BLOCKS,
+};
+use easy_fs::{
+    block_cache_sync_all, BlockDevice, EasyFileSystem, Inode, InodeFormat, IoError,
+    NAME_LENGTH_LIMIT,
+};
+use std::convert::TryFrom;
+use std::fs::{create_dir_all, read_dir, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::Mutex;
 
@@ -27,47 +43,285 @@ impl BlockDevice for BlockFile {
     fn handle_irq(&self) {
         unimplemented!();
     }
-}
 
-fn main() {
-    easy_fs_pack().expect("Error when packing easy-fs!");
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
+        let mut file = self.0.lock().unwrap();
+        file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
+            .expect("Error when seeking!");
+        file.read_exact(buf).expect("Not complete blocks!");
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) {
+        let mut file = self.0.lock().unwrap();
+        file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
+            .expect("Error when seeking!");
+        file.write_all(buf).expect("Not complete blocks!");
+    }
 }
 
-fn easy_fs_pack() -> std::io::Result<()> {
-    let matches = App::new("EasyFileSystem packer")
-        .arg(
-            Arg::with_name("source")
-                .short("s")
-                .long("source")
+fn main() {
+    let image_arg = || {
+        Arg::with_name("image")
+            .required(true)
+            .help("Path of the easy-fs image")
+    };
+    let size_args = || {
+        [
+            Arg::with_name("size")
+                .long("size")
                 .takes_value(true)
-                .help("Executable source dir(with backslash)"),
-        )
-        .arg(
-            Arg::with_name("target")
-                .short("t")
-                .long("target")
+                .default_value("32")
+                .help("Image size in MiB"),
+            Arg::with_name("inodes")
+                .long("inodes")
                 .takes_value(true)
-                .help("Executable target dir(with backslash)"),
+                .default_value("4096")
+                .help("Number of inodes, the root directory included"),
+            Arg::with_name("extents")
+                .long("extents")
+                .help("Map file blocks with extents, allowing files up to 4 GiB"),
+        ]
+    };
+    let matches = App::new("EasyFileSystem packer")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("pack")
+                .about("Create fs.img in the target dir with every app of the source dir")
+                .arg(
+                    Arg::with_name("source")
+                        .short("s")
+                        .long("source")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Executable source dir(with backslash)"),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .short("t")
+                        .long("target")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Executable target dir(with backslash)"),
+                )
+                .arg(
+                    Arg::with_name("compress")
+                        .long("compress")
+                        .help("Store the apps LZ4 compressed, in clusters of 4 KiB"),
+                )
+                .args(&size_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("mkfs")
+                .about("Create an empty image")
+                .arg(image_arg())
+                .args(&size_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("ls")
+                .about("List a directory of the image")
+                .arg(image_arg())
+                .arg(Arg::with_name("path").default_value("/")),
+        )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("Copy a file or a directory tree from the image to the host")
+                .arg(image_arg())
+                .arg(Arg::with_name("path").required(true))
+                .arg(Arg::with_name("host_path").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Copy a host file or directory tree into the image, overwriting files")
+                .arg(image_arg())
+                .arg(Arg::with_name("host_path").required(true))
+                .arg(
+                    Arg::with_name("path")
+                        .help("Destination in the image, default to its name under /"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rm")
+                .about("Remove a file or an empty directory from the image")
+                .arg(image_arg())
+                .arg(Arg::with_name("path").required(true))
+                .arg(
+                    Arg::with_name("recursive")
+                        .short("r")
+                        .help("Remove directories and their contents"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mkdir")
+                .about("Create a directory in the image")
+                .arg(image_arg())
+                .arg(Arg::with_name("path").required(true))
+                .arg(
+                    Arg::with_name("parents")
+                        .short("p")
+                        .help("Create missing parent directories too"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mount")
+                .about("Serve the image through FUSE until it is unmounted")
+                .arg(image_arg())
+                .arg(Arg::with_name("mountpoint").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("fsck")
+                .about("Check an easy-fs image for consistency")
+                .arg(image_arg())
+                .arg(
+                    Arg::with_name("repair")
+                        .short("r")
+                        .long("repair")
+                        .help("Fix the problems found"),
+                ),
         )
         .get_matches();
-    let src_path = matches.value_of("source").unwrap();
-    let target_path = matches.value_of("target").unwrap();
-    println!("src_path = {}\ntarget_path = {}", src_path, target_path);
+    let result = match matches.subcommand() {
+        ("pack", Some(sub_matches)) => easy_fs_pack(sub_matches),
+        ("mkfs", Some(sub_matches)) => easy_fs_mkfs(sub_matches),
+        ("ls", Some(sub_matches)) => easy_fs_ls(sub_matches),
+        ("extract", Some(sub_matches)) => easy_fs_extract(sub_matches),
+        ("add", Some(sub_matches)) => easy_fs_add(sub_matches),
+        ("rm", Some(sub_matches)) => easy_fs_rm(sub_matches),
+        ("mkdir", Some(sub_matches)) => easy_fs_mkdir(sub_matches),
+        ("mount", Some(sub_matches)) => easy_fs_mount(sub_matches),
+        ("fsck", Some(sub_matches)) => easy_fs_fsck(sub_matches).map(|problems| {
+            if problems > 0 && !sub_matches.is_present("repair") {
+                std::process::exit(1);
+            }
+        }),
+        _ => unreachable!(),
+    };
+    // the block cache is write-back
+    block_cache_sync_all();
+    if let Err(err) = result {
+        eprintln!("easy-fs-fuse: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn invalid_input(msg: String) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidInput, msg)
+}
+
+/// A block of the image failing its checksum.
+fn corrupted(err: IoError) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, err.to_string())
+}
+
+/// Create an image file of `--size` MiB holding `--inodes` inodes, return
+/// its root directory.
+fn create_image(path: &str, matches: &ArgMatches) -> std::io::Result<Arc<Inode>> {
+    let size: u32 = value_t!(matches, "size", u32).map_err(|e| invalid_input(e.message))?;
+    let inodes: u32 = value_t!(matches, "inodes", u32).map_err(|e| invalid_input(e.message))?;
+    let total_blocks = u32::try_from(size as u64 * 1024 * 1024 / BLOCK_SZ as u64)
+        .map_err(|_| invalid_input(format!("--size {}: too many blocks", size)))?;
+    // inodes with owners take twice the space, half of the bitmap is used
+    let inode_bitmap_blocks = inodes.div_ceil(BLOCK_SZ as u32 * 4);
     let block_file = Arc::new(BlockFile(Mutex::new({
         let f = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(format!("{}{}", target_path, "fs.img"))?;
-        f.set_len(32 * 2048 * 512).unwrap();
+            .truncate(true)
+            .open(path)?;
+        f.set_len(total_blocks as u64 * BLOCK_SZ as u64)?;
         f
     })));
-    // 32MiB, at most 4095 files
-    let efs = EasyFileSystem::create(block_file, 32 * 2048, 1);
-    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let inode_format = match matches.is_present("extents") {
+        true => InodeFormat::Extents,
+        false => InodeFormat::Indirect,
+    };
+    let efs = EasyFileSystem::create(block_file, total_blocks, inode_bitmap_blocks, inode_format);
+    Ok(Arc::new(EasyFileSystem::root_inode(&efs)))
+}
+
+/// Open an image file and return its root directory. A read-only image
+/// with a transaction left in its journal is refused, as replaying it
+/// would write.
+fn open_image(path: &str, writable: bool) -> std::io::Result<Arc<Inode>> {
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new().read(true).write(writable).open(path)?,
+    )));
+    let efs = match writable {
+        true => EasyFileSystem::open(block_file).map_err(corrupted)?,
+        false => EasyFileSystem::open_read_only(block_file).map_err(corrupted)?,
+    };
+    if efs.lock().journal_pending() {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: journal not replayed, run fsck --repair", path),
+        ));
+    }
+    Ok(Arc::new(EasyFileSystem::root_inode(&efs)))
+}
+
+fn find_path(root_inode: &Arc<Inode>, path: &str) -> std::io::Result<Arc<Inode>> {
+    let mut inode = Arc::clone(root_inode);
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        if !inode.is_dir().map_err(corrupted)? {
+            return Err(invalid_input(format!("{}: not a directory", path)));
+        }
+        inode = inode.find(name).map_err(corrupted)?.ok_or_else(|| {
+            std::io::Error::new(ErrorKind::NotFound, format!("{}: no such file", path))
+        })?;
+    }
+    Ok(inode)
+}
+
+/// Return the directory holding `path` and the last component of it.
+fn find_parent<'a>(
+    root_inode: &Arc<Inode>,
+    path: &'a str,
+) -> std::io::Result<(Arc<Inode>, &'a str)> {
+    let path = path.trim_end_matches('/');
+    let (parent_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name.is_empty() {
+        return Err(invalid_input(format!("{}: is the root directory", path)));
+    }
+    if name.len() > NAME_LENGTH_LIMIT {
+        return Err(invalid_input(format!("{}: name too long", path)));
+    }
+    let parent = find_path(root_inode, parent_path)?;
+    if !parent.is_dir().map_err(corrupted)? {
+        return Err(invalid_input(format!("{}: not a directory", parent_path)));
+    }
+    Ok((parent, name))
+}
+
+fn easy_fs_fsck(matches: &ArgMatches) -> std::io::Result<usize> {
+    let image_path = matches.value_of("image").unwrap();
+    let repair = matches.is_present("repair");
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new()
+            .read(true)
+            .write(repair)
+            .open(image_path)?,
+    )));
+    // a SuperBlock failing its checksum is reported by fsck
+    let efs = EasyFileSystem::open_unchecked(block_file).map_err(corrupted)?;
+    let problems = efs.lock().fsck(repair);
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    match (problems.len(), repair) {
+        (0, _) => println!("{}: clean", image_path),
+        (n, false) => println!("{}: {} problems found", image_path, n),
+        (n, true) => println!("{}: {} problems repaired", image_path, n),
+    }
+    Ok(problems.len())
+}
+
+fn easy_fs_pack(matches: &ArgMatches) -> std::io::Result<()> {
+    let src_path = matches.value_of("source").unwrap();
+    let target_path = matches.value_of("target").unwrap();
+    println!("src_path = {}\ntarget_path = {}", src_path, target_path);
+    let root_inode = create_image(&format!("{}{}", target_path, "fs.img"), matches)?;
     let apps: Vec<_> = read_dir(src_path)
         .unwrap()
-        .into_iter()
         .map(|dir_entry| {
             let mut name_with_ext = dir_entry.unwrap().file_name().into_string().unwrap();
             name_with_ext.drain(name_with_ext.find('.').unwrap()..name_with_ext.len());
@@ -80,59 +334,298 @@ fn easy_fs_pack() -> std::io::Result<()> {
         let mut all_data: Vec<u8> = Vec::new();
         host_file.read_to_end(&mut all_data).unwrap();
         // create a file in easy-fs
-        let inode = root_inode.create(app.as_str()).unwrap();
+        let inode = root_inode.create(app.as_str()).unwrap().unwrap();
         // write data to easy-fs
-        inode.write_at(0, all_data.as_slice());
+        if matches.is_present("compress") {
+            inode.write_compressed(all_data.as_slice()).unwrap();
+        } else {
+            inode.write_at(0, all_data.as_slice()).unwrap();
+        }
+        set_mode(&inode, 0o755).unwrap();
+    }
+    Ok(())
+}
+
+fn easy_fs_mkfs(matches: &ArgMatches) -> std::io::Result<()> {
+    create_image(matches.value_of("image").unwrap(), matches)?;
+    Ok(())
+}
+
+fn easy_fs_ls(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches.value_of("image").unwrap(), false)?;
+    let path = matches.value_of("path").unwrap();
+    let inode = find_path(&root_inode, path)?;
+    let entries = if inode.is_dir().map_err(corrupted)? {
+        inode.read_dir().map_err(corrupted)?
+    } else {
+        vec![(String::from(path), inode)]
+    };
+    for (name, inode) in entries.iter() {
+        let kind = if inode.is_dir().map_err(corrupted)? {
+            'd'
+        } else if inode.is_fifo().map_err(corrupted)? {
+            'p'
+        } else {
+            '-'
+        };
+        println!(
+            "{} {:>5} {:>9} {}",
+            kind,
+            inode.inode_id(),
+            inode.size().map_err(corrupted)?,
+            name
+        );
+    }
+    Ok(())
+}
+
+fn easy_fs_extract(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches.value_of("image").unwrap(), false)?;
+    let inode = find_path(&root_inode, matches.value_of("path").unwrap())?;
+    extract(&inode, Path::new(matches.value_of("host_path").unwrap()))
+}
+
+/// Copy `inode` to `host_path`, directories recursively, FIFOs are
+/// skipped.
+fn extract(inode: &Arc<Inode>, host_path: &Path) -> std::io::Result<()> {
+    if inode.is_fifo().map_err(corrupted)? {
+        return Ok(());
+    }
+    if inode.is_dir().map_err(corrupted)? {
+        create_dir_all(host_path)?;
+        for (name, child) in inode.read_dir().map_err(corrupted)? {
+            extract(&child, &host_path.join(name))?;
+        }
+    } else {
+        let mut data = vec![0u8; inode.size().map_err(corrupted)? as usize];
+        inode.read_at(0, &mut data).map_err(corrupted)?;
+        File::create(host_path)?.write_all(&data)?;
+    }
+    Ok(())
+}
+
+fn easy_fs_add(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches.value_of("image").unwrap(), true)?;
+    let host_path = Path::new(matches.value_of("host_path").unwrap());
+    let path = match matches.value_of("path") {
+        Some(path) => String::from(path),
+        None => match host_path.file_name() {
+            Some(name) => format!("/{}", name.to_string_lossy()),
+            None => {
+                return Err(invalid_input(format!(
+                    "{}: no file name",
+                    host_path.display()
+                )))
+            }
+        },
+    };
+    add(&root_inode, host_path, &path)
+}
+
+/// Copy `host_path` to `path`, directories recursively, existing files are
+/// overwritten.
+fn add(root_inode: &Arc<Inode>, host_path: &Path, path: &str) -> std::io::Result<()> {
+    let (parent, name) = find_parent(root_inode, path)?;
+    let existing = parent.find(name).map_err(corrupted)?;
+    if host_path.is_dir() {
+        match existing {
+            Some(inode) if !inode.is_dir().map_err(corrupted)? => {
+                return Err(invalid_input(format!("{}: not a directory", path)))
+            }
+            Some(_) => {}
+            None => {
+                parent.create_dir(name).map_err(corrupted)?;
+            }
+        }
+        for entry in read_dir(host_path)? {
+            let entry = entry?;
+            let child_path = format!(
+                "{}/{}",
+                path.trim_end_matches('/'),
+                entry.file_name().to_string_lossy()
+            );
+            add(root_inode, &entry.path(), &child_path)?;
+        }
+    } else {
+        let mut data: Vec<u8> = Vec::new();
+        File::open(host_path)?.read_to_end(&mut data)?;
+        let inode = match existing {
+            Some(inode) if inode.is_dir().map_err(corrupted)? => {
+                return Err(invalid_input(format!("{}: is a directory", path)))
+            }
+            Some(inode) => {
+                inode.clear().map_err(corrupted)?;
+                inode
+            }
+            None => parent.create(name).map_err(corrupted)?.unwrap(),
+        };
+        inode.write_at(0, &data).map_err(corrupted)?;
+        set_mode(&inode, host_path.metadata()?.permissions().mode()).map_err(corrupted)?;
+    }
+    Ok(())
+}
+
+/// Give `inode` the permission bits of `mode`, nothing to do on images made
+/// before owners.
+fn set_mode(inode: &Inode, mode: u32) -> Result<(), IoError> {
+    if let Some(mut owner) = inode.owner()? {
+        owner.mode = mode & 0o7777;
+        inode.set_owner(owner)?;
     }
-    // list apps
-    // for app in root_inode.ls() {
-    //     println!("{}", app);
-    // }
     Ok(())
 }
 
+fn easy_fs_rm(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches.value_of("image").unwrap(), true)?;
+    remove(
+        &root_inode,
+        matches.value_of("path").unwrap(),
+        matches.is_present("recursive"),
+    )
+}
+
+fn remove(root_inode: &Arc<Inode>, path: &str, recursive: bool) -> std::io::Result<()> {
+    let (parent, name) = find_parent(root_inode, path)?;
+    let inode = find_path(&parent, name)?;
+    if recursive && inode.is_dir().map_err(corrupted)? {
+        for child in inode.ls().map_err(corrupted)? {
+            remove(
+                root_inode,
+                &format!("{}/{}", path.trim_end_matches('/'), child),
+                true,
+            )?;
+        }
+    }
+    if !parent.unlink(name).map_err(corrupted)? {
+        return Err(invalid_input(format!("{}: directory not empty", path)));
+    }
+    Ok(())
+}
+
+fn easy_fs_mkdir(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches.value_of("image").unwrap(), true)?;
+    make_dir(
+        &root_inode,
+        matches.value_of("path").unwrap(),
+        matches.is_present("parents"),
+    )
+}
+
+fn easy_fs_mount(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches.value_of("image").unwrap(), true)?;
+    fuse::mount(
+        root_inode,
+        Path::new(matches.value_of("mountpoint").unwrap()),
+    )
+}
+
+fn make_dir(root_inode: &Arc<Inode>, path: &str, parents: bool) -> std::io::Result<()> {
+    if parents {
+        let mut current = String::new();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            current = format!("{}/{}", current, name);
+            let (parent, name) = find_parent(root_inode, &current)?;
+            match parent.find(name).map_err(corrupted)? {
+                Some(inode) if !inode.is_dir().map_err(corrupted)? => {
+                    return Err(invalid_input(format!("{}: not a directory", current)))
+                }
+                Some(_) => {}
+                None => {
+                    parent.create_dir(name).map_err(corrupted)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+    let (parent, name) = find_parent(root_inode, path)?;
+    match parent.create_dir(name).map_err(corrupted)? {
+        Some(_) => Ok(()),
+        None => Err(std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{}: already exists", path),
+        )),
+    }
+}
+
+/// Tests share the global block cache of easy-fs, run them one by one.
+#[cfg(test)]
+static EFS_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Guard of the test lock, the image file and its file system.
+#[cfg(test)]
+type TestImage = (
+    std::sync::MutexGuard<'static, ()>,
+    Arc<dyn BlockDevice>,
+    Arc<spin::Mutex<EasyFileSystem>>,
+);
+
+/// Fresh easy-fs image of `blocks` blocks at `target/<name>`, with the
+/// test lock held until the guard is dropped.
+#[cfg(test)]
+fn new_test_image(
+    name: &str,
+    blocks: u32,
+    inode_format: InodeFormat,
+) -> std::io::Result<TestImage> {
+    let guard = EFS_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    block_cache_clear();
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(Path::new("target").join(name))?;
+    f.set_len(blocks as u64 * BLOCK_SZ as u64)?;
+    let block_file: Arc<dyn BlockDevice> = Arc::new(BlockFile(Mutex::new(f)));
+    let efs = EasyFileSystem::create(Arc::clone(&block_file), blocks, 1, inode_format);
+    Ok((guard, block_file, efs))
+}
+
 #[test]
 fn efs_test() -> std::io::Result<()> {
+    let _guard = EFS_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    block_cache_clear();
     let block_file = Arc::new(BlockFile(Mutex::new({
         let f = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
+            .truncate(true)
             .open("target/fs.img")?;
         f.set_len(8192 * 512).unwrap();
         f
     })));
-    EasyFileSystem::create(block_file.clone(), 4096, 1);
-    let efs = EasyFileSystem::open(block_file.clone());
+    EasyFileSystem::create(block_file.clone(), 4096, 1, InodeFormat::Indirect);
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
     let root_inode = EasyFileSystem::root_inode(&efs);
-    root_inode.create("filea");
-    root_inode.create("fileb");
-    for name in root_inode.ls() {
+    root_inode.create("filea").unwrap();
+    root_inode.create("fileb").unwrap();
+    for name in root_inode.ls().unwrap() {
         println!("{}", name);
     }
-    let filea = root_inode.find("filea").unwrap();
+    let filea = root_inode.find("filea").unwrap().unwrap();
     let greet_str = "Hello, world!";
-    filea.write_at(0, greet_str.as_bytes());
+    filea.write_at(0, greet_str.as_bytes()).unwrap();
     //let mut buffer = [0u8; 512];
     let mut buffer = [0u8; 233];
-    let len = filea.read_at(0, &mut buffer);
+    let len = filea.read_at(0, &mut buffer).unwrap();
     assert_eq!(greet_str, core::str::from_utf8(&buffer[..len]).unwrap(),);
 
     let mut random_str_test = |len: usize| {
-        filea.clear();
-        assert_eq!(filea.read_at(0, &mut buffer), 0,);
+        filea.clear().unwrap();
+        assert_eq!(filea.read_at(0, &mut buffer).unwrap(), 0,);
         let mut str = String::new();
         use rand;
         // random digit
         for _ in 0..len {
-            str.push(char::from('0' as u8 + rand::random::<u8>() % 10));
+            str.push(char::from(b'0' + rand::random::<u8>() % 10));
         }
-        filea.write_at(0, str.as_bytes());
+        filea.write_at(0, str.as_bytes()).unwrap();
         let mut read_buffer = [0u8; 127];
         let mut offset = 0usize;
         let mut read_str = String::new();
         loop {
-            let len = filea.read_at(offset, &mut read_buffer);
+            let len = filea.read_at(offset, &mut read_buffer).unwrap();
             if len == 0 {
                 break;
             }
@@ -153,3 +646,944 @@ fn efs_test() -> std::io::Result<()> {
 
     Ok(())
 }
+
+/// Block file that stops reaching the disk after the first `budget` writes,
+/// as if the machine lost power at that moment. Later writes are only kept
+/// in memory so that the running file system still sees them.
+#[cfg(test)]
+struct CrashBlockFile {
+    file: BlockFile,
+    budget: usize,
+    lost: Mutex<std::collections::HashMap<usize, Vec<u8>>>,
+    written: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+impl CrashBlockFile {
+    fn new(file: BlockFile, budget: usize) -> Self {
+        Self {
+            file,
+            budget,
+            lost: Mutex::new(std::collections::HashMap::new()),
+            written: std::sync::atomic::AtomicUsize::new(0),
+        }
+    }
+
+    fn crashed(&self) -> bool {
+        self.written.load(std::sync::atomic::Ordering::SeqCst) > self.budget
+    }
+}
+
+#[cfg(test)]
+impl BlockDevice for CrashBlockFile {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        match self.lost.lock().unwrap().get(&block_id) {
+            Some(data) => buf.copy_from_slice(data),
+            None => self.file.read_block(block_id, buf),
+        }
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let written = self
+            .written
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        if written < self.budget {
+            self.file.write_block(block_id, buf);
+        } else {
+            self.lost.lock().unwrap().insert(block_id, buf.to_vec());
+        }
+    }
+
+    fn handle_irq(&self) {
+        unimplemented!();
+    }
+}
+
+#[test]
+fn efs_crash_test() -> std::io::Result<()> {
+    let open_image = |path: &str| -> std::io::Result<BlockFile> {
+        let f = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(BlockFile(Mutex::new(f)))
+    };
+    // an image holding a single file, copied before every crash
+    let (_guard, _, efs) = new_test_image("crash_base.img", 4096, InodeFormat::Indirect)?;
+    EasyFileSystem::root_inode(&efs)
+        .create("filea")
+        .unwrap()
+        .unwrap()
+        .write_at(0, &[1u8; 3 * BLOCK_SZ])
+        .unwrap();
+    drop(efs);
+    block_cache_clear();
+
+    let mut budget = 0;
+    let mut pending_journals = 0;
+    loop {
+        std::fs::copy("target/crash_base.img", "target/crash.img")?;
+        let device = Arc::new(CrashBlockFile::new(open_image("target/crash.img")?, budget));
+        {
+            let efs = EasyFileSystem::open(device.clone()).unwrap();
+            let root_inode = EasyFileSystem::root_inode(&efs);
+            root_inode.create("fileb").unwrap();
+            // crosses the direct blocks, so several transactions are needed
+            let filea = root_inode.find("filea").unwrap().unwrap();
+            filea.write_at(3 * BLOCK_SZ, &[2u8; 40 * BLOCK_SZ]).unwrap();
+            root_inode.create("filec").unwrap();
+            filea.clear().unwrap();
+        }
+        // power loss: whatever is still cached never reaches the disk
+        block_cache_clear();
+        let crashed = device.crashed();
+
+        // checking leaves a read-only image alone, even with a pending journal
+        let read_only: Arc<dyn BlockDevice> = Arc::new(BlockFile(Mutex::new(
+            OpenOptions::new().read(true).open("target/crash.img")?,
+        )));
+        let efs = EasyFileSystem::open_unchecked(read_only).unwrap();
+        let pending = efs.lock().journal_pending();
+        let problems = efs.lock().fsck(false);
+        assert_eq!(
+            problems
+                .iter()
+                .any(|p| matches!(p, FsckProblem::PendingJournal)),
+            pending
+        );
+        pending_journals += pending as usize;
+        drop(efs);
+        block_cache_clear();
+        // ls and extract refuse it rather than list stale metadata
+        assert_eq!(crate::open_image("target/crash.img", false).is_err(), pending);
+        block_cache_clear();
+
+        // replay the journal and check that metadata is consistent
+        let block_file: Arc<dyn BlockDevice> = Arc::new(open_image("target/crash.img")?);
+        let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let entries = root_inode.read_dir().unwrap();
+        let mut used_blocks = root_inode.blocks().unwrap() as usize;
+        for (name, inode) in entries.iter() {
+            let size = inode.size().unwrap() as usize;
+            let mut data = vec![0u8; size];
+            assert_eq!(inode.read_at(0, &mut data).unwrap(), size, "{}", name);
+            used_blocks += inode.blocks().unwrap() as usize;
+        }
+        let efs_locked = efs.lock();
+        assert_eq!(
+            efs_locked.inode_bitmap.allocated(&block_file),
+            entries.len() + 1,
+            "leaked inode after {} writes",
+            budget
+        );
+        assert_eq!(
+            efs_locked.data_bitmap.allocated(&block_file),
+            used_blocks,
+            "leaked data block after {} writes",
+            budget
+        );
+        drop(efs_locked);
+        block_cache_clear();
+        if !crashed {
+            break;
+        }
+        budget += 1;
+    }
+    assert!(pending_journals > 0);
+    Ok(())
+}
+
+#[test]
+fn efs_fsck_test() -> std::io::Result<()> {
+    let (_guard, block_file, efs) = new_test_image("fsck.img", 4096, InodeFormat::Indirect)?;
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let filea = root_inode.create("filea").unwrap().unwrap();
+    filea.write_at(0, &[1u8; 200 * BLOCK_SZ]).unwrap();
+    root_inode.create("fileb").unwrap();
+    assert!(efs.lock().fsck(false).is_empty());
+
+    {
+        let efs = efs.lock();
+        // an orphan inode, a leaked block and a block of filea marked free
+        let orphan = efs.inode_bitmap.alloc(&block_file).unwrap();
+        let leaked = efs.data_bitmap.alloc(&block_file).unwrap();
+        efs.data_bitmap.dealloc(&block_file, 0);
+        let problems = efs.fsck(true);
+        assert_eq!(problems.len(), 3);
+        let report: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+        assert!(report.contains(&format!("inode {} leaked", orphan)));
+        assert!(report.contains(&format!(
+            "block {} leaked",
+            efs.get_data_block_id(leaked as u32)
+        )));
+        assert!(report.contains(&format!(
+            "block {} in use but free",
+            efs.get_data_block_id(0)
+        )));
+        assert!(efs.fsck(false).is_empty());
+    }
+    let mut buffer = [0u8; BLOCK_SZ];
+    assert_eq!(
+        filea.read_at(199 * BLOCK_SZ, &mut buffer).unwrap(),
+        BLOCK_SZ
+    );
+    assert_eq!(buffer, [1u8; BLOCK_SZ]);
+    block_cache_clear();
+    Ok(())
+}
+
+/// Long names and a directory big enough for a two level hash index,
+/// then the fixed 32 byte entries of images made before version 1.
+#[test]
+fn efs_dir_index_test() -> std::io::Result<()> {
+    let (guard, block_file, efs) = new_test_image("dir_index.img", 16384, InodeFormat::Indirect)?;
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let long_name = "l".repeat(NAME_LENGTH_LIMIT);
+    assert!(root_inode.create(&long_name).unwrap().is_some());
+    assert!(root_inode
+        .create(&"l".repeat(NAME_LENGTH_LIMIT + 1))
+        .unwrap()
+        .is_none());
+    let name = |i: usize| format!("file {} with a longer name", i);
+    // more entries than the leaves a single index block points to
+    for i in 0..2000 {
+        assert!(
+            root_inode.create(&name(i)).unwrap().is_some(),
+            "{}",
+            name(i)
+        );
+    }
+    assert!(root_inode.create(&name(1234)).unwrap().is_none());
+    assert_eq!(root_inode.ls().unwrap().len(), 2001);
+    for i in (0..2000).step_by(3) {
+        assert!(root_inode.unlink(&name(i)).unwrap());
+    }
+    for i in 0..2000 {
+        assert_eq!(root_inode.find(&name(i)).unwrap().is_some(), i % 3 != 0);
+    }
+    assert!(efs.lock().fsck(false).is_empty());
+
+    // everything is found again from the disk
+    block_cache_clear();
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert!(root_inode.find(&long_name).unwrap().is_some());
+    assert!(root_inode.find(&name(1999)).unwrap().is_some());
+    assert_eq!(root_inode.ls().unwrap().len(), 1 + 2000 - 667);
+
+    // a broken leaf loses its entries, the rest of the directory is rebuilt
+    block_cache_clear();
+    let mut block = [0u8; BLOCK_SZ];
+    let needle = name(1).into_bytes();
+    let leaf = (0..16384)
+        .find(|block_id| {
+            block_file.read_block(*block_id, &mut block);
+            block.windows(needle.len()).any(|w| w == &needle[..])
+        })
+        .unwrap();
+    block[4] = 3;
+    block_file.write_block(leaf, &block);
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let problems = efs.lock().fsck(true);
+    let report: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+    assert!(
+        report.iter().any(|p| p.ends_with("is corrupted")),
+        "{:?}",
+        report
+    );
+    assert!(efs.lock().fsck(false).is_empty());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert!(root_inode.find(&name(1)).unwrap().is_none());
+    let left = root_inode.ls().unwrap().len();
+    assert!(left > 1000 && left < 1 + 2000 - 667);
+    block_cache_clear();
+
+    // version 0: the SuperBlock field is zero, names stop at 27 bytes, and
+    // no block is checksummed
+    drop(guard);
+    let (_guard, block_file, _) = new_test_image("dir_index.img", 16384, InodeFormat::Indirect)?;
+    block_cache_clear();
+    block_file.read_block(0, &mut block);
+    block[28..32].copy_from_slice(&0u32.to_le_bytes());
+    block[32] &= !4;
+    block_file.write_block(0, &block);
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert!(root_inode.create(&"f".repeat(28)).unwrap().is_none());
+    for i in 0..40 {
+        assert!(root_inode
+            .create(&format!("fixed {}", i))
+            .unwrap()
+            .is_some());
+    }
+    assert_eq!(root_inode.size().unwrap() as usize, 40 * 32);
+    assert!(root_inode.unlink("fixed 3").unwrap());
+    assert_eq!(root_inode.ls().unwrap()[3], "fixed 39");
+    assert!(efs.lock().fsck(false).is_empty());
+    block_cache_clear();
+    Ok(())
+}
+
+/// A file larger than the indirect blocks reach, then two files growing
+/// side by side until their extent trees need two levels of index blocks.
+#[test]
+fn efs_extent_test() -> std::io::Result<()> {
+    let (_guard, block_file, efs) = new_test_image("extent.img", 24576, InodeFormat::Extents)?;
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let big = root_inode.create("big").unwrap().unwrap();
+    // the blocks of the root directory stay after the files are gone
+    let in_use = efs.lock().data_bitmap.allocated(&block_file);
+    let chunk: Vec<u8> = (0..64 * BLOCK_SZ).map(|i| (i / BLOCK_SZ) as u8).collect();
+    let data_blocks = 18 * 1024;
+    for i in 0..data_blocks / 64 {
+        assert_eq!(big.write_at(i * chunk.len(), &chunk).unwrap(), chunk.len());
+    }
+    assert_eq!(big.size().unwrap() as usize, data_blocks * BLOCK_SZ);
+    // allocated in a single run
+    assert_eq!(big.blocks().unwrap() as usize, data_blocks);
+    assert!(efs.lock().fsck(false).is_empty());
+    block_cache_sync_all();
+    block_cache_clear();
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let big = root_inode.find("big").unwrap().unwrap();
+    let mut buffer = [0u8; BLOCK_SZ];
+    assert_eq!(
+        big.read_at((data_blocks - 1) * BLOCK_SZ, &mut buffer)
+            .unwrap(),
+        BLOCK_SZ
+    );
+    assert_eq!(buffer, [63u8; BLOCK_SZ]);
+    // cutting the only extent in two
+    big.punch_hole(1000 * BLOCK_SZ, 1000 * BLOCK_SZ).unwrap();
+    assert_eq!(big.blocks().unwrap() as usize, data_blocks - 1000);
+    assert_eq!(big.read_at(2000 * BLOCK_SZ, &mut buffer).unwrap(), BLOCK_SZ);
+    assert_eq!(buffer, [(2000 % 64) as u8; BLOCK_SZ]);
+    assert!(efs.lock().fsck(false).is_empty());
+    assert!(root_inode.unlink("big").unwrap());
+
+    let a = root_inode.create("a").unwrap().unwrap();
+    let b = root_inode.create("b").unwrap().unwrap();
+    for i in 0..600 {
+        a.write_at(i * BLOCK_SZ, &[(i % 251) as u8; BLOCK_SZ])
+            .unwrap();
+        b.write_at(i * BLOCK_SZ, &[(i % 241) as u8; BLOCK_SZ])
+            .unwrap();
+    }
+    // one extent per block, more than a single index level holds
+    assert!(a.blocks().unwrap() > 600 + 9);
+    assert!(efs.lock().fsck(false).is_empty());
+    for i in 0..600 {
+        assert_eq!(a.read_at(i * BLOCK_SZ, &mut buffer).unwrap(), BLOCK_SZ);
+        assert_eq!(buffer, [(i % 251) as u8; BLOCK_SZ]);
+        assert_eq!(b.read_at(i * BLOCK_SZ, &mut buffer).unwrap(), BLOCK_SZ);
+        assert_eq!(buffer, [(i % 241) as u8; BLOCK_SZ]);
+    }
+    a.clear().unwrap();
+    assert_eq!(a.blocks().unwrap(), 0);
+    assert!(efs.lock().fsck(false).is_empty());
+    assert!(root_inode.unlink("a").unwrap());
+    assert!(root_inode.unlink("b").unwrap());
+    assert_eq!(efs.lock().data_bitmap.allocated(&block_file), in_use);
+    assert!(efs.lock().fsck(false).is_empty());
+    block_cache_clear();
+    Ok(())
+}
+
+/// Holes left by writes past the end, filled in any order and punched
+/// again, with both inode formats.
+#[test]
+fn efs_sparse_test() -> std::io::Result<()> {
+    for inode_format in [InodeFormat::Indirect, InodeFormat::Extents] {
+        let (_guard, block_file, efs) = new_test_image("sparse.img", 8192, inode_format)?;
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let sparse = root_inode.create("sparse").unwrap().unwrap();
+        let in_use = efs.lock().data_bitmap.allocated(&block_file);
+        let mut buffer = [0xffu8; BLOCK_SZ];
+
+        // 6 MiB of holes, more than the image holds, then one block
+        let offset = 6 * 1024 * 1024;
+        assert_eq!(sparse.write_at(offset, &[7u8; BLOCK_SZ]).unwrap(), BLOCK_SZ);
+        assert_eq!(sparse.size().unwrap() as usize, offset + BLOCK_SZ);
+        assert!(sparse.blocks().unwrap() <= 3);
+        assert_eq!(sparse.read_at(offset / 2, &mut buffer).unwrap(), BLOCK_SZ);
+        assert_eq!(buffer, [0u8; BLOCK_SZ]);
+        assert_eq!(sparse.read_at(offset, &mut buffer).unwrap(), BLOCK_SZ);
+        assert_eq!(buffer, [7u8; BLOCK_SZ]);
+        assert!(efs.lock().fsck(false).is_empty());
+
+        // fill the first blocks backwards, next to another file growing
+        let other = root_inode.create("other").unwrap().unwrap();
+        for i in (0..400).rev() {
+            sparse
+                .write_at(i * BLOCK_SZ, &[(i % 251) as u8; BLOCK_SZ])
+                .unwrap();
+            other.write_at(i * BLOCK_SZ, &[1u8; BLOCK_SZ]).unwrap();
+        }
+        assert!(efs.lock().fsck(false).is_empty());
+        for i in 0..400 {
+            assert_eq!(sparse.read_at(i * BLOCK_SZ, &mut buffer).unwrap(), BLOCK_SZ);
+            assert_eq!(buffer, [(i % 251) as u8; BLOCK_SZ]);
+        }
+
+        // whole blocks become holes, partial ones are zeroed
+        let blocks = sparse.blocks().unwrap();
+        sparse
+            .punch_hole(100 * BLOCK_SZ + 10, 200 * BLOCK_SZ)
+            .unwrap();
+        assert!(sparse.blocks().unwrap() <= blocks - 199);
+        assert_eq!(sparse.size().unwrap() as usize, offset + BLOCK_SZ);
+        assert_eq!(
+            sparse.read_at(100 * BLOCK_SZ, &mut buffer).unwrap(),
+            BLOCK_SZ
+        );
+        assert_eq!(buffer[..10], [100u8; 10]);
+        assert_eq!(buffer[10..], [0u8; BLOCK_SZ - 10]);
+        for i in 101..300 {
+            sparse.read_at(i * BLOCK_SZ, &mut buffer).unwrap();
+            assert_eq!(buffer, [0u8; BLOCK_SZ]);
+        }
+        sparse.read_at(300 * BLOCK_SZ, &mut buffer).unwrap();
+        assert_eq!(buffer[..10], [0u8; 10]);
+        assert_eq!(buffer[10..], [(300 % 251) as u8; BLOCK_SZ - 10]);
+        sparse.read_at(99 * BLOCK_SZ, &mut buffer).unwrap();
+        assert_eq!(buffer, [99u8; BLOCK_SZ]);
+        assert!(efs.lock().fsck(false).is_empty());
+        // punching up to the end frees the last block too
+        sparse.punch_hole(offset, 2 * BLOCK_SZ).unwrap();
+        sparse.read_at(offset, &mut buffer).unwrap();
+        assert_eq!(buffer, [0u8; BLOCK_SZ]);
+        assert!(efs.lock().fsck(false).is_empty());
+
+        // what was punched can be written again
+        sparse.write_at(200 * BLOCK_SZ, &[9u8; BLOCK_SZ]).unwrap();
+        sparse.read_at(200 * BLOCK_SZ, &mut buffer).unwrap();
+        assert_eq!(buffer, [9u8; BLOCK_SZ]);
+        assert!(efs.lock().fsck(false).is_empty());
+        assert!(root_inode.unlink("sparse").unwrap());
+        assert!(root_inode.unlink("other").unwrap());
+        assert_eq!(efs.lock().data_bitmap.allocated(&block_file), in_use);
+        assert!(efs.lock().fsck(false).is_empty());
+        block_cache_clear();
+    }
+    Ok(())
+}
+
+/// Punching megabytes out of a file is split into transactions that fit
+/// the block cache and the journal.
+#[test]
+fn efs_punch_large_test() -> std::io::Result<()> {
+    for inode_format in [InodeFormat::Indirect, InodeFormat::Extents] {
+        let (_guard, block_file, efs) = new_test_image("punch.img", 32768, inode_format)?;
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let file = root_inode.create("large").unwrap().unwrap();
+        let in_use = efs.lock().data_bitmap.allocated(&block_file);
+        let chunk = [5u8; 64 * BLOCK_SZ];
+        let size = 8 * 1024 * 1024;
+        for offset in (0..size).step_by(chunk.len()) {
+            assert_eq!(file.write_at(offset, &chunk).unwrap(), chunk.len());
+        }
+
+        // 7 MiB from the second block on, leaving both ends in place
+        file.punch_hole(BLOCK_SZ, 7 * 1024 * 1024).unwrap();
+        assert_eq!(file.size().unwrap() as usize, size);
+        // what is left of the data, plus the index blocks mapping it
+        let left = (size - 7 * 1024 * 1024) / BLOCK_SZ;
+        assert!(file.blocks().unwrap() as usize <= left + left / 64);
+        let mut buffer = [0xffu8; BLOCK_SZ];
+        file.read_at(0, &mut buffer).unwrap();
+        assert_eq!(buffer, [5u8; BLOCK_SZ]);
+        for i in [1, 1000, 14336] {
+            file.read_at(i * BLOCK_SZ, &mut buffer).unwrap();
+            assert_eq!(buffer, [0u8; BLOCK_SZ]);
+        }
+        file.read_at(14337 * BLOCK_SZ, &mut buffer).unwrap();
+        assert_eq!(buffer, [5u8; BLOCK_SZ]);
+        assert!(efs.lock().fsck(false).is_empty());
+
+        // the rest, up to the end of the file
+        file.punch_hole(0, size).unwrap();
+        assert_eq!(file.blocks().unwrap(), 0);
+        assert!(efs.lock().fsck(false).is_empty());
+        assert!(root_inode.unlink("large").unwrap());
+        assert_eq!(efs.lock().data_bitmap.allocated(&block_file), in_use);
+        block_cache_clear();
+    }
+    Ok(())
+}
+
+/// Files scattered over more bitmap blocks than the journal holds are
+/// truncated and deleted, a transaction that does not fit is refused.
+#[test]
+fn efs_journal_overflow_test() -> std::io::Result<()> {
+    const BLOCK_BITS: usize = BLOCK_SZ * 8;
+    let regions = JOURNAL_BLOCKS as usize + 8;
+    let blocks = ((regions + 1) * BLOCK_BITS) as u32;
+    for inode_format in [InodeFormat::Indirect, InodeFormat::Extents] {
+        let (_guard, block_file, efs) = new_test_image("overflow.img", blocks, inode_format)?;
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let file = root_inode.create("large").unwrap().unwrap();
+        // leave one free block per bitmap block, the bits set by hand are
+        // all fsck finds wrong
+        let mut leaked = 0;
+        let bits = efs.lock().data_bitmap.maximum();
+        for bit in 0..bits {
+            let efs = efs.lock();
+            if bit % BLOCK_BITS != BLOCK_BITS - 1 && !efs.data_bitmap.is_allocated(&block_file, bit)
+            {
+                efs.data_bitmap.set(&block_file, bit);
+                leaked += 1;
+            }
+        }
+        let check = || {
+            let problems = efs.lock().fsck(false);
+            assert_eq!(problems.len(), leaked);
+            assert!(problems
+                .iter()
+                .all(|p| matches!(p, FsckProblem::BlockLeaked(_))));
+        };
+        let in_use = efs.lock().data_bitmap.allocated(&block_file);
+        let data = [7u8; 64 * BLOCK_SZ];
+        assert!(data.len() / BLOCK_SZ > efs.lock().op_capacity());
+
+        file.write_at(0, &data).unwrap();
+        check();
+        file.clear().unwrap();
+        assert_eq!(file.size().unwrap(), 0);
+        assert_eq!(file.blocks().unwrap(), 0);
+        assert_eq!(efs.lock().data_bitmap.allocated(&block_file), in_use);
+        check();
+        file.write_at(0, &data).unwrap();
+        assert!(root_inode.unlink("large").unwrap());
+        assert_eq!(efs.lock().data_bitmap.allocated(&block_file), in_use);
+        check();
+
+        // every block of a refused transaction stays pinned until it is
+        // rolled back, the cache must hold them all
+        set_block_cache_capacity(256);
+        let file = root_inode.create("large").unwrap().unwrap();
+        let in_use = efs.lock().data_bitmap.allocated(&block_file);
+        efs.lock().begin_op();
+        assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+        let err = efs.lock().end_op().unwrap_err();
+        assert_eq!(err.kind, IoErrorKind::JournalFull);
+        assert_eq!(file.size().unwrap(), 0);
+        assert_eq!(file.blocks().unwrap(), 0);
+        assert_eq!(efs.lock().data_bitmap.allocated(&block_file), in_use);
+        check();
+        block_cache_clear();
+        assert_eq!(file.size().unwrap(), 0);
+        check();
+        // back to the default capacity
+        set_block_cache_capacity(64);
+        block_cache_clear();
+    }
+    Ok(())
+}
+
+/// Files packed compressed read back at any offset, get smaller when they
+/// compress and are stored plainly again once written.
+#[test]
+fn efs_compress_test() -> std::io::Result<()> {
+    // text, then bytes that do not compress, 8 KiB of zeros and a short
+    // last cluster
+    let mut data: Vec<u8> = (0..600)
+        .flat_map(|i| format!("line {} of a compressible file\n", i).into_bytes())
+        .collect();
+    let mut seed = 0x2545_f491u32;
+    data.extend((0..4096).map(|_| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed as u8
+    }));
+    data.resize(data.len() + 8192, 0);
+    data.extend_from_slice(b"the end");
+    for inode_format in [InodeFormat::Indirect, InodeFormat::Extents] {
+        let (_guard, block_file, efs) = new_test_image("compress.img", 4096, inode_format)?;
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let plain = root_inode.create("plain").unwrap().unwrap();
+        let in_use = efs.lock().data_bitmap.allocated(&block_file);
+        plain.write_at(0, &data).unwrap();
+        let packed = root_inode.create("packed").unwrap().unwrap();
+        assert!(packed.write_compressed(&data).unwrap());
+        assert!(!packed.write_compressed(&data).unwrap());
+        assert!(packed.is_compressed().unwrap());
+        assert_eq!(packed.size().unwrap() as usize, data.len());
+        assert!(packed.blocks().unwrap() * 2 < plain.blocks().unwrap());
+        assert!(efs.lock().fsck(false).is_empty());
+
+        // reads of any length at any offset, also from the disk
+        let check = |inode: &Inode, data: &[u8]| {
+            let mut buffer = vec![0u8; data.len() + 100];
+            assert_eq!(inode.read_at(0, &mut buffer).unwrap(), data.len());
+            assert_eq!(buffer[..data.len()], data[..]);
+            for (offset, len) in [(1, 10), (4090, 20), (20000, 700), (29000, 5000)] {
+                let len = inode.read_at(offset, &mut buffer[..len]).unwrap();
+                assert_eq!(buffer[..len], data[offset..offset + len]);
+            }
+            assert_eq!(inode.read_at(data.len(), &mut buffer).unwrap(), 0);
+        };
+        check(&packed, &data);
+        block_cache_sync_all();
+        block_cache_clear();
+        let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let packed = root_inode.find("packed").unwrap().unwrap();
+        check(&packed, &data);
+
+        // a write decompresses the whole file first
+        packed.write_at(5, b"changed").unwrap();
+        let mut changed = data.clone();
+        changed[5..12].copy_from_slice(b"changed");
+        assert!(!packed.is_compressed().unwrap());
+        check(&packed, &changed);
+        assert!(efs.lock().fsck(false).is_empty());
+
+        // blocks of freed clusters hold other data
+        assert!(root_inode.unlink("packed").unwrap());
+        let packed = root_inode.create("packed").unwrap().unwrap();
+        assert!(packed.write_compressed(&data[4096..]).unwrap());
+        let other = root_inode.create("other").unwrap().unwrap();
+        other.write_at(0, &[3u8; 8192]).unwrap();
+        let mut buffer = vec![0u8; data.len()];
+        let len = packed.read_at(0, &mut buffer).unwrap();
+        assert_eq!(buffer[..len], data[4096..]);
+        packed.clear().unwrap();
+        assert!(!packed.is_compressed().unwrap());
+        assert!(root_inode.unlink("packed").unwrap());
+        assert!(root_inode.unlink("other").unwrap());
+        assert!(root_inode.unlink("plain").unwrap());
+        assert_eq!(efs.lock().data_bitmap.allocated(&block_file), in_use);
+        assert!(efs.lock().fsck(false).is_empty());
+        block_cache_clear();
+    }
+    Ok(())
+}
+
+#[test]
+fn efs_owner_test() -> std::io::Result<()> {
+    let (_guard, block_file, efs) = new_test_image("owner.img", 4096, InodeFormat::Extents)?;
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let root_owner = |mode| InodeOwner {
+        uid: 0,
+        gid: 0,
+        mode,
+    };
+    assert_eq!(root_inode.owner().unwrap(), Some(root_owner(0o755)));
+    let file = root_inode.create("file").unwrap().unwrap();
+    assert_eq!(file.owner().unwrap(), Some(root_owner(0o644)));
+    let dir = root_inode.create_dir("dir").unwrap().unwrap();
+    assert_eq!(dir.owner().unwrap(), Some(root_owner(0o755)));
+    let owner = InodeOwner {
+        uid: 1000,
+        gid: 100,
+        mode: 0o4750,
+    };
+    assert!(file.set_owner(owner).unwrap());
+    file.write_at(0, b"owned").unwrap();
+    // a new inode in the same slot does not inherit the old owner
+    assert!(dir.set_owner(owner).unwrap());
+    assert!(root_inode.unlink("dir").unwrap());
+    let dir = root_inode.create_dir("dir").unwrap().unwrap();
+    assert_eq!(dir.owner().unwrap(), Some(root_owner(0o755)));
+    // a FIFO is an inode of its own type, without data
+    let fifo = root_inode.create_fifo("fifo").unwrap().unwrap();
+    assert!(fifo.is_fifo().unwrap() && !fifo.is_dir().unwrap());
+    assert_eq!(fifo.owner().unwrap(), Some(root_owner(0o644)));
+    assert!(root_inode.create_fifo("fifo").unwrap().is_none());
+    assert!(efs.lock().fsck(false).is_empty());
+    block_cache_sync_all();
+
+    block_cache_clear();
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let file = root_inode.find("file").unwrap().unwrap();
+    assert_eq!(file.owner().unwrap(), Some(owner));
+    let mut buffer = [0u8; 5];
+    assert_eq!(file.read_at(0, &mut buffer).unwrap(), 5);
+    assert_eq!(&buffer, b"owned");
+    let fifo = root_inode.find("fifo").unwrap().unwrap();
+    assert!(fifo.is_fifo().unwrap());
+    assert_eq!(fifo.size().unwrap(), 0);
+    Ok(())
+}
+
+/// Block file remembering every write request as its first block and
+/// number of blocks.
+#[cfg(test)]
+struct RecordingBlockFile {
+    file: BlockFile,
+    writes: Mutex<Vec<(usize, usize)>>,
+}
+
+#[cfg(test)]
+impl BlockDevice for RecordingBlockFile {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.file.read_block(block_id, buf)
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        self.write_blocks(block_id, buf)
+    }
+
+    fn handle_irq(&self) {
+        unimplemented!();
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) {
+        self.writes
+            .lock()
+            .unwrap()
+            .push((block_id, buf.len() / BLOCK_SZ));
+        self.file.write_blocks(block_id, buf)
+    }
+}
+
+/// Writing the cache back merges adjacent dirty blocks into one request.
+#[test]
+fn efs_write_merge_test() -> std::io::Result<()> {
+    let (_guard, _, efs) = new_test_image("merge.img", 4096, InodeFormat::Extents)?;
+    drop(efs);
+    block_cache_clear();
+    // the image again, through a device recording the requests
+    let recorder = Arc::new(RecordingBlockFile {
+        file: BlockFile(Mutex::new(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("target/merge.img")?,
+        )),
+        writes: Mutex::new(Vec::new()),
+    });
+    let block_file: Arc<dyn BlockDevice> = recorder.clone();
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let file = root_inode.create("file").unwrap().unwrap();
+    block_cache_sync_all();
+    recorder.writes.lock().unwrap().clear();
+    let data: Vec<u8> = (0..16 * BLOCK_SZ).map(|i| (i / 7) as u8).collect();
+    file.write_at(0, &data).unwrap();
+    // the journal logs the metadata of the write in one request as well
+    assert!(recorder
+        .writes
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|&(block_id, blocks)| block_id == 2 && blocks > 1));
+    recorder.writes.lock().unwrap().clear();
+    block_cache_sync_all();
+    let writes = recorder.writes.lock().unwrap().clone();
+    // one extent of data blocks goes down as a single request
+    assert!(writes.iter().any(|&(_, blocks)| blocks >= 16));
+    // requests come in block order and no block is written twice
+    assert!(writes
+        .windows(2)
+        .all(|pair| pair[0].0 + pair[0].1 <= pair[1].0));
+
+    // the flusher merges its copies the same way and leaves the blocks clean
+    let data: Vec<u8> = data.iter().map(|b| b.wrapping_add(1)).collect();
+    file.write_at(0, &data).unwrap();
+    recorder.writes.lock().unwrap().clear();
+    assert!(block_cache_try_sync_all());
+    assert!(recorder
+        .writes
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|&(_, blocks)| blocks >= 16));
+    recorder.writes.lock().unwrap().clear();
+    block_cache_sync_all();
+    assert!(recorder.writes.lock().unwrap().is_empty());
+
+    block_cache_clear();
+    let efs = EasyFileSystem::open(block_file).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let file = root_inode.find("file").unwrap().unwrap();
+    let mut buffer = vec![0u8; data.len()];
+    assert_eq!(file.read_at(0, &mut buffer).unwrap(), data.len());
+    assert_eq!(buffer, data);
+    block_cache_clear();
+    Ok(())
+}
+
+/// Blocks failing their checksum are I/O errors until fsck seals them again.
+#[test]
+fn efs_checksum_test() -> std::io::Result<()> {
+    let (_guard, block_file, efs) = new_test_image("checksum.img", 4096, InodeFormat::Extents)?;
+    assert!(efs.lock().has_checksums());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let dir = root_inode.create_dir("dir").unwrap().unwrap();
+    dir.create("inner").unwrap().unwrap();
+    // not in the inode block of the root
+    let file = root_inode.create("file").unwrap().unwrap();
+    file.write_at(0, b"checksummed").unwrap();
+    let inode_id = file.inode_id();
+    let (inode_block, inode_offset) = efs.lock().get_disk_inode_pos(inode_id);
+    block_cache_sync_all();
+    block_cache_clear();
+    let flip = |block_id: usize, offset: usize| {
+        let mut block = [0u8; BLOCK_SZ];
+        block_file.read_block(block_id, &mut block);
+        block[offset] ^= 0x5a;
+        block_file.write_block(block_id, &block);
+    };
+
+    // a reserved byte of the inode, only the checksum tells
+    flip(inode_block as usize, inode_offset + 128 + 20);
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let file = root_inode.find("file").unwrap().unwrap();
+    let err = file.size().unwrap_err();
+    assert_eq!(err.block_id, inode_block as usize);
+    assert!(file.read_at(0, &mut [0u8; 16]).is_err());
+    let report: Vec<String> = efs
+        .lock()
+        .fsck(true)
+        .iter()
+        .map(|p| p.to_string())
+        .collect();
+    assert_eq!(
+        report,
+        [format!("block {} does not match its checksum", inode_block)]
+    );
+    assert!(efs.lock().fsck(false).is_empty());
+    assert_eq!(file.size().unwrap(), 11);
+
+    // the tail of a directory leaf
+    block_cache_clear();
+    let needle = b"inner";
+    let mut block = [0u8; BLOCK_SZ];
+    // the journal may hold an older copy
+    let data_start = efs.lock().get_data_block_id(0) as usize;
+    let leaf = (data_start..4096)
+        .find(|block_id| {
+            block_file.read_block(*block_id, &mut block);
+            block.windows(needle.len()).any(|w| w == needle)
+        })
+        .unwrap();
+    flip(leaf, BLOCK_SZ - 1);
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let dir = root_inode.find("dir").unwrap().unwrap();
+    assert_eq!(dir.find("inner").err().unwrap().block_id, leaf);
+    assert!(dir.ls().is_err());
+    assert!(dir.create("other").is_err());
+    assert!(efs
+        .lock()
+        .fsck(false)
+        .iter()
+        .any(|p| p.to_string() == format!("block {} does not match its checksum", leaf)));
+    efs.lock().fsck(true);
+    assert!(efs.lock().fsck(false).is_empty());
+    assert!(dir.find("inner").unwrap().is_some());
+
+    // the SuperBlock, the image cannot be opened before fsck
+    block_cache_clear();
+    flip(0, BLOCK_SZ - 1);
+    assert_eq!(
+        EasyFileSystem::open(block_file.clone())
+            .err()
+            .unwrap()
+            .block_id,
+        0
+    );
+    block_cache_clear();
+    let efs = EasyFileSystem::open_unchecked(block_file.clone()).unwrap();
+    assert_eq!(efs.lock().fsck(true).len(), 1);
+    block_cache_clear();
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root_inode.ls().unwrap().len(), 2);
+    block_cache_clear();
+    Ok(())
+}
+
+#[test]
+fn efs_manage_test() -> std::io::Result<()> {
+    let (_guard, _, efs) = new_test_image("manage.img", 4096, InodeFormat::Indirect)?;
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    // a host tree with a file using indirect2 blocks
+    let host_dir = Path::new("target/manage_src");
+    let _ = std::fs::remove_dir_all(host_dir);
+    create_dir_all(host_dir.join("sub"))?;
+    std::fs::write(host_dir.join("small"), b"small file")?;
+    let big: Vec<u8> = (0..(28 + 128 + 10) * BLOCK_SZ).map(|i| i as u8).collect();
+    std::fs::write(host_dir.join("sub/big"), &big)?;
+
+    add(&root_inode, host_dir, "/tree")?;
+    add(&root_inode, &host_dir.join("small"), "/tree/sub/big")?;
+    make_dir(&root_inode, "/a/b/c", true)?;
+    assert!(make_dir(&root_inode, "/a", false).is_err());
+    assert_eq!(
+        find_path(&root_inode, "/tree/sub/big")?.size().unwrap() as usize,
+        b"small file".len()
+    );
+    let _ = std::fs::remove_dir_all("target/manage_dst");
+    extract(
+        &find_path(&root_inode, "/tree")?,
+        Path::new("target/manage_dst"),
+    )?;
+    assert_eq!(std::fs::read("target/manage_dst/small")?, b"small file");
+
+    assert!(remove(&root_inode, "/a", false).is_err());
+    remove(&root_inode, "/a", true)?;
+    remove(&root_inode, "/tree/small", false)?;
+    assert_eq!(root_inode.ls().unwrap(), vec!["tree"]);
+    assert!(efs.lock().fsck(false).is_empty());
+    block_cache_clear();
+    Ok(())
+}
+
+/// Images on two devices are cached side by side, their blocks share ids
+/// but not data, and each can be written back on its own.
+#[test]
+fn efs_two_devices_test() -> std::io::Result<()> {
+    let (_guard, _, first) = new_test_image("first.img", 4096, InodeFormat::Extents)?;
+    let second_file: Arc<dyn BlockDevice> = Arc::new(BlockFile(Mutex::new({
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("target/second.img")?;
+        f.set_len(4096 * 512).unwrap();
+        f
+    })));
+    let second = EasyFileSystem::create(second_file.clone(), 4096, 1, InodeFormat::Extents);
+    for (efs, byte) in [(&first, 1u8), (&second, 2u8)] {
+        let file = EasyFileSystem::root_inode(efs)
+            .create("file")
+            .unwrap()
+            .unwrap();
+        file.write_at(0, &[byte; 3 * BLOCK_SZ]).unwrap();
+    }
+    for (efs, byte) in [(&first, 1u8), (&second, 2u8)] {
+        let file = EasyFileSystem::root_inode(efs)
+            .find("file")
+            .unwrap()
+            .unwrap();
+        let mut buffer = [0u8; 3 * BLOCK_SZ];
+        assert_eq!(file.read_at(0, &mut buffer).unwrap(), buffer.len());
+        assert!(buffer.iter().all(|&b| b == byte));
+    }
+    // a device opened again does not share the cached blocks, so it only
+    // sees what was written back
+    block_cache_sync(&second_file);
+    let reopened: Arc<dyn BlockDevice> = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("target/second.img")?,
+    )));
+    let efs = EasyFileSystem::open(reopened).unwrap();
+    let file = EasyFileSystem::root_inode(&efs)
+        .find("file")
+        .unwrap()
+        .unwrap();
+    let mut buffer = [0u8; 3 * BLOCK_SZ];
+    assert_eq!(file.read_at(0, &mut buffer).unwrap(), buffer.len());
+    assert!(buffer.iter().all(|&b| b == 2));
+    assert!(first.lock().fsck(false).is_empty());
+    assert!(second.lock().fsck(false).is_empty());
+    block_cache_clear();
+    Ok(())
+}
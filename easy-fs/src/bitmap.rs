@@ -27,32 +27,88 @@ impl Bitmap {
 
     pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
         for block_id in 0..self.blocks {
-            let pos = get_block_cache(
-                block_id + self.start_block_id as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |bitmap_block: &mut BitmapBlock| {
-                if let Some((bits64_pos, inner_pos)) = bitmap_block
+            let bitmap_block =
+                get_block_cache(block_id + self.start_block_id, Arc::clone(block_device));
+            // only modify the block we allocate from, so that a transaction
+            // does not record every full bitmap block we went through
+            let pos = bitmap_block.lock().read(0, |bitmap_block: &BitmapBlock| {
+                bitmap_block
                     .iter()
                     .enumerate()
                     .find(|(_, bits64)| **bits64 != u64::MAX)
                     .map(|(bits64_pos, bits64)| (bits64_pos, bits64.trailing_ones() as usize))
-                {
-                    // modify cache
-                    bitmap_block[bits64_pos] |= 1u64 << inner_pos;
-                    Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos as usize)
-                } else {
-                    None
-                }
             });
-            if pos.is_some() {
-                return pos;
+            if let Some((bits64_pos, inner_pos)) = pos {
+                // modify cache
+                bitmap_block
+                    .lock()
+                    .modify(0, |bitmap_block: &mut BitmapBlock| {
+                        bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+                    });
+                return Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos);
             }
         }
         None
     }
 
+    /// Allocate a bit below `limit` close after `goal`: the goal itself, a
+    /// free bit in the next 64, the start of an entirely free word so that
+    /// the following allocations stay contiguous, or any free bit.
+    pub fn alloc_near(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        goal: usize,
+        limit: usize,
+    ) -> Option<usize> {
+        let limit = limit.min(self.maximum());
+        let goal = if goal < limit { goal } else { 0 };
+        let bit = self.find_near(block_device, goal, limit)?;
+        self.set(block_device, bit);
+        Some(bit)
+    }
+
+    fn find_near(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        goal: usize,
+        limit: usize,
+    ) -> Option<usize> {
+        // copy each bitmap block once instead of locking it for every word
+        let mut cached: Option<(usize, BitmapBlock)> = None;
+        let mut word = |index: usize| {
+            let block_pos = index * 64 / BLOCK_BITS;
+            if cached.is_none_or(|(pos, _)| pos != block_pos) {
+                let bitmap_block =
+                    get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+                        .lock()
+                        .read(0, |bitmap_block: &BitmapBlock| *bitmap_block);
+                cached = Some((block_pos, bitmap_block));
+            }
+            cached.unwrap().1[index % (BLOCK_BITS / 64)]
+        };
+        let is_free = |bits64: u64, bit: usize| bits64 & (1u64 << (bit % 64)) == 0;
+        if let Some(bit) = (goal..(goal + 64).min(limit)).find(|&bit| is_free(word(bit / 64), bit))
+        {
+            return Some(bit);
+        }
+        let words = limit / 64;
+        if words > 0 {
+            let first = goal / 64 % words;
+            let free_word = (first..words)
+                .chain(0..first)
+                .find(|&index| word(index) == 0);
+            if let Some(index) = free_word {
+                return Some(index * 64);
+            }
+        }
+        (0..limit.div_ceil(64))
+            .find_map(|index| {
+                let bits64 = word(index);
+                (bits64 != u64::MAX).then(|| index * 64 + bits64.trailing_ones() as usize)
+            })
+            .filter(|&bit| bit < limit)
+    }
+
     pub fn dealloc(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) {
         let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
         get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
@@ -63,6 +119,41 @@ impl Bitmap {
             });
     }
 
+    pub fn is_allocated(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> bool {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .read(0, |bitmap_block: &BitmapBlock| {
+                bitmap_block[bits64_pos] & (1u64 << inner_pos) > 0
+            })
+    }
+
+    /// Mark a given bit as allocated, used when repairing the bitmap.
+    pub fn set(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .modify(0, |bitmap_block: &mut BitmapBlock| {
+                bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+            });
+    }
+
+    /// Count bits in use.
+    pub fn allocated(&self, block_device: &Arc<dyn BlockDevice>) -> usize {
+        (0..self.blocks)
+            .map(|block_id| {
+                get_block_cache(block_id + self.start_block_id, Arc::clone(block_device))
+                    .lock()
+                    .read(0, |bitmap_block: &BitmapBlock| {
+                        bitmap_block
+                            .iter()
+                            .map(|bits64| bits64.count_ones() as usize)
+                            .sum::<usize>()
+                    })
+            })
+            .sum()
+    }
+
     pub fn maximum(&self) -> usize {
         self.blocks * BLOCK_BITS
     }
@@ -1,29 +1,95 @@
+use super::checksum::{checksum_matches, seal_block};
+use super::compress::cluster_cache_clear;
 use super::{BlockDevice, BLOCK_SZ};
-use alloc::collections::VecDeque;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+use hashbrown::HashMap;
 use lazy_static::*;
-use spin::Mutex;
+use spin::{Mutex, MutexGuard};
+
+/// A block that cannot be used, it is reported instead.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IoError {
+    pub block_id: usize,
+    pub kind: IoErrorKind,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IoErrorKind {
+    /// A checksummed block whose content does not match its checksum.
+    Checksum,
+    /// A transaction modified more blocks than the journal starting at
+    /// `block_id` holds, it has been rolled back.
+    JournalFull,
+}
+
+impl IoError {
+    pub fn checksum(block_id: usize) -> Self {
+        Self {
+            block_id,
+            kind: IoErrorKind::Checksum,
+        }
+    }
+}
+
+impl Display for IoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.kind {
+            IoErrorKind::Checksum => {
+                write!(f, "block {} does not match its checksum", self.block_id)
+            }
+            IoErrorKind::JournalFull => {
+                write!(f, "transaction overflows the journal at block {}", self.block_id)
+            }
+        }
+    }
+}
 
 pub struct BlockCache {
     cache: Vec<u8>,
     block_id: usize,
     block_device: Arc<dyn BlockDevice>,
     modified: bool,
+    /// Modified inside a transaction, must stay in memory until committed.
+    pinned: bool,
+    /// The tail of the block held its checksum when last looked at, only
+    /// meaningful for checksummed blocks.
+    checksum_ok: bool,
+    /// Bumped by every modification, tells whether a copy of the block is
+    /// still what the cache holds.
+    version: usize,
 }
 
 impl BlockCache {
-    /// Load a new BlockCache from disk.
+    /// Load a new BlockCache from disk, the checksum is verified right away
+    /// in case the block is a checksummed one.
     pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Self {
         // for alignment and move effciency
         let mut cache = vec![0u8; BLOCK_SZ];
         block_device.read_block(block_id, &mut cache);
+        let checksum_ok = checksum_matches(&cache);
         Self {
             cache,
             block_id,
             block_device,
             modified: false,
+            pinned: false,
+            checksum_ok,
+            version: 0,
+        }
+    }
+
+    /// Fail if this checksummed block does not match its checksum.
+    pub fn verify(&mut self) -> Result<(), IoError> {
+        // a plain modification may have brought a whole sealed block in
+        if !self.checksum_ok {
+            self.checksum_ok = checksum_matches(&self.cache);
+        }
+        match self.checksum_ok {
+            true => Ok(()),
+            false => Err(IoError::checksum(self.block_id)),
         }
     }
 
@@ -48,6 +114,11 @@ impl BlockCache {
         let type_size = core::mem::size_of::<T>();
         assert!(offset + type_size <= BLOCK_SZ);
         self.modified = true;
+        self.checksum_ok = false;
+        self.version = self.version.wrapping_add(1);
+        if !self.pinned && record_in_transaction(device_id(&self.block_device), self.block_id) {
+            self.pinned = true;
+        }
         let addr = self.addr_of_offset(offset);
         unsafe { &mut *(addr as *mut T) }
     }
@@ -60,8 +131,47 @@ impl BlockCache {
         f(self.get_mut(offset))
     }
 
+    /// Like `read`, for a checksummed block.
+    pub fn read_checked<T, V>(
+        &mut self,
+        offset: usize,
+        f: impl FnOnce(&T) -> V,
+    ) -> Result<V, IoError> {
+        self.verify()?;
+        Ok(self.read(offset, f))
+    }
+
+    /// Like `modify`, then update the checksum of the block.
+    pub fn modify_checked<T, V>(&mut self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
+        let value = self.modify(offset, f);
+        self.seal();
+        value
+    }
+
+    /// Write the checksum of the current content into the tail.
+    pub fn seal(&mut self) {
+        self.modify(0, |block: &mut [u8; BLOCK_SZ]| seal_block(block));
+        self.checksum_ok = true;
+    }
+
+    /// Release a block after its transaction has been committed to the journal.
+    pub fn unpin(&mut self) {
+        self.pinned = false;
+    }
+
+    /// Undo the modifications of a transaction that is not committed, they
+    /// never left memory so the disk holds the block as it was before.
+    pub fn discard(&mut self) {
+        self.block_device.read_block(self.block_id, &mut self.cache);
+        self.modified = false;
+        self.pinned = false;
+        self.checksum_ok = checksum_matches(&self.cache);
+        self.version = self.version.wrapping_add(1);
+    }
+
     pub fn sync(&mut self) {
-        if self.modified {
+        // pinned blocks reach their home location only after commit
+        if self.modified && !self.pinned {
             self.modified = false;
             self.block_device.write_block(self.block_id, &self.cache);
         }
@@ -74,17 +184,86 @@ impl Drop for BlockCache {
     }
 }
 
-const BLOCK_CACHE_SIZE: usize = 16;
+/// Capacity used until `set_block_cache_capacity` is called.
+const DEFAULT_BLOCK_CACHE_SIZE: usize = 64;
+
+struct CacheSlot {
+    /// (`device_id` of the device, block id)
+    key: (usize, usize),
+    cache: Arc<Mutex<BlockCache>>,
+    /// Second chance bit of the CLOCK replacement.
+    referenced: bool,
+}
+
+impl CacheSlot {
+    fn evictable(&self) -> bool {
+        Arc::strong_count(&self.cache) == 1 && !self.cache.lock().pinned
+    }
+}
 
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    capacity: usize,
+    slots: Vec<CacheSlot>,
+    /// (device id, block id) -> position in `slots`
+    index: HashMap<(usize, usize), usize>,
+    /// CLOCK hand
+    hand: usize,
 }
 
 impl BlockCacheManager {
     pub fn new() -> Self {
         Self {
-            queue: VecDeque::new(),
+            capacity: DEFAULT_BLOCK_CACHE_SIZE,
+            slots: Vec::new(),
+            index: HashMap::new(),
+            hand: 0,
+        }
+    }
+
+    /// Find a slot to reuse, blocks in use or pinned by a transaction are
+    /// skipped, recently used ones get a second chance.
+    fn victim(&mut self) -> usize {
+        for _ in 0..2 * self.slots.len() {
+            let pos = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+            let slot = &mut self.slots[pos];
+            if !slot.evictable() {
+                continue;
+            }
+            if slot.referenced {
+                slot.referenced = false;
+            } else {
+                return pos;
+            }
         }
+        panic!("Run out of BlockCache!");
+    }
+
+    fn insert(
+        &mut self,
+        block_id: usize,
+        block_device: Arc<dyn BlockDevice>,
+        referenced: bool,
+    ) -> Arc<Mutex<BlockCache>> {
+        // load block into mem
+        let key = (device_id(&block_device), block_id);
+        let slot = CacheSlot {
+            key,
+            cache: Arc::new(Mutex::new(BlockCache::new(block_id, block_device))),
+            referenced,
+        };
+        let block_cache = Arc::clone(&slot.cache);
+        if self.slots.len() < self.capacity {
+            self.index.insert(key, self.slots.len());
+            self.slots.push(slot);
+        } else {
+            // substitute, the old block is written back when dropped
+            let pos = self.victim();
+            let old = core::mem::replace(&mut self.slots[pos], slot);
+            self.index.remove(&old.key);
+            self.index.insert(key, pos);
+        }
+        block_cache
     }
 
     pub fn get_block_cache(
@@ -92,30 +271,37 @@ impl BlockCacheManager {
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
     ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Arc::clone(&pair.1)
+        if let Some(&pos) = self.index.get(&(device_id(&block_device), block_id)) {
+            let slot = &mut self.slots[pos];
+            slot.referenced = true;
+            Arc::clone(&slot.cache)
         } else {
-            // substitute
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of BlockCache!");
-                }
+            self.insert(block_id, block_device, true)
+        }
+    }
+
+    /// Load a block we are likely to need soon, it is the first to go if
+    /// nobody reads it before the hand comes by.
+    pub fn prefetch(&mut self, block_id: usize, block_device: Arc<dyn BlockDevice>) {
+        if !self
+            .index
+            .contains_key(&(device_id(&block_device), block_id))
+        {
+            self.insert(block_id, block_device, false);
+        }
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        assert!(capacity > 0);
+        self.capacity = capacity;
+        while self.slots.len() > capacity {
+            let pos = self.victim();
+            let old = self.slots.swap_remove(pos);
+            self.index.remove(&old.key);
+            if pos < self.slots.len() {
+                self.index.insert(self.slots[pos].key, pos);
             }
-            // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(BlockCache::new(
-                block_id,
-                Arc::clone(&block_device),
-            )));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
-            block_cache
+            self.hand %= self.slots.len().max(1);
         }
     }
 }
@@ -123,6 +309,79 @@ impl BlockCacheManager {
 lazy_static! {
     pub static ref BLOCK_CACHE_MANAGER: Mutex<BlockCacheManager> =
         Mutex::new(BlockCacheManager::new());
+    /// Transaction logs of the open file systems, looked up by the blocks
+    /// they modify.
+    static ref TRANSACTIONS: Mutex<Vec<Weak<Transaction>>> = Mutex::new(Vec::new());
+}
+
+/// Identity of a block device, the same for every clone of its `Arc`.
+pub fn device_id(block_device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(block_device) as *const () as usize
+}
+
+/// Blocks of one device modified while its file system has a transaction
+/// open, the journal of that file system owns it.
+///
+/// Operations on a file system are serialised by its lock, so a
+/// transaction begun while another one is open is part of it and
+/// everything is committed when the outermost one ends.
+pub struct Transaction {
+    device: usize,
+    state: Mutex<TransactionState>,
+}
+
+#[derive(Default)]
+struct TransactionState {
+    /// Open transactions, nested in each other.
+    depth: usize,
+    blocks: Vec<usize>,
+}
+
+impl Transaction {
+    pub fn new(block_device: &Arc<dyn BlockDevice>) -> Arc<Self> {
+        let transaction = Arc::new(Self {
+            device: device_id(block_device),
+            state: Mutex::new(TransactionState::default()),
+        });
+        let mut transactions = TRANSACTIONS.lock();
+        transactions.retain(|transaction| transaction.strong_count() > 0);
+        transactions.push(Arc::downgrade(&transaction));
+        transaction
+    }
+
+    /// Start recording every modified block, they will not be written back
+    /// until `end` hands them to the journal.
+    pub fn begin(&self) {
+        self.state.lock().depth += 1;
+    }
+
+    /// Close the innermost transaction, return the blocks modified once the
+    /// outermost one is closed.
+    pub fn end(&self) -> Option<Vec<usize>> {
+        let mut state = self.state.lock();
+        assert!(state.depth > 0, "No transaction to end!");
+        state.depth -= 1;
+        (state.depth == 0).then(|| core::mem::take(&mut state.blocks))
+    }
+
+    fn record(&self, block_id: usize) -> bool {
+        let mut state = self.state.lock();
+        if state.depth > 0 {
+            state.blocks.push(block_id);
+        }
+        state.depth > 0
+    }
+}
+
+/// Record `block_id` of device `device` in the open transaction of its file
+/// system, false if there is none.
+fn record_in_transaction(device: usize, block_id: usize) -> bool {
+    TRANSACTIONS
+        .lock()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .filter(|transaction| transaction.device == device)
+        .any(|transaction| transaction.record(block_id))
 }
 
 pub fn get_block_cache(
@@ -134,9 +393,160 @@ pub fn get_block_cache(
         .get_block_cache(block_id, block_device)
 }
 
+/// Read a block into the cache in advance.
+pub fn block_cache_prefetch(block_id: usize, block_device: Arc<dyn BlockDevice>) {
+    BLOCK_CACHE_MANAGER.lock().prefetch(block_id, block_device);
+}
+
+/// Change the number of cached blocks, e.g. according to the free memory.
+pub fn set_block_cache_capacity(capacity: usize) {
+    BLOCK_CACHE_MANAGER.lock().set_capacity(capacity);
+}
+
+/// Write the dirty blocks among `caches` back device by device in the
+/// order of their block ids; runs of adjacent blocks go down in one
+/// request, so the device can queue them together.
+fn write_back(mut caches: Vec<MutexGuard<'_, BlockCache>>) {
+    caches.retain(|cache| cache.modified && !cache.pinned);
+    caches.sort_unstable_by_key(|cache| (device_id(&cache.block_device), cache.block_id));
+    let mut start = 0;
+    while start < caches.len() {
+        let device = device_id(&caches[start].block_device);
+        let mut end = start + 1;
+        while end < caches.len()
+            && caches[end].block_id == caches[end - 1].block_id + 1
+            && device_id(&caches[end].block_device) == device
+        {
+            end += 1;
+        }
+        let run = &mut caches[start..end];
+        if run.len() == 1 {
+            run[0].sync();
+        } else {
+            let mut buf = Vec::with_capacity(run.len() * BLOCK_SZ);
+            for cache in run.iter() {
+                buf.extend_from_slice(&cache.cache);
+            }
+            run[0].block_device.write_blocks(run[0].block_id, &buf);
+            for cache in run.iter_mut() {
+                cache.modified = false;
+            }
+        }
+        start = end;
+    }
+}
+
+/// Write every dirty block back.
 pub fn block_cache_sync_all() {
     let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
-        cache.lock().sync();
+    write_back(manager.slots.iter().map(|slot| slot.cache.lock()).collect());
+}
+
+/// Write the dirty blocks of `block_device` back.
+pub fn block_cache_sync(block_device: &Arc<dyn BlockDevice>) {
+    let device = device_id(block_device);
+    let manager = BLOCK_CACHE_MANAGER.lock();
+    write_back(
+        manager
+            .slots
+            .iter()
+            .filter(|slot| slot.key.0 == device)
+            .map(|slot| slot.cache.lock())
+            .collect(),
+    );
+}
+
+/// Copy of a dirty block, written back by `block_cache_try_sync_all` once
+/// every lock is released.
+struct DirtyBlock {
+    cache: Weak<Mutex<BlockCache>>,
+    version: usize,
+    block_id: usize,
+    block_device: Arc<dyn BlockDevice>,
+    data: Vec<u8>,
+}
+
+impl DirtyBlock {
+    fn copy(slot: &CacheSlot) -> Option<Self> {
+        let cache = slot.cache.try_lock()?;
+        (cache.modified && !cache.pinned).then(|| Self {
+            cache: Arc::downgrade(&slot.cache),
+            version: cache.version,
+            block_id: cache.block_id,
+            block_device: Arc::clone(&cache.block_device),
+            data: cache.cache.clone(),
+        })
     }
+
+    /// Whether the cache still holds the copy and nobody is using the block.
+    fn current(&self) -> bool {
+        let cache = match self.cache.upgrade() {
+            Some(cache) => cache,
+            None => return false,
+        };
+        let cache = cache.try_lock();
+        cache.is_some_and(|cache| cache.version == self.version && !cache.pinned)
+    }
+
+    /// The copy reached the disk, the block is clean unless it changed since.
+    fn written(&self) {
+        if let Some(cache) = self.cache.upgrade() {
+            if let Some(mut cache) = cache.try_lock() {
+                if cache.version == self.version {
+                    cache.modified = false;
+                }
+            }
+        }
+    }
+}
+
+/// Like `block_cache_sync_all`, but skip blocks that are locked right now
+/// instead of waiting for them, for flushing from an interrupt.
+///
+/// The dirty blocks are copied and every lock is released before writing,
+/// as the device may sleep. A run of copies is only written if none of its
+/// blocks changed or got locked since, so it never lands after a newer
+/// write of the same block: nothing else runs between that check and the
+/// request reaching the device.
+///
+/// Return false if the cache itself was busy.
+pub fn block_cache_try_sync_all() -> bool {
+    let mut dirty: Vec<DirtyBlock> = match BLOCK_CACHE_MANAGER.try_lock() {
+        Some(manager) => manager.slots.iter().filter_map(DirtyBlock::copy).collect(),
+        None => return false,
+    };
+    dirty.sort_unstable_by_key(|block| (device_id(&block.block_device), block.block_id));
+    let mut start = 0;
+    while start < dirty.len() {
+        let device = device_id(&dirty[start].block_device);
+        let mut end = start + 1;
+        while end < dirty.len()
+            && dirty[end].block_id == dirty[end - 1].block_id + 1
+            && device_id(&dirty[end].block_device) == device
+        {
+            end += 1;
+        }
+        let run = &dirty[start..end];
+        if run.iter().all(DirtyBlock::current) {
+            let buf: Vec<u8> = run
+                .iter()
+                .flat_map(|block| block.data.iter().copied())
+                .collect();
+            run[0].block_device.write_blocks(run[0].block_id, &buf);
+            run.iter().for_each(DirtyBlock::written);
+        }
+        start = end;
+    }
+    true
+}
+
+/// Drop every cached block, dirty ones are written back first, and every
+/// decompressed cluster.
+pub fn block_cache_clear() {
+    cluster_cache_clear();
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    write_back(manager.slots.iter().map(|slot| slot.cache.lock()).collect());
+    manager.slots.clear();
+    manager.index.clear();
+    manager.hand = 0;
 }
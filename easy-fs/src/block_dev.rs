@@ -1,7 +1,21 @@
+use super::BLOCK_SZ;
 use core::any::Any;
 
 pub trait BlockDevice: Send + Sync + Any {
     fn read_block(&self, block_id: usize, buf: &mut [u8]);
     fn write_block(&self, block_id: usize, buf: &[u8]);
     fn handle_irq(&self);
+    /// Read the blocks from `block_id` on into `buf`, a whole number of
+    /// blocks. Devices that can queue several requests override it.
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
+        for (i, block) in buf.chunks_mut(BLOCK_SZ).enumerate() {
+            self.read_block(block_id + i, block);
+        }
+    }
+    /// Write `buf`, a whole number of blocks, from `block_id` on.
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) {
+        for (i, block) in buf.chunks(BLOCK_SZ).enumerate() {
+            self.write_block(block_id + i, block);
+        }
+    }
 }
@@ -0,0 +1,49 @@
+//! CRC32C of metadata blocks
+//!
+//! A checksummed block keeps the CRC32C of its first `BLOCK_SZ - 4` bytes
+//! in its last 4 bytes, little endian.
+use super::BLOCK_SZ;
+
+/// Bytes at the end of a checksummed block holding the checksum.
+pub const CHECKSUM_SZ: usize = 4;
+
+/// Castagnoli polynomial, bit reversed.
+const CRC32C_POLY: u32 = 0x82f6_3b78;
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32C_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        CRC32C_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Whether the tail of `block` matches the rest of it.
+pub fn checksum_matches(block: &[u8]) -> bool {
+    let (data, tail) = block.split_at(BLOCK_SZ - CHECKSUM_SZ);
+    crc32c(data).to_le_bytes() == tail
+}
+
+/// Write the checksum of `block` into its tail.
+pub fn seal_block(block: &mut [u8]) {
+    let (data, tail) = block.split_at_mut(BLOCK_SZ - CHECKSUM_SZ);
+    tail.copy_from_slice(&crc32c(data).to_le_bytes());
+}
@@ -0,0 +1,167 @@
+//! Files stored compressed, written by `Inode::write_compressed`
+//!
+//! The data of a compressed file is cut in clusters of `CLUSTER_BLOCKS`
+//! blocks, the last one may be shorter. A cluster is kept in one of three
+//! ways, told apart by what is mapped:
+//! - the last block of the cluster is mapped: the data is stored as is,
+//! - only the first blocks are mapped: they hold a header, the length of
+//!   the compressed data then its CRC32C, followed by the data in the LZ4
+//!   block format,
+//! - nothing is mapped: the cluster is all zeros.
+//!
+//! A read decompresses the clusters it touches, the last ones decompressed
+//! are kept so that small reads in a row do not decompress a cluster again.
+use super::block_cache::device_id;
+use super::checksum::crc32c;
+use super::lz4;
+use super::{BlockDevice, DiskInode, IoError, BLOCK_SZ};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+pub const CLUSTER_BLOCKS: u32 = 8;
+pub const CLUSTER_SZ: usize = CLUSTER_BLOCKS as usize * BLOCK_SZ;
+/// Length of the compressed data, then its CRC32C.
+const HEADER_SZ: usize = 8;
+/// Decompressed clusters kept in memory.
+const CLUSTER_CACHE_SIZE: usize = 16;
+
+/// (device id, first block of the cluster on disk)
+type ClusterKey = (usize, usize);
+
+lazy_static! {
+    /// Decompressed clusters by their key, the most recently used last.
+    static ref CLUSTER_CACHE: Mutex<Vec<(ClusterKey, Arc<Vec<u8>>)>> = Mutex::new(Vec::new());
+}
+
+/// Drop every decompressed cluster.
+pub fn cluster_cache_clear() {
+    CLUSTER_CACHE.lock().clear();
+}
+
+/// Drop the cluster stored from `block_id` on, the block is about to hold
+/// something else.
+pub fn forget_cluster(block_device: &Arc<dyn BlockDevice>, block_id: usize) {
+    let key = (device_id(block_device), block_id);
+    CLUSTER_CACHE.lock().retain(|(first, _)| *first != key);
+}
+
+fn cached_cluster(key: ClusterKey) -> Option<Arc<Vec<u8>>> {
+    let mut cache = CLUSTER_CACHE.lock();
+    let pos = cache.iter().position(|(first, _)| *first == key)?;
+    let entry = cache.remove(pos);
+    let data = Arc::clone(&entry.1);
+    cache.push(entry);
+    Some(data)
+}
+
+fn cache_cluster(key: ClusterKey, data: Arc<Vec<u8>>) {
+    let mut cache = CLUSTER_CACHE.lock();
+    if cache.len() == CLUSTER_CACHE_SIZE {
+        cache.remove(0);
+    }
+    cache.push((key, data));
+}
+
+/// What to store for the cluster `data`, None if it takes fewer blocks
+/// as is than compressed.
+pub fn compress_cluster(data: &[u8]) -> Option<Vec<u8>> {
+    let compressed = lz4::compress(data);
+    let mut stored = Vec::with_capacity(HEADER_SZ + compressed.len());
+    stored.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+    stored.extend_from_slice(&crc32c(&compressed).to_le_bytes());
+    stored.extend_from_slice(&compressed);
+    (stored.len().div_ceil(BLOCK_SZ) < data.len().div_ceil(BLOCK_SZ)).then_some(stored)
+}
+
+impl DiskInode {
+    /// Data blocks of cluster `cluster`.
+    fn cluster_blocks(&self, cluster: u32) -> u32 {
+        (self.data_blocks() - cluster * CLUSTER_BLOCKS).min(CLUSTER_BLOCKS)
+    }
+    /// Bytes of cluster `cluster`.
+    fn cluster_len(&self, cluster: u32) -> usize {
+        (self.size as usize - cluster as usize * CLUSTER_SZ).min(CLUSTER_SZ)
+    }
+    /// First block of cluster `cluster` if it is stored compressed.
+    fn compressed_block(&self, cluster: u32, block_device: &Arc<dyn BlockDevice>) -> Option<u32> {
+        let first = cluster * CLUSTER_BLOCKS;
+        let last = first + self.cluster_blocks(cluster) - 1;
+        if self.get_block_id(last, block_device) != 0 {
+            return None;
+        }
+        match self.get_block_id(first, block_device) {
+            0 => None,
+            block_id => Some(block_id),
+        }
+    }
+    /// Decompressed data of cluster `cluster`, None unless it is stored
+    /// compressed. Fail if the stored data does not match its CRC32C or is
+    /// not valid LZ4, the error names the first block of the cluster.
+    pub fn compressed_cluster(
+        &self,
+        cluster: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Option<Arc<Vec<u8>>>, IoError> {
+        let block_id = match self.compressed_block(cluster, block_device) {
+            Some(block_id) => block_id as usize,
+            None => return Ok(None),
+        };
+        let key = (device_id(block_device), block_id);
+        if let Some(data) = cached_cluster(key) {
+            return Ok(Some(data));
+        }
+        let error = IoError::checksum(block_id);
+        let start = cluster as usize * CLUSTER_SZ;
+        let mut header = [0u8; HEADER_SZ];
+        self.read_at(start, &mut header, block_device);
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        // the blocks after the compressed data are holes
+        if HEADER_SZ + len > (self.cluster_blocks(cluster) as usize - 1) * BLOCK_SZ {
+            return Err(error);
+        }
+        let mut compressed = vec![0u8; len];
+        self.read_at(start + HEADER_SZ, &mut compressed, block_device);
+        if crc32c(&compressed) != crc {
+            return Err(error);
+        }
+        let mut data = vec![0u8; self.cluster_len(cluster)];
+        if lz4::decompress(&compressed, &mut data) != Some(data.len()) {
+            return Err(error);
+        }
+        let data = Arc::new(data);
+        cache_cluster(key, Arc::clone(&data));
+        Ok(Some(data))
+    }
+    /// Like `read_at` for a compressed file, only the clusters in the range
+    /// are decompressed.
+    pub fn read_compressed(
+        &self,
+        offset: usize,
+        buf: &mut [u8],
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<usize, IoError> {
+        let end = (offset + buf.len()).min(self.size as usize);
+        let mut start = offset;
+        while start < end {
+            let cluster = (start / CLUSTER_SZ) as u32;
+            let cluster_start = cluster as usize * CLUSTER_SZ;
+            let cluster_end = end.min(cluster_start + CLUSTER_SZ);
+            let dst = &mut buf[start - offset..cluster_end - offset];
+            match self.compressed_cluster(cluster, block_device)? {
+                Some(data) => {
+                    dst.copy_from_slice(&data[start - cluster_start..cluster_end - cluster_start])
+                }
+                // raw data or a hole
+                None => {
+                    self.read_at(start, dst, block_device);
+                }
+            }
+            start = cluster_end;
+        }
+        Ok(end.saturating_sub(offset))
+    }
+}
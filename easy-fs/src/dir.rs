@@ -0,0 +1,714 @@
+//! Directory contents in both on-disk formats
+//!
+//! A `Fixed` directory is an array of `DirEntry`. An `Indexed` directory
+//! starts with a root index block mapping ranges of name hashes to leaf
+//! blocks, with at most one more level of index blocks below it. Leaves
+//! hold variable length records that never cross a block boundary, so a
+//! lookup reads at most three blocks whatever the size of the directory.
+use super::checksum::seal_block;
+use super::{
+    get_block_cache, BlockDevice, DirEntry, DirFormat, DiskInode, IoError, BLOCK_SZ, DIRENT_SZ,
+};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// First word of an index block, leaves never start with it.
+const DIR_INDEX_MAGIC: u32 = 0x4449_5831;
+/// Magic u32, levels u16, count u16.
+const DIR_INDEX_HEADER_SZ: usize = 8;
+/// Height of the root index block above the leaves at most.
+const DIR_INDEX_MAX_LEVELS: u16 = 2;
+/// Inode u32, record length u16, name length u8, unused u8.
+const DIR_RECORD_HEADER_SZ: usize = 8;
+/// Inode number of a record only holding free space, 0 is the root.
+const DIR_RECORD_FREE: u32 = u32::MAX;
+
+type DataBlock = [u8; BLOCK_SZ];
+
+fn get_u16(block: &DataBlock, pos: usize) -> u16 {
+    u16::from_le_bytes([block[pos], block[pos + 1]])
+}
+
+fn get_u32(block: &DataBlock, pos: usize) -> u32 {
+    u32::from_le_bytes([block[pos], block[pos + 1], block[pos + 2], block[pos + 3]])
+}
+
+fn put_u16(block: &mut DataBlock, pos: usize, value: u16) {
+    block[pos..pos + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(block: &mut DataBlock, pos: usize, value: u32) {
+    block[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// (first hash, block) pairs held by an index block of `space` bytes.
+fn index_capacity(space: usize) -> usize {
+    (space - DIR_INDEX_HEADER_SZ) / 8
+}
+
+/// FNV-1a, it only has to spread names over the leaves.
+fn name_hash(name: &str) -> u32 {
+    name.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Index block contents, `levels` is 1 when `entries` point to leaves.
+struct IndexNode {
+    levels: u16,
+    /// (first hash, block), the first hash of the root is 0.
+    entries: Vec<(u32, u32)>,
+}
+
+impl IndexNode {
+    fn parse(block: &DataBlock, space: usize) -> Option<Self> {
+        let levels = get_u16(block, 4);
+        let count = get_u16(block, 6) as usize;
+        if get_u32(block, 0) != DIR_INDEX_MAGIC
+            || levels == 0
+            || levels > DIR_INDEX_MAX_LEVELS
+            || count == 0
+            || count > index_capacity(space)
+        {
+            return None;
+        }
+        let entries: Vec<(u32, u32)> = (0..count)
+            .map(|i| {
+                let pos = DIR_INDEX_HEADER_SZ + i * 8;
+                (get_u32(block, pos), get_u32(block, pos + 4))
+            })
+            .collect();
+        let sorted = entries.windows(2).all(|pair| pair[0].0 < pair[1].0);
+        sorted.then_some(Self { levels, entries })
+    }
+
+    fn write(&self, block: &mut DataBlock) {
+        block.fill(0);
+        put_u32(block, 0, DIR_INDEX_MAGIC);
+        put_u16(block, 4, self.levels);
+        put_u16(block, 6, self.entries.len() as u16);
+        for (i, (hash, child)) in self.entries.iter().enumerate() {
+            put_u32(block, DIR_INDEX_HEADER_SZ + i * 8, *hash);
+            put_u32(block, DIR_INDEX_HEADER_SZ + i * 8 + 4, *child);
+        }
+    }
+
+    /// Entry whose hash range holds `hash`.
+    fn slot(&self, hash: u32) -> usize {
+        self.entries
+            .partition_point(|(first, _)| *first <= hash)
+            .saturating_sub(1)
+    }
+}
+
+fn record_len(name_len: usize) -> usize {
+    (DIR_RECORD_HEADER_SZ + name_len + 3) & !3
+}
+
+fn leaf_len(entries: &[(String, u32)]) -> usize {
+    entries.iter().map(|(name, _)| record_len(name.len())).sum()
+}
+
+/// Entries of a leaf, None if its records do not tile the first `space`
+/// bytes of the block or a name is not UTF-8.
+fn parse_leaf(block: &DataBlock, space: usize) -> Option<Vec<(String, u32)>> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos < space {
+        let inode = get_u32(block, pos);
+        let rec_len = get_u16(block, pos + 4) as usize;
+        let name_len = block[pos + 6] as usize;
+        if rec_len < DIR_RECORD_HEADER_SZ
+            || rec_len & 3 != 0
+            || pos + rec_len > space
+            || DIR_RECORD_HEADER_SZ + name_len > rec_len
+        {
+            return None;
+        }
+        if inode != DIR_RECORD_FREE {
+            let name = &block[pos + DIR_RECORD_HEADER_SZ..pos + DIR_RECORD_HEADER_SZ + name_len];
+            let name = core::str::from_utf8(name).ok().filter(|n| !n.is_empty())?;
+            entries.push((String::from(name), inode));
+        }
+        pos += rec_len;
+    }
+    Some(entries)
+}
+
+/// `entries` must fit in `space` bytes, the last record takes the free
+/// space left.
+fn write_leaf(block: &mut DataBlock, entries: &[(String, u32)], space: usize) {
+    block.fill(0);
+    if entries.is_empty() {
+        put_u32(block, 0, DIR_RECORD_FREE);
+        put_u16(block, 4, space as u16);
+        return;
+    }
+    let mut pos = 0;
+    for (i, (name, inode)) in entries.iter().enumerate() {
+        let rec_len = if i + 1 == entries.len() {
+            space - pos
+        } else {
+            record_len(name.len())
+        };
+        put_u32(block, pos, *inode);
+        put_u16(block, pos + 4, rec_len as u16);
+        block[pos + 6] = name.len() as u8;
+        block[pos + DIR_RECORD_HEADER_SZ..pos + DIR_RECORD_HEADER_SZ + name.len()]
+            .copy_from_slice(name.as_bytes());
+        pos += rec_len;
+    }
+}
+
+/// Where to split entries sorted by hash so that both halves fit in a
+/// leaf of `space` bytes, as evenly as possible. Equal hashes must stay in
+/// the same leaf.
+fn split_point(entries: &[(String, u32)], space: usize) -> Option<usize> {
+    (1..entries.len())
+        .filter(|at| name_hash(&entries[at - 1].0) != name_hash(&entries[*at].0))
+        .map(|at| (at, leaf_len(&entries[..at]), leaf_len(&entries[at..])))
+        .filter(|(_, lower, upper)| *lower <= space && *upper <= space)
+        .min_by_key(|(_, lower, upper)| lower.abs_diff(*upper))
+        .map(|(at, _, _)| at)
+}
+
+/// Blocks of an indexed directory holding `entries`, leaves packed as full
+/// as they go, sealed if `format` has checksums. Used by fsck to rebuild a
+/// directory.
+pub fn build_indexed_dir(mut entries: Vec<(String, u32)>, format: DirFormat) -> Vec<DataBlock> {
+    if entries.is_empty() {
+        return Vec::new();
+    }
+    let space = format.block_space();
+    let capacity = index_capacity(space);
+    entries.sort_by_key(|(name, _)| name_hash(name));
+    let mut leaves: Vec<Vec<(String, u32)>> = vec![Vec::new()];
+    for entry in entries {
+        let leaf = leaves.last().unwrap();
+        let same_hash = leaf
+            .last()
+            .is_some_and(|(name, _)| name_hash(name) == name_hash(&entry.0));
+        if !same_hash && leaf_len(leaf) + record_len(entry.0.len()) > space {
+            leaves.push(Vec::new());
+        }
+        leaves.last_mut().unwrap().push(entry);
+    }
+    let first_hash = |i: usize| match i {
+        0 => 0,
+        _ => name_hash(&leaves[i][0].0),
+    };
+    // root, then the index blocks of the second level if needed, then leaves
+    let mut index = Vec::new();
+    if leaves.len() <= capacity {
+        let entries = (0..leaves.len())
+            .map(|i| (first_hash(i), 1 + i as u32))
+            .collect();
+        index.push(IndexNode { levels: 1, entries });
+    } else {
+        let chunks = leaves.len().div_ceil(capacity).min(capacity);
+        let first_leaf = 1 + chunks;
+        let entries = (0..chunks)
+            .map(|c| (first_hash(c * capacity), 1 + c as u32))
+            .collect();
+        index.push(IndexNode { levels: 2, entries });
+        for c in 0..chunks {
+            let end = ((c + 1) * capacity).min(leaves.len());
+            let entries = (c * capacity..end)
+                .map(|i| (first_hash(i), (first_leaf + i) as u32))
+                .collect();
+            index.push(IndexNode { levels: 1, entries });
+        }
+        leaves.truncate(chunks * capacity);
+    }
+    let mut blocks = Vec::new();
+    for node in index.iter() {
+        let mut block = [0u8; BLOCK_SZ];
+        node.write(&mut block);
+        blocks.push(block);
+    }
+    for leaf in leaves.iter() {
+        let mut block = [0u8; BLOCK_SZ];
+        write_leaf(&mut block, leaf, space);
+        blocks.push(block);
+    }
+    if format == DirFormat::Checksummed {
+        blocks.iter_mut().for_each(|block| seal_block(block));
+    }
+    blocks
+}
+
+impl DiskInode {
+    /// Read block `index` without looking at its checksum.
+    fn raw_dir_block<V>(
+        &self,
+        index: u32,
+        block_device: &Arc<dyn BlockDevice>,
+        f: impl FnOnce(&DataBlock) -> V,
+    ) -> V {
+        get_block_cache(
+            self.get_block_id(index, block_device) as usize,
+            Arc::clone(block_device),
+        )
+        .lock()
+        .read(0, f)
+    }
+
+    fn dir_block<V>(
+        &self,
+        index: u32,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+        f: impl FnOnce(&DataBlock) -> V,
+    ) -> Result<V, IoError> {
+        let block_cache = get_block_cache(
+            self.get_block_id(index, block_device) as usize,
+            Arc::clone(block_device),
+        );
+        let mut block_cache = block_cache.lock();
+        match format {
+            DirFormat::Checksummed => block_cache.read_checked(0, f),
+            _ => Ok(block_cache.read(0, f)),
+        }
+    }
+
+    fn modify_dir_block<V>(
+        &self,
+        index: u32,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+        f: impl FnOnce(&mut DataBlock) -> V,
+    ) -> V {
+        let block_cache = get_block_cache(
+            self.get_block_id(index, block_device) as usize,
+            Arc::clone(block_device),
+        );
+        let mut block_cache = block_cache.lock();
+        match format {
+            DirFormat::Checksummed => block_cache.modify_checked(0, f),
+            _ => block_cache.modify(0, f),
+        }
+    }
+
+    /// Index block `index` among the first `blocks` blocks.
+    fn index_node(
+        &self,
+        index: u32,
+        blocks: u32,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Option<IndexNode>, IoError> {
+        if index >= blocks {
+            return Ok(None);
+        }
+        self.dir_block(index, format, block_device, |block| {
+            IndexNode::parse(block, format.block_space())
+        })
+    }
+
+    /// Leaf block `index` among the first `blocks` blocks.
+    fn leaf(
+        &self,
+        index: u32,
+        blocks: u32,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Option<Vec<(String, u32)>>, IoError> {
+        if index >= blocks {
+            return Ok(None);
+        }
+        self.dir_block(index, format, block_device, |block| {
+            parse_leaf(block, format.block_space())
+        })
+    }
+
+    /// (index block, slot) from the root down, and the leaf covering `hash`.
+    #[allow(clippy::type_complexity)]
+    fn index_path(
+        &self,
+        hash: u32,
+        blocks: u32,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Option<(Vec<(u32, usize)>, u32)>, IoError> {
+        let mut path = Vec::new();
+        let mut block = 0;
+        let mut levels = DIR_INDEX_MAX_LEVELS + 1;
+        loop {
+            let node = match self.index_node(block, blocks, format, block_device)? {
+                Some(node) => node,
+                None => return Ok(None),
+            };
+            // one level less at every step, so the walk ends
+            if node.levels >= levels {
+                return Ok(None);
+            }
+            levels = node.levels;
+            let slot = node.slot(hash);
+            path.push((block, slot));
+            block = node.entries[slot].1;
+            if levels == 1 {
+                return Ok(Some((path, block)));
+            }
+        }
+    }
+
+    /// Leaves below index block `block` in hash order, index blocks that
+    /// cannot be parsed are skipped.
+    fn collect_leaves(
+        &self,
+        block: u32,
+        max_levels: u16,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+        leaves: &mut Vec<u32>,
+    ) -> Result<(), IoError> {
+        let node = match self.index_node(block, self.data_blocks(), format, block_device)? {
+            Some(node) if node.levels < max_levels => node,
+            _ => return Ok(()),
+        };
+        for (_, child) in node.entries {
+            if node.levels == 1 {
+                leaves.push(child);
+            } else {
+                self.collect_leaves(child, node.levels, format, block_device, leaves)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn append_dir_block(&mut self, grow: &mut dyn FnMut(&mut DiskInode, u32)) -> u32 {
+        let block = self.data_blocks();
+        grow(self, (block + 1) * BLOCK_SZ as u32);
+        block
+    }
+
+    /// Inode number of the entry `name`.
+    pub fn find_entry(
+        &self,
+        name: &str,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Option<u32>, IoError> {
+        assert!(self.is_dir());
+        if format == DirFormat::Fixed {
+            let file_count = (self.size as usize) / DIRENT_SZ;
+            let mut dirent = DirEntry::empty();
+            for i in 0..file_count {
+                assert_eq!(
+                    self.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), block_device),
+                    DIRENT_SZ,
+                );
+                if dirent.name() == name {
+                    return Ok(Some(dirent.inode_number()));
+                }
+            }
+            return Ok(None);
+        }
+        let blocks = self.data_blocks();
+        let leaf = match self.index_path(name_hash(name), blocks, format, block_device)? {
+            Some((_, leaf)) => leaf,
+            None => return Ok(None),
+        };
+        Ok(self
+            .leaf(leaf, blocks, format, block_device)?
+            .and_then(|entries| {
+                entries
+                    .into_iter()
+                    .find(|(entry_name, _)| entry_name == name)
+            })
+            .map(|(_, inode)| inode))
+    }
+
+    /// (name, inode number) of every entry, in hash order for an indexed
+    /// directory.
+    pub fn entries(
+        &self,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Vec<(String, u32)>, IoError> {
+        assert!(self.is_dir());
+        if format == DirFormat::Fixed {
+            let file_count = (self.size as usize) / DIRENT_SZ;
+            let mut dirent = DirEntry::empty();
+            return Ok((0..file_count)
+                .map(|i| {
+                    assert_eq!(
+                        self.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), block_device),
+                        DIRENT_SZ,
+                    );
+                    (String::from(dirent.name()), dirent.inode_number())
+                })
+                .collect());
+        }
+        let mut leaves = Vec::new();
+        self.collect_leaves(
+            0,
+            DIR_INDEX_MAX_LEVELS + 1,
+            format,
+            block_device,
+            &mut leaves,
+        )?;
+        let mut entries = Vec::new();
+        for leaf in leaves {
+            if let Some(leaf) = self.leaf(leaf, self.data_blocks(), format, block_device)? {
+                entries.extend(leaf);
+            }
+        }
+        Ok(entries)
+    }
+
+    pub fn is_empty_dir(
+        &self,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<bool, IoError> {
+        // indexed directories keep their blocks when entries go away
+        Ok(self.size == 0 || self.entries(format, block_device)?.is_empty())
+    }
+
+    /// Add the entry `name` for `inode`, `grow` extends the directory to
+    /// the size it is given. Return false if the name is too long or the
+    /// directory cannot take it.
+    pub fn insert_entry(
+        &mut self,
+        name: &str,
+        inode: u32,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+        mut grow: impl FnMut(&mut DiskInode, u32),
+    ) -> Result<bool, IoError> {
+        assert!(self.is_dir());
+        if name.is_empty() || name.len() > format.name_length_limit() {
+            return Ok(false);
+        }
+        if format == DirFormat::Fixed {
+            let file_count = (self.size as usize) / DIRENT_SZ;
+            grow(self, ((file_count + 1) * DIRENT_SZ) as u32);
+            let dirent = DirEntry::new(name, inode);
+            self.write_at(file_count * DIRENT_SZ, dirent.as_bytes(), block_device);
+            return Ok(true);
+        }
+        self.insert_indexed(name, inode, format, block_device, &mut grow)
+    }
+
+    fn insert_indexed(
+        &mut self,
+        name: &str,
+        inode: u32,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+        grow: &mut dyn FnMut(&mut DiskInode, u32),
+    ) -> Result<bool, IoError> {
+        let space = format.block_space();
+        if self.size == 0 {
+            let root = self.append_dir_block(grow);
+            let leaf = self.append_dir_block(grow);
+            let node = IndexNode {
+                levels: 1,
+                entries: vec![(0, leaf)],
+            };
+            self.modify_dir_block(root, format, block_device, |block| node.write(block));
+            self.modify_dir_block(leaf, format, block_device, |block| {
+                write_leaf(block, &[], space)
+            });
+        }
+        let blocks = self.data_blocks();
+        let hash = name_hash(name);
+        let (path, leaf) = match self.index_path(hash, blocks, format, block_device)? {
+            Some(found) => found,
+            None => return Ok(false),
+        };
+        let mut entries = match self.leaf(leaf, blocks, format, block_device)? {
+            Some(entries) => entries,
+            None => return Ok(false),
+        };
+        entries.push((String::from(name), inode));
+        if leaf_len(&entries) <= space {
+            self.modify_dir_block(leaf, format, block_device, |block| {
+                write_leaf(block, &entries, space)
+            });
+            return Ok(true);
+        }
+        // the upper hashes of a full leaf move to a new leaf
+        entries.sort_by_key(|(name, _)| name_hash(name));
+        let at = match split_point(&entries, space) {
+            Some(at) => at,
+            None => return Ok(false),
+        };
+        let (parent_block, slot) = *path.last().unwrap();
+        let mut parent = self
+            .index_node(parent_block, blocks, format, block_device)?
+            .unwrap();
+        let mut root = self.index_node(0, blocks, format, block_device)?.unwrap();
+        // nothing is written unless the index can take one more leaf
+        let capacity = index_capacity(space);
+        let parent_full = parent.entries.len() == capacity;
+        let root_full = match path.len() {
+            1 => root.levels == DIR_INDEX_MAX_LEVELS,
+            _ => root.entries.len() == capacity,
+        };
+        if parent_full && root_full {
+            return Ok(false);
+        }
+        let new_leaf = self.append_dir_block(grow);
+        self.modify_dir_block(leaf, format, block_device, |block| {
+            write_leaf(block, &entries[..at], space)
+        });
+        self.modify_dir_block(new_leaf, format, block_device, |block| {
+            write_leaf(block, &entries[at..], space)
+        });
+        parent
+            .entries
+            .insert(slot + 1, (name_hash(&entries[at].0), new_leaf));
+        if !parent_full {
+            self.modify_dir_block(parent_block, format, block_device, |block| {
+                parent.write(block)
+            });
+            return Ok(true);
+        }
+        // the upper half of a full index block moves to a new one
+        let upper = IndexNode {
+            levels: parent.levels,
+            entries: parent.entries.split_off(parent.entries.len() / 2),
+        };
+        let upper_hash = upper.entries[0].0;
+        if path.len() == 1 {
+            // the root grows one level, both halves go below it
+            let lower_block = self.append_dir_block(grow);
+            let upper_block = self.append_dir_block(grow);
+            self.modify_dir_block(lower_block, format, block_device, |block| {
+                parent.write(block)
+            });
+            self.modify_dir_block(upper_block, format, block_device, |block| {
+                upper.write(block)
+            });
+            root = IndexNode {
+                levels: parent.levels + 1,
+                entries: vec![(0, lower_block), (upper_hash, upper_block)],
+            };
+        } else {
+            let upper_block = self.append_dir_block(grow);
+            self.modify_dir_block(parent_block, format, block_device, |block| {
+                parent.write(block)
+            });
+            self.modify_dir_block(upper_block, format, block_device, |block| {
+                upper.write(block)
+            });
+            root.entries
+                .insert(path[0].1 + 1, (upper_hash, upper_block));
+        }
+        self.modify_dir_block(0, format, block_device, |block| root.write(block));
+        Ok(true)
+    }
+
+    /// Drop the entry `name`, return its inode number and the blocks the
+    /// directory no longer needs.
+    #[allow(clippy::type_complexity)]
+    pub fn remove_entry(
+        &mut self,
+        name: &str,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Option<(u32, Vec<u32>)>, IoError> {
+        assert!(self.is_dir());
+        if format == DirFormat::Fixed {
+            let file_count = (self.size as usize) / DIRENT_SZ;
+            let mut dirent = DirEntry::empty();
+            let (index, inode) = match (0..file_count).find_map(|i| {
+                self.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), block_device);
+                (dirent.name() == name).then(|| (i, dirent.inode_number()))
+            }) {
+                Some(found) => found,
+                None => return Ok(None),
+            };
+            // move the last entry into the hole
+            self.read_at(
+                DIRENT_SZ * (file_count - 1),
+                dirent.as_bytes_mut(),
+                block_device,
+            );
+            self.write_at(DIRENT_SZ * index, dirent.as_bytes(), block_device);
+            let blocks = self.decrease_size(((file_count - 1) * DIRENT_SZ) as u32, block_device);
+            return Ok(Some((inode, blocks)));
+        }
+        let blocks = self.data_blocks();
+        let leaf = match self.index_path(name_hash(name), blocks, format, block_device)? {
+            Some((_, leaf)) => leaf,
+            None => return Ok(None),
+        };
+        let mut entries = match self.leaf(leaf, blocks, format, block_device)? {
+            Some(entries) => entries,
+            None => return Ok(None),
+        };
+        let pos = match entries
+            .iter()
+            .position(|(entry_name, _)| entry_name == name)
+        {
+            Some(pos) => pos,
+            None => return Ok(None),
+        };
+        let (_, inode) = entries.remove(pos);
+        let space = format.block_space();
+        self.modify_dir_block(leaf, format, block_device, |block| {
+            write_leaf(block, &entries, space)
+        });
+        // leaves are never merged, the directory keeps its blocks
+        Ok(Some((inode, Vec::new())))
+    }
+
+    /// Check the first `blocks` blocks of an indexed directory. Return the
+    /// entries of every leaf that parses, the blocks that do not, and the
+    /// blocks failing their checksum, which are parsed all the same. Block 0
+    /// is also reported when the index misses some entries.
+    #[allow(clippy::type_complexity)]
+    pub fn check_indexed_dir(
+        &self,
+        blocks: u32,
+        format: DirFormat,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> (Vec<(String, u32)>, Vec<u32>, Vec<u32>) {
+        let space = format.block_space();
+        let mut entries = Vec::new();
+        let mut bad_blocks = Vec::new();
+        let mut bad_checksums = Vec::new();
+        for block in 0..blocks {
+            if self.dir_block(block, format, block_device, |_| ()).is_err() {
+                bad_checksums.push(block);
+            }
+            let data = self.raw_dir_block(block, block_device, |data| *data);
+            if get_u32(&data, 0) == DIR_INDEX_MAGIC {
+                let valid = IndexNode::parse(&data, space).is_some_and(|node| {
+                    node.entries
+                        .iter()
+                        .all(|(_, child)| *child > 0 && *child < blocks)
+                });
+                if !valid {
+                    bad_blocks.push(block);
+                }
+            } else {
+                match parse_leaf(&data, space) {
+                    Some(leaf) => entries.extend(leaf),
+                    None => bad_blocks.push(block),
+                }
+            }
+        }
+        // a lookup through a block failing its checksum finds nothing
+        let indexed = |name: &str, inode: u32| {
+            let leaf = match self.index_path(name_hash(name), blocks, format, block_device) {
+                Ok(Some((_, leaf))) => leaf,
+                _ => return false,
+            };
+            match self.leaf(leaf, blocks, format, block_device) {
+                Ok(Some(leaf)) => leaf.iter().any(|entry| entry.0 == name && entry.1 == inode),
+                _ => false,
+            }
+        };
+        let missed = entries.iter().any(|(name, inode)| !indexed(name, *inode));
+        if missed && !bad_blocks.contains(&0) {
+            bad_blocks.insert(0, 0);
+        }
+        (entries, bad_blocks, bad_checksums)
+    }
+}
@@ -1,6 +1,7 @@
+use super::compress::forget_cluster;
 use super::{
-    block_cache_sync_all, get_block_cache, Bitmap, BlockDevice, DiskInode, DiskInodeType, Inode,
-    SuperBlock,
+    block_cache_sync, get_block_cache, Bitmap, BlockDevice, DirFormat, DiskInode, DiskInodeExtra,
+    DiskInodeType, Inode, InodeFormat, InodeOwner, IoError, Journal, SuperBlock,
 };
 use crate::BLOCK_SZ;
 use alloc::sync::Arc;
@@ -10,106 +11,242 @@ pub struct EasyFileSystem {
     pub block_device: Arc<dyn BlockDevice>,
     pub inode_bitmap: Bitmap,
     pub data_bitmap: Bitmap,
+    journal: Journal,
+    pub dir_format: DirFormat,
+    /// Format of the inodes created from now on.
+    pub inode_format: InodeFormat,
+    /// Bytes per inode, twice a `DiskInode` on images with owners.
+    inode_size: usize,
+    /// The SuperBlock, inode blocks and directory blocks are checksummed.
+    checksums: bool,
+    /// Inodes the inode area holds, the inode bitmap may have more bits.
+    inode_count: u32,
     inode_area_start_block: u32,
     data_area_start_block: u32,
+    data_area_blocks: u32,
 }
 
 type DataBlock = [u8; BLOCK_SZ];
 
+/// Blocks reserved right after the SuperBlock for the metadata journal.
+pub const JOURNAL_BLOCKS: u32 = 64;
+
 impl EasyFileSystem {
+    /// Format `block_device`, the inode area holds `BLOCK_SZ * 4` inodes
+    /// per block of inode bitmap.
     pub fn create(
         block_device: Arc<dyn BlockDevice>,
         total_blocks: u32,
         inode_bitmap_blocks: u32,
+        inode_format: InodeFormat,
     ) -> Arc<Mutex<Self>> {
         // calculate block size of areas & create bitmaps
-        let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
-        let inode_num = inode_bitmap.maximum();
-        let inode_area_blocks =
-            ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
+        let journal_blocks = JOURNAL_BLOCKS;
+        let inode_bitmap = Bitmap::new(1 + journal_blocks as usize, inode_bitmap_blocks as usize);
+        // owners double the size of an inode, the inode area keeps the size
+        // it had without them and holds half as many
+        let inode_size = 2 * core::mem::size_of::<DiskInode>();
+        let inode_area_blocks = (inode_bitmap.maximum() * core::mem::size_of::<DiskInode>())
+            .div_ceil(BLOCK_SZ) as u32;
+        let inode_count = inode_area_blocks * (BLOCK_SZ / inode_size) as u32;
         let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks;
-        let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
+        let data_total_blocks = total_blocks - 1 - journal_blocks - inode_total_blocks;
+        let data_bitmap_blocks = data_total_blocks.div_ceil(4097);
         let data_area_blocks = data_total_blocks - data_bitmap_blocks;
         let data_bitmap = Bitmap::new(
-            (1 + inode_bitmap_blocks + inode_area_blocks) as usize,
+            (1 + journal_blocks + inode_bitmap_blocks + inode_area_blocks) as usize,
             data_bitmap_blocks as usize,
         );
         let mut efs = Self {
             block_device: Arc::clone(&block_device),
             inode_bitmap,
             data_bitmap,
-            inode_area_start_block: 1 + inode_bitmap_blocks,
-            data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            journal: Journal::new(1, journal_blocks as usize, &block_device),
+            dir_format: DirFormat::Checksummed,
+            inode_format,
+            inode_size,
+            checksums: true,
+            inode_count,
+            inode_area_start_block: 1 + journal_blocks + inode_bitmap_blocks,
+            data_area_start_block: 1 + journal_blocks + inode_total_blocks + data_bitmap_blocks,
+            data_area_blocks,
         };
-        // clear all blocks
+        // clear all blocks, inode blocks are sealed empty
+        let inode_area = efs.inode_area_start_block..efs.inode_area_start_block + inode_area_blocks;
         for i in 0..total_blocks {
-            get_block_cache(i as usize, Arc::clone(&block_device))
-                .lock()
-                .modify(0, |data_block: &mut DataBlock| {
-                    for byte in data_block.iter_mut() {
-                        *byte = 0;
-                    }
-                });
+            let block_cache = get_block_cache(i as usize, Arc::clone(&block_device));
+            let mut block_cache = block_cache.lock();
+            block_cache.modify(0, |data_block: &mut DataBlock| {
+                for byte in data_block.iter_mut() {
+                    *byte = 0;
+                }
+            });
+            if inode_area.contains(&i) {
+                block_cache.seal();
+            }
         }
         // initialize SuperBlock
-        get_block_cache(0, Arc::clone(&block_device)).lock().modify(
-            0,
-            |super_block: &mut SuperBlock| {
+        get_block_cache(0, Arc::clone(&block_device))
+            .lock()
+            .modify_checked(0, |super_block: &mut SuperBlock| {
                 super_block.initialize(
                     total_blocks,
                     inode_bitmap_blocks,
                     inode_area_blocks,
                     data_bitmap_blocks,
                     data_area_blocks,
+                    journal_blocks,
                 );
-            },
-        );
+                super_block.set_inode_format(inode_format);
+            });
         // write back immediately
         // create a inode for root node "/"
         assert_eq!(efs.alloc_inode(), 0);
         let (root_inode_block_id, root_inode_offset) = efs.get_disk_inode_pos(0);
-        get_block_cache(root_inode_block_id as usize, Arc::clone(&block_device))
+        let root_inode_cache =
+            get_block_cache(root_inode_block_id as usize, Arc::clone(&block_device));
+        root_inode_cache
             .lock()
-            .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
-                disk_inode.initialize(DiskInodeType::Directory);
+            .modify_checked(root_inode_offset, |disk_inode: &mut DiskInode| {
+                disk_inode.initialize(DiskInodeType::Directory, inode_format);
             });
-        block_cache_sync_all();
+        root_inode_cache.lock().modify_checked(
+            root_inode_offset + core::mem::size_of::<DiskInode>(),
+            |extra: &mut DiskInodeExtra| {
+                extra.initialize(InodeOwner::root(&DiskInodeType::Directory));
+            },
+        );
+        block_cache_sync(&block_device);
         Arc::new(Mutex::new(efs))
     }
 
-    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
+    /// Open the image on `block_device`, fail if its SuperBlock does not
+    /// match its checksum.
+    pub fn open(block_device: Arc<dyn BlockDevice>) -> Result<Arc<Mutex<Self>>, IoError> {
+        let efs = Self::open_read_only(block_device)?;
+        // finish the last committed transaction before anyone reads metadata
+        efs.lock().replay_journal();
+        Ok(efs)
+    }
+
+    /// Like `open`, but the journal is not replayed, nothing is written to
+    /// the image. Metadata is stale while `journal_pending` holds.
+    pub fn open_read_only(block_device: Arc<dyn BlockDevice>) -> Result<Arc<Mutex<Self>>, IoError> {
+        let efs = Self::open_unchecked(block_device)?;
+        if efs.lock().checksums {
+            get_block_cache(0, Arc::clone(&efs.lock().block_device))
+                .lock()
+                .verify()?;
+        }
+        Ok(efs)
+    }
+
+    /// Like `open`, even with a SuperBlock not matching its checksum, for
+    /// `fsck` to report it. The journal is not replayed, nothing is written
+    /// to the image. Fail if block 0 does not hold an easy-fs SuperBlock.
+    pub fn open_unchecked(block_device: Arc<dyn BlockDevice>) -> Result<Arc<Mutex<Self>>, IoError> {
         // read SuperBlock
-        get_block_cache(0, Arc::clone(&block_device))
-            .lock()
-            .read(0, |super_block: &SuperBlock| {
-                assert!(super_block.is_valid(), "Error loading EFS!");
+        let efs = get_block_cache(0, Arc::clone(&block_device)).lock().read(
+            0,
+            |super_block: &SuperBlock| {
+                if !super_block.is_valid() {
+                    return Err(IoError::checksum(0));
+                }
+                // images without a journal have journal_blocks == 0
+                let journal_blocks = super_block.journal_blocks;
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
-                let efs = Self {
+                let journal = Journal::new(1, journal_blocks as usize, &block_device);
+                let inode_bitmap = Bitmap::new(
+                    (1 + journal_blocks) as usize,
+                    super_block.inode_bitmap_blocks as usize,
+                );
+                let inode_count = (super_block.inode_area_blocks
+                    * (BLOCK_SZ / super_block.inode_size()) as u32)
+                    .min(inode_bitmap.maximum() as u32);
+                Ok(Self {
                     block_device,
-                    inode_bitmap: Bitmap::new(1, super_block.inode_bitmap_blocks as usize),
+                    inode_bitmap,
                     data_bitmap: Bitmap::new(
-                        (1 + inode_total_blocks) as usize,
+                        (1 + journal_blocks + inode_total_blocks) as usize,
                         super_block.data_bitmap_blocks as usize,
                     ),
-                    inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
-                    data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
-                };
-                Arc::new(Mutex::new(efs))
-            })
+                    journal,
+                    dir_format: super_block.dir_format(),
+                    inode_format: super_block.inode_format(),
+                    inode_size: super_block.inode_size(),
+                    checksums: super_block.has_checksums(),
+                    inode_count,
+                    inode_area_start_block: 1 + journal_blocks + super_block.inode_bitmap_blocks,
+                    data_area_start_block: 1
+                        + journal_blocks
+                        + inode_total_blocks
+                        + super_block.data_bitmap_blocks,
+                    data_area_blocks: super_block.data_area_blocks,
+                })
+            },
+        )?;
+        Ok(Arc::new(Mutex::new(efs)))
+    }
+
+    /// Whether the journal holds a committed transaction not installed yet.
+    pub fn journal_pending(&self) -> bool {
+        self.journal.pending(&self.block_device)
+    }
+
+    /// Install the transaction left in the journal, if any.
+    pub fn replay_journal(&self) {
+        self.journal.replay(&self.block_device);
+    }
+
+    /// Group all following metadata modifications into one transaction.
+    /// Callers hold the lock of the file system, so ops on it run one at a
+    /// time, an op begun inside another one is committed with it.
+    pub fn begin_op(&self) {
+        self.journal.begin();
+    }
+
+    /// Commit the transaction opened by the matching `begin_op`, fail
+    /// without modifying anything if it does not fit in the journal.
+    pub fn end_op(&self) -> Result<(), IoError> {
+        self.journal.commit(&self.block_device)
+    }
+
+    /// Max number of blocks a single transaction may modify.
+    pub fn op_capacity(&self) -> usize {
+        match self.journal.enabled() {
+            true => self.journal.capacity(),
+            false => usize::MAX,
+        }
     }
 
     pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
         let block_device = Arc::clone(&efs.lock().block_device);
         // acquire efs lock temporarily
         let (block_id, block_offset) = efs.lock().get_disk_inode_pos(0);
+        let checksums = efs.lock().checksums;
         // release efs lock
-        Inode::new(block_id, block_offset, Arc::clone(efs), block_device)
+        Inode::new(
+            block_id,
+            block_offset,
+            Arc::clone(efs),
+            block_device,
+            checksums,
+        )
+    }
+
+    /// Whether every inode is followed by a `DiskInodeExtra`.
+    pub fn has_owners(&self) -> bool {
+        self.inode_size > core::mem::size_of::<DiskInode>()
+    }
+
+    /// Whether metadata blocks end with their checksum.
+    pub fn has_checksums(&self) -> bool {
+        self.checksums
     }
 
     pub fn get_disk_inode_pos(&self, inode_id: u32) -> (u32, usize) {
-        let inode_size = core::mem::size_of::<DiskInode>();
+        let inode_size = self.inode_size;
         let inodes_per_block = (BLOCK_SZ / inode_size) as u32;
         let block_id = self.inode_area_start_block + inode_id / inodes_per_block;
         (
@@ -118,12 +255,27 @@ impl EasyFileSystem {
         )
     }
 
+    /// Inverse of `get_disk_inode_pos`.
+    pub fn get_disk_inode_id(&self, block_id: u32, block_offset: usize) -> u32 {
+        let inode_size = self.inode_size;
+        let inodes_per_block = (BLOCK_SZ / inode_size) as u32;
+        (block_id - self.inode_area_start_block) * inodes_per_block
+            + (block_offset / inode_size) as u32
+    }
+
     pub fn get_data_block_id(&self, data_block_id: u32) -> u32 {
         self.data_area_start_block + data_block_id
     }
 
     pub fn alloc_inode(&mut self) -> u32 {
-        self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
+        self.inode_bitmap
+            .alloc_near(&self.block_device, 0, self.inode_count as usize)
+            .unwrap() as u32
+    }
+
+    pub fn dealloc_inode(&mut self, inode_id: u32) {
+        self.inode_bitmap
+            .dealloc(&self.block_device, inode_id as usize)
     }
 
     /// Return a block ID not ID in the data area.
@@ -131,7 +283,52 @@ impl EasyFileSystem {
         self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
     }
 
+    /// Like `alloc_data`, but prefer `goal_block_id` or a block soon after
+    /// it so that files stay contiguous.
+    pub fn alloc_data_near(&mut self, goal_block_id: u32) -> u32 {
+        let goal = goal_block_id.saturating_sub(self.data_area_start_block);
+        self.data_bitmap
+            .alloc_near(
+                &self.block_device,
+                goal as usize,
+                self.data_area_blocks as usize,
+            )
+            .unwrap() as u32
+            + self.data_area_start_block
+    }
+
     pub fn dealloc_data(&mut self, block_id: u32) {
+        self.clear_data(block_id);
+        self.free_data(block_id);
+    }
+
+    /// Only release the block in data bitmap.
+    pub fn free_data(&mut self, block_id: u32) {
+        self.data_bitmap.dealloc(
+            &self.block_device,
+            (block_id - self.data_area_start_block) as usize,
+        )
+    }
+
+    /// Mark the image as holding compressed files, it cannot be opened by
+    /// versions that do not know them anymore.
+    pub fn enable_compression(&self) {
+        let block_cache = get_block_cache(0, Arc::clone(&self.block_device));
+        let mut block_cache = block_cache.lock();
+        if block_cache.read(0, |super_block: &SuperBlock| super_block.has_compression()) {
+            return;
+        }
+        let enable = |super_block: &mut SuperBlock| super_block.set_compression();
+        match self.checksums {
+            true => block_cache.modify_checked(0, enable),
+            false => block_cache.modify(0, enable),
+        }
+    }
+
+    /// Fill a data block with zero.
+    pub fn clear_data(&self, block_id: u32) {
+        // it may have held a compressed cluster
+        forget_cluster(&self.block_device, block_id as usize);
         get_block_cache(block_id as usize, Arc::clone(&self.block_device))
             .lock()
             .modify(0, |data_block: &mut DataBlock| {
@@ -139,9 +336,5 @@ impl EasyFileSystem {
                     *p = 0;
                 })
             });
-        self.data_bitmap.dealloc(
-            &self.block_device,
-            (block_id - self.data_area_start_block) as usize,
-        )
     }
 }
@@ -0,0 +1,523 @@
+//! Extent tree of `InodeFormat::Extents` inodes
+//!
+//! The root node lives in the `direct` array of the inode, deeper nodes
+//! take a whole block. A leaf entry maps a run of logical blocks to as many
+//! consecutive disk blocks, an index entry points to the node mapping the
+//! logical blocks from its start on, so a block not mapped by any leaf is a
+//! hole. A full node is split, keeping it full when the new entry is the
+//! last one as files mostly grow at their end.
+use super::{get_block_cache, BlockDevice, DiskInode, BLOCK_SZ, INODE_DIRECT_COUNT};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// First word of a tree block, the header follows it.
+const EXTENT_MAGIC: u32 = 0x4558_5431;
+/// Logical start, then disk block and length for a leaf, or the child
+/// node for an index.
+const ENTRY_WORDS: usize = 3;
+/// Entries of the root, after its header word.
+const ROOT_CAPACITY: usize = (INODE_DIRECT_COUNT - 1) / ENTRY_WORDS;
+/// Entries of a tree block, after its magic and header words.
+const NODE_CAPACITY: usize = (BLOCK_SZ / 4 - 2) / ENTRY_WORDS;
+/// Enough for a file of 4 GiB made of single block extents in half full
+/// nodes.
+const MAX_DEPTH: u16 = 5;
+
+type NodeBlock = [u32; BLOCK_SZ / 4];
+
+#[derive(Clone, Copy)]
+struct Extent {
+    start: u32,
+    block: u32,
+    /// 0 in an index entry.
+    len: u32,
+}
+
+impl Extent {
+    fn end(&self) -> u32 {
+        self.start + self.len
+    }
+}
+
+/// Node of the tree, `depth` is 0 for a leaf.
+struct ExtentNode {
+    depth: u16,
+    entries: Vec<Extent>,
+}
+
+impl ExtentNode {
+    /// `words` starts with the header: depth in the high half, entry count
+    /// in the low half.
+    fn parse(words: &[u32], capacity: usize) -> Option<Self> {
+        let depth = (words[0] >> 16) as u16;
+        let count = (words[0] & 0xffff) as usize;
+        if depth > MAX_DEPTH || count > capacity {
+            return None;
+        }
+        let entries: Vec<Extent> = words[1..1 + count * ENTRY_WORDS]
+            .chunks(ENTRY_WORDS)
+            .map(|entry| Extent {
+                start: entry[0],
+                block: entry[1],
+                len: entry[2],
+            })
+            .collect();
+        let valid = entries.iter().all(|extent| match depth {
+            0 => extent.len > 0 && extent.start.checked_add(extent.len).is_some(),
+            _ => extent.block != 0,
+        });
+        let sorted = entries.windows(2).all(|pair| match depth {
+            0 => pair[0].end() <= pair[1].start,
+            _ => pair[0].start < pair[1].start,
+        });
+        (valid && sorted).then_some(Self { depth, entries })
+    }
+
+    fn write(&self, words: &mut [u32]) {
+        words.fill(0);
+        words[0] = ((self.depth as u32) << 16) | self.entries.len() as u32;
+        for (i, extent) in self.entries.iter().enumerate() {
+            let entry = &mut words[1 + i * ENTRY_WORDS..1 + (i + 1) * ENTRY_WORDS];
+            entry.copy_from_slice(&[extent.start, extent.block, extent.len]);
+        }
+    }
+
+    /// Last entry starting at or before `inner_id`.
+    fn find(&self, inner_id: u32) -> Option<&Extent> {
+        let pos = self
+            .entries
+            .partition_point(|extent| extent.start <= inner_id);
+        pos.checked_sub(1).map(|pos| &self.entries[pos])
+    }
+}
+
+fn read_node(block_id: u32, depth: u16, block_device: &Arc<dyn BlockDevice>) -> Option<ExtentNode> {
+    get_block_cache(block_id as usize, Arc::clone(block_device))
+        .lock()
+        .read(0, |block: &NodeBlock| {
+            if block[0] != EXTENT_MAGIC {
+                return None;
+            }
+            ExtentNode::parse(&block[1..], NODE_CAPACITY)
+        })
+        .filter(|node| node.depth == depth && !node.entries.is_empty())
+}
+
+fn write_node(block_id: u32, node: &ExtentNode, block_device: &Arc<dyn BlockDevice>) {
+    get_block_cache(block_id as usize, Arc::clone(block_device))
+        .lock()
+        .modify(0, |block: &mut NodeBlock| {
+            block[0] = EXTENT_MAGIC;
+            node.write(&mut block[1..]);
+        });
+}
+
+/// Map `inner_id` to `block_id` below `node`, extending a neighbouring
+/// extent when the blocks are contiguous. Return a new node of the same
+/// depth for the caller to link on the right when `node` overflows.
+fn insert(
+    node: &mut ExtentNode,
+    capacity: usize,
+    inner_id: u32,
+    block_id: u32,
+    block_device: &Arc<dyn BlockDevice>,
+    alloc: &mut dyn FnMut(u32) -> u32,
+) -> Option<ExtentNode> {
+    let pos = node
+        .entries
+        .partition_point(|extent| extent.start <= inner_id);
+    if node.depth == 0 {
+        if pos > 0 {
+            let prev = node.entries[pos - 1];
+            if prev.end() == inner_id && prev.block + prev.len == block_id {
+                node.entries[pos - 1].len += 1;
+                // the hole between two extents may just have been filled
+                if let Some(next) = node.entries.get(pos).copied() {
+                    if next.start == inner_id + 1 && next.block == block_id + 1 {
+                        node.entries[pos - 1].len += next.len;
+                        node.entries.remove(pos);
+                    }
+                }
+                return None;
+            }
+        }
+        if let Some(next) = node.entries.get_mut(pos) {
+            if next.start == inner_id + 1 && next.block == block_id + 1 {
+                next.start -= 1;
+                next.block -= 1;
+                next.len += 1;
+                return None;
+            }
+        }
+        let extent = Extent {
+            start: inner_id,
+            block: block_id,
+            len: 1,
+        };
+        node.entries.insert(pos, extent);
+        return split(node, capacity, pos);
+    }
+    // a block before the first child goes into it
+    let pos = pos.saturating_sub(1);
+    let entry = &mut node.entries[pos];
+    entry.start = entry.start.min(inner_id);
+    let child_block = entry.block;
+    let mut child = read_node(child_block, node.depth - 1, block_device).unwrap();
+    let sibling = insert(
+        &mut child,
+        NODE_CAPACITY,
+        inner_id,
+        block_id,
+        block_device,
+        alloc,
+    );
+    write_node(child_block, &child, block_device);
+    let sibling = sibling?;
+    let entry = link(&sibling, block_id + 1, block_device, alloc);
+    node.entries.insert(pos + 1, entry);
+    split(node, capacity, pos + 1)
+}
+
+/// Unmap data blocks `first..last` below `node`, an extent across either
+/// end is cut. Released data blocks and tree blocks left empty go to
+/// `freed`. Return a new right sibling as `insert` does, cutting an extent
+/// in two adds an entry.
+fn remove(
+    node: &mut ExtentNode,
+    capacity: usize,
+    (first, last): (u32, u32),
+    block_device: &Arc<dyn BlockDevice>,
+    alloc: &mut dyn FnMut(u32) -> u32,
+    freed: &mut Vec<u32>,
+) -> Option<ExtentNode> {
+    let old = core::mem::take(&mut node.entries);
+    for (i, extent) in old.iter().enumerate() {
+        if node.depth == 0 {
+            if extent.end() <= first || extent.start >= last {
+                node.entries.push(*extent);
+                continue;
+            }
+            let from = extent.start.max(first);
+            let to = extent.end().min(last);
+            if extent.start < from {
+                node.entries.push(Extent {
+                    len: from - extent.start,
+                    ..*extent
+                });
+            }
+            freed.extend(extent.block + (from - extent.start)..extent.block + (to - extent.start));
+            if to < extent.end() {
+                node.entries.push(Extent {
+                    start: to,
+                    block: extent.block + (to - extent.start),
+                    len: extent.end() - to,
+                });
+            }
+            continue;
+        }
+        let end = old.get(i + 1).map_or(u32::MAX, |next| next.start);
+        let child = match end <= first || extent.start >= last {
+            true => None,
+            false => read_node(extent.block, node.depth - 1, block_device),
+        };
+        let mut child = match child {
+            Some(child) => child,
+            None => {
+                node.entries.push(*extent);
+                continue;
+            }
+        };
+        let sibling = remove(
+            &mut child,
+            NODE_CAPACITY,
+            (first, last),
+            block_device,
+            alloc,
+            freed,
+        );
+        if child.entries.is_empty() {
+            freed.push(extent.block);
+        } else {
+            write_node(extent.block, &child, block_device);
+            node.entries.push(*extent);
+        }
+        if let Some(sibling) = sibling {
+            let entry = link(&sibling, extent.block + 1, block_device, alloc);
+            node.entries.push(entry);
+        }
+    }
+    split(node, capacity, 0)
+}
+
+/// Move the entries past `capacity` to a new right sibling. When the entry
+/// at `pos` was appended only that one moves, so that a file written in
+/// order leaves full nodes behind, otherwise half of the entries do.
+fn split(node: &mut ExtentNode, capacity: usize, pos: usize) -> Option<ExtentNode> {
+    if node.entries.len() <= capacity {
+        return None;
+    }
+    let at = match pos == node.entries.len() - 1 {
+        true => capacity,
+        false => node.entries.len() / 2,
+    };
+    Some(ExtentNode {
+        depth: node.depth,
+        entries: node.entries.split_off(at),
+    })
+}
+
+/// Write `node` to a new block, return the index entry pointing to it.
+fn link(
+    node: &ExtentNode,
+    goal: u32,
+    block_device: &Arc<dyn BlockDevice>,
+    alloc: &mut dyn FnMut(u32) -> u32,
+) -> Extent {
+    let block_id = alloc(goal);
+    write_node(block_id, node, block_device);
+    Extent {
+        start: node.entries[0].start,
+        block: block_id,
+        len: 0,
+    }
+}
+
+impl DiskInode {
+    /// An unreadable root maps nothing.
+    fn extent_root(&self) -> ExtentNode {
+        ExtentNode::parse(&self.direct, ROOT_CAPACITY).unwrap_or(ExtentNode {
+            depth: 0,
+            entries: Vec::new(),
+        })
+    }
+
+    fn set_extent_root(&mut self, root: &ExtentNode) {
+        root.write(&mut self.direct);
+    }
+
+    /// Disk block of data block `inner_id`, 0 if nothing maps it.
+    pub fn extent_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
+        let mut node = self.extent_root();
+        loop {
+            let extent = match node.find(inner_id) {
+                Some(extent) => *extent,
+                None => return 0,
+            };
+            if node.depth == 0 {
+                return match inner_id - extent.start < extent.len {
+                    true => extent.block + (inner_id - extent.start),
+                    false => 0,
+                };
+            }
+            node = match read_node(extent.block, node.depth - 1, block_device) {
+                Some(child) => child,
+                None => return 0,
+            };
+        }
+    }
+
+    /// Map data block `inner_id`, a hole, to a new block as close to `goal`
+    /// as the allocator finds. Return that block.
+    pub fn extent_map_block(
+        &mut self,
+        inner_id: u32,
+        goal: u32,
+        block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut dyn FnMut(u32) -> u32,
+    ) -> u32 {
+        let block_id = alloc(goal);
+        let mut root = self.extent_root();
+        let sibling = insert(
+            &mut root,
+            ROOT_CAPACITY,
+            inner_id,
+            block_id,
+            block_device,
+            alloc,
+        );
+        if let Some(sibling) = sibling {
+            root = deepen(root, sibling, block_id + 1, block_device, alloc);
+        }
+        self.set_extent_root(&root);
+        block_id
+    }
+
+    /// Unmap data blocks `first..last`, return the data and tree blocks
+    /// released.
+    pub fn punch_extents(
+        &mut self,
+        first: u32,
+        last: u32,
+        block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut dyn FnMut(u32) -> u32,
+    ) -> Vec<u32> {
+        let mut root = self.extent_root();
+        let mut freed = Vec::new();
+        let sibling = remove(
+            &mut root,
+            ROOT_CAPACITY,
+            (first, last),
+            block_device,
+            alloc,
+            &mut freed,
+        );
+        if let Some(sibling) = sibling {
+            let goal = sibling.entries[0].block + 1;
+            root = deepen(root, sibling, goal, block_device, alloc);
+        }
+        if root.entries.is_empty() {
+            root.depth = 0;
+        }
+        self.set_extent_root(&root);
+        freed
+    }
+
+    /// Stop mapping data blocks from `data_blocks` on. Return the data and
+    /// tree blocks released when `collect` is set, fsck does not read
+    /// dropped nodes as they may be corrupted.
+    pub fn truncate_extents(
+        &mut self,
+        data_blocks: u32,
+        collect: bool,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        let mut root = self.extent_root();
+        let mut freed = Vec::new();
+        truncate(&mut root, data_blocks, collect, block_device, &mut freed);
+        if root.entries.is_empty() {
+            root.depth = 0;
+        }
+        self.set_extent_root(&root);
+        freed
+    }
+
+    /// Visit the tree in logical order, see `walk_blocks`. Entries out of
+    /// order or a node that cannot be read end the walk.
+    pub fn walk_extents(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        visit: &mut dyn FnMut(u32, u32) -> bool,
+    ) -> u32 {
+        let mut next = 0;
+        let data_blocks = self.data_blocks();
+        match walk(
+            &self.extent_root(),
+            data_blocks,
+            &mut next,
+            block_device,
+            visit,
+        ) {
+            true => data_blocks,
+            false => next,
+        }
+    }
+}
+
+/// The root moved to a block on the left of `sibling`, return the new
+/// root one level deeper.
+fn deepen(
+    root: ExtentNode,
+    sibling: ExtentNode,
+    goal: u32,
+    block_device: &Arc<dyn BlockDevice>,
+    alloc: &mut dyn FnMut(u32) -> u32,
+) -> ExtentNode {
+    let left = link(&root, goal, block_device, alloc);
+    let right = link(&sibling, left.block + 1, block_device, alloc);
+    ExtentNode {
+        depth: root.depth + 1,
+        entries: vec![left, right],
+    }
+}
+
+fn truncate(
+    node: &mut ExtentNode,
+    data_blocks: u32,
+    collect: bool,
+    block_device: &Arc<dyn BlockDevice>,
+    freed: &mut Vec<u32>,
+) {
+    let keep = node
+        .entries
+        .partition_point(|extent| extent.start < data_blocks);
+    for extent in node.entries.drain(keep..) {
+        if collect {
+            collect_blocks(&extent, node.depth, block_device, freed);
+        }
+    }
+    let last = match node.entries.last_mut() {
+        Some(last) => last,
+        None => return,
+    };
+    if node.depth == 0 {
+        let len = (data_blocks - last.start).min(last.len);
+        if collect {
+            freed.extend(last.block + len..last.block + last.len);
+        }
+        last.len = len;
+    } else if let Some(mut child) = read_node(last.block, node.depth - 1, block_device) {
+        truncate(&mut child, data_blocks, collect, block_device, freed);
+        write_node(last.block, &child, block_device);
+    }
+}
+
+/// Every block under `extent`, the tree blocks included.
+fn collect_blocks(
+    extent: &Extent,
+    depth: u16,
+    block_device: &Arc<dyn BlockDevice>,
+    blocks: &mut Vec<u32>,
+) {
+    if depth == 0 {
+        blocks.extend(extent.block..extent.block + extent.len);
+        return;
+    }
+    blocks.push(extent.block);
+    if let Some(child) = read_node(extent.block, depth - 1, block_device) {
+        for entry in child.entries.iter() {
+            collect_blocks(entry, depth - 1, block_device, blocks);
+        }
+    }
+}
+
+/// Return false once the walk stopped, `next` is then the first data block
+/// not to trust.
+fn walk(
+    node: &ExtentNode,
+    data_blocks: u32,
+    next: &mut u32,
+    block_device: &Arc<dyn BlockDevice>,
+    visit: &mut dyn FnMut(u32, u32) -> bool,
+) -> bool {
+    for extent in node.entries.iter() {
+        if extent.start >= data_blocks {
+            return true;
+        }
+        if extent.start < *next {
+            *next = extent.start;
+            return false;
+        }
+        *next = extent.start;
+        if node.depth == 0 {
+            for i in 0..extent.len.min(data_blocks - extent.start) {
+                if !visit(extent.block.wrapping_add(i), *next) {
+                    return false;
+                }
+                *next += 1;
+            }
+            continue;
+        }
+        if !visit(extent.block, *next) {
+            return false;
+        }
+        let child = match read_node(extent.block, node.depth - 1, block_device) {
+            Some(child) => child,
+            None => return false,
+        };
+        if !walk(&child, data_blocks, next, block_device, visit) {
+            return false;
+        }
+    }
+    true
+}
@@ -0,0 +1,402 @@
+use super::{
+    block_cache_sync, build_indexed_dir, get_block_cache, DirEntry, DirFormat, DiskInode,
+    EasyFileSystem, SuperBlock, DIRENT_SZ,
+};
+use crate::BLOCK_SZ;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter, Result};
+
+/// Inconsistency found by `EasyFileSystem::fsck`.
+pub enum FsckProblem {
+    /// Transaction committed to the journal but not installed, it is
+    /// replayed before the other checks when repairing.
+    PendingJournal,
+    /// Areas recorded in the SuperBlock do not add up to `total_blocks`.
+    BadSuperBlock,
+    /// Root inode is not a directory, nothing below it can be checked.
+    BadRootInode,
+    /// Block pointer outside of the data area, the file is cut before it.
+    BadBlock { inode: u32, block_id: u32 },
+    /// Block already owned by another inode, the file is cut before it.
+    DoubleAllocated { inode: u32, block_id: u32 },
+    /// Size too large to be addressed, or not a multiple of `DIRENT_SZ`
+    /// (`BLOCK_SZ` when indexed) for a directory.
+    SizeMismatch { inode: u32, size: u32 },
+    /// Directory entry with an unterminated or non UTF-8 name.
+    BadDirEntry { dir: u32, index: u32 },
+    /// Block of an indexed directory whose records or index cannot be
+    /// parsed, block 0 when the index misses entries.
+    BadDirBlock { dir: u32, block: u32 },
+    /// Directory entry pointing out of the inode area.
+    BadInodeNumber { dir: u32, name: String, inode: u32 },
+    /// Inode referenced by a second directory entry.
+    DuplicateLink { dir: u32, name: String, inode: u32 },
+    /// Inode marked allocated but not reachable from the root.
+    InodeLeaked(u32),
+    /// Inode in use but free in the inode bitmap.
+    InodeNotMarked(u32),
+    /// Data block marked allocated but not owned by any inode.
+    BlockLeaked(u32),
+    /// Data block in use but free in the data bitmap.
+    BlockNotMarked(u32),
+    /// SuperBlock, inode block or directory block not matching its
+    /// checksum, it is sealed again once repaired.
+    BadChecksum { block_id: u32 },
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::PendingJournal => write!(f, "journal holds a transaction not installed"),
+            Self::BadSuperBlock => write!(f, "superblock areas do not match total blocks"),
+            Self::BadRootInode => write!(f, "root inode is not a directory"),
+            Self::BadBlock { inode, block_id } => {
+                write!(f, "inode {}: block {} out of data area", inode, block_id)
+            }
+            Self::DoubleAllocated { inode, block_id } => {
+                write!(f, "inode {}: block {} owned twice", inode, block_id)
+            }
+            Self::SizeMismatch { inode, size } => write!(f, "inode {}: bad size {}", inode, size),
+            Self::BadDirEntry { dir, index } => {
+                write!(f, "directory {}: entry {} has a bad name", dir, index)
+            }
+            Self::BadDirBlock { dir, block } => {
+                write!(f, "directory {}: block {} is corrupted", dir, block)
+            }
+            Self::BadInodeNumber { dir, name, inode } => {
+                write!(f, "directory {}: {} has bad inode {}", dir, name, inode)
+            }
+            Self::DuplicateLink { dir, name, inode } => {
+                write!(f, "directory {}: {} links inode {} again", dir, name, inode)
+            }
+            Self::InodeLeaked(inode) => write!(f, "inode {} leaked", inode),
+            Self::InodeNotMarked(inode) => write!(f, "inode {} in use but free", inode),
+            Self::BlockLeaked(block_id) => write!(f, "block {} leaked", block_id),
+            Self::BlockNotMarked(block_id) => write!(f, "block {} in use but free", block_id),
+            Self::BadChecksum { block_id } => {
+                write!(f, "block {} does not match its checksum", block_id)
+            }
+        }
+    }
+}
+
+impl EasyFileSystem {
+    /// Walk the tree from the root inode and check it against the bitmaps.
+    ///
+    /// With `repair`, files are cut before bad or shared blocks, bad entries
+    /// are dropped from directories and both bitmaps are rebuilt. Repairs are
+    /// not journaled, only run it on an image nobody else is using. Without
+    /// `repair` nothing is written, a pending journal is only reported and
+    /// the blocks it holds are checked as they are on disk.
+    pub fn fsck(&self, repair: bool) -> Vec<FsckProblem> {
+        let mut problems = Vec::new();
+        if self.journal_pending() {
+            problems.push(FsckProblem::PendingJournal);
+            if repair {
+                self.replay_journal();
+            }
+        }
+        // inode blocks and the SuperBlock to seal again
+        let mut bad_checksums = Vec::new();
+        if self.bad_checksum(0) {
+            problems.push(FsckProblem::BadChecksum { block_id: 0 });
+            bad_checksums.push(0);
+        }
+        let super_block_ok = get_block_cache(0, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |super_block: &SuperBlock| {
+                1 + super_block.journal_blocks
+                    + super_block.inode_bitmap_blocks
+                    + super_block.inode_area_blocks
+                    + super_block.data_bitmap_blocks
+                    + super_block.data_area_blocks
+                    == super_block.total_blocks
+            });
+        if !super_block_ok {
+            problems.push(FsckProblem::BadSuperBlock);
+            return problems;
+        }
+        let (data_area_blocks, inode_area_blocks, inode_size) =
+            get_block_cache(0, Arc::clone(&self.block_device))
+                .lock()
+                .read(0, |super_block: &SuperBlock| {
+                    (
+                        super_block.data_area_blocks,
+                        super_block.inode_area_blocks,
+                        super_block.inode_size(),
+                    )
+                });
+        let inodes_per_block = (BLOCK_SZ / inode_size) as u32;
+        let inode_count =
+            (inode_area_blocks * inodes_per_block).min(self.inode_bitmap.maximum() as u32);
+        let data_area_start_block = self.get_data_block_id(0);
+
+        if !self.read_inode(0, |disk_inode| disk_inode.is_dir()) {
+            problems.push(FsckProblem::BadRootInode);
+            return problems;
+        }
+        // owner of every data block, indexed as in the data bitmap
+        let mut owners: Vec<Option<u32>> = vec![None; data_area_blocks as usize];
+        let mut reachable = vec![false; inode_count as usize];
+        reachable[0] = true;
+        let mut stack = vec![0u32];
+        while let Some(inode) = stack.pop() {
+            // 1. blocks of the inode
+            // (block, first data block needing it)
+            let mut owned: Vec<(u32, u32)> = Vec::new();
+            let problem_count = problems.len();
+            let (inode_block_id, _) = self.get_disk_inode_pos(inode);
+            if !bad_checksums.contains(&inode_block_id) && self.bad_checksum(inode_block_id) {
+                problems.push(FsckProblem::BadChecksum {
+                    block_id: inode_block_id,
+                });
+                bad_checksums.push(inode_block_id);
+            }
+            let (size, max_size, data_blocks, reachable_blocks) =
+                self.read_inode(inode, |disk_inode| {
+                    let reachable_blocks =
+                        disk_inode.walk_blocks(&self.block_device, |block_id, index| {
+                            let bit = block_id.wrapping_sub(data_area_start_block);
+                            if bit >= data_area_blocks {
+                                problems.push(FsckProblem::BadBlock { inode, block_id });
+                                return false;
+                            }
+                            if owners[bit as usize].is_some() {
+                                problems.push(FsckProblem::DoubleAllocated { inode, block_id });
+                                return false;
+                            }
+                            owners[bit as usize] = Some(inode);
+                            owned.push((block_id, index));
+                            true
+                        });
+                    (
+                        disk_inode.size,
+                        disk_inode.max_size(),
+                        disk_inode.data_blocks(),
+                        reachable_blocks,
+                    )
+                });
+            let mut new_size = size;
+            if size > max_size {
+                problems.push(FsckProblem::SizeMismatch { inode, size });
+            }
+            if reachable_blocks < data_blocks {
+                // a hole or an unreadable node in an extent tree
+                if problems.len() == problem_count {
+                    problems.push(FsckProblem::SizeMismatch { inode, size });
+                }
+                new_size = reachable_blocks * BLOCK_SZ as u32;
+            }
+            // 2. entries of a directory
+            let is_dir = self.read_inode(inode, |disk_inode| disk_inode.is_dir());
+            let mut entries: Vec<(String, u32)> = Vec::new();
+            let mut dir_changed = false;
+            if is_dir {
+                let entry_size = match self.dir_format {
+                    DirFormat::Fixed => DIRENT_SZ,
+                    _ => BLOCK_SZ,
+                };
+                if !(new_size as usize).is_multiple_of(entry_size) {
+                    problems.push(FsckProblem::SizeMismatch { inode, size });
+                    new_size -= new_size % entry_size as u32;
+                }
+                // a directory has no holes, it ends at the first one
+                let hole = self.read_inode(inode, |disk_inode| {
+                    (0..new_size.div_ceil(BLOCK_SZ as u32)).find(|inner_id| {
+                        disk_inode.get_block_id(*inner_id, &self.block_device) == 0
+                    })
+                });
+                if let Some(hole) = hole {
+                    problems.push(FsckProblem::SizeMismatch { inode, size });
+                    new_size = hole * BLOCK_SZ as u32;
+                }
+                let found = match self.dir_format {
+                    DirFormat::Fixed => {
+                        self.fixed_dir_entries(inode, new_size, &mut problems, &mut dir_changed)
+                    }
+                    _ => {
+                        let (found, bad_blocks, bad_dir_checksums) =
+                            self.read_inode(inode, |disk_inode| {
+                                disk_inode.check_indexed_dir(
+                                    new_size / BLOCK_SZ as u32,
+                                    self.dir_format,
+                                    &self.block_device,
+                                )
+                            });
+                        // the rebuilt directory is sealed block by block
+                        for block in bad_dir_checksums {
+                            let block_id = self.read_inode(inode, |disk_inode| {
+                                disk_inode.get_block_id(block, &self.block_device)
+                            });
+                            problems.push(FsckProblem::BadChecksum { block_id });
+                            dir_changed = true;
+                        }
+                        for block in bad_blocks {
+                            problems.push(FsckProblem::BadDirBlock { dir: inode, block });
+                            dir_changed = true;
+                        }
+                        found
+                    }
+                };
+                for (name, child) in found {
+                    if child >= inode_count {
+                        problems.push(FsckProblem::BadInodeNumber {
+                            dir: inode,
+                            name,
+                            inode: child,
+                        });
+                        dir_changed = true;
+                    } else if reachable[child as usize] {
+                        problems.push(FsckProblem::DuplicateLink {
+                            dir: inode,
+                            name,
+                            inode: child,
+                        });
+                        dir_changed = true;
+                    } else {
+                        reachable[child as usize] = true;
+                        stack.push(child);
+                        entries.push((name, child));
+                    }
+                }
+            }
+            // an indexed directory is rebuilt in the blocks it already has
+            let mut dir_blocks = Vec::new();
+            if dir_changed {
+                new_size = match self.dir_format {
+                    DirFormat::Fixed => (entries.len() * DIRENT_SZ) as u32,
+                    _ => {
+                        dir_blocks = build_indexed_dir(entries.clone(), self.dir_format);
+                        dir_blocks.truncate(new_size as usize / BLOCK_SZ);
+                        (dir_blocks.len() * BLOCK_SZ) as u32
+                    }
+                };
+            }
+            // 3. blocks beyond the new size are released
+            if new_size != size || dir_changed {
+                let new_data_blocks = new_size.div_ceil(BLOCK_SZ as u32);
+                for (block_id, _) in owned.iter().filter(|(_, index)| *index >= new_data_blocks) {
+                    owners[(block_id - data_area_start_block) as usize] = None;
+                }
+                if repair {
+                    self.modify_inode(inode, |disk_inode| {
+                        disk_inode.cut_size(new_size, &self.block_device);
+                        if dir_changed && self.dir_format == DirFormat::Fixed {
+                            for (index, (name, child)) in entries.iter().enumerate() {
+                                let dirent = DirEntry::new(name, *child);
+                                disk_inode.write_at(
+                                    index * DIRENT_SZ,
+                                    dirent.as_bytes(),
+                                    &self.block_device,
+                                );
+                            }
+                        }
+                        for (index, block) in dir_blocks.iter().enumerate() {
+                            disk_inode.write_at(index * BLOCK_SZ, block, &self.block_device);
+                        }
+                    });
+                }
+            }
+        }
+        // 4. compare what is in use with both bitmaps
+        for inode in 0..self.inode_bitmap.maximum() {
+            let used = inode < reachable.len() && reachable[inode];
+            let allocated = self.inode_bitmap.is_allocated(&self.block_device, inode);
+            if used && !allocated {
+                problems.push(FsckProblem::InodeNotMarked(inode as u32));
+                if repair {
+                    self.inode_bitmap.set(&self.block_device, inode);
+                }
+            } else if !used && allocated {
+                problems.push(FsckProblem::InodeLeaked(inode as u32));
+                if repair {
+                    self.inode_bitmap.dealloc(&self.block_device, inode);
+                }
+            }
+        }
+        for bit in 0..self.data_bitmap.maximum() {
+            let used = bit < owners.len() && owners[bit].is_some();
+            let allocated = self.data_bitmap.is_allocated(&self.block_device, bit);
+            let block_id = data_area_start_block + bit as u32;
+            if used && !allocated {
+                problems.push(FsckProblem::BlockNotMarked(block_id));
+                if repair {
+                    self.data_bitmap.set(&self.block_device, bit);
+                }
+            } else if !used && allocated {
+                problems.push(FsckProblem::BlockLeaked(block_id));
+                if repair {
+                    self.data_bitmap.dealloc(&self.block_device, bit);
+                }
+            }
+        }
+        if repair {
+            for block_id in bad_checksums {
+                get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+                    .lock()
+                    .seal();
+            }
+        }
+        block_cache_sync(&self.block_device);
+        problems
+    }
+
+    /// Whether the SuperBlock or inode block `block_id` fails its checksum.
+    fn bad_checksum(&self, block_id: u32) -> bool {
+        self.has_checksums()
+            && get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+                .lock()
+                .verify()
+                .is_err()
+    }
+
+    /// Entries of a fixed directory of `size` bytes, those with a bad name
+    /// are reported and left out.
+    fn fixed_dir_entries(
+        &self,
+        inode: u32,
+        size: u32,
+        problems: &mut Vec<FsckProblem>,
+        dir_changed: &mut bool,
+    ) -> Vec<(String, u32)> {
+        let mut entries = Vec::new();
+        for index in 0..size / DIRENT_SZ as u32 {
+            let mut dirent = DirEntry::empty();
+            self.read_inode(inode, |disk_inode| {
+                disk_inode.read_at(
+                    index as usize * DIRENT_SZ,
+                    dirent.as_bytes_mut(),
+                    &self.block_device,
+                )
+            });
+            match dirent.try_name() {
+                Some(name) => entries.push((String::from(name), dirent.inode_number())),
+                None => {
+                    problems.push(FsckProblem::BadDirEntry { dir: inode, index });
+                    *dir_changed = true;
+                }
+            }
+        }
+        entries
+    }
+
+    fn read_inode<V>(&self, inode: u32, f: impl FnOnce(&DiskInode) -> V) -> V {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(block_offset, f)
+    }
+
+    fn modify_inode<V>(&self, inode: u32, f: impl FnOnce(&mut DiskInode) -> V) -> V {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode);
+        let block_cache = get_block_cache(block_id as usize, Arc::clone(&self.block_device));
+        let mut block_cache = block_cache.lock();
+        match self.has_checksums() {
+            true => block_cache.modify_checked(block_offset, f),
+            false => block_cache.modify(block_offset, f),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use super::block_cache::{IoErrorKind, Transaction};
+use super::{block_cache_sync, get_block_cache, BlockDevice, IoError, BLOCK_SZ};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const JOURNAL_MAGIC: u32 = 0x4a524e4c;
+/// Block ids recorded by one header block.
+const JOURNAL_HEADER_CAPACITY: usize = (BLOCK_SZ - 8) / 4;
+
+type DataBlock = [u8; BLOCK_SZ];
+
+/// First block of the journal region, a transaction is committed once a
+/// header with a valid magic and a non-zero count reaches the disk.
+#[repr(C)]
+struct JournalHeader {
+    magic: u32,
+    count: u32,
+    blocks: [u32; JOURNAL_HEADER_CAPACITY],
+}
+
+impl JournalHeader {
+    fn empty() -> Self {
+        Self {
+            magic: 0,
+            count: 0,
+            blocks: [0; JOURNAL_HEADER_CAPACITY],
+        }
+    }
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const _ as usize as *const u8, BLOCK_SZ) }
+    }
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self as *mut _ as usize as *mut u8, BLOCK_SZ) }
+    }
+    fn is_committed(&self) -> bool {
+        self.magic == JOURNAL_MAGIC && self.count > 0
+    }
+}
+
+/// Write-ahead log of metadata blocks.
+///
+/// Block modifications between `begin` and `commit` are kept in the block
+/// cache, copied to the journal, and only then installed at their home
+/// location, so a crash leaves either none or all of them on disk after
+/// `replay`.
+pub struct Journal {
+    start_block_id: usize,
+    blocks: usize,
+    transaction: Arc<Transaction>,
+}
+
+impl Journal {
+    pub fn new(start_block_id: usize, blocks: usize, block_device: &Arc<dyn BlockDevice>) -> Self {
+        Self {
+            start_block_id,
+            blocks,
+            transaction: Transaction::new(block_device),
+        }
+    }
+
+    /// Images created without a journal region are written in place.
+    pub fn enabled(&self) -> bool {
+        self.blocks > 1
+    }
+
+    /// Max number of blocks a single transaction may modify.
+    pub fn capacity(&self) -> usize {
+        (self.blocks - 1).min(JOURNAL_HEADER_CAPACITY)
+    }
+
+    pub fn begin(&self) {
+        if self.enabled() {
+            self.transaction.begin();
+        }
+    }
+
+    /// Commit the outermost transaction. One modifying more blocks than
+    /// the journal holds is rolled back and refused with an error.
+    pub fn commit(&self, block_device: &Arc<dyn BlockDevice>) -> Result<(), IoError> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        let mut block_ids = match self.transaction.end() {
+            Some(block_ids) => block_ids,
+            None => return Ok(()),
+        };
+        block_ids.sort_unstable();
+        block_ids.dedup();
+        if block_ids.is_empty() {
+            return Ok(());
+        }
+        if block_ids.len() > self.capacity() {
+            for block_id in block_ids.iter() {
+                get_block_cache(*block_id, Arc::clone(block_device))
+                    .lock()
+                    .discard();
+            }
+            return Err(IoError {
+                block_id: self.start_block_id,
+                kind: IoErrorKind::JournalFull,
+            });
+        }
+        // 1. log the new content of every block, the log is one run of blocks
+        let mut header = JournalHeader::empty();
+        let mut log: Vec<u8> = Vec::with_capacity(block_ids.len() * BLOCK_SZ);
+        for (i, block_id) in block_ids.iter().enumerate() {
+            get_block_cache(*block_id, Arc::clone(block_device))
+                .lock()
+                .read(0, |data_block: &DataBlock| {
+                    log.extend_from_slice(data_block)
+                });
+            header.blocks[i] = *block_id as u32;
+        }
+        block_device.write_blocks(self.start_block_id + 1, &log);
+        // 2. commit point
+        header.magic = JOURNAL_MAGIC;
+        header.count = block_ids.len() as u32;
+        block_device.write_block(self.start_block_id, header.as_bytes());
+        // 3. install blocks to their home location
+        for block_id in block_ids.iter() {
+            let block_cache = get_block_cache(*block_id, Arc::clone(block_device));
+            let mut block_cache = block_cache.lock();
+            block_cache.unpin();
+            block_cache.sync();
+        }
+        // 4. the journal can be reused
+        header.count = 0;
+        block_device.write_block(self.start_block_id, header.as_bytes());
+        Ok(())
+    }
+
+    /// Whether a committed transaction has not been installed yet.
+    pub fn pending(&self, block_device: &Arc<dyn BlockDevice>) -> bool {
+        if !self.enabled() {
+            return false;
+        }
+        let mut header = JournalHeader::empty();
+        block_device.read_block(self.start_block_id, header.as_bytes_mut());
+        header.is_committed()
+    }
+
+    /// Install a committed but not yet checkpointed transaction, return the
+    /// number of blocks recovered.
+    pub fn replay(&self, block_device: &Arc<dyn BlockDevice>) -> usize {
+        if !self.enabled() {
+            return 0;
+        }
+        let mut header = JournalHeader::empty();
+        block_device.read_block(self.start_block_id, header.as_bytes_mut());
+        if !header.is_committed() {
+            return 0;
+        }
+        let count = (header.count as usize).min(self.capacity());
+        let mut data: Vec<u8> = alloc::vec![0u8; BLOCK_SZ];
+        for i in 0..count {
+            block_device.read_block(self.start_block_id + 1 + i, &mut data);
+            get_block_cache(header.blocks[i] as usize, Arc::clone(block_device))
+                .lock()
+                .modify(0, |data_block: &mut DataBlock| {
+                    data_block.copy_from_slice(&data);
+                });
+        }
+        block_cache_sync(block_device);
+        header.count = 0;
+        block_device.write_block(self.start_block_id, header.as_bytes());
+        count
+    }
+}
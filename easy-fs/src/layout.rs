@@ -1,16 +1,39 @@
-use super::{get_block_cache, BlockDevice, BLOCK_SZ};
+use super::checksum::CHECKSUM_SZ;
+use super::{block_cache_prefetch, get_block_cache, BlockDevice, BLOCK_SZ};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt::{Debug, Formatter, Result};
+use core::ops::Range;
 
 const EFS_MAGIC: u32 = 0x3b800001;
-const INODE_DIRECT_COUNT: usize = 28;
-const NAME_LENGTH_LIMIT: usize = 27;
+pub const INODE_DIRECT_COUNT: usize = 28;
+/// Longest name of an indexed directory.
+pub const NAME_LENGTH_LIMIT: usize = 255;
+/// Longest name of a directory made of `DirEntry`.
+pub const FIXED_NAME_LENGTH_LIMIT: usize = 27;
+/// Directories are arrays of `DirEntry`. Images made before the version
+/// field existed read 0 there, the block was zeroed by `create`.
+const EFS_VERSION_FIXED_DIRS: u32 = 0;
+/// Directories are hash indexed blocks of variable length entries.
+const EFS_VERSION_INDEXED_DIRS: u32 = 1;
+/// New inodes map their data with an extent tree.
+const FEATURE_EXTENTS: u32 = 1;
+/// Inodes take 256 bytes, a `DiskInodeExtra` follows every `DiskInode`.
+const FEATURE_OWNERS: u32 = 2;
+/// The SuperBlock, inode blocks and directory blocks end with a CRC32C of
+/// the rest of the block. Only set along with `FEATURE_OWNERS`, the tail of
+/// an inode block falls in the reserved words of a `DiskInodeExtra`.
+const FEATURE_CHECKSUMS: u32 = 4;
+/// Some files are stored compressed, see `compress`.
+const FEATURE_COMPRESSION: u32 = 8;
+const SUPPORTED_FEATURES: u32 =
+    FEATURE_EXTENTS | FEATURE_OWNERS | FEATURE_CHECKSUMS | FEATURE_COMPRESSION;
+/// Blocks read in advance when a read reaches the end of a block.
+const READ_AHEAD_BLOCKS: usize = 4;
 const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
 const INODE_INDIRECT2_COUNT: usize = INODE_INDIRECT1_COUNT * INODE_INDIRECT1_COUNT;
 const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
 const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
-#[allow(unused)]
 const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
 
 #[repr(C)]
@@ -21,6 +44,35 @@ pub struct SuperBlock {
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
     pub data_area_blocks: u32,
+    pub journal_blocks: u32,
+    pub version: u32,
+    /// Optional features, 0 on images made before the field existed.
+    pub features: u32,
+}
+
+/// On-disk directory format, given by the SuperBlock version and features.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DirFormat {
+    Fixed,
+    Indexed,
+    /// Indexed, every block ends with its checksum.
+    Checksummed,
+}
+
+impl DirFormat {
+    pub fn name_length_limit(self) -> usize {
+        match self {
+            Self::Fixed => FIXED_NAME_LENGTH_LIMIT,
+            _ => NAME_LENGTH_LIMIT,
+        }
+    }
+    /// Bytes of an indexed directory block holding index entries or records.
+    pub fn block_space(self) -> usize {
+        match self {
+            Self::Checksummed => BLOCK_SZ - CHECKSUM_SZ,
+            _ => BLOCK_SZ,
+        }
+    }
 }
 
 impl Debug for SuperBlock {
@@ -31,6 +83,9 @@ impl Debug for SuperBlock {
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
             .field("data_area_blocks", &self.data_area_blocks)
+            .field("journal_blocks", &self.journal_blocks)
+            .field("version", &self.version)
+            .field("features", &self.features)
             .finish()
     }
 }
@@ -43,6 +98,7 @@ impl SuperBlock {
         inode_area_blocks: u32,
         data_bitmap_blocks: u32,
         data_area_blocks: u32,
+        journal_blocks: u32,
     ) {
         *self = Self {
             magic: EFS_MAGIC,
@@ -51,10 +107,55 @@ impl SuperBlock {
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            journal_blocks,
+            version: EFS_VERSION_INDEXED_DIRS,
+            features: FEATURE_OWNERS | FEATURE_CHECKSUMS,
         }
     }
     pub fn is_valid(&self) -> bool {
         self.magic == EFS_MAGIC
+            && self.version <= EFS_VERSION_INDEXED_DIRS
+            && self.features & !SUPPORTED_FEATURES == 0
+    }
+    pub fn set_inode_format(&mut self, format: InodeFormat) {
+        match format {
+            InodeFormat::Indirect => self.features &= !FEATURE_EXTENTS,
+            InodeFormat::Extents => self.features |= FEATURE_EXTENTS,
+        }
+    }
+    /// Format of the inodes created from now on, older ones keep theirs.
+    pub fn inode_format(&self) -> InodeFormat {
+        match self.features & FEATURE_EXTENTS {
+            0 => InodeFormat::Indirect,
+            _ => InodeFormat::Extents,
+        }
+    }
+    pub fn has_checksums(&self) -> bool {
+        self.features & FEATURE_CHECKSUMS != 0
+    }
+    pub fn has_compression(&self) -> bool {
+        self.features & FEATURE_COMPRESSION != 0
+    }
+    pub fn set_compression(&mut self) {
+        self.features |= FEATURE_COMPRESSION;
+    }
+    /// Images made before owners have no `DiskInodeExtra`.
+    pub fn has_owners(&self) -> bool {
+        self.features & FEATURE_OWNERS != 0
+    }
+    /// Bytes taken by every inode of the inode area.
+    pub fn inode_size(&self) -> usize {
+        match self.has_owners() {
+            true => 2 * core::mem::size_of::<DiskInode>(),
+            false => core::mem::size_of::<DiskInode>(),
+        }
+    }
+    pub fn dir_format(&self) -> DirFormat {
+        match self.version {
+            EFS_VERSION_FIXED_DIRS => DirFormat::Fixed,
+            _ if self.has_checksums() => DirFormat::Checksummed,
+            _ => DirFormat::Indexed,
+        }
     }
 }
 
@@ -62,6 +163,59 @@ impl SuperBlock {
 pub enum DiskInodeType {
     File,
     Directory,
+    /// Named pipe, it has no data on disk.
+    Fifo,
+}
+
+/// How an inode maps its data, inodes made before extents read 0 here.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InodeFormat {
+    /// 28 direct blocks, an indirect and a double indirect block.
+    Indirect,
+    /// Extent tree rooted in the `direct` array.
+    Extents,
+}
+
+/// Owner and permission bits of an inode.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InodeOwner {
+    pub uid: u32,
+    pub gid: u32,
+    /// Permission bits, the file type is kept by the `DiskInode`.
+    pub mode: u32,
+}
+
+impl InodeOwner {
+    /// Owner of inodes nobody asked for: root, with rw-r--r-- for files
+    /// and FIFOs and rwxr-xr-x for directories.
+    pub fn root(type_: &DiskInodeType) -> Self {
+        Self {
+            uid: 0,
+            gid: 0,
+            mode: match type_ {
+                DiskInodeType::File | DiskInodeType::Fifo => 0o644,
+                DiskInodeType::Directory => 0o755,
+            },
+        }
+    }
+}
+
+/// Second half of an inode on images with owners.
+#[repr(C)]
+pub struct DiskInodeExtra {
+    pub owner: InodeOwner,
+    /// Pads the inode to 256 bytes, zero until a later field uses it. On
+    /// images with checksums the last word of an inode block is its
+    /// checksum, which lands here in every second inode.
+    reserved: [u32; 29],
+}
+
+impl DiskInodeExtra {
+    pub fn initialize(&mut self, owner: InodeOwner) {
+        self.owner = owner;
+        self.reserved.iter_mut().for_each(|v| *v = 0);
+    }
 }
 
 type IndirectBlock = [u32; BLOCK_SZ / 4];
@@ -74,16 +228,22 @@ pub struct DiskInode {
     pub indirect1: u32,
     pub indirect2: u32,
     type_: DiskInodeType,
+    format: InodeFormat,
+    /// Data is stored in compressed clusters, see `compress`. Inodes made
+    /// before compression read false here.
+    compressed: bool,
 }
 
 impl DiskInode {
     /// indirect1 and indirect2 block are allocated only when they are needed.
-    pub fn initialize(&mut self, type_: DiskInodeType) {
+    pub fn initialize(&mut self, type_: DiskInodeType, format: InodeFormat) {
         self.size = 0;
         self.direct.iter_mut().for_each(|v| *v = 0);
         self.indirect1 = 0;
         self.indirect2 = 0;
         self.type_ = type_;
+        self.format = format;
+        self.compressed = false;
     }
     pub fn is_dir(&self) -> bool {
         self.type_ == DiskInodeType::Directory
@@ -92,204 +252,376 @@ impl DiskInode {
     pub fn is_file(&self) -> bool {
         self.type_ == DiskInodeType::File
     }
+    pub fn is_fifo(&self) -> bool {
+        self.type_ == DiskInodeType::Fifo
+    }
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+    pub fn set_compressed(&mut self, compressed: bool) {
+        self.compressed = compressed;
+    }
     /// Return block number correspond to size.
     pub fn data_blocks(&self) -> u32 {
         Self::_data_blocks(self.size)
     }
     fn _data_blocks(size: u32) -> u32 {
-        (size + BLOCK_SZ as u32 - 1) / BLOCK_SZ as u32
-    }
-    /// Return number of blocks needed include indirect1/2.
-    pub fn total_blocks(size: u32) -> u32 {
-        let data_blocks = Self::_data_blocks(size) as usize;
-        let mut total = data_blocks as usize;
-        // indirect1
-        if data_blocks > INODE_DIRECT_COUNT {
-            total += 1;
-        }
-        // indirect2
-        if data_blocks > INDIRECT1_BOUND {
-            total += 1;
-            // sub indirect1
-            total +=
-                (data_blocks - INDIRECT1_BOUND + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
-        }
-        total as u32
-    }
-    pub fn blocks_num_needed(&self, new_size: u32) -> u32 {
-        assert!(new_size >= self.size);
-        Self::total_blocks(new_size) - Self::total_blocks(self.size)
+        size.div_ceil(BLOCK_SZ as u32)
     }
+    /// Data and index blocks owned by the inode, holes own none.
+    pub fn owned_blocks(&self, block_device: &Arc<dyn BlockDevice>) -> u32 {
+        let mut owned = 0;
+        self.walk_blocks(block_device, |_, _| {
+            owned += 1;
+            true
+        });
+        owned
+    }
+    /// Block holding data block `inner_id`, 0 for a hole.
     pub fn get_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
+        if self.format == InodeFormat::Extents {
+            return self.extent_block_id(inner_id, block_device);
+        }
+        let read_entry = |block_id: u32, index: usize| match block_id {
+            0 => 0,
+            _ => get_block_cache(block_id as usize, Arc::clone(block_device))
+                .lock()
+                .read(0, |indirect_block: &IndirectBlock| indirect_block[index]),
+        };
         let inner_id = inner_id as usize;
-        if inner_id < INODE_DIRECT_COUNT {
+        if inner_id < DIRECT_BOUND {
             self.direct[inner_id]
         } else if inner_id < INDIRECT1_BOUND {
-            get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect_block: &IndirectBlock| {
-                    indirect_block[inner_id - INODE_DIRECT_COUNT]
-                })
+            read_entry(self.indirect1, inner_id - DIRECT_BOUND)
         } else {
             let last = inner_id - INDIRECT1_BOUND;
-            let indirect1 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect2: &IndirectBlock| {
-                    indirect2[last / INODE_INDIRECT1_COUNT]
-                });
-            get_block_cache(indirect1 as usize, Arc::clone(block_device))
+            let indirect1 = read_entry(self.indirect2, last / INODE_INDIRECT1_COUNT);
+            read_entry(indirect1, last % INODE_INDIRECT1_COUNT)
+        }
+    }
+    /// Visit index and data blocks in file order, stop at the first block
+    /// rejected by `visit`. Along with a block, `visit` gets the index of
+    /// the first data block it may map. Holes are skipped.
+    ///
+    /// Return the number of data blocks that can be trusted, all of them
+    /// unless the walk stopped early.
+    pub fn walk_blocks(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        mut visit: impl FnMut(u32, u32) -> bool,
+    ) -> u32 {
+        if self.format == InodeFormat::Extents {
+            return self.walk_extents(block_device, &mut visit);
+        }
+        let read_block = |block_id: u32| {
+            get_block_cache(block_id as usize, Arc::clone(block_device))
                 .lock()
-                .read(0, |indirect1: &IndirectBlock| {
-                    indirect1[last % INODE_INDIRECT1_COUNT]
-                })
+                .read(0, |indirect_block: &IndirectBlock| *indirect_block)
+        };
+        let data_blocks = (self.data_blocks() as usize).min(INDIRECT2_BOUND);
+        let direct_blocks = data_blocks.min(DIRECT_BOUND);
+        if let Some(stop) = visit_entries(&self.direct, 0, direct_blocks, &mut visit) {
+            return stop;
+        }
+        if data_blocks > DIRECT_BOUND && self.indirect1 != 0 {
+            if !visit(self.indirect1, DIRECT_BOUND as u32) {
+                return DIRECT_BOUND as u32;
+            }
+            let last = data_blocks.min(INDIRECT1_BOUND);
+            if let Some(stop) =
+                visit_entries(&read_block(self.indirect1), DIRECT_BOUND, last, &mut visit)
+            {
+                return stop;
+            }
+        }
+        if data_blocks > INDIRECT1_BOUND && self.indirect2 != 0 {
+            if !visit(self.indirect2, INDIRECT1_BOUND as u32) {
+                return INDIRECT1_BOUND as u32;
+            }
+            let indirect2 = read_block(self.indirect2);
+            let count = (data_blocks - INDIRECT1_BOUND).div_ceil(INODE_INDIRECT1_COUNT);
+            for (i, indirect1) in indirect2.iter().enumerate().take(count) {
+                let first = INDIRECT1_BOUND + i * INODE_INDIRECT1_COUNT;
+                if *indirect1 == 0 {
+                    continue;
+                }
+                if !visit(*indirect1, first as u32) {
+                    return first as u32;
+                }
+                let last = data_blocks.min(first + INODE_INDIRECT1_COUNT);
+                if let Some(stop) = visit_entries(&read_block(*indirect1), first, last, &mut visit)
+                {
+                    return stop;
+                }
+            }
+        }
+        data_blocks as u32
+    }
+    /// Largest size the inode can address.
+    pub fn max_size(&self) -> u32 {
+        match self.format {
+            InodeFormat::Indirect => (INDIRECT2_BOUND * BLOCK_SZ) as u32,
+            InodeFormat::Extents => u32::MAX,
         }
     }
+    /// Grow to `new_size` leaving the new blocks as holes.
+    pub fn extend(&mut self, new_size: u32, block_device: &Arc<dyn BlockDevice>) {
+        assert!(new_size >= self.size);
+        // images made before holes existed keep pointers past the end of
+        // shrunk files, they must not come back as data
+        if self.format == InodeFormat::Indirect {
+            self.truncate_indirect(self.data_blocks(), false, block_device);
+        }
+        self.size = new_size;
+    }
+    /// Grow to `new_size` and back every new block, `alloc` returns a free
+    /// block as close as it can to the block it is given.
     pub fn increase_size(
         &mut self,
         new_size: u32,
-        new_blocks: Vec<u32>,
         block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut dyn FnMut(u32) -> u32,
     ) {
-        let mut current_blocks = self.data_blocks();
-        self.size = new_size;
-        let mut total_blocks = self.data_blocks();
-        let mut new_blocks = new_blocks.into_iter();
-        // fill direct
-        while current_blocks < total_blocks.min(INODE_DIRECT_COUNT as u32) {
-            self.direct[current_blocks as usize] = new_blocks.next().unwrap();
-            current_blocks += 1;
-        }
-        // alloc indirect1
-        if total_blocks > INODE_DIRECT_COUNT as u32 {
-            if current_blocks == INODE_DIRECT_COUNT as u32 {
-                self.indirect1 = new_blocks.next().unwrap();
-            }
-            current_blocks -= INODE_DIRECT_COUNT as u32;
-            total_blocks -= INODE_DIRECT_COUNT as u32;
-        } else {
-            return;
+        let data_blocks = self.data_blocks();
+        self.extend(new_size, block_device);
+        for inner_id in data_blocks..self.data_blocks() {
+            self.map_block(inner_id, block_device, alloc);
         }
-        // fill indirect1
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-                while current_blocks < total_blocks.min(INODE_INDIRECT1_COUNT as u32) {
-                    indirect1[current_blocks as usize] = new_blocks.next().unwrap();
-                    current_blocks += 1;
-                }
-            });
-        // alloc indirect2
-        if total_blocks > INODE_INDIRECT1_COUNT as u32 {
-            if current_blocks == INODE_INDIRECT1_COUNT as u32 {
-                self.indirect2 = new_blocks.next().unwrap();
+    }
+    /// Return the block holding data block `inner_id`, allocating it if it
+    /// is a hole.
+    pub fn map_block(
+        &mut self,
+        inner_id: u32,
+        block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut dyn FnMut(u32) -> u32,
+    ) -> u32 {
+        assert!(inner_id < self.data_blocks());
+        let block_id = self.get_block_id(inner_id, block_device);
+        if block_id != 0 {
+            return block_id;
+        }
+        // data goes right after the previous block of the file if it can
+        let goal = match inner_id {
+            0 => 0,
+            _ => self.get_block_id(inner_id - 1, block_device) + 1,
+        };
+        match self.format {
+            InodeFormat::Indirect => self.indirect_map_block(inner_id, goal, block_device, alloc),
+            InodeFormat::Extents => self.extent_map_block(inner_id, goal, block_device, alloc),
+        }
+    }
+    fn indirect_map_block(
+        &mut self,
+        inner_id: u32,
+        mut goal: u32,
+        block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut dyn FnMut(u32) -> u32,
+    ) -> u32 {
+        let inner_id = inner_id as usize;
+        if inner_id < DIRECT_BOUND {
+            self.direct[inner_id] = alloc(goal);
+            return self.direct[inner_id];
+        }
+        let (indirect1, index) = if inner_id < INDIRECT1_BOUND {
+            if self.indirect1 == 0 {
+                self.indirect1 = alloc_indirect(goal, block_device, alloc);
+                goal = self.indirect1 + 1;
             }
-            current_blocks -= INODE_INDIRECT1_COUNT as u32;
-            total_blocks -= INODE_INDIRECT1_COUNT as u32;
+            (self.indirect1, inner_id - DIRECT_BOUND)
         } else {
-            return;
-        }
-        // fill indirect2 from (a0, b0) -> (a1, b1)
-        let mut a0 = current_blocks as usize / INODE_INDIRECT1_COUNT;
-        let mut b0 = current_blocks as usize % INODE_INDIRECT1_COUNT;
-        let a1 = total_blocks as usize / INODE_INDIRECT1_COUNT;
-        let b1 = total_blocks as usize % INODE_INDIRECT1_COUNT;
-        // alloc low-level indirect1
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                while (a0 < a1) || (a0 == a1 && b0 < b1) {
-                    if b0 == 0 {
-                        indirect2[a0] = new_blocks.next().unwrap();
-                    }
-                    // fill current
-                    get_block_cache(indirect2[a0] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            indirect1[b0] = new_blocks.next().unwrap();
-                        });
-                    // move to next
-                    b0 += 1;
-                    if b0 == INODE_INDIRECT1_COUNT {
-                        b0 = 0;
-                        a0 += 1;
-                    }
-                }
-            });
+            if self.indirect2 == 0 {
+                self.indirect2 = alloc_indirect(goal, block_device, alloc);
+                goal = self.indirect2 + 1;
+            }
+            let last = inner_id - INDIRECT1_BOUND;
+            let slot = last / INODE_INDIRECT1_COUNT;
+            let mut indirect1 = read_indirect(self.indirect2, slot, block_device);
+            if indirect1 == 0 {
+                indirect1 = alloc_indirect(goal, block_device, alloc);
+                goal = indirect1 + 1;
+                write_indirect(self.indirect2, slot, indirect1, block_device);
+            }
+            (indirect1, last % INODE_INDIRECT1_COUNT)
+        };
+        let block_id = alloc(goal);
+        write_indirect(indirect1, index, block_id, block_device);
+        block_id
     }
 
+    /// Shrink to `new_size` and return the tail blocks that should be
+    /// deallocated.
+    pub fn decrease_size(
+        &mut self,
+        new_size: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        assert!(new_size <= self.size);
+        let freed = self.truncate_blocks(Self::_data_blocks(new_size), true, block_device);
+        self.size = new_size;
+        freed
+    }
     /// Clear size to zero and return blocks that should be deallocated.
     ///
     /// We will clear the block contents to zero later.
     pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
-        let mut v: Vec<u32> = Vec::new();
-        let mut data_blocks = self.data_blocks() as usize;
-        self.size = 0;
-        let mut current_blocks = 0usize;
-        // direct
-        while current_blocks < data_blocks.min(INODE_DIRECT_COUNT) {
-            v.push(self.direct[current_blocks]);
-            self.direct[current_blocks] = 0;
-            current_blocks += 1;
-        }
-        // indirect1 block
-        if data_blocks > INODE_DIRECT_COUNT {
-            v.push(self.indirect1);
-            data_blocks -= INODE_DIRECT_COUNT;
-            current_blocks = 0;
-        } else {
-            return v;
+        self.decrease_size(0, block_device)
+    }
+    /// Set the size without releasing blocks, fsck accounts for them.
+    pub fn cut_size(&mut self, new_size: u32, block_device: &Arc<dyn BlockDevice>) {
+        if new_size < self.size {
+            self.truncate_blocks(Self::_data_blocks(new_size), false, block_device);
         }
-        // indirect1
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-                while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
-                    v.push(indirect1[current_blocks]);
-                    //indirect1[current_blocks] = 0;
-                    current_blocks += 1;
+        self.size = new_size;
+    }
+    /// Stop mapping data blocks from `data_blocks` on, return the blocks
+    /// released when `collect` is set.
+    fn truncate_blocks(
+        &mut self,
+        data_blocks: u32,
+        collect: bool,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        match self.format {
+            InodeFormat::Indirect => self.truncate_indirect(data_blocks, collect, block_device),
+            InodeFormat::Extents => self.truncate_extents(data_blocks, collect, block_device),
+        }
+    }
+    /// Clear every pointer from `data_blocks` on, index blocks past it are
+    /// dropped without being read.
+    fn truncate_indirect(
+        &mut self,
+        data_blocks: u32,
+        collect: bool,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        let mut freed = Vec::new();
+        if collect {
+            self.walk_blocks(block_device, |block_id, inner_id| {
+                if inner_id >= data_blocks {
+                    freed.push(block_id);
                 }
+                true
             });
-        self.indirect1 = 0;
-        // indirect2 block
-        if data_blocks > INODE_INDIRECT1_COUNT {
-            v.push(self.indirect2);
-            data_blocks -= INODE_INDIRECT1_COUNT;
-        } else {
-            return v;
         }
-        // indirect2
-        assert!(data_blocks <= INODE_INDIRECT2_COUNT);
-        let a1 = data_blocks / INODE_INDIRECT1_COUNT;
-        let b1 = data_blocks % INODE_INDIRECT1_COUNT;
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                // full indirect1 blocks
-                for entry in indirect2.iter_mut().take(a1) {
-                    v.push(*entry);
-                    get_block_cache(*entry as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter() {
-                                v.push(*entry);
-                            }
-                        });
-                }
-                // last indirect1 block
-                if b1 > 0 {
-                    v.push(indirect2[a1]);
-                    get_block_cache(indirect2[a1] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter().take(b1) {
-                                v.push(*entry);
-                            }
-                        });
-                    //indirect2[a1] = 0;
+        let data_blocks = data_blocks as usize;
+        self.direct
+            .iter_mut()
+            .skip(data_blocks)
+            .for_each(|block_id| *block_id = 0);
+        if data_blocks <= DIRECT_BOUND {
+            self.indirect1 = 0;
+        } else if data_blocks < INDIRECT1_BOUND && self.indirect1 != 0 {
+            clear_indirect(self.indirect1, data_blocks - DIRECT_BOUND, block_device);
+        }
+        if data_blocks <= INDIRECT1_BOUND {
+            self.indirect2 = 0;
+        } else if self.indirect2 != 0 {
+            let last = data_blocks - INDIRECT1_BOUND;
+            let slot = last / INODE_INDIRECT1_COUNT;
+            clear_indirect(
+                self.indirect2,
+                last.div_ceil(INODE_INDIRECT1_COUNT),
+                block_device,
+            );
+            let indirect1 = read_indirect(self.indirect2, slot, block_device);
+            if !last.is_multiple_of(INODE_INDIRECT1_COUNT) && indirect1 != 0 {
+                clear_indirect(indirect1, last % INODE_INDIRECT1_COUNT, block_device);
+            }
+        }
+        freed
+    }
+    /// Zero `offset..offset + len` inside the file, blocks entirely in the
+    /// range become holes. Return the blocks to deallocate.
+    pub fn punch_hole(
+        &mut self,
+        offset: usize,
+        len: usize,
+        block_device: &Arc<dyn BlockDevice>,
+        alloc: &mut dyn FnMut(u32) -> u32,
+    ) -> Vec<u32> {
+        let end = (offset + len).min(self.size as usize);
+        if offset >= end {
+            return Vec::new();
+        }
+        // the last block goes as a whole when the range reaches the end
+        let first = offset.div_ceil(BLOCK_SZ);
+        let last = match end == self.size as usize {
+            true => end.div_ceil(BLOCK_SZ),
+            false => end / BLOCK_SZ,
+        };
+        for (from, to) in [
+            (offset, end.min(first * BLOCK_SZ)),
+            (offset.max(last * BLOCK_SZ), end),
+        ] {
+            let block_id = self.get_block_id((from / BLOCK_SZ) as u32, block_device);
+            if from >= to || block_id == 0 {
+                continue;
+            }
+            let base = from / BLOCK_SZ * BLOCK_SZ;
+            get_block_cache(block_id as usize, Arc::clone(block_device))
+                .lock()
+                .modify(0, |data_block: &mut DataBlock| {
+                    data_block[from - base..to - base].fill(0);
+                });
+        }
+        if first >= last {
+            return Vec::new();
+        }
+        let (first, last) = (first as u32, last as u32);
+        match self.format {
+            InodeFormat::Indirect => self.punch_indirect(first, last, block_device),
+            InodeFormat::Extents => self.punch_extents(first, last, block_device, alloc),
+        }
+    }
+    /// Clear the pointers to data blocks `first..last`, index blocks left
+    /// empty are released with them.
+    fn punch_indirect(
+        &mut self,
+        first: u32,
+        last: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        let mut freed = Vec::new();
+        let (first, last) = (first as usize, last as usize);
+        for block_id in self.direct.iter_mut().take(last).skip(first) {
+            if *block_id != 0 {
+                freed.push(*block_id);
+                *block_id = 0;
+            }
+        }
+        if self.indirect1 != 0 && first < INDIRECT1_BOUND && last > DIRECT_BOUND {
+            let range =
+                first.max(DIRECT_BOUND) - DIRECT_BOUND..last.min(INDIRECT1_BOUND) - DIRECT_BOUND;
+            if punch_indirect_block(self.indirect1, range, block_device, &mut freed) {
+                freed.push(self.indirect1);
+                self.indirect1 = 0;
+            }
+        }
+        if self.indirect2 != 0 && last > INDIRECT1_BOUND {
+            let first = first.max(INDIRECT1_BOUND) - INDIRECT1_BOUND;
+            let last = last.min(INDIRECT2_BOUND) - INDIRECT1_BOUND;
+            let mut slots = Vec::new();
+            for slot in first / INODE_INDIRECT1_COUNT..last.div_ceil(INODE_INDIRECT1_COUNT) {
+                let indirect1 = read_indirect(self.indirect2, slot, block_device);
+                let base = slot * INODE_INDIRECT1_COUNT;
+                let range = first.max(base) - base..last.min(base + INODE_INDIRECT1_COUNT) - base;
+                if indirect1 != 0
+                    && punch_indirect_block(indirect1, range, block_device, &mut freed)
+                {
+                    freed.push(indirect1);
+                    slots.push(slot);
                 }
-            });
-        self.indirect2 = 0;
-        v
+            }
+            for slot in slots {
+                write_indirect(self.indirect2, slot, 0, block_device);
+            }
+            if punch_indirect_block(self.indirect2, 0..0, block_device, &mut freed) {
+                freed.push(self.indirect2);
+                self.indirect2 = 0;
+            }
+        }
+        freed
     }
     pub fn read_at(
         &self,
@@ -311,15 +643,16 @@ impl DiskInode {
             // read and update read size
             let block_read_size = end_current_block - start;
             let dst = &mut buf[read_size..read_size + block_read_size];
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .read(0, |data_block: &DataBlock| {
-                let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
-                dst.copy_from_slice(src);
-            });
+            match self.get_block_id(start_block as u32, block_device) {
+                // a hole reads as zeros
+                0 => dst.fill(0),
+                block_id => get_block_cache(block_id as usize, Arc::clone(block_device))
+                    .lock()
+                    .read(0, |data_block: &DataBlock| {
+                        let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
+                        dst.copy_from_slice(src);
+                    }),
+            }
             read_size += block_read_size;
             // move to next block
             if end_current_block == end {
@@ -328,9 +661,19 @@ impl DiskInode {
             start_block += 1;
             start = end_current_block;
         }
+        // a read up to the end of a block is likely followed by one of the next
+        if end.is_multiple_of(BLOCK_SZ) {
+            let data_blocks = self.data_blocks() as usize;
+            for inner_id in end / BLOCK_SZ..(end / BLOCK_SZ + READ_AHEAD_BLOCKS).min(data_blocks) {
+                let block_id = self.get_block_id(inner_id as u32, block_device);
+                if block_id != 0 {
+                    block_cache_prefetch(block_id as usize, Arc::clone(block_device));
+                }
+            }
+        }
         read_size
     }
-    /// File size must be adjusted before.
+    /// File size must be adjusted and the blocks written mapped before.
     pub fn write_at(
         &mut self,
         offset: usize,
@@ -340,6 +683,9 @@ impl DiskInode {
         let mut start = offset;
         let end = (offset + buf.len()).min(self.size as usize);
         assert!(start <= end);
+        if start == end {
+            return 0;
+        }
         let mut start_block = start / BLOCK_SZ;
         let mut write_size = 0usize;
         loop {
@@ -348,16 +694,16 @@ impl DiskInode {
             end_current_block = end_current_block.min(end);
             // write and update write size
             let block_write_size = end_current_block - start;
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
-                let src = &buf[write_size..write_size + block_write_size];
-                let dst = &mut data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_write_size];
-                dst.copy_from_slice(src);
-            });
+            let block_id = self.get_block_id(start_block as u32, block_device);
+            assert!(block_id != 0, "Write to a hole!");
+            get_block_cache(block_id as usize, Arc::clone(block_device))
+                .lock()
+                .modify(0, |data_block: &mut DataBlock| {
+                    let src = &buf[write_size..write_size + block_write_size];
+                    let dst =
+                        &mut data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_write_size];
+                    dst.copy_from_slice(src);
+                });
             write_size += block_write_size;
             // move to next block
             if end_current_block == end {
@@ -370,9 +716,96 @@ impl DiskInode {
     }
 }
 
+/// Visit the data blocks `first..last` mapped by `entries`, return where
+/// the walk stopped.
+fn visit_entries(
+    entries: &[u32],
+    first: usize,
+    last: usize,
+    visit: &mut impl FnMut(u32, u32) -> bool,
+) -> Option<u32> {
+    (first..last)
+        .zip(entries)
+        .find(|(inner_id, block_id)| **block_id != 0 && !visit(**block_id, *inner_id as u32))
+        .map(|(inner_id, _)| inner_id as u32)
+}
+
+fn read_indirect(block_id: u32, index: usize, block_device: &Arc<dyn BlockDevice>) -> u32 {
+    get_block_cache(block_id as usize, Arc::clone(block_device))
+        .lock()
+        .read(0, |indirect_block: &IndirectBlock| indirect_block[index])
+}
+
+fn write_indirect(block_id: u32, index: usize, value: u32, block_device: &Arc<dyn BlockDevice>) {
+    get_block_cache(block_id as usize, Arc::clone(block_device))
+        .lock()
+        .modify(0, |indirect_block: &mut IndirectBlock| {
+            indirect_block[index] = value
+        });
+}
+
+/// A new index block is zeroed here, a block freed before a crash may not
+/// have been cleared.
+fn alloc_indirect(
+    goal: u32,
+    block_device: &Arc<dyn BlockDevice>,
+    alloc: &mut dyn FnMut(u32) -> u32,
+) -> u32 {
+    let block_id = alloc(goal);
+    get_block_cache(block_id as usize, Arc::clone(block_device))
+        .lock()
+        .modify(0, |indirect_block: &mut IndirectBlock| {
+            indirect_block.fill(0)
+        });
+    block_id
+}
+
+/// Zero the entries of an index block from `from` on, the block is only
+/// modified if one of them is set.
+fn clear_indirect(block_id: u32, from: usize, block_device: &Arc<dyn BlockDevice>) {
+    let mut freed = Vec::new();
+    punch_indirect_block(
+        block_id,
+        from..INODE_INDIRECT1_COUNT,
+        block_device,
+        &mut freed,
+    );
+}
+
+/// Zero the entries of an index block in `range`, pushing the blocks they
+/// pointed to in `freed`. Return true if the block maps nothing anymore.
+fn punch_indirect_block(
+    block_id: u32,
+    range: Range<usize>,
+    block_device: &Arc<dyn BlockDevice>,
+    freed: &mut Vec<u32>,
+) -> bool {
+    let block_cache = get_block_cache(block_id as usize, Arc::clone(block_device));
+    let mut indirect_block = block_cache
+        .lock()
+        .read(0, |indirect_block: &IndirectBlock| *indirect_block);
+    let set = indirect_block[range.clone()]
+        .iter()
+        .filter(|entry| **entry != 0)
+        .count();
+    if set > 0 {
+        for entry in indirect_block[range].iter_mut() {
+            if *entry != 0 {
+                freed.push(*entry);
+                *entry = 0;
+            }
+        }
+        block_cache
+            .lock()
+            .modify(0, |block: &mut IndirectBlock| *block = indirect_block);
+    }
+    indirect_block.iter().all(|entry| *entry == 0)
+}
+
+/// Entry of a `DirFormat::Fixed` directory.
 #[repr(C)]
 pub struct DirEntry {
-    name: [u8; NAME_LENGTH_LIMIT + 1],
+    name: [u8; FIXED_NAME_LENGTH_LIMIT + 1],
     inode_number: u32,
 }
 
@@ -381,12 +814,12 @@ pub const DIRENT_SZ: usize = 32;
 impl DirEntry {
     pub fn empty() -> Self {
         Self {
-            name: [0u8; NAME_LENGTH_LIMIT + 1],
+            name: [0u8; FIXED_NAME_LENGTH_LIMIT + 1],
             inode_number: 0,
         }
     }
     pub fn new(name: &str, inode_number: u32) -> Self {
-        let mut bytes = [0u8; NAME_LENGTH_LIMIT + 1];
+        let mut bytes = [0u8; FIXED_NAME_LENGTH_LIMIT + 1];
         bytes[..name.len()].copy_from_slice(name.as_bytes());
         Self {
             name: bytes,
@@ -400,8 +833,12 @@ impl DirEntry {
         unsafe { core::slice::from_raw_parts_mut(self as *mut _ as usize as *mut u8, DIRENT_SZ) }
     }
     pub fn name(&self) -> &str {
-        let len = (0usize..).find(|i| self.name[*i] == 0).unwrap();
-        core::str::from_utf8(&self.name[..len]).unwrap()
+        self.try_name().unwrap()
+    }
+    /// `None` if the name is not terminated or not valid UTF-8.
+    pub fn try_name(&self) -> Option<&str> {
+        let len = self.name.iter().position(|byte| *byte == 0)?;
+        core::str::from_utf8(&self.name[..len]).ok()
     }
     pub fn inode_number(&self) -> u32 {
         self.inode_number
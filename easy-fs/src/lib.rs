@@ -5,14 +5,29 @@ extern crate alloc;
 mod bitmap;
 mod block_cache;
 mod block_dev;
+mod checksum;
+mod compress;
+mod dir;
 mod efs;
+mod extent;
+mod fsck;
+mod journal;
 mod layout;
+mod lz4;
 mod vfs;
 
 pub const BLOCK_SZ: usize = 512;
 use bitmap::Bitmap;
-use block_cache::{block_cache_sync_all, get_block_cache};
+pub use block_cache::{
+    block_cache_clear, block_cache_sync, block_cache_sync_all, block_cache_try_sync_all,
+    set_block_cache_capacity, IoError, IoErrorKind,
+};
+use block_cache::{block_cache_prefetch, get_block_cache};
 pub use block_dev::BlockDevice;
-pub use efs::EasyFileSystem;
+use dir::build_indexed_dir;
+pub use efs::{EasyFileSystem, JOURNAL_BLOCKS};
+pub use fsck::FsckProblem;
+use journal::Journal;
 use layout::*;
+pub use layout::{InodeFormat, InodeOwner, NAME_LENGTH_LIMIT};
 pub use vfs::Inode;
@@ -0,0 +1,128 @@
+//! LZ4 block format, without the frame around it
+//!
+//! A block is a run of sequences: a token with the literal length in its
+//! high nibble and the match length minus 4 in its low nibble, extra
+//! length bytes for a nibble of 15, the literals, then a 16 bit little
+//! endian offset back to the match and extra match length bytes. The last
+//! sequence has literals only.
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+const MIN_MATCH: usize = 4;
+/// The last 5 bytes are always literals.
+const LAST_LITERALS: usize = 5;
+/// No match starts in the last 12 bytes.
+const MF_LIMIT: usize = 12;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_LOG: u32 = 12;
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+}
+
+fn push_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn push_literals(out: &mut Vec<u8>, token: &mut u8, literals: &[u8]) {
+    *token |= (literals.len().min(15) as u8) << 4;
+    out.push(*token);
+    if literals.len() >= 15 {
+        push_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+}
+
+/// Compress `input` greedily, taking the first match a hash of the next
+/// 4 bytes finds.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() + input.len() / 255 + 16);
+    let mut table = [0usize; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut pos = 0;
+    while pos + MF_LIMIT < input.len() {
+        let sequence = read_u32(input, pos);
+        let slot = hash(sequence);
+        // positions are kept one up, 0 is an empty slot
+        let candidate = table[slot].wrapping_sub(1);
+        table[slot] = pos + 1;
+        if candidate >= pos
+            || pos - candidate > MAX_OFFSET
+            || read_u32(input, candidate) != sequence
+        {
+            pos += 1;
+            continue;
+        }
+        let mut len = MIN_MATCH;
+        while pos + len < input.len() - LAST_LITERALS && input[candidate + len] == input[pos + len]
+        {
+            len += 1;
+        }
+        let mut token = (len - MIN_MATCH).min(15) as u8;
+        push_literals(&mut out, &mut token, &input[anchor..pos]);
+        out.extend_from_slice(&((pos - candidate) as u16).to_le_bytes());
+        if len - MIN_MATCH >= 15 {
+            push_length(&mut out, len - MIN_MATCH - 15);
+        }
+        pos += len;
+        anchor = pos;
+    }
+    push_literals(&mut out, &mut 0, &input[anchor..]);
+    out
+}
+
+fn read_length(input: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut len = 0;
+    loop {
+        let byte = *input.get(*pos)?;
+        *pos += 1;
+        len += byte as usize;
+        if byte != 255 {
+            return Some(len);
+        }
+    }
+}
+
+/// Decompress `input` into `output`, return the bytes written or None if
+/// `input` is not a block fitting in `output`.
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let (mut pos, mut written) = (0usize, 0usize);
+    loop {
+        let token = *input.get(pos)?;
+        pos += 1;
+        let mut literals = (token >> 4) as usize;
+        if literals == 15 {
+            literals += read_length(input, &mut pos)?;
+        }
+        output
+            .get_mut(written..written.checked_add(literals)?)?
+            .copy_from_slice(input.get(pos..pos.checked_add(literals)?)?);
+        pos += literals;
+        written += literals;
+        if pos == input.len() {
+            return Some(written);
+        }
+        let offset = u16::from_le_bytes([*input.get(pos)?, *input.get(pos + 1)?]) as usize;
+        pos += 2;
+        let mut len = (token & 15) as usize + MIN_MATCH;
+        if len == 15 + MIN_MATCH {
+            len += read_length(input, &mut pos)?;
+        }
+        if offset == 0 || offset > written || len > output.len() - written {
+            return None;
+        }
+        // the match may overlap what it produces, copy byte by byte
+        for i in written..written + len {
+            output[i] = output[i - offset];
+        }
+        written += len;
+    }
+}
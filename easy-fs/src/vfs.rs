@@ -1,17 +1,34 @@
+use super::compress::{compress_cluster, forget_cluster, CLUSTER_BLOCKS, CLUSTER_SZ};
 use super::{
-    block_cache_sync_all, get_block_cache, BlockDevice, DirEntry, DiskInode, DiskInodeType,
-    EasyFileSystem, DIRENT_SZ,
+    get_block_cache, BlockDevice, DiskInode, DiskInodeExtra, DiskInodeType, EasyFileSystem,
+    InodeOwner, IoError,
 };
+use crate::BLOCK_SZ;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use spin::{Mutex, MutexGuard};
 
+/// Data blocks a single transaction may allocate or free in a file.
+const MAX_OP_DATA_BLOCKS: u32 = 32;
+/// Blocks a transaction may touch whatever its data blocks: the inode, the
+/// SuperBlock and the root of the block map.
+const OP_META_BLOCKS: usize = 4;
+
+/// Data blocks one transaction of `fs` may map or free, each of them may
+/// bring a bitmap and an index block along and all must fit in the journal.
+fn op_data_blocks(fs: &EasyFileSystem) -> u32 {
+    (fs.op_capacity().saturating_sub(OP_META_BLOCKS) / 2).clamp(1, MAX_OP_DATA_BLOCKS as usize)
+        as u32
+}
+
 pub struct Inode {
     block_id: usize,
     block_offset: usize,
     fs: Arc<Mutex<EasyFileSystem>>,
     block_device: Arc<dyn BlockDevice>,
+    /// The block holding the inode ends with its checksum.
+    checksums: bool,
 }
 
 impl Inode {
@@ -21,59 +38,142 @@ impl Inode {
         block_offset: usize,
         fs: Arc<Mutex<EasyFileSystem>>,
         block_device: Arc<dyn BlockDevice>,
+        checksums: bool,
     ) -> Self {
         Self {
             block_id: block_id as usize,
             block_offset,
             fs,
             block_device,
+            checksums,
         }
     }
 
-    fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
-        get_block_cache(self.block_id, Arc::clone(&self.block_device))
-            .lock()
-            .read(self.block_offset, f)
+    /// Read the `T` at `offset` in the block holding the inode, fail if the
+    /// block does not match its checksum.
+    fn read_inode_block<T, V>(&self, offset: usize, f: impl FnOnce(&T) -> V) -> Result<V, IoError> {
+        let block_cache = get_block_cache(self.block_id, Arc::clone(&self.block_device));
+        let mut block_cache = block_cache.lock();
+        match self.checksums {
+            true => block_cache.read_checked(offset, f),
+            false => Ok(block_cache.read(offset, f)),
+        }
+    }
+
+    fn modify_inode_block<T, V>(&self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
+        let block_cache = get_block_cache(self.block_id, Arc::clone(&self.block_device));
+        let mut block_cache = block_cache.lock();
+        match self.checksums {
+            true => block_cache.modify_checked(offset, f),
+            false => block_cache.modify(offset, f),
+        }
+    }
+
+    fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> Result<V, IoError> {
+        self.read_inode_block(self.block_offset, f)
     }
 
     fn modify_disk_inode<V>(&self, f: impl FnOnce(&mut DiskInode) -> V) -> V {
-        get_block_cache(self.block_id, Arc::clone(&self.block_device))
+        self.modify_inode_block(self.block_offset, f)
+    }
+
+    /// Inode numbered `inode_id`, `fs` is the locked file system.
+    fn inode_at(&self, fs: &EasyFileSystem, inode_id: u32) -> Arc<Inode> {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        Arc::new(Self::new(
+            block_id,
+            block_offset,
+            self.fs.clone(),
+            self.block_device.clone(),
+            self.checksums,
+        ))
+    }
+
+    pub fn find(&self, name: &str) -> Result<Option<Arc<Inode>>, IoError> {
+        let fs = self.fs.lock();
+        let found = self.read_disk_inode(|disk_inode| {
+            disk_inode.find_entry(name, fs.dir_format, &self.block_device)
+        })??;
+        Ok(found.map(|inode_id| self.inode_at(&fs, inode_id)))
+    }
+
+    /// Inode numbered `inode_id` on the same file system.
+    pub fn get_inode(&self, inode_id: u32) -> Arc<Inode> {
+        let fs = self.fs.lock();
+        self.inode_at(&fs, inode_id)
+    }
+
+    /// Inode number of this inode on disk.
+    pub fn inode_id(&self) -> u32 {
+        self.fs
             .lock()
-            .modify(self.block_offset, f)
-    }
-
-    fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
-        // assert it is a directory
-        assert!(disk_inode.is_dir());
-        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-        let mut dirent = DirEntry::empty();
-        for i in 0..file_count {
-            assert_eq!(
-                disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device,),
-                DIRENT_SZ,
-            );
-            if dirent.name() == name {
-                return Some(dirent.inode_number() as u32);
-            }
-        }
-        None
+            .get_disk_inode_id(self.block_id as u32, self.block_offset)
     }
 
-    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+    /// Owner and permission bits, None on images made before owners.
+    pub fn owner(&self) -> Result<Option<InodeOwner>, IoError> {
         let fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            self.find_inode_id(name, disk_inode).map(|inode_id| {
-                let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
-                Arc::new(Self::new(
-                    block_id,
-                    block_offset,
-                    self.fs.clone(),
-                    self.block_device.clone(),
-                ))
-            })
+        if !fs.has_owners() {
+            return Ok(None);
+        }
+        self.read_inode_block(self.extra_offset(), |extra: &DiskInodeExtra| {
+            Some(extra.owner)
         })
     }
 
+    /// Change owner and permission bits, false on images made before owners.
+    pub fn set_owner(&self, owner: InodeOwner) -> Result<bool, IoError> {
+        let fs = self.fs.lock();
+        if !fs.has_owners() {
+            return Ok(false);
+        }
+        // never seal a corrupted block again
+        self.read_disk_inode(|_| ())?;
+        fs.begin_op();
+        self.modify_inode_block(self.extra_offset(), |extra: &mut DiskInodeExtra| {
+            extra.owner = owner
+        });
+        fs.end_op()?;
+        Ok(true)
+    }
+
+    fn extra_offset(&self) -> usize {
+        self.block_offset + core::mem::size_of::<DiskInode>()
+    }
+
+    pub fn is_dir(&self) -> Result<bool, IoError> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
+
+    pub fn is_fifo(&self) -> Result<bool, IoError> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.is_fifo())
+    }
+
+    pub fn size(&self) -> Result<u32, IoError> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.size)
+    }
+
+    /// Number of data and index blocks owned by this inode.
+    pub fn blocks(&self) -> Result<u32, IoError> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.owned_blocks(&self.block_device))
+    }
+
+    /// Return (name, inode) of every entry in this directory.
+    pub fn read_dir(&self) -> Result<Vec<(String, Arc<Inode>)>, IoError> {
+        let fs = self.fs.lock();
+        let entries = self.read_disk_inode(|disk_inode| {
+            disk_inode.entries(fs.dir_format, &self.block_device)
+        })??;
+        Ok(entries
+            .into_iter()
+            .map(|(name, inode_id)| (name, self.inode_at(&fs, inode_id)))
+            .collect())
+    }
+
     fn increase_size(
         &self,
         new_size: u32,
@@ -83,104 +183,358 @@ impl Inode {
         if new_size < disk_inode.size {
             return;
         }
-        let blocks_needed = disk_inode.blocks_num_needed(new_size);
-        let mut v: Vec<u32> = Vec::new();
-        for _ in 0..blocks_needed {
-            v.push(fs.alloc_data());
-        }
-        disk_inode.increase_size(new_size, v, &self.block_device);
+        disk_inode.increase_size(new_size, &self.block_device, &mut |goal| {
+            fs.alloc_data_near(goal)
+        });
+    }
+
+    pub fn create(&self, name: &str) -> Result<Option<Arc<Inode>>, IoError> {
+        self.create_inode(name, DiskInodeType::File)
+    }
+
+    pub fn create_dir(&self, name: &str) -> Result<Option<Arc<Inode>>, IoError> {
+        self.create_inode(name, DiskInodeType::Directory)
     }
 
-    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+    /// Create a named pipe, what goes through it never reaches the disk.
+    pub fn create_fifo(&self, name: &str) -> Result<Option<Arc<Inode>>, IoError> {
+        self.create_inode(name, DiskInodeType::Fifo)
+    }
+
+    fn create_inode(
+        &self,
+        name: &str,
+        type_: DiskInodeType,
+    ) -> Result<Option<Arc<Inode>>, IoError> {
         let mut fs = self.fs.lock();
-        let op = |root_inode: &mut DiskInode| {
-            // assert it is a directory
-            assert!(root_inode.is_dir());
+        let format = fs.dir_format;
+        let inode_format = fs.inode_format;
+        if name.len() > format.name_length_limit() {
+            return Ok(None);
+        }
+        let op = |root_inode: &DiskInode| {
             // has the file been created?
-            self.find_inode_id(name, root_inode)
+            root_inode.find_entry(name, format, &self.block_device)
         };
-        if self.modify_disk_inode(op).is_some() {
-            return None;
+        if self.read_disk_inode(op)??.is_some() {
+            return Ok(None);
         }
+        fs.begin_op();
         // create a new file
-        // alloc a inode with an indirect block
         let new_inode_id = fs.alloc_inode();
+        let new_inode = self.inode_at(&fs, new_inode_id);
+        // the block may hold other inodes, never seal it over corruption
+        if let Err(err) = new_inode.read_disk_inode(|_| ()) {
+            fs.dealloc_inode(new_inode_id);
+            fs.end_op()?;
+            return Err(err);
+        }
         // initialize inode
-        let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
-            .lock()
-            .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(DiskInodeType::File);
+        if fs.has_owners() {
+            new_inode.modify_inode_block(new_inode.extra_offset(), |extra: &mut DiskInodeExtra| {
+                extra.initialize(InodeOwner::root(&type_))
             });
-        self.modify_disk_inode(|root_inode| {
-            // append file in the dirent
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            // increase size
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            // write dirent
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
+        }
+        new_inode.modify_disk_inode(|new_inode| {
+            new_inode.initialize(type_, inode_format);
+        });
+        let inserted = self.modify_disk_inode(|root_inode| {
+            root_inode.insert_entry(
+                name,
+                new_inode_id,
+                format,
                 &self.block_device,
-            );
+                |root_inode, new_size| self.increase_size(new_size, root_inode, &mut fs),
+            )
         });
-
-        let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        block_cache_sync_all();
+        if inserted != Ok(true) {
+            fs.dealloc_inode(new_inode_id);
+            fs.end_op()?;
+            return inserted.map(|_| None);
+        }
+        fs.end_op()?;
         // return inode
-        Some(Arc::new(Self::new(
-            block_id,
-            block_offset,
-            self.fs.clone(),
-            self.block_device.clone(),
-        )))
+        Ok(Some(new_inode))
         // release efs lock automatically by compiler
     }
 
-    pub fn ls(&self) -> Vec<String> {
+    /// Remove the entry `name` and release its inode, a directory must be
+    /// empty. Return false if it cannot be removed.
+    pub fn unlink(&self, name: &str) -> Result<bool, IoError> {
+        let mut fs = self.fs.lock();
+        let format = fs.dir_format;
+        let found = self.read_disk_inode(|dir_inode| {
+            dir_inode.find_entry(name, format, &self.block_device)
+        })??;
+        let inode = match found {
+            Some(inode_id) => self.inode_at(&fs, inode_id),
+            None => return Ok(false),
+        };
+        let not_empty = inode.read_disk_inode(|disk_inode| {
+            Ok(disk_inode.is_dir() && !disk_inode.is_empty_dir(format, &self.block_device)?)
+        })??;
+        if not_empty {
+            return Ok(false);
+        }
+        // data goes first in bounded steps like in clear
+        let size = inode.read_disk_inode(|disk_inode| disk_inode.size as usize)?;
+        inode.punch_steps(&mut fs, 0, size)?;
+        fs.begin_op();
+        let mut data_blocks_dealloc =
+            inode.modify_disk_inode(|disk_inode| disk_inode.clear_size(&self.block_device));
+        // the entry has been found above, its blocks cannot fail now
+        let (inode_id, dir_blocks) = self
+            .modify_disk_inode(|dir_inode| dir_inode.remove_entry(name, format, &self.block_device))
+            .unwrap()
+            .unwrap();
+        data_blocks_dealloc.extend(dir_blocks);
+        for data_block in data_blocks_dealloc.iter() {
+            fs.free_data(*data_block);
+        }
+        fs.dealloc_inode(inode_id);
+        fs.end_op()?;
+        for data_block in data_blocks_dealloc.into_iter() {
+            fs.clear_data(data_block);
+        }
+        Ok(true)
+    }
+
+    pub fn ls(&self) -> Result<Vec<String>, IoError> {
+        let fs = self.fs.lock();
+        let entries = self.read_disk_inode(|disk_inode| {
+            disk_inode.entries(fs.dir_format, &self.block_device)
+        })??;
+        Ok(entries.into_iter().map(|(name, _)| name).collect())
+    }
+
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, IoError> {
         let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-            let mut v: Vec<String> = Vec::new();
-            for i in 0..file_count {
-                let mut dirent = DirEntry::empty();
-                assert_eq!(
-                    disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device,),
-                    DIRENT_SZ,
-                );
-                v.push(String::from(dirent.name()));
-            }
-            v
-        })
+        self.read_disk_inode(|disk_inode| match disk_inode.is_compressed() {
+            true => disk_inode.read_compressed(offset, buf, &self.block_device),
+            false => Ok(disk_inode.read_at(offset, buf, &self.block_device)),
+        })?
     }
 
-    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+    /// Whether the data is stored compressed.
+    pub fn is_compressed(&self) -> Result<bool, IoError> {
         let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))
+        self.read_disk_inode(|disk_inode| disk_inode.is_compressed())
     }
 
-    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+    /// Fill an empty file with `data`, compressed cluster by cluster.
+    /// Clusters that do not get smaller are stored as is and clusters of
+    /// zeros not at all. Return false if this is not an empty file.
+    pub fn write_compressed(&self, data: &[u8]) -> Result<bool, IoError> {
         let mut fs = self.fs.lock();
-        let size = self.modify_disk_inode(|disk_inode| {
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
-            disk_inode.write_at(offset, buf, &self.block_device)
+        let empty_file =
+            self.read_disk_inode(|disk_inode| disk_inode.is_file() && disk_inode.size == 0)?;
+        if !empty_file {
+            return Ok(false);
+        }
+        fs.begin_op();
+        fs.enable_compression();
+        self.modify_disk_inode(|disk_inode| {
+            disk_inode.set_compressed(true);
+            disk_inode.extend(data.len() as u32, &self.block_device);
         });
-        block_cache_sync_all();
-        size
+        fs.end_op()?;
+        for (cluster, chunk) in data.chunks(CLUSTER_SZ).enumerate() {
+            if chunk.iter().all(|byte| *byte == 0) {
+                continue;
+            }
+            let stored = compress_cluster(chunk);
+            let stored = stored.as_deref().unwrap_or(chunk);
+            let first = cluster as u32 * CLUSTER_BLOCKS;
+            fs.begin_op();
+            self.modify_disk_inode(|disk_inode| {
+                for id in first..first + stored.len().div_ceil(BLOCK_SZ) as u32 {
+                    disk_inode
+                        .map_block(id, &self.block_device, &mut |goal| fs.alloc_data_near(goal));
+                }
+            });
+            fs.end_op()?;
+            self.modify_disk_inode(|disk_inode| {
+                disk_inode.write_at(cluster * CLUSTER_SZ, stored, &self.block_device)
+            });
+        }
+        Ok(true)
+    }
+
+    /// Store every compressed cluster as is again, a compressed file is
+    /// decompressed as a whole before it is modified.
+    fn decompress(&self, fs: &mut MutexGuard<EasyFileSystem>) -> Result<(), IoError> {
+        let (compressed, data_blocks) = self
+            .read_disk_inode(|disk_inode| (disk_inode.is_compressed(), disk_inode.data_blocks()))?;
+        if !compressed {
+            return Ok(());
+        }
+        for cluster in 0..data_blocks.div_ceil(CLUSTER_BLOCKS) {
+            let data = self.read_disk_inode(|disk_inode| {
+                disk_inode.compressed_cluster(cluster, &self.block_device)
+            })??;
+            let data = match data {
+                Some(data) => data,
+                None => continue,
+            };
+            let first = cluster * CLUSTER_BLOCKS;
+            let last = first + data.len().div_ceil(BLOCK_SZ) as u32;
+            fs.begin_op();
+            let block_id = self.modify_disk_inode(|disk_inode| {
+                for id in first..last {
+                    disk_inode
+                        .map_block(id, &self.block_device, &mut |goal| fs.alloc_data_near(goal));
+                }
+                disk_inode.get_block_id(first, &self.block_device)
+            });
+            fs.end_op()?;
+            forget_cluster(&self.block_device, block_id as usize);
+            self.modify_disk_inode(|disk_inode| {
+                disk_inode.write_at(cluster as usize * CLUSTER_SZ, &data, &self.block_device)
+            });
+        }
+        fs.begin_op();
+        self.modify_disk_inode(|disk_inode| disk_inode.set_compressed(false));
+        fs.end_op()?;
+        Ok(())
     }
 
-    pub fn clear(&self) {
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, IoError> {
         let mut fs = self.fs.lock();
-        self.modify_disk_inode(|disk_inode| {
-            let size = disk_inode.size;
-            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
-            assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
+        self.decompress(&mut fs)?;
+        let end = offset + buf.len();
+        let last = end.div_ceil(BLOCK_SZ) as u32;
+        // map the written blocks step by step, so that metadata touched by
+        // one transaction always fits in the journal, blocks skipped over
+        // stay holes
+        let mut inner_id = (offset / BLOCK_SZ) as u32;
+        while inner_id < last {
+            let step_end = last.min(inner_id + op_data_blocks(&fs));
+            let step_size = end.min(step_end as usize * BLOCK_SZ) as u32;
+            let mapped = self.read_disk_inode(|disk_inode| {
+                disk_inode.size >= step_size
+                    && (inner_id..step_end)
+                        .all(|id| disk_inode.get_block_id(id, &self.block_device) != 0)
+            })?;
+            if !mapped {
+                fs.begin_op();
+                self.modify_disk_inode(|disk_inode| {
+                    if step_size > disk_inode.size {
+                        disk_inode.extend(step_size, &self.block_device);
+                    }
+                    for id in inner_id..step_end {
+                        disk_inode.map_block(id, &self.block_device, &mut |goal| {
+                            fs.alloc_data_near(goal)
+                        });
+                    }
+                });
+                fs.end_op()?;
+            }
+            inner_id = step_end;
+        }
+        // file data is not journaled, it reaches the disk when written back
+        Ok(self
+            .modify_disk_inode(|disk_inode| disk_inode.write_at(offset, buf, &self.block_device)))
+    }
+
+    /// Zero `len` bytes from `offset` on and free the blocks entirely in
+    /// that range, the size of the file does not change.
+    pub fn punch_hole(&self, offset: usize, len: usize) -> Result<(), IoError> {
+        let mut fs = self.fs.lock();
+        self.decompress(&mut fs)?;
+        let size = self.read_disk_inode(|disk_inode| disk_inode.size as usize)?;
+        self.punch_steps(&mut fs, offset, offset.saturating_add(len).min(size))
+    }
+
+    /// Punch `offset..end` step by step like write_at, index and bitmap
+    /// blocks touched by one transaction must fit in the journal, steps end
+    /// on block boundaries so that only the outer blocks are partly zeroed.
+    /// Punching from 0 to the end of the file zeroes no block, even a
+    /// compressed file can be emptied this way.
+    fn punch_steps(
+        &self,
+        fs: &mut MutexGuard<EasyFileSystem>,
+        offset: usize,
+        end: usize,
+    ) -> Result<(), IoError> {
+        let step = op_data_blocks(fs) as usize * BLOCK_SZ;
+        let mut start = offset;
+        while start < end {
+            let step_end = end.min((start / step + 1) * step);
+            fs.begin_op();
+            let data_blocks_dealloc = self.modify_disk_inode(|disk_inode| {
+                disk_inode.punch_hole(start, step_end - start, &self.block_device, &mut |goal| {
+                    fs.alloc_data_near(goal)
+                })
+            });
+            for data_block in data_blocks_dealloc.iter() {
+                fs.free_data(*data_block);
+            }
+            fs.end_op()?;
             for data_block in data_blocks_dealloc.into_iter() {
-                fs.dealloc_data(data_block);
+                fs.clear_data(data_block);
+            }
+            start = step_end;
+        }
+        Ok(())
+    }
+
+    /// Shrink or grow the file to `size` bytes, false if the inode cannot
+    /// address that many. What is cut off is freed by `punch_hole` first,
+    /// bytes added read as a hole.
+    pub fn set_size(&self, size: usize) -> Result<bool, IoError> {
+        let (old_size, max_size) = {
+            let _fs = self.fs.lock();
+            self.read_disk_inode(|disk_inode| (disk_inode.size, disk_inode.max_size()))?
+        };
+        if size > max_size as usize {
+            return Ok(false);
+        }
+        if size < old_size as usize {
+            self.punch_hole(size, old_size as usize - size)?;
+        }
+        let mut fs = self.fs.lock();
+        self.decompress(&mut fs)?;
+        // only index blocks are left past the new end
+        fs.begin_op();
+        let data_blocks_dealloc = self.modify_disk_inode(|disk_inode| {
+            if size < disk_inode.size as usize {
+                disk_inode.decrease_size(size as u32, &self.block_device)
+            } else {
+                disk_inode.extend(size as u32, &self.block_device);
+                Vec::new()
             }
         });
-        block_cache_sync_all();
+        for data_block in data_blocks_dealloc.iter() {
+            fs.free_data(*data_block);
+        }
+        fs.end_op()?;
+        for data_block in data_blocks_dealloc.into_iter() {
+            fs.clear_data(data_block);
+        }
+        Ok(true)
+    }
+
+    pub fn clear(&self) -> Result<(), IoError> {
+        let mut fs = self.fs.lock();
+        let size = self.read_disk_inode(|disk_inode| disk_inode.size as usize)?;
+        // data goes first in bounded steps, only index blocks are left
+        self.punch_steps(&mut fs, 0, size)?;
+        fs.begin_op();
+        let data_blocks_dealloc = self.modify_disk_inode(|disk_inode| {
+            let blocks = disk_inode.owned_blocks(&self.block_device);
+            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
+            assert!(data_blocks_dealloc.len() == blocks as usize);
+            disk_inode.set_compressed(false);
+            data_blocks_dealloc
+        });
+        for data_block in data_blocks_dealloc.iter() {
+            fs.free_data(*data_block);
+        }
+        fs.end_op()?;
+        // zero freed blocks out of the transaction, they may be many
+        for data_block in data_blocks_dealloc.into_iter() {
+            fs.clear_data(data_block);
+        }
+        Ok(())
     }
 }
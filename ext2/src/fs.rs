@@ -0,0 +1,136 @@
+use super::{BlockDevice, Inode, BLOCK_SZ};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The superblock always starts 1024 bytes into the device.
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SZ: usize = 1024;
+const EXT2_MAGIC: u16 = 0xEF53;
+pub(crate) const ROOT_INO: u32 = 2;
+/// Revision 0 file systems have fixed 128 byte inodes.
+const GOOD_OLD_INODE_SZ: usize = 128;
+const GROUP_DESC_SZ: usize = 32;
+/// Directory entries carry a file type byte.
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Group metadata packed together, only moves the tables around.
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+/// Anything else, such as extents or 64 bit block numbers, is refused.
+const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE | INCOMPAT_FLEX_BG;
+
+pub(crate) fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+pub struct Ext2FileSystem {
+    device: Arc<dyn BlockDevice>,
+    pub(crate) block_size: usize,
+    /// Whether directory entries hold a file type byte.
+    pub(crate) filetype: bool,
+    inodes_count: u32,
+    inodes_per_group: u32,
+    inode_size: usize,
+    /// First block of the inode table of every block group.
+    inode_tables: Vec<u32>,
+}
+
+impl Ext2FileSystem {
+    /// Read the superblock and group descriptors, None if the device does
+    /// not hold an ext2 file system this driver understands.
+    pub fn open(device: Arc<dyn BlockDevice>) -> Option<Arc<Self>> {
+        let mut sb = [0u8; SUPERBLOCK_SZ];
+        read_device(&device, SUPERBLOCK_OFFSET, &mut sb);
+        let inodes_count = read_u32(&sb, 0);
+        let blocks_count = read_u32(&sb, 4);
+        let first_data_block = read_u32(&sb, 20);
+        let log_block_size = read_u32(&sb, 24);
+        let blocks_per_group = read_u32(&sb, 32);
+        let inodes_per_group = read_u32(&sb, 40);
+        let rev_level = read_u32(&sb, 76);
+        let (inode_size, incompat) = match rev_level {
+            0 => (GOOD_OLD_INODE_SZ, 0),
+            _ => (read_u16(&sb, 88) as usize, read_u32(&sb, 96)),
+        };
+        if read_u16(&sb, 56) != EXT2_MAGIC
+            || log_block_size > 6
+            || blocks_per_group == 0
+            || inodes_per_group == 0
+            || incompat & !SUPPORTED_INCOMPAT != 0
+            || inode_size < GOOD_OLD_INODE_SZ
+            || !inode_size.is_power_of_two()
+            || blocks_count <= first_data_block
+        {
+            return None;
+        }
+        let block_size = 1024 << log_block_size;
+        let groups = (blocks_count - first_data_block).div_ceil(blocks_per_group) as usize;
+        // group descriptors fill the blocks after the superblock
+        let mut descs = vec![0u8; groups * GROUP_DESC_SZ];
+        read_device(
+            &device,
+            (first_data_block as u64 + 1) * block_size as u64,
+            &mut descs,
+        );
+        let inode_tables = descs
+            .chunks(GROUP_DESC_SZ)
+            .map(|desc| read_u32(desc, 8))
+            .collect();
+        Some(Arc::new(Self {
+            device,
+            block_size,
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+            inodes_count,
+            inodes_per_group,
+            inode_size,
+            inode_tables,
+        }))
+    }
+
+    pub fn root_inode(fs: &Arc<Self>) -> Inode {
+        Self::inode(fs, ROOT_INO).expect("ext2 root inode is missing")
+    }
+
+    /// Inode numbered `ino`, None if there is no such inode.
+    pub fn inode(fs: &Arc<Self>, ino: u32) -> Option<Inode> {
+        if ino == 0 || ino > fs.inodes_count {
+            return None;
+        }
+        let group = ((ino - 1) / fs.inodes_per_group) as usize;
+        let index = ((ino - 1) % fs.inodes_per_group) as u64;
+        let table = *fs.inode_tables.get(group)? as u64;
+        // only the fields of the first 128 bytes are used
+        let mut raw = [0u8; GOOD_OLD_INODE_SZ];
+        fs.read_bytes(
+            table * fs.block_size as u64 + index * fs.inode_size as u64,
+            &mut raw,
+        );
+        Some(Inode::new(fs.clone(), ino, &raw))
+    }
+
+    /// Read bytes starting at byte `pos` of the device.
+    pub(crate) fn read_bytes(&self, pos: u64, buf: &mut [u8]) {
+        read_device(&self.device, pos, buf);
+    }
+}
+
+fn read_device(device: &Arc<dyn BlockDevice>, pos: u64, buf: &mut [u8]) {
+    let mut sector = [0u8; BLOCK_SZ];
+    let mut done = 0;
+    while done < buf.len() {
+        let at = pos + done as u64;
+        let offset = (at % BLOCK_SZ as u64) as usize;
+        let len = (BLOCK_SZ - offset).min(buf.len() - done);
+        device.read_block((at / BLOCK_SZ as u64) as usize, &mut sector);
+        buf[done..done + len].copy_from_slice(&sector[offset..offset + len]);
+        done += len;
+    }
+}
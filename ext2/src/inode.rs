@@ -0,0 +1,232 @@
+use super::fs::{read_u16, read_u32};
+use super::Ext2FileSystem;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const DIRECT_BLOCKS: usize = 12;
+const INDIRECT: usize = 12;
+const DOUBLE_INDIRECT: usize = 13;
+const TRIPLE_INDIRECT: usize = 14;
+/// Symlink targets shorter than this live in the block array itself.
+const FAST_SYMLINK_MAX: u64 = 60;
+const DIR_ENTRY_HEADER_SZ: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+    Unknown,
+}
+
+impl FileType {
+    fn from_mode(mode: u16) -> Self {
+        match mode & 0xF000 {
+            0x8000 => Self::Regular,
+            0x4000 => Self::Directory,
+            0xA000 => Self::Symlink,
+            0x2000 => Self::CharDevice,
+            0x6000 => Self::BlockDevice,
+            0x1000 => Self::Fifo,
+            0xC000 => Self::Socket,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// An inode read from disk. The file system is never written, so the
+/// fields are read once when the inode is looked up.
+pub struct Inode {
+    fs: Arc<Ext2FileSystem>,
+    ino: u32,
+    file_type: FileType,
+    size: u64,
+    /// 512 byte sectors used, including an extended attribute block.
+    sectors: u32,
+    file_acl: u32,
+    block: [u32; 15],
+}
+
+impl Inode {
+    pub(crate) fn new(fs: Arc<Ext2FileSystem>, ino: u32, raw: &[u8]) -> Self {
+        let file_type = FileType::from_mode(read_u16(raw, 0));
+        let mut size = read_u32(raw, 4) as u64;
+        // the high half is only a size for regular files
+        if file_type == FileType::Regular {
+            size |= (read_u32(raw, 108) as u64) << 32;
+        }
+        let mut block = [0u32; 15];
+        for (i, block_id) in block.iter_mut().enumerate() {
+            *block_id = read_u32(raw, 40 + i * 4);
+        }
+        Self {
+            fs,
+            ino,
+            file_type,
+            size,
+            sectors: read_u32(raw, 28),
+            file_acl: read_u32(raw, 104),
+            block,
+        }
+    }
+
+    pub fn inode_id(&self) -> u32 {
+        self.ino
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FileType::Directory
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Entry `index` of the block of block numbers `table`, 0 for a hole.
+    fn indirect(&self, table: u32, index: u64) -> u32 {
+        if table == 0 {
+            return 0;
+        }
+        let mut raw = [0u8; 4];
+        self.fs.read_bytes(
+            table as u64 * self.fs.block_size as u64 + index * 4,
+            &mut raw,
+        );
+        u32::from_le_bytes(raw)
+    }
+
+    /// Block holding block `index` of the file, 0 for a hole.
+    fn block_id(&self, index: u64) -> u32 {
+        let per_block = self.fs.block_size as u64 / 4;
+        if index < DIRECT_BLOCKS as u64 {
+            return self.block[index as usize];
+        }
+        let index = index - DIRECT_BLOCKS as u64;
+        if index < per_block {
+            return self.indirect(self.block[INDIRECT], index);
+        }
+        let index = index - per_block;
+        if index < per_block * per_block {
+            let table = self.indirect(self.block[DOUBLE_INDIRECT], index / per_block);
+            return self.indirect(table, index % per_block);
+        }
+        let index = index - per_block * per_block;
+        if index < per_block * per_block * per_block {
+            let table = self.indirect(self.block[TRIPLE_INDIRECT], index / (per_block * per_block));
+            let table = self.indirect(table, index / per_block % per_block);
+            return self.indirect(table, index % per_block);
+        }
+        0
+    }
+
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let block_size = self.fs.block_size as u64;
+        let start = offset as u64;
+        let end = self.size.min(start + buf.len() as u64);
+        let mut pos = start;
+        while pos < end {
+            let in_block = pos % block_size;
+            let len = (block_size - in_block).min(end - pos) as usize;
+            let dst = &mut buf[(pos - start) as usize..(pos - start) as usize + len];
+            match self.block_id(pos / block_size) {
+                0 => dst.fill(0),
+                block_id => self
+                    .fs
+                    .read_bytes(block_id as u64 * block_size + in_block, dst),
+            }
+            pos += len as u64;
+        }
+        end.saturating_sub(start) as usize
+    }
+
+    /// (name, inode number) of every entry of this directory, with "." and "..".
+    fn entries(&self) -> Vec<(String, u32)> {
+        if !self.is_dir() {
+            return Vec::new();
+        }
+        let mut data = vec![0u8; self.size as usize];
+        let len = self.read_at(0, &mut data);
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos + DIR_ENTRY_HEADER_SZ <= len {
+            let ino = read_u32(&data, pos);
+            let rec_len = read_u16(&data, pos + 4) as usize;
+            let name_len = if self.fs.filetype {
+                data[pos + 6] as usize
+            } else {
+                read_u16(&data, pos + 6) as usize
+            };
+            // a broken record ends the directory
+            if rec_len < DIR_ENTRY_HEADER_SZ
+                || pos + rec_len > len
+                || DIR_ENTRY_HEADER_SZ + name_len > rec_len
+            {
+                break;
+            }
+            if ino != 0 {
+                let name = &data[pos + DIR_ENTRY_HEADER_SZ..pos + DIR_ENTRY_HEADER_SZ + name_len];
+                entries.push((String::from_utf8_lossy(name).into_owned(), ino));
+            }
+            pos += rec_len;
+        }
+        entries
+    }
+
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        let (_, ino) = self
+            .entries()
+            .into_iter()
+            .find(|(entry_name, _)| entry_name == name)?;
+        Ext2FileSystem::inode(&self.fs, ino).map(Arc::new)
+    }
+
+    /// Return (name, inode) of every entry in this directory but "." and "..".
+    pub fn read_dir(&self) -> Vec<(String, Arc<Inode>)> {
+        self.entries()
+            .into_iter()
+            .filter(|(name, _)| name != "." && name != "..")
+            .filter_map(|(name, ino)| {
+                Ext2FileSystem::inode(&self.fs, ino).map(|inode| (name, Arc::new(inode)))
+            })
+            .collect()
+    }
+
+    pub fn ls(&self) -> Vec<String> {
+        self.entries()
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| name != "." && name != "..")
+            .collect()
+    }
+
+    /// Target of a symbolic link, None for other inodes.
+    pub fn read_link(&self) -> Option<String> {
+        if self.file_type != FileType::Symlink {
+            return None;
+        }
+        let acl_sectors = match self.file_acl {
+            0 => 0,
+            _ => (self.fs.block_size / 512) as u32,
+        };
+        let mut target = vec![0u8; self.size as usize];
+        // short targets are kept in the block array, no data block is used
+        if self.size < FAST_SYMLINK_MAX && self.sectors == acl_sectors {
+            let bytes: Vec<u8> = self.block.iter().flat_map(|b| b.to_le_bytes()).collect();
+            target.copy_from_slice(&bytes[..self.size as usize]);
+        } else {
+            let len = self.read_at(0, &mut target);
+            target.truncate(len);
+        }
+        Some(String::from_utf8_lossy(&target).into_owned())
+    }
+}
@@ -0,0 +1,106 @@
+//! Read-only ext2 driver on top of the `BlockDevice` trait of easy-fs
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+mod fs;
+mod inode;
+
+pub use easy_fs::{BlockDevice, BLOCK_SZ};
+pub use fs::Ext2FileSystem;
+pub use inode::{FileType, Inode};
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockDevice, Ext2FileSystem, FileType, BLOCK_SZ};
+    use std::fs::{create_dir_all, File};
+    use std::io::{Read, Seek, SeekFrom};
+    use std::path::Path;
+    use std::process::Command;
+    use std::sync::{Arc, Mutex};
+
+    /// Image file on the host, the driver never writes it.
+    struct BlockFile(Mutex<File>);
+
+    impl BlockDevice for BlockFile {
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+            let mut file = self.0.lock().unwrap();
+            file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
+                .expect("Error when seeking!");
+            file.read_exact(buf).expect("Not a complete block!");
+        }
+
+        fn write_block(&self, _block_id: usize, _buf: &[u8]) {
+            unimplemented!();
+        }
+
+        fn handle_irq(&self) {
+            unimplemented!();
+        }
+    }
+
+    /// Read an image made by `mke2fs -d` from a host tree, skipped when the
+    /// host has no mke2fs.
+    #[test]
+    fn ext2_test() -> std::io::Result<()> {
+        let host_dir = Path::new("target/ext2_src");
+        let _ = std::fs::remove_dir_all(host_dir);
+        create_dir_all(host_dir.join("bin/sub"))?;
+        std::fs::write(host_dir.join("hello"), b"hello, ext2\n")?;
+        // 1 KiB blocks: past the direct, indirect and into double indirect blocks
+        let big: Vec<u8> = (0..(12 + 256 + 300) * 1024 + 5)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(host_dir.join("bin/big"), &big)?;
+        std::os::unix::fs::symlink("../hello", host_dir.join("bin/fast_link"))?;
+        let long_target = format!("sub/{}", "x".repeat(80));
+        std::fs::write(host_dir.join("bin").join(&long_target), b"slow")?;
+        std::os::unix::fs::symlink(&long_target, host_dir.join("bin/slow_link"))?;
+        let path = "target/ext2.img";
+        let _ = std::fs::remove_file(path);
+        let made = Command::new("mke2fs")
+            .args(["-q", "-F", "-t", "ext2", "-b", "1024", "-d"])
+            .arg(host_dir)
+            .args([path, "4096"])
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false);
+        if !made {
+            println!("mke2fs is not available, ext2 test skipped");
+            return Ok(());
+        }
+        let block_file: Arc<dyn BlockDevice> = Arc::new(BlockFile(Mutex::new(File::open(path)?)));
+        let fs = Ext2FileSystem::open(block_file).expect("not an ext2 image");
+        let root_inode = Ext2FileSystem::root_inode(&fs);
+        let mut names = root_inode.ls();
+        names.sort();
+        assert_eq!(names, vec!["bin", "hello", "lost+found"]);
+
+        let hello = root_inode.find("hello").unwrap();
+        let mut buffer = vec![0u8; big.len() + 100];
+        let len = hello.read_at(0, &mut buffer);
+        assert_eq!(&buffer[..len], b"hello, ext2\n");
+
+        let bin = root_inode.find("bin").unwrap();
+        assert!(bin.is_dir());
+        let big_inode = bin.find("big").unwrap();
+        assert_eq!(big_inode.size() as usize, big.len());
+        assert_eq!(big_inode.read_at(0, &mut buffer), big.len());
+        assert_eq!(&buffer[..big.len()], &big[..]);
+        // reads not aligned to blocks
+        let mut part = [0u8; 3000];
+        assert_eq!(big_inode.read_at(270 * 1024 - 7, &mut part), 3000);
+        assert_eq!(&part[..], &big[270 * 1024 - 7..270 * 1024 + 2993]);
+
+        let fast_link = bin.find("fast_link").unwrap();
+        assert_eq!(fast_link.file_type(), FileType::Symlink);
+        assert_eq!(fast_link.read_link().unwrap(), "../hello");
+        assert_eq!(
+            bin.find("slow_link").unwrap().read_link().unwrap(),
+            long_target
+        );
+        assert!(hello.read_link().is_none());
+        assert!(bin.find("missing").is_none());
+        Ok(())
+    }
+}
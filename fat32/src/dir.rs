@@ -0,0 +1,289 @@
+//! On-disk layout of 32 byte directory entries and long file names
+use super::fs::{read_u16, read_u32, write_u16, write_u32};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const DIR_ENTRY_SZ: usize = 32;
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+pub const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+/// First name byte of a free entry, 0x00 also ends the directory.
+pub const ENTRY_FREE: u8 = 0xE5;
+pub const ENTRY_END: u8 = 0x00;
+/// Set in the order byte of the last (first stored) long name entry.
+const LFN_LAST: u8 = 0x40;
+/// UCS-2 characters held by one long name entry, and where they are.
+const LFN_CHARS: usize = 13;
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// Case bits of the NT reserved byte, set by Windows and Linux when a
+/// lower case 8.3 name is stored without a long name.
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+/// No clock in the driver, everything is dated 2000-01-01 00:00.
+const FIXED_DATE: u16 = (20 << 9) | (1 << 5) | 1;
+pub const NAME_MAX: usize = 255;
+
+pub type ShortName = [u8; 11];
+
+/// A file or directory found in a directory with the slots it occupies.
+pub struct DirEntry {
+    pub name: String,
+    pub short_name: ShortName,
+    pub attr: u8,
+    pub first_cluster: u32,
+    /// Index of the 8.3 entry in the directory.
+    pub slot: usize,
+    /// Sector and byte offset of the 8.3 entry.
+    pub pos: (u32, usize),
+    /// Number of slots used, long name entries come right before the 8.3 one.
+    pub slots: usize,
+}
+
+impl DirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+    pub fn is_dot(&self) -> bool {
+        self.short_name[0] == b'.'
+    }
+}
+
+pub fn entry_attr(raw: &[u8]) -> u8 {
+    raw[11]
+}
+
+pub fn entry_case(raw: &[u8]) -> u8 {
+    raw[12]
+}
+
+/// Long name entries carry all four low attribute bits.
+pub fn is_long_name(raw: &[u8]) -> bool {
+    entry_attr(raw) & 0x3F == ATTR_LONG_NAME
+}
+
+pub fn entry_cluster(raw: &[u8]) -> u32 {
+    ((read_u16(raw, 20) as u32) << 16) | read_u16(raw, 26) as u32
+}
+
+pub fn set_entry_cluster(raw: &mut [u8], cluster: u32) {
+    write_u16(raw, 20, (cluster >> 16) as u16);
+    write_u16(raw, 26, cluster as u16);
+}
+
+pub fn entry_size(raw: &[u8]) -> u32 {
+    read_u32(raw, 28)
+}
+
+pub fn set_entry_size(raw: &mut [u8], size: u32) {
+    write_u32(raw, 28, size);
+}
+
+/// Build an 8.3 entry.
+pub fn short_entry(name: &ShortName, attr: u8, cluster: u32) -> [u8; DIR_ENTRY_SZ] {
+    let mut raw = [0u8; DIR_ENTRY_SZ];
+    raw[0..11].copy_from_slice(name);
+    raw[11] = attr;
+    // creation, access and modification dates
+    write_u16(&mut raw, 16, FIXED_DATE);
+    write_u16(&mut raw, 18, FIXED_DATE);
+    write_u16(&mut raw, 24, FIXED_DATE);
+    set_entry_cluster(&mut raw, cluster);
+    raw
+}
+
+pub fn checksum(name: &ShortName) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, c| sum.rotate_right(1).wrapping_add(*c))
+}
+
+/// Long name entries for `name` in the order they are stored on disk.
+pub fn lfn_entries(name: &str, short_name: &ShortName) -> Vec<[u8; DIR_ENTRY_SZ]> {
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    let count = chars.len().div_ceil(LFN_CHARS);
+    // a name filling the last entry has no terminator
+    if chars.len() < count * LFN_CHARS {
+        chars.push(0);
+    }
+    chars.resize(count * LFN_CHARS, 0xFFFF);
+    let sum = checksum(short_name);
+    (0..count)
+        .rev()
+        .map(|i| {
+            let mut raw = [0u8; DIR_ENTRY_SZ];
+            raw[0] = (i + 1) as u8 | if i + 1 == count { LFN_LAST } else { 0 };
+            raw[11] = ATTR_LONG_NAME;
+            raw[13] = sum;
+            for (j, offset) in LFN_OFFSETS.iter().enumerate() {
+                write_u16(&mut raw, *offset, chars[i * LFN_CHARS + j]);
+            }
+            raw
+        })
+        .collect()
+}
+
+/// Collects the long name entries that precede an 8.3 entry.
+#[derive(Default)]
+pub struct LfnBuilder {
+    parts: Vec<[u16; LFN_CHARS]>,
+    checksum: u8,
+    expected: u8,
+}
+
+impl LfnBuilder {
+    pub fn push(&mut self, raw: &[u8]) {
+        let order = raw[0] & !LFN_LAST;
+        if order == 0 {
+            self.clear();
+            return;
+        }
+        if raw[0] & LFN_LAST != 0 {
+            self.parts.clear();
+            self.checksum = raw[13];
+            self.expected = order;
+        } else if order != self.expected || raw[13] != self.checksum {
+            // out of sequence, drop what was collected
+            self.clear();
+            return;
+        }
+        let mut part = [0u16; LFN_CHARS];
+        for (j, offset) in LFN_OFFSETS.iter().enumerate() {
+            part[j] = read_u16(raw, *offset);
+        }
+        self.parts.push(part);
+        self.expected = order - 1;
+    }
+    pub fn len(&self) -> usize {
+        self.parts.len()
+    }
+    pub fn clear(&mut self) {
+        self.parts.clear();
+        self.expected = 0;
+    }
+    /// The long name if it is complete and belongs to `short_name`.
+    pub fn take(&mut self, short_name: &ShortName) -> Option<String> {
+        let complete = !self.parts.is_empty() && self.expected == 0;
+        let valid = complete && self.checksum == checksum(short_name);
+        let chars: Vec<u16> = self
+            .parts
+            .iter()
+            .rev()
+            .flat_map(|part| part.iter().cloned())
+            .take_while(|c| *c != 0 && *c != 0xFFFF)
+            .collect();
+        self.clear();
+        if !valid {
+            return None;
+        }
+        String::from_utf16(&chars).ok()
+    }
+}
+
+/// The 8.3 name as shown when there is no long name, `case` holds the
+/// lower case bits of the entry.
+pub fn short_name_display(short_name: &ShortName, case: u8) -> String {
+    let lower = |c: u8, flag: u8| {
+        if case & flag != 0 {
+            c.to_ascii_lowercase()
+        } else {
+            c
+        }
+    };
+    let mut name = String::new();
+    for c in short_name[0..8].iter().take_while(|c| **c != b' ') {
+        name.push(lower(*c, CASE_LOWER_BASE) as char);
+    }
+    // 0x05 stands for a leading 0xE5 byte
+    if short_name[0] == 0x05 {
+        name.replace_range(0..1, "\u{e5}");
+    }
+    let ext: Vec<u8> = short_name[8..11]
+        .iter()
+        .take_while(|c| **c != b' ')
+        .map(|c| lower(*c, CASE_LOWER_EXT))
+        .collect();
+    if !ext.is_empty() {
+        name.push('.');
+        ext.iter().for_each(|c| name.push(*c as char));
+    }
+    name
+}
+
+/// Whether `name` may be stored in a long name entry.
+pub fn valid_long_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= NAME_MAX
+        && name.chars().all(|c| c >= ' ' && !"\"*/:<>?\\|".contains(c))
+}
+
+fn short_char(c: char) -> Option<u8> {
+    match c {
+        'a'..='z' => Some(c.to_ascii_uppercase() as u8),
+        'A'..='Z' | '0'..='9' => Some(c as u8),
+        '!' | '#' | '$' | '%' | '&' | '\'' | '(' | ')' | '-' | '@' | '^' | '_' | '`' | '{'
+        | '}' | '~' => Some(c as u8),
+        _ => None,
+    }
+}
+
+/// The 8.3 name `name` maps to, and whether it can be stored without
+/// a long name because nothing is lost.
+pub fn short_name_of(name: &str) -> (ShortName, bool) {
+    let mut short = [b' '; 11];
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(dot) => (&trimmed[..dot], &trimmed[dot + 1..]),
+        None => (trimmed, ""),
+    };
+    let mut lossless = trimmed.len() == name.len() && base.len() <= 8 && ext.len() <= 3;
+    let mut fill = |part: &str, range: core::ops::Range<usize>| {
+        let mut i = range.start;
+        for c in part.chars().filter(|c| *c != ' ' && *c != '.') {
+            let byte = short_char(c).unwrap_or(b'_');
+            if byte != c as u8 {
+                lossless = false;
+            }
+            if i < range.end {
+                short[i] = byte;
+                i += 1;
+            }
+        }
+    };
+    fill(base, 0..8);
+    fill(ext, 8..11);
+    if base.contains([' ', '.']) || ext.contains(' ') {
+        lossless = false;
+    }
+    if short[0] == b' ' {
+        short[0] = b'_';
+        lossless = false;
+    }
+    (short, lossless)
+}
+
+/// Replace the tail of the base name with "~n" so it differs from
+/// other 8.3 names in the directory.
+pub fn numbered_short_name(short: &ShortName, n: usize) -> ShortName {
+    let mut numbered = *short;
+    let mut tail = [0u8; 8];
+    let mut len = 0;
+    let mut rest = n;
+    while rest > 0 {
+        tail[len] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+        len += 1;
+    }
+    tail[len] = b'~';
+    len += 1;
+    let base_len = short[..8].iter().position(|c| *c == b' ').unwrap_or(8);
+    let start = base_len.min(8 - len);
+    for i in 0..len {
+        numbered[start + i] = tail[len - 1 - i];
+    }
+    numbered
+}
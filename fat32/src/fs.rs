@@ -0,0 +1,348 @@
+use super::{BlockDevice, Inode, BLOCK_SZ};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+
+type Sector = [u8; BLOCK_SZ];
+
+/// FAT entries at or above this value end a cluster chain.
+const FAT_EOC: u32 = 0x0FFF_FFF8;
+/// Value written to end a cluster chain.
+const FAT_EOC_MARK: u32 = 0x0FFF_FFFF;
+/// Only the low 28 bits of a FAT32 entry are used.
+const FAT_MASK: u32 = 0x0FFF_FFFF;
+
+const FSINFO_LEAD_SIG: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIG: u32 = 0x6141_7272;
+const FSINFO_TRAIL_SIG: u32 = 0xAA55_0000;
+/// Free cluster count meaning "unknown, count it yourself".
+const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// FAT sectors kept in memory, a chain is mostly walked inside one.
+const FAT_CACHE_SIZE: usize = 8;
+/// Cluster chains kept in memory, e.g. of a file being copied to another.
+const CHAIN_CACHE_SIZE: usize = 4;
+
+pub(crate) fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+pub(crate) fn write_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+pub struct FatFileSystem {
+    pub(crate) device: Arc<dyn BlockDevice>,
+    pub(crate) sectors_per_cluster: u32,
+    pub(crate) root_cluster: u32,
+    fat_start: u32,
+    fat_sectors: u32,
+    num_fats: u32,
+    data_start: u32,
+    cluster_count: u32,
+    fsinfo_sector: u32,
+    /// Where the search for a free cluster starts, from FSInfo at first.
+    next_free: AtomicU32,
+    /// Recently used FAT sectors by sector number, the most recent last.
+    /// Writes go through to every FAT.
+    fat_cache: Mutex<Vec<(u32, Sector)>>,
+    /// Recently walked chains, the most recent last. A chain grows when a
+    /// cluster is linked after its last one and is dropped when freed.
+    chain_cache: Mutex<Vec<Arc<Vec<u32>>>>,
+    /// Held by every operation of an `Inode`, directories and the FAT
+    /// are only changed under it.
+    pub(crate) op_lock: Mutex<()>,
+}
+
+impl FatFileSystem {
+    /// Read the boot sector, None if the device does not hold FAT32.
+    pub fn open(device: Arc<dyn BlockDevice>) -> Option<Arc<Self>> {
+        let mut boot = [0u8; BLOCK_SZ];
+        device.read_block(0, &mut boot);
+        let bytes_per_sector = read_u16(&boot, 11) as usize;
+        let sectors_per_cluster = boot[13] as u32;
+        let reserved_sectors = read_u16(&boot, 14) as u32;
+        let num_fats = boot[16] as u32;
+        let root_entry_count = read_u16(&boot, 17);
+        let total_sectors = match read_u16(&boot, 19) {
+            0 => read_u32(&boot, 32),
+            total_sectors => total_sectors as u32,
+        };
+        let fat_size_16 = read_u16(&boot, 22);
+        let fat_sectors = read_u32(&boot, 36);
+        let root_cluster = read_u32(&boot, 44);
+        let fsinfo_sector = read_u16(&boot, 48) as u32;
+        // FAT12/16 keep a fixed root directory and a 16 bit FAT size
+        if read_u16(&boot, 510) != 0xAA55
+            || bytes_per_sector != BLOCK_SZ
+            || sectors_per_cluster == 0
+            || !sectors_per_cluster.is_power_of_two()
+            || num_fats == 0
+            || root_entry_count != 0
+            || fat_size_16 != 0
+            || fat_sectors == 0
+        {
+            return None;
+        }
+        let data_start = reserved_sectors + num_fats * fat_sectors;
+        if total_sectors <= data_start {
+            return None;
+        }
+        // a FAT sector holds 128 entries, clusters 0 and 1 are reserved
+        let cluster_count = ((total_sectors - data_start) / sectors_per_cluster)
+            .min(fat_sectors * (BLOCK_SZ as u32 / 4) - 2);
+        if root_cluster < 2 || root_cluster >= cluster_count + 2 {
+            return None;
+        }
+        // the next free cluster hint of FSInfo, mkfs leaves it unknown
+        let mut next_free = 2;
+        if fsinfo_sector != 0 && fsinfo_sector != 0xFFFF {
+            let mut fsinfo = [0u8; BLOCK_SZ];
+            device.read_block(fsinfo_sector as usize, &mut fsinfo);
+            let hint = read_u32(&fsinfo, 492);
+            if read_u32(&fsinfo, 0) == FSINFO_LEAD_SIG
+                && read_u32(&fsinfo, 484) == FSINFO_STRUCT_SIG
+                && hint >= 2
+                && hint < cluster_count + 2
+            {
+                next_free = hint;
+            }
+        }
+        Some(Arc::new(Self {
+            device,
+            sectors_per_cluster,
+            root_cluster,
+            fat_start: reserved_sectors,
+            fat_sectors,
+            num_fats,
+            data_start,
+            cluster_count,
+            fsinfo_sector,
+            next_free: AtomicU32::new(next_free),
+            fat_cache: Mutex::new(Vec::new()),
+            chain_cache: Mutex::new(Vec::new()),
+            op_lock: Mutex::new(()),
+        }))
+    }
+
+    /// Write an empty FAT32 file system over the first `total_sectors`
+    /// sectors, laid out the way `mkfs.vfat -F 32` does.
+    pub fn format(device: Arc<dyn BlockDevice>, total_sectors: u32) -> Arc<Self> {
+        let reserved_sectors: u32 = 32;
+        let num_fats: u32 = 2;
+        let sectors_per_cluster: u32 = if total_sectors < 532_480 { 1 } else { 8 };
+        // large enough for every cluster the data area could hold
+        let clusters = (total_sectors - reserved_sectors) / sectors_per_cluster;
+        let fat_sectors = ((clusters + 2) * 4).div_ceil(BLOCK_SZ as u32);
+        let mut boot = [0u8; BLOCK_SZ];
+        boot[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        boot[3..11].copy_from_slice(b"EASYFAT ");
+        write_u16(&mut boot, 11, BLOCK_SZ as u16);
+        boot[13] = sectors_per_cluster as u8;
+        write_u16(&mut boot, 14, reserved_sectors as u16);
+        boot[16] = num_fats as u8;
+        boot[21] = 0xF8;
+        write_u16(&mut boot, 24, 32);
+        write_u16(&mut boot, 26, 64);
+        write_u32(&mut boot, 32, total_sectors);
+        write_u32(&mut boot, 36, fat_sectors);
+        write_u32(&mut boot, 44, 2);
+        write_u16(&mut boot, 48, 1);
+        write_u16(&mut boot, 50, 6);
+        boot[64] = 0x80;
+        boot[66] = 0x29;
+        write_u32(&mut boot, 67, 0x2024_0101);
+        boot[71..82].copy_from_slice(b"NO NAME    ");
+        boot[82..90].copy_from_slice(b"FAT32   ");
+        write_u16(&mut boot, 510, 0xAA55);
+        let mut fsinfo = [0u8; BLOCK_SZ];
+        write_u32(&mut fsinfo, 0, FSINFO_LEAD_SIG);
+        write_u32(&mut fsinfo, 484, FSINFO_STRUCT_SIG);
+        write_u32(&mut fsinfo, 488, FSINFO_UNKNOWN);
+        write_u32(&mut fsinfo, 492, FSINFO_UNKNOWN);
+        write_u32(&mut fsinfo, 508, FSINFO_TRAIL_SIG);
+        // clear reserved sectors, both FATs and the root directory
+        let zero = [0u8; BLOCK_SZ];
+        for sector in 0..reserved_sectors + num_fats * fat_sectors + sectors_per_cluster {
+            device.write_block(sector as usize, &zero);
+        }
+        for (sector, data) in [(0, &boot), (1, &fsinfo), (6, &boot), (7, &fsinfo)] {
+            device.write_block(sector, data);
+        }
+        let fs = Self::open(device).expect("formatted image is not FAT32");
+        fs.set_fat_entry(0, 0x0FFF_FFF8);
+        fs.set_fat_entry(1, FAT_EOC_MARK);
+        fs.set_fat_entry(2, FAT_EOC_MARK);
+        fs
+    }
+
+    pub fn root_inode(fs: &Arc<Self>) -> Inode {
+        Inode::root(Arc::clone(fs))
+    }
+
+    pub(crate) fn read_sector(&self, sector: u32, buf: &mut Sector) {
+        self.device.read_block(sector as usize, buf);
+    }
+
+    pub(crate) fn write_sector(&self, sector: u32, buf: &Sector) {
+        self.device.write_block(sector as usize, buf);
+    }
+
+    pub(crate) fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * BLOCK_SZ
+    }
+
+    pub(crate) fn cluster_sector(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.cluster_count + 2
+    }
+
+    fn fat_entry_pos(&self, cluster: u32) -> (u32, usize) {
+        let offset = cluster as usize * 4;
+        (
+            self.fat_start + (offset / BLOCK_SZ) as u32,
+            offset % BLOCK_SZ,
+        )
+    }
+
+    /// Call `f` with FAT sector `sector` of the first FAT, read through the
+    /// cache.
+    fn with_fat_sector<V>(&self, sector: u32, f: impl FnOnce(&mut Sector) -> V) -> V {
+        let mut cache = self.fat_cache.lock();
+        let pos = match cache.iter().position(|(cached, _)| *cached == sector) {
+            Some(pos) => pos,
+            None => {
+                if cache.len() == FAT_CACHE_SIZE {
+                    cache.remove(0);
+                }
+                let mut buf = [0u8; BLOCK_SZ];
+                self.read_sector(sector, &mut buf);
+                cache.push((sector, buf));
+                cache.len() - 1
+            }
+        };
+        let entry = cache.remove(pos);
+        cache.push(entry);
+        f(&mut cache.last_mut().unwrap().1)
+    }
+
+    fn fat_entry(&self, cluster: u32) -> u32 {
+        let (sector, offset) = self.fat_entry_pos(cluster);
+        self.with_fat_sector(sector, |buf| read_u32(buf, offset) & FAT_MASK)
+    }
+
+    /// Update every copy of the FAT, the top 4 bits are kept.
+    fn set_fat_entry(&self, cluster: u32, value: u32) {
+        let (sector, offset) = self.fat_entry_pos(cluster);
+        let buf = self.with_fat_sector(sector, |buf| {
+            let old = read_u32(buf, offset);
+            write_u32(buf, offset, (old & !FAT_MASK) | (value & FAT_MASK));
+            *buf
+        });
+        for i in 0..self.num_fats {
+            self.write_sector(sector + i * self.fat_sectors, &buf);
+        }
+    }
+
+    /// Clusters of the chain starting at `first`, a broken chain is cut
+    /// at the first bad link.
+    pub(crate) fn chain(&self, first: u32) -> Arc<Vec<u32>> {
+        let mut cache = self.chain_cache.lock();
+        if let Some(pos) = cache.iter().position(|chain| chain[0] == first) {
+            let chain = cache.remove(pos);
+            cache.push(Arc::clone(&chain));
+            return chain;
+        }
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while self.is_valid_cluster(cluster) && clusters.len() < self.cluster_count as usize {
+            clusters.push(cluster);
+            let next = self.fat_entry(cluster);
+            if next >= FAT_EOC {
+                break;
+            }
+            cluster = next;
+        }
+        let chain = Arc::new(clusters);
+        if !chain.is_empty() {
+            if cache.len() == CHAIN_CACHE_SIZE {
+                cache.remove(0);
+            }
+            cache.push(Arc::clone(&chain));
+        }
+        chain
+    }
+
+    /// Take a free cluster and link it after `prev`, None when the disk is full.
+    /// Directory clusters must be zeroed, file clusters are written by the caller.
+    pub(crate) fn alloc_cluster(&self, prev: Option<u32>, zero: bool) -> Option<u32> {
+        let start = self.next_free.load(Ordering::Relaxed);
+        let cluster = (0..self.cluster_count)
+            .map(|i| 2 + (start - 2 + i) % self.cluster_count)
+            .find(|cluster| self.fat_entry(*cluster) == 0)?;
+        self.next_free.store(cluster + 1, Ordering::Relaxed);
+        if self.next_free.load(Ordering::Relaxed) >= self.cluster_count + 2 {
+            self.next_free.store(2, Ordering::Relaxed);
+        }
+        self.set_fat_entry(cluster, FAT_EOC_MARK);
+        if let Some(prev) = prev {
+            self.set_fat_entry(prev, cluster);
+            for chain in self.chain_cache.lock().iter_mut() {
+                if chain.last() == Some(&prev) {
+                    Arc::make_mut(chain).push(cluster);
+                }
+            }
+        }
+        if zero {
+            let buf = [0u8; BLOCK_SZ];
+            let sector = self.cluster_sector(cluster);
+            for i in 0..self.sectors_per_cluster {
+                self.write_sector(sector + i, &buf);
+            }
+        }
+        self.invalidate_fsinfo();
+        Some(cluster)
+    }
+
+    /// Release every cluster of the chain starting at `first`.
+    pub(crate) fn free_chain(&self, first: u32) {
+        let chain = self.chain(first);
+        self.chain_cache.lock().retain(|cached| cached[0] != first);
+        for cluster in chain.iter() {
+            self.set_fat_entry(*cluster, 0);
+        }
+        self.invalidate_fsinfo();
+    }
+
+    /// The free count in FSInfo is only a hint, mark it unknown instead of
+    /// keeping it up to date.
+    fn invalidate_fsinfo(&self) {
+        if self.fsinfo_sector == 0 || self.fsinfo_sector == 0xFFFF {
+            return;
+        }
+        let mut buf = [0u8; BLOCK_SZ];
+        self.read_sector(self.fsinfo_sector, &mut buf);
+        if read_u32(&buf, 0) != FSINFO_LEAD_SIG || read_u32(&buf, 488) == FSINFO_UNKNOWN {
+            return;
+        }
+        write_u32(&mut buf, 488, FSINFO_UNKNOWN);
+        self.write_sector(self.fsinfo_sector, &buf);
+    }
+}
@@ -0,0 +1,460 @@
+use super::dir::{
+    entry_attr, entry_case, entry_cluster, entry_size, is_long_name, lfn_entries,
+    numbered_short_name, set_entry_cluster, set_entry_size, short_entry, short_name_display,
+    short_name_of, valid_long_name, DirEntry, LfnBuilder, ShortName, ATTR_ARCHIVE, ATTR_DIRECTORY,
+    ATTR_VOLUME_ID, DIR_ENTRY_SZ, ENTRY_END, ENTRY_FREE,
+};
+use super::{FatFileSystem, BLOCK_SZ};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+const ENTRIES_PER_SECTOR: usize = BLOCK_SZ / DIR_ENTRY_SZ;
+
+/// A file or directory. Size and first cluster are read from the directory
+/// entry on every access, the clusters come from the chains cached by the
+/// file system.
+pub struct Inode {
+    fs: Arc<FatFileSystem>,
+    /// Sector and byte offset of the 8.3 entry, None for the root directory.
+    entry: Option<(u32, usize)>,
+    is_dir: bool,
+}
+
+impl Inode {
+    pub(crate) fn root(fs: Arc<FatFileSystem>) -> Self {
+        Self {
+            fs,
+            entry: None,
+            is_dir: true,
+        }
+    }
+
+    fn child(&self, entry: (u32, usize), is_dir: bool) -> Arc<Inode> {
+        Arc::new(Self {
+            fs: self.fs.clone(),
+            entry: Some(entry),
+            is_dir,
+        })
+    }
+
+    fn read_entry(&self) -> Option<[u8; DIR_ENTRY_SZ]> {
+        let (sector, offset) = self.entry?;
+        let mut buf = [0u8; BLOCK_SZ];
+        self.fs.read_sector(sector, &mut buf);
+        let mut raw = [0u8; DIR_ENTRY_SZ];
+        raw.copy_from_slice(&buf[offset..offset + DIR_ENTRY_SZ]);
+        Some(raw)
+    }
+
+    fn modify_entry(&self, f: impl FnOnce(&mut [u8])) {
+        if let Some((sector, offset)) = self.entry {
+            let mut buf = [0u8; BLOCK_SZ];
+            self.fs.read_sector(sector, &mut buf);
+            f(&mut buf[offset..offset + DIR_ENTRY_SZ]);
+            self.fs.write_sector(sector, &buf);
+        }
+    }
+
+    fn first_cluster(&self) -> u32 {
+        match self.read_entry() {
+            Some(raw) => entry_cluster(&raw),
+            None => self.fs.root_cluster,
+        }
+    }
+
+    /// Position in the image of directory slot `slot`.
+    fn slot_pos(&self, chain: &[u32], slot: usize) -> (u32, usize) {
+        let per_cluster = self.fs.sectors_per_cluster as usize * ENTRIES_PER_SECTOR;
+        let cluster = chain[slot / per_cluster];
+        let index = slot % per_cluster;
+        (
+            self.fs.cluster_sector(cluster) + (index / ENTRIES_PER_SECTOR) as u32,
+            index % ENTRIES_PER_SECTOR * DIR_ENTRY_SZ,
+        )
+    }
+
+    /// Call `f` with the sector, the range inside it and the range of the
+    /// caller's buffer for every sector of bytes `range` of the file.
+    fn for_each_sector(
+        &self,
+        chain: &[u32],
+        range: Range<usize>,
+        mut f: impl FnMut(u32, Range<usize>, Range<usize>),
+    ) {
+        let cluster_size = self.fs.cluster_size();
+        let mut pos = range.start;
+        while pos < range.end {
+            let in_cluster = pos % cluster_size;
+            let sector =
+                self.fs.cluster_sector(chain[pos / cluster_size]) + (in_cluster / BLOCK_SZ) as u32;
+            let offset = pos % BLOCK_SZ;
+            let len = (BLOCK_SZ - offset).min(range.end - pos);
+            let buf_start = pos - range.start;
+            f(sector, offset..offset + len, buf_start..buf_start + len);
+            pos += len;
+        }
+    }
+
+    fn write_bytes(&self, chain: &[u32], offset: usize, data: &[u8]) {
+        let mut buf = [0u8; BLOCK_SZ];
+        self.for_each_sector(
+            chain,
+            offset..offset + data.len(),
+            |sector, in_sector, in_data| {
+                if in_sector.len() < BLOCK_SZ {
+                    self.fs.read_sector(sector, &mut buf);
+                }
+                buf[in_sector].copy_from_slice(&data[in_data]);
+                self.fs.write_sector(sector, &buf);
+            },
+        );
+    }
+
+    fn write_slot(&self, chain: &[u32], slot: usize, raw: &[u8]) {
+        let (sector, offset) = self.slot_pos(chain, slot);
+        let mut buf = [0u8; BLOCK_SZ];
+        self.fs.read_sector(sector, &mut buf);
+        buf[offset..offset + raw.len()].copy_from_slice(raw);
+        self.fs.write_sector(sector, &buf);
+    }
+
+    /// Every used slot of this directory, long names joined to their
+    /// 8.3 entries. Volume labels are skipped.
+    fn entries(&self) -> Vec<DirEntry> {
+        let chain = self.fs.chain(self.first_cluster());
+        let mut entries = Vec::new();
+        let mut lfn = LfnBuilder::default();
+        let mut buf = [0u8; BLOCK_SZ];
+        let mut slot = 0;
+        for &cluster in chain.iter() {
+            let first_sector = self.fs.cluster_sector(cluster);
+            for sector in first_sector..first_sector + self.fs.sectors_per_cluster {
+                self.fs.read_sector(sector, &mut buf);
+                for (i, raw) in buf.chunks(DIR_ENTRY_SZ).enumerate() {
+                    slot += 1;
+                    match raw[0] {
+                        ENTRY_END => return entries,
+                        ENTRY_FREE => {
+                            lfn.clear();
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    if is_long_name(raw) {
+                        lfn.push(raw);
+                        continue;
+                    }
+                    let mut short_name: ShortName = [0u8; 11];
+                    short_name.copy_from_slice(&raw[0..11]);
+                    let lfn_slots = lfn.len();
+                    let long_name = lfn.take(&short_name);
+                    if entry_attr(raw) & ATTR_VOLUME_ID != 0 {
+                        continue;
+                    }
+                    let (name, slots) = match long_name {
+                        Some(name) => (name, lfn_slots + 1),
+                        None => (short_name_display(&short_name, entry_case(raw)), 1),
+                    };
+                    entries.push(DirEntry {
+                        name,
+                        short_name,
+                        attr: entry_attr(raw),
+                        first_cluster: entry_cluster(raw),
+                        slot: slot - 1,
+                        pos: (sector, i * DIR_ENTRY_SZ),
+                        slots,
+                    });
+                }
+            }
+        }
+        entries
+    }
+
+    /// Names are compared ignoring ASCII case, against the long and the 8.3 name.
+    fn matches(entry: &DirEntry, name: &str) -> bool {
+        !entry.is_dot()
+            && (entry.name.eq_ignore_ascii_case(name)
+                || short_name_display(&entry.short_name, 0).eq_ignore_ascii_case(name))
+    }
+
+    fn find_entry(&self, name: &str) -> Option<DirEntry> {
+        self.entries()
+            .into_iter()
+            .find(|entry| Self::matches(entry, name))
+    }
+
+    /// Find `count` consecutive free slots, growing the directory when there
+    /// are none. Return the clusters of the directory and the first slot.
+    fn free_slots(&self, count: usize) -> Option<(Vec<u32>, usize)> {
+        let mut chain = self.fs.chain(self.first_cluster()).to_vec();
+        let per_cluster = self.fs.sectors_per_cluster as usize * ENTRIES_PER_SECTOR;
+        let mut buf = [0u8; BLOCK_SZ];
+        let mut slot = 0;
+        let mut run_start = 0;
+        let mut run = 0;
+        'scan: for cluster in chain.iter() {
+            let first_sector = self.fs.cluster_sector(*cluster);
+            for sector in first_sector..first_sector + self.fs.sectors_per_cluster {
+                self.fs.read_sector(sector, &mut buf);
+                for raw in buf.chunks(DIR_ENTRY_SZ) {
+                    match raw[0] {
+                        // every slot after the end marker is free
+                        ENTRY_END => {
+                            if run == 0 {
+                                run_start = slot;
+                            }
+                            run = chain.len() * per_cluster - run_start;
+                            break 'scan;
+                        }
+                        ENTRY_FREE => {
+                            if run == 0 {
+                                run_start = slot;
+                            }
+                            run += 1;
+                            if run == count {
+                                break 'scan;
+                            }
+                        }
+                        _ => run = 0,
+                    }
+                    slot += 1;
+                }
+            }
+        }
+        if run == 0 {
+            run_start = chain.len() * per_cluster;
+        }
+        while chain.len() * per_cluster < run_start + count {
+            let cluster = self.fs.alloc_cluster(chain.last().copied(), true)?;
+            chain.push(cluster);
+        }
+        Some((chain, run_start))
+    }
+
+    fn create_inode(&self, name: &str, is_dir: bool) -> Option<Arc<Inode>> {
+        let _op = self.fs.op_lock.lock();
+        if !self.is_dir || !valid_long_name(name) {
+            return None;
+        }
+        let entries = self.entries();
+        if entries.iter().any(|entry| Self::matches(entry, name)) {
+            return None;
+        }
+        // a long name is only stored when the 8.3 name loses something
+        let (mut short_name, lossless) = short_name_of(name);
+        let taken = |short: &ShortName| entries.iter().any(|entry| entry.short_name == *short);
+        let mut raws = Vec::new();
+        if !lossless || taken(&short_name) {
+            short_name = (1..1_000_000)
+                .map(|n| numbered_short_name(&short_name, n))
+                .find(|short| !taken(short))?;
+            raws = lfn_entries(name, &short_name);
+        }
+        let (attr, cluster) = if is_dir {
+            (ATTR_DIRECTORY, self.fs.alloc_cluster(None, true)?)
+        } else {
+            (ATTR_ARCHIVE, 0)
+        };
+        raws.push(short_entry(&short_name, attr, cluster));
+        let (chain, start) = match self.free_slots(raws.len()) {
+            Some(found) => found,
+            None => {
+                self.fs.free_chain(cluster);
+                return None;
+            }
+        };
+        if is_dir {
+            // ".." of a directory in the root holds cluster 0
+            let parent = match self.entry {
+                Some(_) => self.first_cluster(),
+                None => 0,
+            };
+            let mut buf = [0u8; BLOCK_SZ];
+            buf[..DIR_ENTRY_SZ].copy_from_slice(&short_entry(
+                b".          ",
+                ATTR_DIRECTORY,
+                cluster,
+            ));
+            buf[DIR_ENTRY_SZ..2 * DIR_ENTRY_SZ].copy_from_slice(&short_entry(
+                b"..         ",
+                ATTR_DIRECTORY,
+                parent,
+            ));
+            self.fs.write_sector(self.fs.cluster_sector(cluster), &buf);
+        }
+        for (i, raw) in raws.iter().enumerate() {
+            self.write_slot(&chain, start + i, raw);
+        }
+        Some(self.child(self.slot_pos(&chain, start + raws.len() - 1), is_dir))
+    }
+
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        let _op = self.fs.op_lock.lock();
+        if !self.is_dir {
+            return None;
+        }
+        self.find_entry(name)
+            .map(|entry| self.child(entry.pos, entry.is_dir()))
+    }
+
+    /// Derived from where the 8.3 entry is, the root directory is 1.
+    pub fn inode_id(&self) -> u32 {
+        match self.entry {
+            Some((sector, offset)) => sector
+                .wrapping_mul(ENTRIES_PER_SECTOR as u32)
+                .wrapping_add((offset / DIR_ENTRY_SZ) as u32),
+            None => 1,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// Directories always have size 0.
+    pub fn size(&self) -> u32 {
+        let _op = self.fs.op_lock.lock();
+        self.read_entry().map_or(0, |raw| entry_size(&raw))
+    }
+
+    /// Return (name, inode) of every entry in this directory but "." and "..".
+    pub fn read_dir(&self) -> Vec<(String, Arc<Inode>)> {
+        let _op = self.fs.op_lock.lock();
+        if !self.is_dir {
+            return Vec::new();
+        }
+        self.entries()
+            .into_iter()
+            .filter(|entry| !entry.is_dot())
+            .map(|entry| {
+                let inode = self.child(entry.pos, entry.is_dir());
+                (entry.name, inode)
+            })
+            .collect()
+    }
+
+    pub fn ls(&self) -> Vec<String> {
+        self.read_dir().into_iter().map(|(name, _)| name).collect()
+    }
+
+    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, false)
+    }
+
+    pub fn create_dir(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, true)
+    }
+
+    /// Remove the entry `name` and free its clusters, a directory must be
+    /// empty. Return false if it cannot be removed.
+    pub fn unlink(&self, name: &str) -> bool {
+        let _op = self.fs.op_lock.lock();
+        if !self.is_dir {
+            return false;
+        }
+        let entry = match self.find_entry(name) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if entry.is_dir()
+            && self
+                .child(entry.pos, true)
+                .entries()
+                .iter()
+                .any(|child| !child.is_dot())
+        {
+            return false;
+        }
+        let chain = self.fs.chain(self.first_cluster());
+        for slot in entry.slot + 1 - entry.slots..=entry.slot {
+            self.write_slot(&chain, slot, &[ENTRY_FREE]);
+        }
+        self.fs.free_chain(entry.first_cluster);
+        true
+    }
+
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let _op = self.fs.op_lock.lock();
+        let raw = match self.read_entry() {
+            Some(raw) if !self.is_dir => raw,
+            _ => return 0,
+        };
+        let chain = self.fs.chain(entry_cluster(&raw));
+        // a chain shorter than the size is cut there
+        let end = (entry_size(&raw) as usize)
+            .min(offset + buf.len())
+            .min(chain.len() * self.fs.cluster_size());
+        if offset >= end {
+            return 0;
+        }
+        let mut sector_buf = [0u8; BLOCK_SZ];
+        self.for_each_sector(&chain, offset..end, |sector, in_sector, in_buf| {
+            self.fs.read_sector(sector, &mut sector_buf);
+            buf[in_buf].copy_from_slice(&sector_buf[in_sector]);
+        });
+        end - offset
+    }
+
+    /// Grow the file as needed, a short count means the disk is full.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let _op = self.fs.op_lock.lock();
+        let raw = match self.read_entry() {
+            Some(raw) if !self.is_dir && !buf.is_empty() => raw,
+            _ => return 0,
+        };
+        let size = entry_size(&raw) as usize;
+        let cluster_size = self.fs.cluster_size();
+        // sizes are 32 bit
+        let end = (offset + buf.len()).min(u32::MAX as usize);
+        // clusters linked after the last one join the cached chain
+        let (mut first_cluster, mut last, mut clusters) = {
+            let chain = self.fs.chain(entry_cluster(&raw));
+            (chain.first().copied(), chain.last().copied(), chain.len())
+        };
+        while clusters * cluster_size < end {
+            match self.fs.alloc_cluster(last, false) {
+                Some(cluster) => {
+                    first_cluster = first_cluster.or(Some(cluster));
+                    last = Some(cluster);
+                    clusters += 1;
+                }
+                None => break,
+            }
+        }
+        let first_cluster = first_cluster.unwrap_or(0);
+        let chain = self.fs.chain(first_cluster);
+        let end = end.min(chain.len() * cluster_size);
+        // a hole before the write reads back as zeros
+        let zeros = [0u8; BLOCK_SZ];
+        let mut pos = size;
+        while pos < offset.min(end) {
+            let len = BLOCK_SZ.min(offset.min(end) - pos);
+            self.write_bytes(&chain, pos, &zeros[..len]);
+            pos += len;
+        }
+        let written = end.saturating_sub(offset);
+        self.write_bytes(&chain, offset, &buf[..written]);
+        self.modify_entry(|raw| {
+            set_entry_cluster(raw, first_cluster);
+            set_entry_size(raw, size.max(end) as u32);
+        });
+        written
+    }
+
+    /// Drop all data of a file.
+    pub fn clear(&self) {
+        let _op = self.fs.op_lock.lock();
+        if self.is_dir {
+            return;
+        }
+        if let Some(raw) = self.read_entry() {
+            self.fs.free_chain(entry_cluster(&raw));
+            self.modify_entry(|raw| {
+                set_entry_cluster(raw, 0);
+                set_entry_size(raw, 0);
+            });
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! FAT32 driver on top of the `BlockDevice` trait of easy-fs
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+mod dir;
+mod fs;
+mod inode;
+
+pub use easy_fs::{BlockDevice, BLOCK_SZ};
+pub use fs::FatFileSystem;
+pub use inode::Inode;
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockDevice, FatFileSystem, BLOCK_SZ};
+    use std::fs::{create_dir_all, File, OpenOptions};
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::process::Command;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Image file on the host, counting the blocks read.
+    struct BlockFile {
+        file: Mutex<File>,
+        reads: AtomicUsize,
+    }
+
+    impl BlockFile {
+        fn new(file: File) -> Self {
+            Self {
+                file: Mutex::new(file),
+                reads: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl BlockDevice for BlockFile {
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
+                .expect("Error when seeking!");
+            file.read_exact(buf).expect("Not a complete block!");
+        }
+
+        fn write_block(&self, block_id: usize, buf: &[u8]) {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
+                .expect("Error when seeking!");
+            file.write_all(buf).expect("Not a complete block!");
+        }
+
+        fn handle_irq(&self) {
+            unimplemented!();
+        }
+    }
+
+    /// Run the FAT32 driver against an image made by `mkfs.vfat -F 32` if the
+    /// host has it, otherwise against one formatted by the driver itself.
+    #[test]
+    fn fat32_test() -> std::io::Result<()> {
+        create_dir_all("target")?;
+        let path = "target/fat32.img";
+        let _ = std::fs::remove_file(path);
+        // 64 MiB, enough clusters for mkfs.vfat to accept FAT32
+        let made_by_mkfs = Command::new("mkfs.vfat")
+            .args(["-F", "32", "-C", path, "65536"])
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false);
+        let block_file: Arc<dyn BlockDevice> = Arc::new(BlockFile::new({
+            let f = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(!made_by_mkfs)
+                .open(path)?;
+            f.set_len(65536 * 1024).unwrap();
+            f
+        }));
+        if !made_by_mkfs {
+            FatFileSystem::format(block_file.clone(), 131072);
+        }
+        let fs = FatFileSystem::open(block_file.clone()).expect("not a FAT32 image");
+        let root_inode = FatFileSystem::root_inode(&fs);
+
+        // long and 8.3 names, looked up ignoring case
+        let long_name = "A rather long file name.text";
+        let file = root_inode.create(long_name).unwrap();
+        assert!(root_inode.create("README.TXT").is_some());
+        assert!(root_inode.create("readme.txt").is_none());
+        assert!(root_inode.find("a RATHER long FILE name.TEXT").is_some());
+        assert!(root_inode.create(&"n".repeat(255)).is_some());
+        assert!(root_inode.create(&"n".repeat(256)).is_none());
+        assert!(root_inode.create("bad:name").is_none());
+
+        let data: Vec<u8> = (0..300 * BLOCK_SZ + 7).map(|i| (i * 7) as u8).collect();
+        assert_eq!(file.write_at(0, &data), data.len());
+        let mut buffer = vec![0u8; data.len() + 100];
+        assert_eq!(file.read_at(0, &mut buffer), data.len());
+        assert_eq!(&buffer[..data.len()], &data[..]);
+        // writing past the end leaves zeros in between
+        let hole_end = data.len() + 3 * BLOCK_SZ;
+        file.write_at(hole_end, b"tail");
+        assert_eq!(file.size() as usize, hole_end + 4);
+        let mut hole = vec![1u8; 3 * BLOCK_SZ];
+        file.read_at(data.len(), &mut hole);
+        assert!(hole.iter().all(|b| *b == 0));
+        file.clear();
+        assert_eq!(file.read_at(0, &mut buffer), 0);
+        file.write_at(0, &data);
+
+        // directories, and a root growing past its first cluster
+        let sub = root_inode.create_dir("sub dir").unwrap();
+        let nested = sub.create("nested file").unwrap();
+        nested.write_at(0, b"nested");
+        for i in 0..40 {
+            assert!(root_inode.create(&format!("file number {}", i)).is_some());
+        }
+        assert_eq!(root_inode.ls().len(), 2 + 1 + 1 + 40);
+        assert!(!root_inode.unlink("sub dir"));
+        assert!(sub.unlink("nested file"));
+        assert!(root_inode.unlink("sub dir"));
+        assert!(root_inode.unlink("file number 7"));
+        assert!(root_inode.find("file number 7").is_none());
+        assert!(root_inode.create("file number 40").is_some());
+
+        // everything is on disk when the image is opened again
+        let fs = FatFileSystem::open(block_file).unwrap();
+        let root_inode = FatFileSystem::root_inode(&fs);
+        let file = root_inode.find(long_name).unwrap();
+        assert_eq!(file.read_at(0, &mut buffer), data.len());
+        assert_eq!(&buffer[..data.len()], &data[..]);
+        assert!(root_inode.find("sub dir").is_none());
+        assert_eq!(root_inode.ls().len(), 2 + 1 + 40);
+
+        // reading sector by sector does not walk the FAT again every time, a
+        // sector of the file costs its own read and one of the entry
+        let recorder = Arc::new(BlockFile::new(
+            OpenOptions::new().read(true).write(true).open(path)?,
+        ));
+        let fs = FatFileSystem::open(recorder.clone()).unwrap();
+        let file = FatFileSystem::root_inode(&fs).find(long_name).unwrap();
+        let reads_before = recorder.reads.load(Ordering::SeqCst);
+        for (i, chunk) in data.chunks(BLOCK_SZ).enumerate() {
+            let mut sector = [0u8; BLOCK_SZ];
+            assert_eq!(file.read_at(i * BLOCK_SZ, &mut sector), chunk.len());
+            assert_eq!(&sector[..chunk.len()], chunk);
+        }
+        let reads = recorder.reads.load(Ordering::SeqCst) - reads_before;
+        assert!(
+            reads <= 2 * data.len().div_ceil(BLOCK_SZ) + 16,
+            "{} reads",
+            reads
+        );
+        // the host tools agree with the driver
+        if let Ok(output) = Command::new("fsck.vfat").args(["-n", path]).output() {
+            assert!(output.status.success(), "{:?}", output);
+        }
+        Ok(())
+    }
+}
@@ -4,6 +4,9 @@ use crate::drivers::*;
 use crate::drivers::chardev::{CharDevice, UART};
 use crate::config::set_mmio_uart_ready;
 use crate::println;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::string::String;
+use alloc::vec::Vec;
 
 //qemu clock frequency; which means ticks in a second
 pub const CLOCK_FREQ : usize = 12500000;
@@ -12,6 +15,10 @@ pub const AVALIABLE_FRAMES_END : usize = 0x83600000;
 //DMA area: 10MB: 0x83600000 ~ 0x84000000
 //avaliable memrory(64MB):0x80000000 ~ 0x84000000
 pub const AVALIABLE_MEMORY_END : usize = 0x84000000;
+//initrd(64MB): 0x84000000 ~ 0x88000000, the rest of the 128MB of qemu;
+//the loader writes the image size in the first page and the image after it
+pub const INITRD_START : usize = 0x84000000;
+pub const INITRD_END : usize = 0x88000000;
 pub const VIRT_PLIC: usize = 0xC00_0000;
 pub const VIRT_UART: usize = 0x1000_0000;
 //memory-mapped input/output devices
@@ -33,12 +40,14 @@ pub fn init_plic()
     let machine = IntrTargetPriority::Machine;
     plic.set_threshold(hart_id, supervisor, 0);
     plic.set_threshold(hart_id, machine, 1);
-    //irq nums: 5 keyboard, 6 mouse, 8 block, 10 uart
-    //for intr_src_id in [5usize, 6, 8, 10] {
-    for intr_src_id in [8, 10] {
+    //irq nums: 1-8 virtio slots with disks among them, 5 keyboard,
+    //6 mouse, 10 uart; keyboard and mouse are not enabled
+    for (intr_src_id, _) in block_irqs() {
         plic.enable(hart_id, supervisor, intr_src_id);
         plic.set_priority(intr_src_id, 1);
     }
+    plic.enable(hart_id, supervisor, 10);
+    plic.set_priority(10, 1);
     unsafe {
         sie::set_sext();
     }
@@ -62,19 +71,53 @@ pub fn init_qemu_devices()
     //init mouse
     let _mouse = MOUSE_DEVICE.clone();
     println!("mouse init done");
+    //probe the disks, their partitions and the initrd
+    for device in block_devices() {
+        println!("block device {}: {} blocks", device.name, device.blocks);
+    }
     //init plic and enable all interrupts
     init_plic();
 }
 
+//PLIC sources in use and the devices behind them, disks are found by probing
+const IRQ_SOURCES: &[(usize, &str)] = &[
+    (5, "virtio-keyboard"),
+    (6, "virtio-mouse"),
+    (10, "uart"),
+];
+
+//every source in use with its device, in order
+pub fn irq_sources() -> Vec<(usize, String)> {
+    let mut sources = block_irqs();
+    sources.extend(IRQ_SOURCES.iter().map(|(intr_src_id, name)| (*intr_src_id, String::from(*name))));
+    sources.sort_by_key(|(intr_src_id, _)| *intr_src_id);
+    sources
+}
+const MAX_IRQ_SOURCE : usize = 16;
+#[allow(clippy::declare_interior_mutable_const)]
+const IRQ_COUNT_ZERO : AtomicUsize = AtomicUsize::new(0);
+static IRQ_COUNTS: [AtomicUsize; MAX_IRQ_SOURCE] = [IRQ_COUNT_ZERO; MAX_IRQ_SOURCE];
+
+//how often each source in irq_sources() has fired
+pub fn irq_count(intr_src_id: usize) -> usize {
+    IRQ_COUNTS[intr_src_id].load(Ordering::Relaxed)
+}
+
 pub fn irq_handler() {
     let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
     let intr_src_id = plic.claim(0, IntrTargetPriority::Supervisor);
+    if intr_src_id < MAX_IRQ_SOURCE {
+        IRQ_COUNTS[intr_src_id].fetch_add(1, Ordering::Relaxed);
+    }
     match intr_src_id {
         5 => KEYBOARD_DEVICE.handle_irq(),
         6 => MOUSE_DEVICE.handle_irq(),
-        8 => BLOCK_DEVICE.handle_irq(),
         10 => UART.handle_irq(),
-        _ => panic!("unsupported IRQ {}", intr_src_id),
+        _ => {
+            if !handle_block_irq(intr_src_id) {
+                panic!("unsupported IRQ {}", intr_src_id);
+            }
+        }
     }
     plic.complete(0, IntrTargetPriority::Supervisor, intr_src_id);
 }
\ No newline at end of file
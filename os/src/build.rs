@@ -4,6 +4,7 @@ use std::io::{Result, Write};
 fn main() {
     println!("cargo:rerun-if-changed=../user/src/");
     println!("cargo:rerun-if-changed={}", TARGET_PATH);
+    println!("cargo:rerun-if-env-changed=ROOT_DEVICE");
     insert_app_data().unwrap();
 }
 
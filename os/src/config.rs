@@ -5,15 +5,30 @@ pub const KERNEL_STACK_SIZE : usize = 2*4096;
 pub const SCHEDUL_INTERVAL : usize = 10;
 //system heap size, 10M should be enough?
 pub const KERNEL_HEAP_SIZE : usize = 0xA00000;
+//part of the free kernel heap given to the block cache (1/N)
+pub const BLOCK_CACHE_HEAP_SHARE : usize = 8;
+//part of all frames the RAM disks made at run time may take together (1/N)
+pub const RAM_DISK_FRAME_SHARE : usize = 4;
+//time interval(unit: ms) to write dirty blocks back to disk
+pub const BLOCK_FLUSH_INTERVAL : usize = 1000;
+//block device holding the easy-fs root, a disk like "vda", a partition
+//like "vda2" or the initrd "ram0"; set by ROOT_DEVICE when building, the
+//other devices can be mounted by name
+pub const ROOT_DEVICE : &str = match option_env!("ROOT_DEVICE") {
+    Some(device) => device,
+    None => "vda",
+};
 //system page size, fixed to 4096(4K)
 pub const KERNEL_PAGE_SIZE : usize = 4096;
 pub const KERNEL_PAGE_WIDTH_BITS : usize = 12;
 
-pub use crate::board::{CLOCK_FREQ, AVALIABLE_FRAMES_END, AVALIABLE_MEMORY_END, MMIO};
+pub use crate::board::{CLOCK_FREQ, AVALIABLE_FRAMES_END, AVALIABLE_MEMORY_END, INITRD_START, INITRD_END, MMIO};
 
 //Dynamic configs for ALL OS
 pub struct DynamicConfigs{
-    //non blocking file operations
+    //block devices sleep until the completion interrupt instead of
+    //spinning, only once tasks are scheduled; whether reads and writes of
+    //a file may wait is the NONBLOCK flag of every open file
     pub nb_file_ops : bool,
     //if MMIO UART is ready,
     pub uart_ready : bool,
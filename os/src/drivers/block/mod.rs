@@ -1,11 +1,160 @@
+mod partition;
+mod ramdisk;
 mod virtio_blk;
 
+pub use partition::Partition;
+pub use ramdisk::RamDisk;
 pub use virtio_blk::VirtIOBlock;
+use crate::config::{INITRD_END, INITRD_START, KERNEL_PAGE_SIZE, RAM_DISK_FRAME_SHARE};
 use crate::drivers::BlockDeviceImpl;
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
-use easy_fs::BlockDevice;
+use alloc::vec::Vec;
+use core::ptr::read_volatile;
+use easy_fs::{BlockDevice, BLOCK_SZ};
 use lazy_static::*;
+use partition::read_partitions;
+use spin::Mutex;
+use crate::mm::frame_allocator::frame_stats;
+
+//virtio-mmio slots of the QEMU virt machine, slot i raises PLIC source i + 1
+const VIRTIO_MMIO_BASE: usize = 0x10001000;
+const VIRTIO_MMIO_SIZE: usize = 0x1000;
+const VIRTIO_MMIO_SLOTS: usize = 8;
+const VIRTIO_MAGIC: u32 = 0x74726976;
+const VIRTIO_ID_BLOCK: u32 = 2;
+//the capacity in 512 byte sectors leads the config of a virtio-blk device
+const VIRTIO_BLK_CAPACITY: usize = 0x100;
+
+///A disk or a partition of one
+#[derive(Clone)]
+pub struct NamedBlockDevice {
+    pub name: String,
+    pub device: Arc<dyn BlockDevice>,
+    pub blocks: usize,
+}
 
 lazy_static! {
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
-}
\ No newline at end of file
+    ///Every virtio-blk disk with the PLIC source it interrupts on
+    static ref DISKS: Vec<(usize, Arc<dyn BlockDevice>)> = probe_disks();
+    ///The disks named vda, vdb.. in the order QEMU was given them, each
+    ///followed by its partitions vda1, vda2.., then the RAM disks ram0,
+    ///ram1.. with the initrd first and its partitions ram0p1..
+    static ref BLOCK_DEVICES: Mutex<Vec<NamedBlockDevice>> = Mutex::new(name_block_devices());
+}
+
+///Frames held by the RAM disks made at run time, they are never given back
+static RAM_DISK_FRAMES: Mutex<usize> = Mutex::new(0);
+
+//QEMU gives the first device on its command line the last slot
+fn probe_disks() -> Vec<(usize, Arc<dyn BlockDevice>)> {
+    let mut disks = Vec::new();
+    for slot in (0..VIRTIO_MMIO_SLOTS).rev() {
+        let base = VIRTIO_MMIO_BASE + slot * VIRTIO_MMIO_SIZE;
+        //only look at what a virtio-blk device is, others keep their state
+        let (magic, device_id) = unsafe {
+            (read_volatile(base as *const u32), read_volatile((base + 8) as *const u32))
+        };
+        if magic != VIRTIO_MAGIC || device_id != VIRTIO_ID_BLOCK {
+            continue;
+        }
+        if let Some(disk) = BlockDeviceImpl::probe(base) {
+            disks.push((slot + 1, Arc::new(disk) as Arc<dyn BlockDevice>));
+        }
+    }
+    disks
+}
+
+fn disk_name(index: usize) -> String {
+    format!("vd{}", (b'a' + index as u8) as char)
+}
+
+//a disk followed by the partitions in its table, named like vda1 or,
+//after a digit, ram0p1
+fn push_disk(devices: &mut Vec<NamedBlockDevice>, name: String, disk: Arc<dyn BlockDevice>, blocks: usize) {
+    let partitions = read_partitions(&disk, blocks);
+    let separator = if name.ends_with(|c: char| c.is_ascii_digit()) { "p" } else { "" };
+    devices.push(NamedBlockDevice { name: name.clone(), device: disk.clone(), blocks });
+    for (number, start, len) in partitions {
+        devices.push(NamedBlockDevice {
+            name: format!("{}{}{}", name, separator, number),
+            device: Arc::new(Partition::new(disk.clone(), start, len)),
+            blocks: len,
+        });
+    }
+}
+
+fn name_block_devices() -> Vec<NamedBlockDevice> {
+    let mut devices = Vec::new();
+    for (i, (irq, disk)) in DISKS.iter().enumerate() {
+        let base = VIRTIO_MMIO_BASE + (irq - 1) * VIRTIO_MMIO_SIZE;
+        let blocks = unsafe { read_volatile((base + VIRTIO_BLK_CAPACITY) as *const u64) } as usize;
+        push_disk(&mut devices, disk_name(i), disk.clone(), blocks);
+    }
+    //without an initrd the size stays zero like the rest of the memory
+    let initrd_size = unsafe { read_volatile(INITRD_START as *const u64) } as usize;
+    if initrd_size > 0 && initrd_size <= INITRD_END - INITRD_START - KERNEL_PAGE_SIZE {
+        let blocks = initrd_size / BLOCK_SZ;
+        let initrd = RamDisk::from_memory(INITRD_START + KERNEL_PAGE_SIZE, blocks);
+        push_disk(&mut devices, String::from("ram0"), Arc::new(initrd), blocks);
+    }
+    devices
+}
+
+///Every disk and partition as it is now, RAM disks may be added later
+pub fn block_devices() -> Vec<NamedBlockDevice> {
+    BLOCK_DEVICES.lock().clone()
+}
+
+///The disk or partition called `name`
+pub fn block_device(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES
+        .lock()
+        .iter()
+        .find(|device| device.name == name)
+        .map(|device| device.device.clone())
+}
+
+///Make a zeroed RAM disk of `blocks` blocks, return N of its name ramN
+///with the disk. It lives until the system goes down and may take half
+///of the free frames at most, all of them together a share of the memory
+pub fn create_ram_disk(blocks: usize) -> Option<(usize, Arc<dyn BlockDevice>)> {
+    let frames = blocks.div_ceil(KERNEL_PAGE_SIZE / BLOCK_SZ);
+    let (total_frames, free_frames) = frame_stats();
+    let mut taken = RAM_DISK_FRAMES.lock();
+    if blocks == 0 || frames > free_frames / 2
+        || *taken + frames > total_frames / RAM_DISK_FRAME_SHARE {
+        return None;
+    }
+    let disk: Arc<dyn BlockDevice> = Arc::new(RamDisk::new(blocks)?);
+    *taken += frames;
+    let mut devices = BLOCK_DEVICES.lock();
+    let number = devices
+        .iter()
+        .filter(|device| device.name.starts_with("ram") && !device.name.contains('p'))
+        .count();
+    devices.push(NamedBlockDevice { name: format!("ram{}", number), device: disk.clone(), blocks });
+    Some((number, disk))
+}
+
+///The PLIC sources of the disks with their names
+pub fn block_irqs() -> Vec<(usize, String)> {
+    DISKS
+        .iter()
+        .enumerate()
+        .map(|(i, (irq, _))| (*irq, format!("virtio-blk {}", disk_name(i))))
+        .collect()
+}
+
+///Let the disk on PLIC source `intr_src_id` finish its requests, false if
+///there is none
+pub fn handle_block_irq(intr_src_id: usize) -> bool {
+    match DISKS.iter().find(|(irq, _)| *irq == intr_src_id) {
+        Some((_, disk)) => {
+            disk.handle_irq();
+            true
+        }
+        None => false,
+    }
+}
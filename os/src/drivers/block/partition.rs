@@ -0,0 +1,144 @@
+//! MBR and GPT partition tables, every partition is a block device of its own
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use easy_fs::{BlockDevice, BLOCK_SZ};
+
+const MBR_TABLE: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+//a disk with a GPT has one MBR entry of this type covering it
+const MBR_GPT_PROTECTIVE: u8 = 0xEE;
+//partitions inside extended partitions are not looked for
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MAX_ENTRIES: usize = 128;
+
+///A range of blocks of a disk
+pub struct Partition {
+    device: Arc<dyn BlockDevice>,
+    start: usize,
+    blocks: usize,
+}
+
+impl Partition {
+    pub fn new(device: Arc<dyn BlockDevice>, start: usize, blocks: usize) -> Self {
+        Self { device, start, blocks }
+    }
+    //a file system must never reach into the next partition
+    fn check(&self, block_id: usize, len: usize) -> bool {
+        let fits = block_id
+            .checked_add((len + BLOCK_SZ - 1) / BLOCK_SZ)
+            .map_or(false, |end| end <= self.blocks);
+        if !fits {
+            println!("[kernel] block {} is out of the partition", block_id);
+        }
+        fits
+    }
+}
+
+//requests past the end read as zeros and are not written, like a failed
+//request, a corrupt file system must not take the kernel down
+impl BlockDevice for Partition {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        match self.check(block_id, buf.len()) {
+            true => self.device.read_block(self.start + block_id, buf),
+            false => buf.fill(0),
+        }
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        if self.check(block_id, buf.len()) {
+            self.device.write_block(self.start + block_id, buf);
+        }
+    }
+    //the disk handles the interrupts of its partitions
+    fn handle_irq(&self) {}
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
+        match self.check(block_id, buf.len()) {
+            true => self.device.read_blocks(self.start + block_id, buf),
+            false => buf.fill(0),
+        }
+    }
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) {
+        if self.check(block_id, buf.len()) {
+            self.device.write_blocks(self.start + block_id, buf);
+        }
+    }
+}
+
+fn le_u32(bytes: &[u8]) -> usize {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize
+}
+
+fn le_u64(bytes: &[u8]) -> usize {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize
+}
+
+///The partitions of a disk of `disk_blocks` blocks as (number, first
+///block, number of blocks); numbers count from 1 in table order like
+///vda1, vda2.. Empty if there is no table or it does not fit the disk
+pub fn read_partitions(device: &Arc<dyn BlockDevice>, disk_blocks: usize) -> Vec<(usize, usize, usize)> {
+    let mut mbr = [0u8; BLOCK_SZ];
+    device.read_block(0, &mut mbr);
+    if mbr[510..512] != MBR_SIGNATURE {
+        return Vec::new();
+    }
+    let entries: Vec<&[u8]> = mbr[MBR_TABLE..MBR_TABLE + 4 * MBR_ENTRY_SIZE].chunks(MBR_ENTRY_SIZE).collect();
+    //a FAT boot sector has the signature as well, but code where the
+    //boot flags of the entries would be
+    if entries.iter().any(|entry| entry[0] != 0 && entry[0] != 0x80) {
+        return Vec::new();
+    }
+    let partitions: Vec<(usize, usize, usize)> = if entries.iter().any(|entry| entry[4] == MBR_GPT_PROTECTIVE) {
+        read_gpt(device, disk_blocks)
+    } else {
+        entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry[4] != 0 && !MBR_EXTENDED.contains(&entry[4]))
+            .map(|(i, entry)| (i + 1, le_u32(&entry[8..]), le_u32(&entry[12..])))
+            .collect()
+    };
+    let fits = |&(_, start, blocks): &(usize, usize, usize)| {
+        start > 0 && blocks > 0 && start.checked_add(blocks).map_or(false, |end| end <= disk_blocks)
+    };
+    if partitions.iter().all(fits) {
+        partitions
+    } else {
+        Vec::new()
+    }
+}
+
+//the header is in block 1, its checksums are not checked
+fn read_gpt(device: &Arc<dyn BlockDevice>, disk_blocks: usize) -> Vec<(usize, usize, usize)> {
+    let mut header = [0u8; BLOCK_SZ];
+    device.read_block(1, &mut header);
+    if &header[..8] != GPT_SIGNATURE {
+        return Vec::new();
+    }
+    let entries_start = le_u64(&header[72..]);
+    let entry_count = le_u32(&header[80..]).min(GPT_MAX_ENTRIES);
+    let entry_size = le_u32(&header[84..]);
+    if entry_size < 48 || entry_size > BLOCK_SZ || BLOCK_SZ % entry_size != 0 {
+        return Vec::new();
+    }
+    let entry_blocks = (entry_count * entry_size + BLOCK_SZ - 1) / BLOCK_SZ;
+    //the entries follow the header and must be on the disk
+    if entries_start < 2 || entries_start > disk_blocks || disk_blocks - entries_start < entry_blocks {
+        return Vec::new();
+    }
+    let mut entries = vec![0u8; entry_blocks * BLOCK_SZ];
+    device.read_blocks(entries_start, &mut entries);
+    entries
+        .chunks(entry_size)
+        .take(entry_count)
+        .enumerate()
+        //unused entries have no type
+        .filter(|(_, entry)| entry[..16].iter().any(|byte| *byte != 0))
+        .map(|(i, entry)| {
+            let first = le_u64(&entry[32..]);
+            let last = le_u64(&entry[40..]);
+            (i + 1, first, (last + 1).saturating_sub(first))
+        })
+        .collect()
+}
@@ -0,0 +1,69 @@
+//! Disks kept in memory: the initrd QEMU loaded and scratch disks made at
+//! run time, both without any round trip to a device
+use alloc::vec::Vec;
+use core::ptr::copy_nonoverlapping;
+use easy_fs::{BlockDevice, BLOCK_SZ};
+use crate::config::KERNEL_PAGE_SIZE;
+use crate::mm::address::PhysAddr;
+use crate::mm::frame_allocator::{frame_alloc, FrameWrapper};
+
+const BLOCKS_PER_PAGE: usize = KERNEL_PAGE_SIZE / BLOCK_SZ;
+
+///Blocks in memory the kernel reaches through the identical mapping
+pub struct RamDisk {
+    //address of every page of the disk
+    pages: Vec<usize>,
+    blocks: usize,
+    //frames of a disk made at run time, they go back with the disk
+    _frames: Vec<FrameWrapper>,
+}
+
+impl RamDisk {
+    ///The `blocks` blocks of memory from `start` on, like the initrd
+    pub fn from_memory(start: usize, blocks: usize) -> Self {
+        let pages = (0..blocks.div_ceil(BLOCKS_PER_PAGE))
+            .map(|i| start + i * KERNEL_PAGE_SIZE)
+            .collect();
+        Self { pages, blocks, _frames: Vec::new() }
+    }
+    ///A disk of `blocks` zeroed blocks, None if the frames run out
+    pub fn new(blocks: usize) -> Option<Self> {
+        let mut frames = Vec::new();
+        for _ in 0..blocks.div_ceil(BLOCKS_PER_PAGE) {
+            let frame = frame_alloc()?;
+            frame.clear_frame();
+            frames.push(frame);
+        }
+        let pages = frames.iter().map(|frame| PhysAddr::from(frame.ppn).into()).collect();
+        Some(Self { pages, blocks, _frames: frames })
+    }
+    //None for a block past the end of the disk
+    fn block_addr(&self, block_id: usize) -> Option<usize> {
+        if block_id >= self.blocks {
+            println!("[kernel] block {} is out of the RAM disk", block_id);
+            return None;
+        }
+        Some(self.pages[block_id / BLOCKS_PER_PAGE] + block_id % BLOCKS_PER_PAGE * BLOCK_SZ)
+    }
+}
+
+//blocks past the end read as zeros and are not written, like a failed request
+impl BlockDevice for RamDisk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        for (i, block) in buf.chunks_mut(BLOCK_SZ).enumerate() {
+            match self.block_addr(block_id + i) {
+                Some(addr) => unsafe { copy_nonoverlapping(addr as *const u8, block.as_mut_ptr(), block.len()) },
+                None => block.fill(0),
+            }
+        }
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        for (i, block) in buf.chunks(BLOCK_SZ).enumerate() {
+            if let Some(addr) = self.block_addr(block_id + i) {
+                unsafe { copy_nonoverlapping(block.as_ptr(), addr as *mut u8, block.len()) };
+            }
+        }
+    }
+    //every request is done before it returns
+    fn handle_irq(&self) {}
+}
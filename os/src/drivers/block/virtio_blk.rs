@@ -1,47 +1,138 @@
-
-use alloc::vec::Vec;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use virtio_drivers::{VirtIOBlk, VirtIOHeader, BlkResp, RespStatus};
+use easy_fs::BLOCK_SZ;
 use crate::drivers::bus::virtio::VirtioHal;
 use crate::drivers::block::BlockDevice;
-use crate::sync::Condvar;
 use crate::config::get_file_block_mode;
 use crate::task::block_task_and_run_next;
-const VIRTIO0: usize = 0x10008000;
+use crate::task::process::wakeup_if_blocked;
+use crate::task::schedule::{get_current_task, TaskID};
 use crate::sync::OneCoreCell;
 use crate::sync::InterruptMask;
 
+//the virtqueue has 16 descriptors, a request takes three of them for its
+//header, data and status
+const MAX_INFLIGHT: usize = 16 / 3;
+
+//one block of a batch, waiting for room in the virtqueue
+struct BlkRequest {
+    batch: usize,
+    write: bool,
+    //address of the block in the buffer of the sleeping task
+    buf: usize,
+}
+
+//the blocks a task asked for in one call, it sleeps until all are done
+struct Batch {
+    task: TaskID,
+    remaining: usize,
+    ok: bool,
+}
 
 pub struct MTVirtBlk {
     pub virt_hal : VirtIOBlk<'static, VirtioHal>,
-    //there will be 16 queues at most
-    pub condvars : Vec<Condvar>,
+    //requests not in the virtqueue yet by block id and batch, they go to
+    //the device in one direction across the disk like an elevator
+    pending: BTreeMap<(usize, usize), BlkRequest>,
+    //the block the elevator is at
+    head: usize,
+    //requests in the virtqueue by token with their batch and block, the
+    //device writes the status
+    inflight: BTreeMap<u16, (usize, usize, Box<BlkResp>)>,
+    batches: BTreeMap<usize, Batch>,
+    next_batch: usize,
 }
 
 impl MTVirtBlk {
-    #[allow(unused)]
-    pub fn new() -> Self {
-        let virt_hal = unsafe {VirtIOBlk::<VirtioHal>::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap()};
-        let mut condvars = Vec::with_capacity(16);
-        for _ in 0..16{
-            condvars.push(Condvar::new());
-        }
-        Self {
+    //None if there is no block device at this virtio mmio address
+    pub fn probe(base : usize) -> Option<Self> {
+        let virt_hal = unsafe {VirtIOBlk::<VirtioHal>::new(&mut *(base as *mut VirtIOHeader)).ok()?};
+        Some(Self {
             virt_hal,
-            condvars,
-        }
+            pending: BTreeMap::new(),
+            head: 0,
+            inflight: BTreeMap::new(),
+            batches: BTreeMap::new(),
+            next_batch: 0,
+        })
     }
-    pub fn nb_read(&mut self, block_id: usize, buf: &mut [u8], resp : &mut BlkResp) {
-        let queue_id = unsafe {self.virt_hal.read_block_nb(block_id, buf, resp).unwrap()};
-        self.condvars[queue_id as usize].wait_no_schedule();
+    //queue the `len` bytes at `buf` as blocks from block_id on for the
+    //current task, return the batch to wait for
+    fn submit(&mut self, block_id: usize, buf: usize, len: usize, write: bool) -> usize {
+        let batch = self.next_batch;
+        self.next_batch += 1;
+        let blocks = len / BLOCK_SZ;
+        self.batches.insert(batch, Batch { task: get_current_task(), remaining: blocks, ok: true });
+        for i in 0..blocks {
+            self.pending.insert((block_id + i, batch), BlkRequest { batch, write, buf: buf + i * BLOCK_SZ });
+        }
+        self.dispatch();
+        batch
     }
-    pub fn nb_write(&mut self, block_id: usize, buf: &[u8], resp : &mut BlkResp) {
-        let queue_id = unsafe { self.virt_hal.write_block_nb(block_id, buf, resp).unwrap() };
-        self.condvars[queue_id as usize].wait_no_schedule();
+    //fill the virtqueue with the pending requests at or after the head,
+    //going back to the lowest block at the end of the disk. A block already
+    //in the virtqueue waits for it, the device may finish requests in any
+    //order and a write must not overtake an older one
+    fn dispatch(&mut self) {
+        while self.inflight.len() < MAX_INFLIGHT {
+            let inflight = &self.inflight;
+            let key = self.pending.range((self.head, 0)..)
+                .chain(self.pending.range(..(self.head, 0)))
+                .map(|(key, _)| *key)
+                .find(|key| !inflight.values().any(|(_, block_id, _)| *block_id == key.0));
+            let key = match key {
+                Some(key) => key,
+                None => break,
+            };
+            let request = self.pending.remove(&key).unwrap();
+            let mut resp = Box::new(BlkResp::default());
+            let token = unsafe {
+                if request.write {
+                    let buf = core::slice::from_raw_parts(request.buf as *const u8, BLOCK_SZ);
+                    self.virt_hal.write_block_nb(key.0, buf, &mut resp)
+                } else {
+                    let buf = core::slice::from_raw_parts_mut(request.buf as *mut u8, BLOCK_SZ);
+                    self.virt_hal.read_block_nb(key.0, buf, &mut resp)
+                }
+            };
+            match token {
+                Ok(token) => {
+                    self.head = key.0;
+                    self.inflight.insert(token, (request.batch, key.0, resp));
+                }
+                //no free descriptors after all, try again on the next interrupt
+                Err(_) => {
+                    self.pending.insert(key, request);
+                    break;
+                }
+            }
+        }
     }
+    //count the finished requests against their batches, wake the tasks
+    //whose batch is done and refill the virtqueue
     pub fn work_done(&mut self) {
-        while let Ok(queue_id) =  self.virt_hal.pop_used(){
-            self.condvars[queue_id as usize].signal_one();
+        while let Ok(token) = self.virt_hal.pop_used() {
+            let (batch_id, _, resp) = match self.inflight.remove(&token) {
+                Some(request) => request,
+                None => continue,
+            };
+            let batch = self.batches.get_mut(&batch_id).unwrap();
+            batch.ok &= resp.status() == RespStatus::Ok;
+            batch.remaining -= 1;
+            //the task may still be running before it checks its batch
+            if batch.remaining == 0 {
+                wakeup_if_blocked(batch.task);
+            }
+        }
+        self.dispatch();
+    }
+    //Some(if every block went well) once the batch is done
+    fn finish(&mut self, batch: usize) -> Option<bool> {
+        if self.batches.get(&batch).unwrap().remaining > 0 {
+            return None;
         }
+        self.batches.remove(&batch).map(|batch| batch.ok)
     }
 }
 
@@ -51,44 +142,31 @@ pub struct VirtIOBlock {
 
 impl BlockDevice for VirtIOBlock {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.read_blocks(block_id, buf);
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        self.write_blocks(block_id, buf);
+    }
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
         if get_file_block_mode() {
-            let mut int_ctrl = InterruptMask::new();
-            int_ctrl.mask_interrupt();
+            let ok = self.queued_io(block_id, buf.as_mut_ptr() as usize, buf.len(), false);
+            assert!(ok, "Error when reading VirtIOBlk");
+        } else {
             let mut block_device = self.virtio_blk.exclusive_access();
-            let mut resp = BlkResp::default();
-            block_device.nb_read(block_id, buf, &mut resp);
-            drop(block_device);
-            //TODO: we must disable INTERRUPT until sechule, if open it at this pointer,
-            //we migth recive irq frist before we went to sleep, which will cause wakeup-lost
-            //problem, then thread went to sleep amd never wake up
-            block_task_and_run_next();
-            int_ctrl.unmask_interrupt();
-            assert_eq!(
-                resp.status(),
-                RespStatus::Ok,
-                "Error when reading VirtIOBlk"
-            );
-        }else{
-            self.virtio_blk.exclusive_access().virt_hal.read_block(block_id, buf).expect("Error when reading VirtIOBlk");
+            for (i, block) in buf.chunks_mut(BLOCK_SZ).enumerate() {
+                block_device.virt_hal.read_block(block_id + i, block).expect("Error when reading VirtIOBlk");
+            }
         }
     }
-    fn write_block(&self, block_id: usize, buf: &[u8]) {
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) {
         if get_file_block_mode() {
-            let mut int_ctrl = InterruptMask::new();
-            int_ctrl.mask_interrupt();
-            let mut block_device = self.virtio_blk.exclusive_access();
-            let mut resp = BlkResp::default();
-            block_device.nb_write(block_id, buf, &mut resp);
-            drop(block_device);
-            block_task_and_run_next();
-            int_ctrl.unmask_interrupt();
-            assert_eq!(
-                resp.status(),
-                RespStatus::Ok,
-                "Error when writing VirtIOBlk"
-            );
+            let ok = self.queued_io(block_id, buf.as_ptr() as usize, buf.len(), true);
+            assert!(ok, "Error when writing VirtIOBlk");
         } else {
-            self.virtio_blk.exclusive_access().virt_hal.write_block(block_id, buf).expect("Error when writing VirtIOBlk");
+            let mut block_device = self.virtio_blk.exclusive_access();
+            for (i, block) in buf.chunks(BLOCK_SZ).enumerate() {
+                block_device.virt_hal.write_block(block_id + i, block).expect("Error when writing VirtIOBlk");
+            }
         }
     }
     fn handle_irq(&self) {
@@ -99,10 +177,31 @@ impl BlockDevice for VirtIOBlock {
 }
 
 impl VirtIOBlock {
-    #[allow(unused)]
-    pub fn new() -> Self {
-        Self { 
-            virtio_blk: unsafe {OneCoreCell::new(MTVirtBlk::new())},
+    pub fn probe(base : usize) -> Option<Self> {
+        let virtio_blk = MTVirtBlk::probe(base)?;
+        Some(Self {
+            virtio_blk: unsafe {OneCoreCell::new(virtio_blk)},
+        })
+    }
+    //queue the blocks behind those of other tasks and sleep until the
+    //completion interrupts of all of them came in.
+    //Return false if the device failed any of them
+    fn queued_io(&self, block_id: usize, buf: usize, len: usize, write: bool) -> bool {
+        let mut int_ctrl = InterruptMask::new();
+        //the interrupt handler shares the queue
+        int_ctrl.mask_interrupt();
+        let batch = self.virtio_blk.exclusive_access().submit(block_id, buf, len, write);
+        int_ctrl.unmask_interrupt();
+        loop {
+            //a completion must not come in between checking the batch and
+            //going to sleep
+            int_ctrl.mask_interrupt();
+            if let Some(ok) = self.virtio_blk.exclusive_access().finish(batch) {
+                int_ctrl.unmask_interrupt();
+                return ok;
+            }
+            block_task_and_run_next();
+            int_ctrl.unmask_interrupt();
         }
     }
-}
\ No newline at end of file
+}
@@ -2,6 +2,7 @@ mod ns16550a;
 
 use crate::board::CharDeviceImpl;
 use alloc::sync::Arc;
+use crate::task::schedule::TaskID;
 use lazy_static::*;
 pub use ns16550a::NS16550a;
 
@@ -9,6 +10,8 @@ pub trait CharDevice {
     fn init(&self);
     fn read(&self) -> u8;
     fn write(&self, ch: u8);
+    //wake the polling task once input arrives
+    fn poll_wait(&self, task_id: TaskID);
     fn handle_irq(&self);
 }
 
@@ -9,6 +9,8 @@ use volatile::{ReadOnly, Volatile, WriteOnly};
 use crate::task::block_task_and_run_next;
 use crate::sync::OneCoreCell;
 use crate::sync::InterruptMask;
+use crate::sync::PollQueue;
+use crate::task::schedule::TaskID;
 
 bitflags! {
     /// InterruptEnableRegister
@@ -129,6 +131,8 @@ struct NS16550aInner {
 
 pub struct NS16550a<const BASE_ADDR: usize> {
     inner: OneCoreCell<NS16550aInner>,
+    //kept out of inner, pollers queue up with interrupts on
+    poll_queue: PollQueue,
 }
 
 impl<const BASE_ADDR: usize> NS16550a<BASE_ADDR> {
@@ -143,6 +147,7 @@ impl<const BASE_ADDR: usize> NS16550a<BASE_ADDR> {
                     }
                 )
             },
+            poll_queue: PollQueue::new(),
         }
         //inner.ns16550a.init();
     }
@@ -182,6 +187,9 @@ impl<const BASE_ADDR: usize> CharDevice for NS16550a<BASE_ADDR> {
         self.inner.exclusive_access().ns16550a.write(ch);
         int_ctrl.unmask_interrupt();
     }
+    fn poll_wait(&self, task_id: TaskID) {
+        self.poll_queue.register(task_id);
+    }
     fn handle_irq(&self) {
         let mut uart = self.inner.exclusive_access();
         let mut count = 0;
@@ -195,6 +203,7 @@ impl<const BASE_ADDR: usize> CharDevice for NS16550a<BASE_ADDR> {
         }
         if count > 0 {
             uart.condvar.signal_one();
+            self.poll_queue.wake_all();
         }
     }
 }
@@ -6,6 +6,8 @@ use core::any::Any;
 use virtio_drivers::{VirtIOHeader, VirtIOInput};
 use crate::task::block_task_and_run_next;
 use crate::sync::InterruptMask;
+use crate::sync::PollQueue;
+use crate::task::schedule::TaskID;
 
 const VIRTIO5: usize = 0x10005000;
 const VIRTIO6: usize = 0x10006000;
@@ -18,12 +20,15 @@ struct VirtIOInputInner {
 
 struct VirtIOInputWrapper {
     inner: OneCoreCell<VirtIOInputInner>,
+    poll_queue: PollQueue,
 }
 
 pub trait InputDevice: Send + Sync + Any {
     fn read_event(&self) -> u64;
     fn handle_irq(&self);
     fn is_empty(&self) -> bool;
+    //wake the polling task once an event arrives
+    fn poll_wait(&self, task_id: TaskID);
 }
 
 lazy_static::lazy_static!(
@@ -42,6 +47,7 @@ impl VirtIOInputWrapper {
         };
         Self {
             inner: unsafe { OneCoreCell::new(inner) },
+            poll_queue: PollQueue::new(),
         }
     }
 }
@@ -51,6 +57,10 @@ impl InputDevice for VirtIOInputWrapper {
         self.inner.exclusive_access().events.is_empty()
     }
 
+    fn poll_wait(&self, task_id: TaskID) {
+        self.poll_queue.register(task_id);
+    }
+
     fn read_event(&self) -> u64 {
         let mut int_ctrl = InterruptMask::new();
         loop {
@@ -83,6 +93,7 @@ impl InputDevice for VirtIOInputWrapper {
         }
         if count > 0 {
             input.condvar.signal_one();
+            self.poll_queue.wake_all();
         };
     }
 }
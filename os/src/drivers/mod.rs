@@ -6,7 +6,7 @@ pub mod gpu;
 pub mod input;
 pub mod net;
 
-pub use block::BLOCK_DEVICE;
+pub use block::{block_device, block_devices, block_irqs, create_ram_disk, handle_block_irq};
 pub use gpu::GPU_DEVICE;
 pub use input::KEYBOARD_DEVICE;
 pub use input::MOUSE_DEVICE;
@@ -0,0 +1,393 @@
+//! Device file system, every node hands out a File talking to a driver
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+use crate::drivers::chardev::{CharDevice, UART};
+use crate::drivers::input::InputDevice;
+use crate::drivers::{block_devices, GPU_DEVICE, KEYBOARD_DEVICE, MOUSE_DEVICE};
+use crate::mm::memory_set::UserBuffer;
+use crate::task::schedule::TaskID;
+use crate::timer::get_time;
+use easy_fs::{BlockDevice, BLOCK_SZ};
+use super::vfs::{SuperBlock, Vnode, VnodeType};
+use super::{File, PollEvents};
+
+type OpenFn = Box<dyn Fn(bool, bool) -> Option<Arc<dyn File>> + Send + Sync>;
+
+pub struct DevfsSuperBlock {
+    root: Arc<DevNode>,
+}
+
+pub struct DevNode {
+    id: u32,
+    vtype: VnodeType,
+    open: Option<OpenFn>,
+    children: Vec<(String, Arc<DevNode>)>,
+    //the root lists every disk and partition next to its children, RAM
+    //disks come and go at run time
+    disks: bool,
+}
+
+impl DevNode {
+    fn device(
+        id: u32,
+        vtype: VnodeType,
+        open: impl Fn(bool, bool) -> Option<Arc<dyn File>> + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Arc::new(Self { id, vtype, open: Some(Box::new(open)), children: Vec::new(), disks: false })
+    }
+    fn dir(id: u32, children: Vec<(String, Arc<DevNode>)>) -> Arc<Self> {
+        Arc::new(Self { id, vtype: VnodeType::Dir, open: None, children, disks: false })
+    }
+    fn children(&self) -> Vec<(String, Arc<DevNode>)> {
+        let mut children = self.children.clone();
+        if self.disks {
+            for (i, disk) in block_devices().into_iter().enumerate() {
+                let (device, blocks) = (disk.device, disk.blocks);
+                let open = move |readable, writable| open_disk(device.clone(), blocks, readable, writable);
+                children.push((disk.name, DevNode::device(BLOCK_DEVICE_ID_BASE + i as u32, VnodeType::BlockDevice, open)));
+            }
+        }
+        children
+    }
+}
+
+//node ids of the disks and partitions start after the fixed devices
+const BLOCK_DEVICE_ID_BASE: u32 = 16;
+
+lazy_static! {
+    static ref DEVFS: Arc<DevfsSuperBlock> = {
+        let input = DevNode::dir(1, vec![
+            ("event0".into(), DevNode::device(2, VnodeType::CharDevice, open_keyboard)),
+            ("event1".into(), DevNode::device(3, VnodeType::CharDevice, open_mouse)),
+        ]);
+        let root = DevNode {
+            id: 0,
+            vtype: VnodeType::Dir,
+            open: None,
+            children: vec![
+                ("ttyS0".into(), DevNode::device(4, VnodeType::CharDevice, open_tty)),
+                ("input".into(), input),
+                ("fb0".into(), DevNode::device(5, VnodeType::CharDevice, open_fb)),
+                ("null".into(), DevNode::device(7, VnodeType::CharDevice, open_null)),
+                ("zero".into(), DevNode::device(8, VnodeType::CharDevice, open_zero)),
+                ("random".into(), DevNode::device(9, VnodeType::CharDevice, open_random)),
+            ],
+            disks: true,
+        };
+        Arc::new(DevfsSuperBlock {
+            root: Arc::new(root),
+        })
+    };
+}
+
+///There is only one set of devices, every mount shows the same tree
+pub fn mount_devfs(_source: &str) -> Option<Arc<dyn SuperBlock>> {
+    Some(DEVFS.clone())
+}
+
+impl SuperBlock for DevfsSuperBlock {
+    fn fs_type(&self) -> &'static str {
+        "devfs"
+    }
+    fn root(&self) -> Arc<dyn Vnode> {
+        self.root.clone()
+    }
+}
+
+impl Vnode for DevNode {
+    fn id(&self) -> u32 {
+        self.id
+    }
+    fn vtype(&self) -> VnodeType {
+        self.vtype
+    }
+    fn size(&self) -> usize {
+        0
+    }
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> usize {
+        0
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn Vnode>> {
+        self.children()
+            .into_iter()
+            .find(|(child_name, _)| child_name == name)
+            .map(|(_, node)| node as Arc<dyn Vnode>)
+    }
+    fn read_dir(&self) -> Vec<(String, Arc<dyn Vnode>)> {
+        self.children()
+            .into_iter()
+            .map(|(name, node)| (name, node as Arc<dyn Vnode>))
+            .collect()
+    }
+    fn open_device(&self, readable: bool, writable: bool) -> Option<Arc<dyn File>> {
+        self.open.as_ref().and_then(|open| open(readable, writable))
+    }
+}
+
+///The pieces of a user buffer as kernel slices
+fn user_slices(buf: &UserBuffer) -> Vec<&'static mut [u8]> {
+    (0..buf.kernel_bufs.len())
+        .map(|i| {
+            let phys_buf = buf.kernel_bufs.get(&i).unwrap();
+            unsafe { core::slice::from_raw_parts_mut(phys_buf.start as *mut u8, phys_buf.len) }
+        })
+        .collect()
+}
+
+///Device with a byte stream that can not seek
+struct StreamDevice {
+    readable: bool,
+    writable: bool,
+    read: fn(&mut [u8]) -> usize,
+    write: fn(&[u8]) -> usize,
+    //whether a read returns at once and how to wait until it does,
+    //None if reads never block
+    input: Option<(fn() -> bool, fn(TaskID))>,
+}
+
+impl File for StreamDevice {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, buf: &UserBuffer) -> usize {
+        let mut total_read_size = 0usize;
+        for slice in user_slices(buf) {
+            let read_size = (self.read)(slice);
+            total_read_size += read_size;
+            if read_size < slice.len() {
+                break;
+            }
+        }
+        total_read_size
+    }
+    fn write(&self, buf: &UserBuffer) -> usize {
+        let mut total_write_size = 0usize;
+        for slice in user_slices(buf) {
+            total_write_size += (self.write)(slice);
+        }
+        total_write_size
+    }
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::empty();
+        if self.readable && self.input.map_or(true, |(has_input, _)| has_input()) {
+            events |= PollEvents::IN;
+        }
+        if self.writable {
+            events |= PollEvents::OUT;
+        }
+        events
+    }
+    fn poll_wait(&self, task_id: TaskID) {
+        if let Some((_, wait)) = self.input {
+            wait(task_id);
+        }
+    }
+}
+
+fn stream(readable: bool, writable: bool, read: fn(&mut [u8]) -> usize, write: fn(&[u8]) -> usize)
+    -> Option<Arc<dyn File>> {
+    Some(Arc::new(StreamDevice { readable, writable, read, write, input: None }))
+}
+
+fn input_stream(readable: bool, writable: bool, read: fn(&mut [u8]) -> usize, write: fn(&[u8]) -> usize,
+    input: (fn() -> bool, fn(TaskID))) -> Option<Arc<dyn File>> {
+    Some(Arc::new(StreamDevice { readable, writable, read, write, input: Some(input) }))
+}
+
+fn discard(buf: &[u8]) -> usize {
+    buf.len()
+}
+
+fn open_null(readable: bool, writable: bool) -> Option<Arc<dyn File>> {
+    stream(readable, writable, |_| 0, discard)
+}
+
+fn open_zero(readable: bool, writable: bool) -> Option<Arc<dyn File>> {
+    stream(readable, writable, |buf| {
+        buf.fill(0);
+        buf.len()
+    }, discard)
+}
+
+static RANDOM_STATE: Mutex<u64> = Mutex::new(0);
+
+//xorshift64 seeded from the cycle counter, not suitable for cryptography
+fn read_random(buf: &mut [u8]) -> usize {
+    let mut state = RANDOM_STATE.lock();
+    if *state == 0 {
+        *state = get_time() as u64 | 1;
+    }
+    for byte in buf.iter_mut() {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *byte = *state as u8;
+    }
+    buf.len()
+}
+
+fn open_random(readable: bool, writable: bool) -> Option<Arc<dyn File>> {
+    stream(readable, writable, read_random, discard)
+}
+
+//block for the first byte, then take what has already arrived
+fn read_tty(buf: &mut [u8]) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+    buf[0] = UART.read();
+    let mut len = 1;
+    while len < buf.len() && !UART.read_buffer_is_empty() {
+        buf[len] = UART.read();
+        len += 1;
+    }
+    len
+}
+
+fn write_tty(buf: &[u8]) -> usize {
+    for ch in buf {
+        UART.write(*ch);
+    }
+    buf.len()
+}
+
+fn open_tty(readable: bool, writable: bool) -> Option<Arc<dyn File>> {
+    input_stream(readable, writable, read_tty, write_tty,
+        (|| !UART.read_buffer_is_empty(), |task_id| UART.poll_wait(task_id)))
+}
+
+//events are u64 values, block for the first one, then take what is queued
+fn read_events(device: &Arc<dyn InputDevice>, buf: &mut [u8]) -> usize {
+    let event_size = core::mem::size_of::<u64>();
+    let mut len = 0;
+    while len + event_size <= buf.len() && (len == 0 || !device.is_empty()) {
+        let event = device.read_event();
+        buf[len..len + event_size].copy_from_slice(&event.to_ne_bytes());
+        len += event_size;
+    }
+    len
+}
+
+fn open_keyboard(readable: bool, writable: bool) -> Option<Arc<dyn File>> {
+    if writable {
+        return None;
+    }
+    input_stream(readable, false, |buf| read_events(&KEYBOARD_DEVICE, buf), discard,
+        (|| !KEYBOARD_DEVICE.is_empty(), |task_id| KEYBOARD_DEVICE.poll_wait(task_id)))
+}
+
+fn open_mouse(readable: bool, writable: bool) -> Option<Arc<dyn File>> {
+    if writable {
+        return None;
+    }
+    input_stream(readable, false, |buf| read_events(&MOUSE_DEVICE, buf), discard,
+        (|| !MOUSE_DEVICE.is_empty(), |task_id| MOUSE_DEVICE.poll_wait(task_id)))
+}
+
+///Device addressed by offset, the offset moves with every read and write
+struct SeekDevice {
+    readable: bool,
+    writable: bool,
+    offset: Mutex<usize>,
+    read_at: Box<dyn Fn(usize, &mut [u8]) -> usize + Send + Sync>,
+    write_at: Box<dyn Fn(usize, &[u8]) -> usize + Send + Sync>,
+}
+
+impl File for SeekDevice {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, buf: &UserBuffer) -> usize {
+        let mut offset = self.offset.lock();
+        let mut total_read_size = 0usize;
+        for slice in user_slices(buf) {
+            let read_size = (self.read_at)(*offset, slice);
+            *offset += read_size;
+            total_read_size += read_size;
+            if read_size < slice.len() {
+                break;
+            }
+        }
+        total_read_size
+    }
+    fn write(&self, buf: &UserBuffer) -> usize {
+        let mut offset = self.offset.lock();
+        let mut total_write_size = 0usize;
+        for slice in user_slices(buf) {
+            let write_size = (self.write_at)(*offset, slice);
+            *offset += write_size;
+            total_write_size += write_size;
+            if write_size < slice.len() {
+                break;
+            }
+        }
+        total_write_size
+    }
+}
+
+fn read_fb(offset: usize, buf: &mut [u8]) -> usize {
+    let fb = GPU_DEVICE.get_framebuffer();
+    let len = buf.len().min(fb.len().saturating_sub(offset));
+    buf[..len].copy_from_slice(&fb[offset..offset + len]);
+    len
+}
+
+fn write_fb(offset: usize, buf: &[u8]) -> usize {
+    let fb = GPU_DEVICE.get_framebuffer();
+    let len = buf.len().min(fb.len().saturating_sub(offset));
+    fb[offset..offset + len].copy_from_slice(&buf[..len]);
+    GPU_DEVICE.flush();
+    len
+}
+
+fn open_fb(readable: bool, writable: bool) -> Option<Arc<dyn File>> {
+    Some(Arc::new(SeekDevice {
+        readable,
+        writable,
+        offset: Mutex::new(0),
+        read_at: Box::new(read_fb),
+        write_at: Box::new(write_fb),
+    }))
+}
+
+//reads stop at the end of the device
+fn read_disk(device: &Arc<dyn BlockDevice>, blocks: usize, offset: usize, buf: &mut [u8]) -> usize {
+    let end = (offset + buf.len()).min(blocks * BLOCK_SZ);
+    let mut block = [0u8; BLOCK_SZ];
+    let mut pos = offset;
+    while pos < end {
+        let block_offset = pos % BLOCK_SZ;
+        let len = (BLOCK_SZ - block_offset).min(end - pos);
+        device.read_block(pos / BLOCK_SZ, &mut block);
+        buf[pos - offset..pos - offset + len]
+            .copy_from_slice(&block[block_offset..block_offset + len]);
+        pos += len;
+    }
+    end.saturating_sub(offset)
+}
+
+//mounted file systems cache blocks of the disks and partitions, so
+//writing around them is refused
+fn open_disk(device: Arc<dyn BlockDevice>, blocks: usize, readable: bool, writable: bool) -> Option<Arc<dyn File>> {
+    if writable {
+        return None;
+    }
+    Some(Arc::new(SeekDevice {
+        readable,
+        writable: false,
+        offset: Mutex::new(0),
+        read_at: Box::new(move |offset, buf| read_disk(&device, blocks, offset, buf)),
+        write_at: Box::new(|_, _| 0),
+    }))
+}
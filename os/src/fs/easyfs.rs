@@ -0,0 +1,187 @@
+//! easy-fs on a block device, seen through the VFS
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use easy_fs::{
+    block_cache_sync, block_cache_try_sync_all, set_block_cache_capacity, BlockDevice,
+    EasyFileSystem, Inode, InodeFormat, InodeOwner, IoError, BLOCK_SZ,
+};
+use lazy_static::*;
+use spin::Mutex;
+use crate::config::{BLOCK_CACHE_HEAP_SHARE, BLOCK_FLUSH_INTERVAL, ROOT_DEVICE};
+use crate::drivers::block_device;
+use crate::mm::heap_allocator::heap_free_bytes;
+use crate::sync::InterruptMask;
+use crate::task::block_task_and_run_next;
+use crate::task::schedule::get_current_task;
+use crate::timer::{add_timer, get_time_in_ms};
+use crate::trap::enable_supervisor_interrupt;
+use super::vfs::{SuperBlock, Vnode, VnodeType};
+
+///Room for the SuperBlock, the journal, one inode bitmap block with its
+///inodes and some data
+pub const EASYFS_MIN_BLOCKS: usize = 4096;
+
+pub struct EasyFsSuperBlock {
+    device: Arc<dyn BlockDevice>,
+    root: Arc<Inode>,
+}
+
+lazy_static! {
+    ///The instances by the block device they were opened on
+    static ref INSTANCES: Mutex<BTreeMap<String, Arc<EasyFsSuperBlock>>> = Mutex::new(BTreeMap::new());
+    ///easy-fs on ROOT_DEVICE, mounted at "/"
+    pub static ref ROOT_FS: Arc<EasyFsSuperBlock> = open_easy_fs(ROOT_DEVICE)
+        .expect("no easy-fs on the root device");
+}
+
+///The instance on the device called `source`, opened on first use.
+///Every mount of a device shares it, so no block is cached twice
+fn open_easy_fs(source: &str) -> Option<Arc<EasyFsSuperBlock>> {
+    if let Some(sb) = INSTANCES.lock().get(source) {
+        return Some(sb.clone());
+    }
+    //opening reads the disk, which may sleep, so not under the lock
+    let device = block_device(source)?;
+    let efs = match EasyFileSystem::open(device.clone()) {
+        Ok(efs) => efs,
+        Err(err) => {
+            println!("[kernel] easy-fs on {}: {}", source, err);
+            return None;
+        }
+    };
+    let sb = Arc::new(EasyFsSuperBlock {
+        device,
+        root: Arc::new(EasyFileSystem::root_inode(&efs)),
+    });
+    //a mount racing with this one may have opened it first
+    Some(INSTANCES.lock().entry(String::from(source)).or_insert(sb).clone())
+}
+
+pub fn mount_easy_fs(source: &str) -> Option<Arc<dyn SuperBlock>> {
+    open_easy_fs(source).map(|sb| sb as Arc<dyn SuperBlock>)
+}
+
+///Write an empty easy-fs over the `blocks` blocks of `device`, at least
+///EASYFS_MIN_BLOCKS of them
+pub fn format_easy_fs(device: Arc<dyn BlockDevice>, blocks: usize) {
+    assert!(blocks >= EASYFS_MIN_BLOCKS, "{} blocks are too few for easy-fs", blocks);
+    //create writes every block back before it returns
+    EasyFileSystem::create(device, blocks.min(u32::MAX as usize) as u32, 1, InodeFormat::Indirect);
+}
+
+impl SuperBlock for EasyFsSuperBlock {
+    fn fs_type(&self) -> &'static str {
+        "easyfs"
+    }
+    fn root(&self) -> Arc<dyn Vnode> {
+        self.root.clone()
+    }
+    fn sync(&self) {
+        block_cache_sync(&self.device);
+    }
+}
+
+///A block failing its checksum is reported and the operation fails the
+///way the VNode interface lets it: nothing read, nothing found
+fn checked<T>(result: Result<T, IoError>, failed: T) -> T {
+    result.unwrap_or_else(|err| {
+        println!("[kernel] easy-fs: {}", err);
+        failed
+    })
+}
+
+impl Vnode for Inode {
+    fn id(&self) -> u32 {
+        self.inode_id()
+    }
+    fn vtype(&self) -> VnodeType {
+        if checked(Inode::is_dir(self), false) {
+            VnodeType::Dir
+        } else if checked(Inode::is_fifo(self), false) {
+            VnodeType::Fifo
+        } else {
+            VnodeType::File
+        }
+    }
+    fn size(&self) -> usize {
+        checked(Inode::size(self), 0) as usize
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        checked(Inode::read_at(self, offset, buf), 0)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        checked(Inode::write_at(self, offset, buf), 0)
+    }
+    fn truncate(&self) {
+        checked(self.clear(), ());
+    }
+    fn punch_hole(&self, offset: usize, len: usize) -> bool {
+        checked(Inode::punch_hole(self, offset, len).map(|_| true), false)
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn Vnode>> {
+        checked(self.find(name), None).map(|inode| inode as Arc<dyn Vnode>)
+    }
+    fn create(&self, name: &str, vtype: VnodeType) -> Option<Arc<dyn Vnode>> {
+        let inode = match vtype {
+            VnodeType::File => Inode::create(self, name),
+            VnodeType::Dir => self.create_dir(name),
+            VnodeType::Fifo => self.create_fifo(name),
+            _ => Ok(None),
+        };
+        checked(inode, None).map(|inode| inode as Arc<dyn Vnode>)
+    }
+    fn unlink(&self, name: &str) -> bool {
+        checked(Inode::unlink(self, name), false)
+    }
+    fn read_dir(&self) -> Vec<(String, Arc<dyn Vnode>)> {
+        checked(Inode::read_dir(self), Vec::new())
+            .into_iter()
+            .map(|(name, inode)| (name, inode as Arc<dyn Vnode>))
+            .collect()
+    }
+    fn owner(&self) -> (u32, u32) {
+        checked(Inode::owner(self), None).map_or((0, 0), |owner| (owner.uid, owner.gid))
+    }
+    //an unreadable owner leaves the file to root alone
+    fn mode(&self) -> u32 {
+        checked(Inode::owner(self).map(|owner| owner.map_or(0o777, |owner| owner.mode)), 0)
+    }
+    fn chmod(&self, mode: u32) -> bool {
+        match checked(Inode::owner(self), None) {
+            Some(owner) => checked(self.set_owner(InodeOwner { mode, ..owner }), false),
+            None => false,
+        }
+    }
+    fn chown(&self, uid: u32, gid: u32) -> bool {
+        match checked(Inode::owner(self), None) {
+            Some(owner) => checked(self.set_owner(InodeOwner { uid, gid, ..owner }), false),
+            None => false,
+        }
+    }
+}
+
+/// Size the block cache from the free kernel heap, before the first disk access
+pub fn init_block_cache() {
+    let capacity = heap_free_bytes() / BLOCK_CACHE_HEAP_SHARE / BLOCK_SZ;
+    set_block_cache_capacity(capacity);
+    println!("block cache: {} blocks", capacity);
+}
+
+/// Kernel task writing dirty blocks back every BLOCK_FLUSH_INTERVAL ms.
+/// It sleeps on a timer in between, skips blocks which are busy and holds
+/// no lock of the cache while the disk works.
+pub fn block_flusher() -> ! {
+    //switched in with interrupts masked, disk I/O waits for its interrupt
+    enable_supervisor_interrupt();
+    loop {
+        //the timer must not fire before we are blocked, or the wakeup is lost
+        let mut int_ctrl = InterruptMask::new();
+        int_ctrl.mask_interrupt();
+        add_timer(get_time_in_ms() + BLOCK_FLUSH_INTERVAL, get_current_task());
+        block_task_and_run_next();
+        int_ctrl.unmask_interrupt();
+        block_cache_try_sync_all();
+    }
+}
@@ -0,0 +1,79 @@
+//! Read-only ext2 on a disk or partition, for images built
+//! with `mke2fs -d`
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use ext2::{Ext2FileSystem, FileType, Inode};
+use lazy_static::*;
+use spin::Mutex;
+use crate::drivers::block_device;
+use super::vfs::{SuperBlock, Vnode, VnodeType};
+
+pub struct Ext2SuperBlock {
+    root: Arc<Inode>,
+}
+
+lazy_static! {
+    ///The instances by the block device they were opened on
+    static ref INSTANCES: Mutex<BTreeMap<String, Arc<Ext2SuperBlock>>> = Mutex::new(BTreeMap::new());
+}
+
+///Every mount of a device shares one instance
+pub fn mount_ext2(source: &str) -> Option<Arc<dyn SuperBlock>> {
+    let mut instances = INSTANCES.lock();
+    if let Some(sb) = instances.get(source) {
+        return Some(sb.clone());
+    }
+    let fs = Ext2FileSystem::open(block_device(source)?)?;
+    let sb = Arc::new(Ext2SuperBlock {
+        root: Arc::new(Ext2FileSystem::root_inode(&fs)),
+    });
+    instances.insert(String::from(source), sb.clone());
+    Some(sb)
+}
+
+//nothing is ever written
+impl SuperBlock for Ext2SuperBlock {
+    fn fs_type(&self) -> &'static str {
+        "ext2"
+    }
+    fn root(&self) -> Arc<dyn Vnode> {
+        self.root.clone()
+    }
+}
+
+//device nodes have no driver behind them here, fifos and sockets read as files
+impl Vnode for Inode {
+    fn id(&self) -> u32 {
+        self.inode_id()
+    }
+    fn vtype(&self) -> VnodeType {
+        match self.file_type() {
+            FileType::Directory => VnodeType::Dir,
+            FileType::Symlink => VnodeType::Symlink,
+            FileType::CharDevice => VnodeType::CharDevice,
+            FileType::BlockDevice => VnodeType::BlockDevice,
+            FileType::Fifo => VnodeType::Fifo,
+            _ => VnodeType::File,
+        }
+    }
+    fn size(&self) -> usize {
+        Inode::size(self) as usize
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        Inode::read_at(self, offset, buf)
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn Vnode>> {
+        self.find(name).map(|inode| inode as Arc<dyn Vnode>)
+    }
+    fn read_dir(&self) -> Vec<(String, Arc<dyn Vnode>)> {
+        Inode::read_dir(self)
+            .into_iter()
+            .map(|(name, inode)| (name, inode as Arc<dyn Vnode>))
+            .collect()
+    }
+    fn read_link(&self) -> Option<String> {
+        Inode::read_link(self)
+    }
+}
@@ -0,0 +1,99 @@
+//! FAT32 on a disk or partition, for exchanging files with the host
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use fat32::{FatFileSystem, Inode};
+use lazy_static::*;
+use spin::Mutex;
+use crate::drivers::block_device;
+use easy_fs::BlockDevice;
+use super::vfs::{SuperBlock, Vnode, VnodeType};
+
+///Room for the 32 reserved sectors, two FATs and some clusters
+pub const FAT_MIN_BLOCKS: usize = 64;
+
+pub struct FatSuperBlock {
+    root: Arc<Inode>,
+}
+
+lazy_static! {
+    ///The instances by the block device they were opened on
+    static ref INSTANCES: Mutex<BTreeMap<String, Arc<FatSuperBlock>>> = Mutex::new(BTreeMap::new());
+}
+
+///Every mount of a device shares one instance, so its FAT is never changed twice at once
+pub fn mount_fat(source: &str) -> Option<Arc<dyn SuperBlock>> {
+    let mut instances = INSTANCES.lock();
+    if let Some(sb) = instances.get(source) {
+        return Some(sb.clone());
+    }
+    let fs = FatFileSystem::open(block_device(source)?)?;
+    let sb = Arc::new(FatSuperBlock {
+        root: Arc::new(FatFileSystem::root_inode(&fs)),
+    });
+    instances.insert(String::from(source), sb.clone());
+    Some(sb)
+}
+
+///Write an empty FAT32 over the `blocks` blocks of `device`, at least
+///FAT_MIN_BLOCKS of them
+pub fn format_fat(device: Arc<dyn BlockDevice>, blocks: usize) {
+    assert!(blocks >= FAT_MIN_BLOCKS, "{} blocks are too few for FAT32", blocks);
+    FatFileSystem::format(device, blocks.min(u32::MAX as usize) as u32);
+}
+
+//writes go straight to the disk, there is nothing to sync
+impl SuperBlock for FatSuperBlock {
+    fn fs_type(&self) -> &'static str {
+        "vfat"
+    }
+    fn root(&self) -> Arc<dyn Vnode> {
+        self.root.clone()
+    }
+}
+
+impl Vnode for Inode {
+    fn id(&self) -> u32 {
+        self.inode_id()
+    }
+    fn vtype(&self) -> VnodeType {
+        if Inode::is_dir(self) {
+            VnodeType::Dir
+        } else {
+            VnodeType::File
+        }
+    }
+    fn size(&self) -> usize {
+        Inode::size(self) as usize
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        Inode::read_at(self, offset, buf)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        Inode::write_at(self, offset, buf)
+    }
+    fn truncate(&self) {
+        self.clear();
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn Vnode>> {
+        self.find(name).map(|inode| inode as Arc<dyn Vnode>)
+    }
+    fn create(&self, name: &str, vtype: VnodeType) -> Option<Arc<dyn Vnode>> {
+        let inode = match vtype {
+            VnodeType::File => Inode::create(self, name),
+            VnodeType::Dir => self.create_dir(name),
+            _ => None,
+        };
+        inode.map(|inode| inode as Arc<dyn Vnode>)
+    }
+    fn unlink(&self, name: &str) -> bool {
+        Inode::unlink(self, name)
+    }
+    fn read_dir(&self) -> Vec<(String, Arc<dyn Vnode>)> {
+        Inode::read_dir(self)
+            .into_iter()
+            .map(|(name, inode)| (name, inode as Arc<dyn Vnode>))
+            .collect()
+    }
+}
@@ -0,0 +1,52 @@
+//! Entries of the fd table
+use alloc::sync::Arc;
+use spin::Mutex;
+use super::{File, OpenFlags, PollEvents};
+
+///An fd of a process: the open file, its status flags shared with every
+///fd dup and fork made of the same open, and flags of this fd alone
+#[derive(Clone)]
+pub struct FileDescriptor {
+    pub file: Arc<dyn File + Send + Sync>,
+    status: Arc<Mutex<OpenFlags>>,
+    ///Closed by exec
+    pub cloexec: bool,
+}
+
+impl FileDescriptor {
+    ///Keep what of `flags` belongs to the open file or the fd
+    pub fn new(file: Arc<dyn File + Send + Sync>, flags: OpenFlags) -> Self {
+        Self {
+            file,
+            status: Arc::new(Mutex::new(flags & OpenFlags::NONBLOCK)),
+            cloexec: flags.contains(OpenFlags::CLOEXEC),
+        }
+    }
+    ///Another fd of the same open file, dup clears CLOEXEC
+    pub fn dup(&self, cloexec: bool) -> Self {
+        Self {
+            file: self.file.clone(),
+            status: self.status.clone(),
+            cloexec,
+        }
+    }
+    ///The access mode and status flags the file is open with
+    pub fn status_flags(&self) -> OpenFlags {
+        let access = match (self.file.readable(), self.file.writable()) {
+            (true, true) => OpenFlags::RDWR,
+            (false, true) => OpenFlags::WRONLY,
+            _ => OpenFlags::RDONLY,
+        };
+        access | *self.status.lock()
+    }
+    ///Only NONBLOCK can be changed, the access mode stays
+    pub fn set_status_flags(&self, flags: OpenFlags) {
+        *self.status.lock() = flags & OpenFlags::NONBLOCK;
+    }
+    ///A read or write asking for `events` would wait and the file is open
+    ///with NONBLOCK; errors and hang ups let it go on and return at once
+    pub fn would_block(&self, events: PollEvents) -> bool {
+        self.status.lock().contains(OpenFlags::NONBLOCK)
+            && !self.file.poll().intersects(events | PollEvents::ERR | PollEvents::HUP)
+    }
+}
@@ -1,13 +1,15 @@
-
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::*;
-use easy_fs::{EasyFileSystem, Inode};
-use lazy_static::*;
-use crate::drivers::BLOCK_DEVICE;
 use spin::Mutex;
-use crate::fs::File;
+use crate::fs::{File, EISDIR};
+use super::lock::{self, Flock, RangeLockCmd, F_RDLCK, F_WRLCK};
 use crate::mm::memory_set::UserBuffer;
+use super::perm::{permitted, Credentials, MAY_EXEC, MAY_READ, MAY_WRITE};
+use super::pipe::open_fifo;
+use super::vfs::{lookup, lookup_parent, Dentry, Mount, Vnode, VnodeType};
 
 pub struct OSInode {
     readable: bool,
@@ -17,25 +19,42 @@ pub struct OSInode {
 /// The OS inode inner in 'UPSafeCell'
 pub struct OSInodeInner {
     offset: usize,
-    inode: Arc<Inode>,
+    dentry: Dentry,
+    //entries of a directory read by getdents from offset 0 on, later
+    //calls go on from the offset in them
+    dir_entries: Option<Vec<(String, Arc<dyn Vnode>)>>,
 }
 
 impl OSInode {
-    /// Construct an OS inode from a inode
-    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+    /// Construct an OS inode from a resolved path
+    pub fn new(readable: bool, writable: bool, dentry: Dentry) -> Self {
         Self {
             readable,
             writable,
-            inner: Mutex::new(OSInodeInner { offset: 0, inode }),
+            inner: Mutex::new(OSInodeInner { offset: 0, dentry, dir_entries: None }),
         }
     }
+    /// The vnode this file reads and writes
+    pub fn vnode(&self) -> Arc<dyn Vnode> {
+        self.inner.lock().dentry.vnode.clone()
+    }
+    /// The mount the vnode was found in
+    pub fn mount(&self) -> Arc<Mount> {
+        self.inner.lock().dentry.mount.clone()
+    }
+    /// The file system and inode number, the same for every mount of the
+    /// file system and every open of the inode
+    pub fn inode_key(&self) -> (usize, u32) {
+        let inner = self.inner.lock();
+        (Arc::as_ptr(&inner.dentry.mount.sb) as *const () as usize, inner.dentry.vnode.id())
+    }
     /// Read all data inside a inode into vector
     pub fn read_all(&self) -> Vec<u8> {
         let mut inner = self.inner.lock();
         let mut buffer = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
+            let len = inner.dentry.vnode.read_at(inner.offset, &mut buffer);
             if len == 0 {
                 break;
             }
@@ -46,17 +65,11 @@ impl OSInode {
     }
 }
 
-lazy_static! {
-    pub static ref ROOT_INODE: Arc<Inode> = {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
-        Arc::new(EasyFileSystem::root_inode(&efs))
-    };
-}
-/// List all files in the filesystems
+/// List all files in the root directory
 pub fn list_apps() {
     println!("/**** APPS ****");
-    for app in ROOT_INODE.ls() {
-        println!("{}", app);
+    for (name, _) in lookup("/").unwrap().vnode.read_dir() {
+        println!("{}", name);
     }
     println!("**************/");
 }
@@ -74,16 +87,44 @@ bitflags! {
         const CREATE = 1 << 9;
         ///Clear file and return an empty one
         const TRUNC = 1 << 10;
+        ///Reads and writes that would wait fail instead
+        const NONBLOCK = 1 << 11;
+        ///Must be a directory
+        const DIRECTORY = 1 << 16;
+        ///Close the fd on exec
+        const CLOEXEC = 1 << 19;
     }
 }
 
+///Directory entry types reported by getdents
+pub const DT_FIFO: u8 = 1;
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_BLK: u8 = 6;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+
+///Record header written by getdents, followed by a NUL terminated name;
+///every record is padded to 4 bytes. A FAT long name of 255 UTF-16 units
+///takes up to 765 bytes, more than a byte can count
+#[repr(C)]
+pub struct DirentHeader {
+    pub d_ino: u32,
+    pub d_size: u32,
+    pub d_reclen: u16,
+    pub d_namelen: u16,
+    pub d_type: u8,
+}
+
 impl OpenFlags {
     /// Do not check validity for simplicity
     /// Return (readable, writable)
     pub fn read_write(&self) -> (bool, bool) {
-        if self.is_empty() {
+        //flags of the fd itself ask for no access
+        let access = *self - Self::NONBLOCK - Self::CLOEXEC;
+        if access.is_empty() {
             (true, false)
-        } else if self.contains(Self::WRONLY) {
+        } else if access.contains(Self::WRONLY) {
             (false, true)
         } else {
             (true, true)
@@ -91,27 +132,158 @@ impl OpenFlags {
     }
 }
 
-///Open file with flags
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+///Open file with flags as `cred`, -1 if it does not exist or `cred` may
+///not access it that way, EISDIR if a directory is opened to be written
+pub fn open_file(name: &str, flags: OpenFlags, cred: &Credentials) -> Result<Arc<OSInode>, isize> {
     let (readable, writable) = flags.read_write();
-    if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
-            // clear size
-            inode.clear();
-            Some(Arc::new(OSInode::new(readable, writable, inode)))
-        } else {
+    let mut mask = 0;
+    if readable {
+        mask |= MAY_READ;
+    }
+    if writable {
+        mask |= MAY_WRITE;
+    }
+    if flags.contains(OpenFlags::DIRECTORY) {
+        return lookup(name)
+            .filter(|dentry| {
+                dentry.vnode.is_dir() && permitted(dentry.vnode.as_ref(), cred, MAY_READ)
+            })
+            .map(|dentry| Arc::new(OSInode::new(true, false, dentry)))
+            .ok_or(-1);
+    }
+    let dentry = match lookup(name) {
+        Some(dentry) => dentry,
+        None if flags.contains(OpenFlags::CREATE) => {
             // create file
-            ROOT_INODE
-                .create(name)
-                .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
+            let (parent, file_name) = lookup_parent(name).ok_or(-1)?;
+            if !permitted(parent.vnode.as_ref(), cred, MAY_WRITE | MAY_EXEC) {
+                return Err(-1);
+            }
+            let vnode = parent.vnode.create(&file_name, VnodeType::File).ok_or(-1)?;
+            // file systems without owners leave it to root
+            vnode.chown(cred.euid, cred.gid);
+            let dentry = Dentry {
+                path: format!("{}/{}", parent.path.trim_end_matches('/'), file_name),
+                vnode,
+                mount: parent.mount,
+            };
+            return Ok(Arc::new(OSInode::new(readable, writable, dentry)));
         }
+        None => return Err(-1),
+    };
+    let truncate = flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC);
+    if dentry.vnode.is_dir() && (truncate || writable) {
+        return Err(EISDIR);
+    }
+    if !permitted(dentry.vnode.as_ref(), cred, mask) {
+        return Err(-1);
+    }
+    // clear size, FIFOs and devices are opened as they are
+    if truncate && dentry.vnode.vtype() == VnodeType::File {
+        dentry.vnode.truncate();
+    }
+    Ok(Arc::new(OSInode::new(readable, writable, dentry)))
+}
+
+///Open any kind of file, device nodes give their own File and FIFOs one
+///end of the pipe shared by everyone who opened them
+pub fn open(name: &str, flags: OpenFlags, cred: &Credentials) -> Result<Arc<dyn File>, isize> {
+    let (readable, writable) = flags.read_write();
+    let file = open_file(name, flags, cred)?;
+    if let Some(device) = file.vnode().open_device(readable, writable) {
+        return Ok(device);
+    }
+    if file.vnode().vtype() == VnodeType::Fifo {
+        //the same inode seen through another mount of its file system is
+        //the same FIFO
+        let (fs, inode_id) = file.inode_key();
+        return open_fifo(fs, inode_id, flags).map(|pipe| pipe as Arc<dyn File>);
+    }
+    if file.vnode().vtype() != VnodeType::File && file.vnode().vtype() != VnodeType::Dir {
+        //a device refused the open mode
+        return Err(-1);
+    }
+    Ok(file)
+}
+
+///Remove a file or an empty directory, writing its parent needs write and
+///exec permission there.
+///Return -1 if it does not exist or cannot be removed, -2 if `cred` may not
+pub fn unlink(name: &str, cred: &Credentials) -> isize {
+    let (parent, file_name) = match lookup_parent(name) {
+        Some(found) => found,
+        None => return -1,
+    };
+    if parent.vnode.lookup(&file_name).is_none() {
+        return -1;
+    }
+    if !permitted(parent.vnode.as_ref(), cred, MAY_WRITE | MAY_EXEC) {
+        return -2;
+    }
+    if parent.vnode.unlink(&file_name) {
+        0
     } else {
-        ROOT_INODE.find(name).map(|inode| {
-            if flags.contains(OpenFlags::TRUNC) {
-                inode.clear();
-            }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
+        -1
+    }
+}
+
+///Create the FIFO `name` owned by `cred` with permission bits `mode`,
+///writing its parent needs write and exec permission there.
+///Return -1 if it exists or the file system cannot hold FIFOs, -2 if
+///`cred` may not
+pub fn mkfifo(name: &str, mode: u32, cred: &Credentials) -> isize {
+    let (parent, file_name) = match lookup_parent(name) {
+        Some(found) => found,
+        None => return -1,
+    };
+    if parent.vnode.lookup(&file_name).is_some() {
+        return -1;
+    }
+    if !permitted(parent.vnode.as_ref(), cred, MAY_WRITE | MAY_EXEC) {
+        return -2;
+    }
+    match parent.vnode.create(&file_name, VnodeType::Fifo) {
+        Some(vnode) => {
+            // file systems without owners leave it to root
+            vnode.chown(cred.euid, cred.gid);
+            vnode.chmod(mode & 0o777);
+            0
+        }
+        None => -1,
+    }
+}
+
+///Change the permission bits of a file, only its owner and root may.
+///Return -1 if it does not exist or the file system cannot, -2 if `cred` may not
+pub fn chmod(name: &str, mode: u32, cred: &Credentials) -> isize {
+    let dentry = match lookup(name) {
+        Some(dentry) => dentry,
+        None => return -1,
+    };
+    if !cred.is_root() && dentry.vnode.owner().0 != cred.euid {
+        return -2;
+    }
+    if dentry.vnode.chmod(mode & 0o7777) {
+        0
+    } else {
+        -1
+    }
+}
+
+///Give a file to another user and group, only root may.
+///Return -1 if it does not exist or the file system cannot, -2 if `cred` may not
+pub fn chown(name: &str, uid: u32, gid: u32, cred: &Credentials) -> isize {
+    let dentry = match lookup(name) {
+        Some(dentry) => dentry,
+        None => return -1,
+    };
+    if !cred.is_root() {
+        return -2;
+    }
+    if dentry.vnode.chown(uid, gid) {
+        0
+    } else {
+        -1
     }
 }
 
@@ -129,7 +301,7 @@ impl File for OSInode {
         for i in 0..nums {
             let phys_buf = buf.kernel_bufs.get(&i).unwrap();
             let cur_buf = unsafe {core::slice::from_raw_parts_mut(phys_buf.start as *mut u8, phys_buf.len)};
-            let read_size = inner.inode.read_at(inner.offset, cur_buf);
+            let read_size = inner.dentry.vnode.read_at(inner.offset, cur_buf);
             if read_size == 0 {
                 break;
             }
@@ -145,11 +317,113 @@ impl File for OSInode {
         for i in 0..nums {
             let phys_buf = buf.kernel_bufs.get(&i).unwrap();
             let cur_buf = unsafe {core::slice::from_raw_parts(phys_buf.start as *const u8, phys_buf.len)};
-            let write_size = inner.inode.write_at(inner.offset, cur_buf);
-            assert_eq!(write_size, phys_buf.len);
+            let write_size = inner.dentry.vnode.write_at(inner.offset, cur_buf);
             inner.offset += write_size;
             total_write_size += write_size;
+            //the file system is full
+            if write_size < phys_buf.len {
+                break;
+            }
         }
         total_write_size
     }
-}
\ No newline at end of file
+    fn punch_hole(&self, offset: usize, len: usize) -> isize {
+        let inner = self.inner.lock();
+        if !self.writable || inner.dentry.vnode.vtype() != VnodeType::File {
+            return -1;
+        }
+        if inner.dentry.vnode.punch_hole(offset, len) {
+            0
+        } else {
+            -1
+        }
+    }
+    //the open file owns its flock lock, dups and forks share it
+    fn flock(&self, op: usize) -> isize {
+        let vtype = self.vnode().vtype();
+        if vtype != VnodeType::File && vtype != VnodeType::Dir {
+            return -1;
+        }
+        lock::flock(self.inode_key(), self as *const Self as usize, op)
+    }
+    fn lock_range(&self, pid: usize, cmd: RangeLockCmd, flock: &mut Flock) -> isize {
+        let (base, vtype) = {
+            let inner = self.inner.lock();
+            let base = match flock.l_whence {
+                0 => 0,
+                1 => inner.offset,
+                2 => inner.dentry.vnode.size(),
+                _ => return -1,
+            };
+            (base, inner.dentry.vnode.vtype())
+        };
+        if vtype != VnodeType::File {
+            return -1;
+        }
+        //setting a lock needs the access it stands for
+        if cmd != RangeLockCmd::Test
+            && (flock.l_type == F_RDLCK && !self.readable || flock.l_type == F_WRLCK && !self.writable)
+        {
+            return -1;
+        }
+        lock::lock_range(self.inode_key(), pid, base, flock, cmd)
+    }
+    fn release_locks(&self, pid: usize) {
+        lock::release_range_locks(self.inode_key(), pid);
+    }
+    //offset of a directory counts entries instead of bytes
+    fn getdents(&self, buf: &UserBuffer) -> isize {
+        let mut inner = self.inner.lock();
+        if !inner.dentry.vnode.is_dir() {
+            return -1;
+        }
+        //the directory is read once per pass, a seek back to 0 starts another
+        if inner.offset == 0 || inner.dir_entries.is_none() {
+            inner.dir_entries = Some(inner.dentry.vnode.read_dir());
+        }
+        let header_len = core::mem::size_of::<DirentHeader>();
+        let mut records: Vec<u8> = Vec::new();
+        let inner = &mut *inner;
+        let entries = inner.dir_entries.as_ref().unwrap();
+        for (name, inode) in entries.iter().skip(inner.offset) {
+            let reclen = (header_len + name.len() + 1 + 3) & !3;
+            if records.len() + reclen > buf.len {
+                break;
+            }
+            let header = DirentHeader {
+                d_ino: inode.id(),
+                d_size: inode.size() as u32,
+                d_reclen: reclen as u16,
+                d_type: match inode.vtype() {
+                    VnodeType::File => DT_REG,
+                    VnodeType::Dir => DT_DIR,
+                    VnodeType::CharDevice => DT_CHR,
+                    VnodeType::BlockDevice => DT_BLK,
+                    VnodeType::Symlink => DT_LNK,
+                    VnodeType::Fifo => DT_FIFO,
+                },
+                d_namelen: name.len() as u16,
+            };
+            let header_bytes = unsafe {
+                core::slice::from_raw_parts(&header as *const DirentHeader as *const u8, header_len)
+            };
+            let start = records.len();
+            records.extend_from_slice(header_bytes);
+            records.extend_from_slice(name.as_bytes());
+            records.resize(start + reclen, 0);
+            inner.offset += 1;
+        }
+        if records.is_empty() && inner.offset < entries.len() {
+            //user buffer can not hold even one record
+            return -1;
+        }
+        buf.write_kernel_slice_to_user(records.as_ptr() as usize, records.len());
+        records.len() as isize
+    }
+}
+impl Drop for OSInode {
+    //the last fd of the open file is closed
+    fn drop(&mut self) {
+        lock::release_file_locks(self.inode_key(), self as *const Self as usize);
+    }
+}
@@ -0,0 +1,246 @@
+//! Advisory locks: whole-file flock locks owned by an open file and POSIX
+//! byte-range locks owned by a process. Both kinds are kept per inode and
+//! never conflict with each other
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+use crate::sync::InterruptMask;
+use crate::task::block_task_and_run_next;
+use crate::task::process::try_wakeup_task;
+use crate::task::schedule::{get_current_task, TaskID};
+use super::{EAGAIN, EINVAL};
+
+///flock operations, NB may be added to SH and EX
+pub const LOCK_SH: usize = 1;
+pub const LOCK_EX: usize = 2;
+pub const LOCK_NB: usize = 4;
+pub const LOCK_UN: usize = 8;
+
+///Types of a byte-range lock
+pub const F_RDLCK: i16 = 0;
+pub const F_WRLCK: i16 = 1;
+pub const F_UNLCK: i16 = 2;
+
+///Byte-range lock passed to fcntl, the same layout as Linux's flock
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Flock {
+    pub l_type: i16,
+    ///SEEK_SET, SEEK_CUR or SEEK_END, what l_start counts from
+    pub l_whence: i16,
+    pub l_start: i64,
+    ///0 locks up to the end of the file, however far it grows
+    pub l_len: i64,
+    ///Filled in by F_GETLK
+    pub l_pid: i32,
+}
+
+///What fcntl does with a `Flock`
+#[derive(Clone, Copy, PartialEq)]
+pub enum RangeLockCmd {
+    ///Report the first lock in the way, F_UNLCK if there is none
+    Test,
+    ///Set or clear the lock, fail if another owner is in the way
+    Set,
+    ///Set or clear the lock, waiting for other owners
+    SetWait,
+}
+
+///Files are identified by the address of the open file, so `dup` and
+///`fork` share their flock lock
+#[derive(Clone, Copy, PartialEq)]
+enum LockOwner {
+    File(usize),
+    Process(usize),
+}
+
+#[derive(Clone, Copy)]
+struct Lock {
+    owner: LockOwner,
+    start: usize,
+    ///Exclusive, usize::MAX for locks to the end of the file
+    end: usize,
+    exclusive: bool,
+}
+
+impl Lock {
+    fn conflicts(&self, other: &Lock) -> bool {
+        let same_kind = matches!(
+            (self.owner, other.owner),
+            (LockOwner::File(_), LockOwner::File(_)) | (LockOwner::Process(_), LockOwner::Process(_))
+        );
+        same_kind
+            && self.owner != other.owner
+            && (self.exclusive || other.exclusive)
+            && self.start < other.end
+            && other.start < self.end
+    }
+}
+
+#[derive(Default)]
+struct InodeLocks {
+    locks: Vec<Lock>,
+    waiters: VecDeque<TaskID>,
+}
+
+impl InodeLocks {
+    fn conflict(&self, lock: &Lock) -> Option<Lock> {
+        self.locks.iter().find(|held| held.conflicts(lock)).copied()
+    }
+    //the owner gives up [start, end), what it held around that is kept.
+    //Return if it held anything there
+    fn remove(&mut self, owner: LockOwner, start: usize, end: usize) -> bool {
+        let mut kept = Vec::new();
+        let mut removed = false;
+        for lock in self.locks.drain(..) {
+            if lock.owner != owner || lock.end <= start || end <= lock.start {
+                kept.push(lock);
+                continue;
+            }
+            removed = true;
+            if lock.start < start {
+                kept.push(Lock { end: start, ..lock });
+            }
+            if end < lock.end {
+                kept.push(Lock { start: end, ..lock });
+            }
+        }
+        self.locks = kept;
+        removed
+    }
+    fn wake_all(&mut self) {
+        while let Some(task_id) = self.waiters.pop_front() {
+            try_wakeup_task(task_id);
+        }
+    }
+}
+
+lazy_static! {
+    ///Locks by file system and inode number, like the FIFOs
+    static ref LOCKS: Mutex<BTreeMap<(usize, u32), InodeLocks>> = Mutex::new(BTreeMap::new());
+}
+
+///Take `lock` once no other owner is in the way, replacing the owner's
+///old locks in its range. Return EAGAIN instead of waiting unless `wait`
+fn acquire(key: (usize, u32), lock: Lock, wait: bool) -> isize {
+    let mut int_ctrl = InterruptMask::new();
+    loop {
+        //nobody may unlock between queueing up and going to sleep
+        int_ctrl.mask_interrupt();
+        let mut locks = LOCKS.lock();
+        let inode_locks = locks.entry(key).or_default();
+        if inode_locks.conflict(&lock).is_none() {
+            //turning an exclusive lock into a shared one may let others in
+            if inode_locks.remove(lock.owner, lock.start, lock.end) {
+                inode_locks.wake_all();
+            }
+            inode_locks.locks.push(lock);
+            drop(locks);
+            int_ctrl.unmask_interrupt();
+            return 0;
+        }
+        if !wait {
+            drop(locks);
+            int_ctrl.unmask_interrupt();
+            return EAGAIN;
+        }
+        inode_locks.waiters.push_back(get_current_task());
+        drop(locks);
+        block_task_and_run_next();
+        int_ctrl.unmask_interrupt();
+    }
+}
+
+fn release(key: (usize, u32), owner: LockOwner, start: usize, end: usize) {
+    let mut locks = LOCKS.lock();
+    if let Some(inode_locks) = locks.get_mut(&key) {
+        if inode_locks.remove(owner, start, end) {
+            inode_locks.wake_all();
+        }
+        if inode_locks.locks.is_empty() {
+            locks.remove(&key);
+        }
+    }
+}
+
+///flock on the inode `key` for the open file at `file`
+pub fn flock(key: (usize, u32), file: usize, op: usize) -> isize {
+    let owner = LockOwner::File(file);
+    let wait = op & LOCK_NB == 0;
+    match op & !LOCK_NB {
+        LOCK_SH | LOCK_EX => {
+            let lock = Lock { owner, start: 0, end: usize::MAX, exclusive: op & LOCK_EX != 0 };
+            acquire(key, lock, wait)
+        }
+        LOCK_UN => {
+            release(key, owner, 0, usize::MAX);
+            0
+        }
+        _ => -1,
+    }
+}
+
+///The open file at `file` is gone, so is its flock lock
+pub fn release_file_locks(key: (usize, u32), file: usize) {
+    release(key, LockOwner::File(file), 0, usize::MAX);
+}
+
+///fcntl byte-range locking of process `pid` on the inode `key`, `base` is
+///the offset `flock.l_start` counts from
+pub fn lock_range(key: (usize, u32), pid: usize, base: usize, flock: &mut Flock, cmd: RangeLockCmd) -> isize {
+    //a range before the file or past what an offset holds is refused
+    let start = match (base as i64).checked_add(flock.l_start) {
+        Some(start) if start >= 0 && flock.l_len >= 0 => start as usize,
+        _ => return EINVAL,
+    };
+    let end = match flock.l_len {
+        0 => usize::MAX,
+        len => match start.checked_add(len as usize) {
+            Some(end) => end,
+            None => return EINVAL,
+        },
+    };
+    let owner = LockOwner::Process(pid);
+    let lock = Lock { owner, start, end, exclusive: flock.l_type == F_WRLCK };
+    match (cmd, flock.l_type) {
+        (RangeLockCmd::Test, F_RDLCK | F_WRLCK) => {
+            let conflict = LOCKS.lock().get(&key).and_then(|inode_locks| inode_locks.conflict(&lock));
+            match conflict {
+                Some(held) => {
+                    flock.l_type = if held.exclusive { F_WRLCK } else { F_RDLCK };
+                    flock.l_whence = 0;
+                    flock.l_start = held.start as i64;
+                    flock.l_len = if held.end == usize::MAX { 0 } else { (held.end - held.start) as i64 };
+                    flock.l_pid = match held.owner {
+                        LockOwner::Process(pid) => pid as i32,
+                        LockOwner::File(_) => -1,
+                    };
+                }
+                None => flock.l_type = F_UNLCK,
+            }
+            0
+        }
+        (RangeLockCmd::Test, _) => -1,
+        (_, F_UNLCK) => {
+            release(key, owner, start, end);
+            0
+        }
+        (_, F_RDLCK | F_WRLCK) => acquire(key, lock, cmd == RangeLockCmd::SetWait),
+        _ => -1,
+    }
+}
+
+///The process closed an fd of the inode `key`, POSIX drops all its locks
+///there even if other fds of the inode stay open
+pub fn release_range_locks(key: (usize, u32), pid: usize) {
+    release(key, LockOwner::Process(pid), 0, usize::MAX);
+}
+
+///The process exits, drop its byte-range locks everywhere
+pub fn release_process_locks(pid: usize) {
+    let keys: Vec<(usize, u32)> = LOCKS.lock().keys().copied().collect();
+    for key in keys {
+        release_range_locks(key, pid);
+    }
+}
@@ -1,9 +1,33 @@
 //! File system in os
+mod devfs;
+mod easyfs;
+mod ext2fs;
+mod fat;
+mod fd;
 mod inode;
+mod lock;
+mod perm;
+mod poll;
+mod procfs;
 mod stdio;
+mod tmpfs;
 pub mod pipe;
+pub mod vfs;
 
+use crate::config::ROOT_DEVICE;
 use crate::mm::memory_set::UserBuffer;
+use crate::task::schedule::TaskID;
+/// The Linux value, returned when only root may do what is asked
+pub const EPERM: isize = -1;
+/// The Linux value, returned when a FIFO is opened to write without waiting
+/// and nobody reads it
+pub const ENXIO: isize = -6;
+/// The Linux value, returned by calls that would wait when asked not to
+pub const EAGAIN: isize = -11;
+/// The Linux value, returned when a directory is opened to be written
+pub const EISDIR: isize = -21;
+/// The Linux value, returned for arguments out of range
+pub const EINVAL: isize = -22;
 /// File trait
 pub trait File: Send + Sync {
     /// If readable
@@ -14,7 +38,74 @@ pub trait File: Send + Sync {
     fn read(&self, buf: &UserBuffer) -> usize;
     /// Write `UserBuffer` to file
     fn write(&self, buf: &UserBuffer) -> usize;
+    /// Fill `UserBuffer` with directory entries, only directories support it
+    fn getdents(&self, _buf: &UserBuffer) -> isize {
+        -1
+    }
+    /// Free the blocks of `len` bytes from `offset` on, only regular files
+    /// support it
+    fn punch_hole(&self, _offset: usize, _len: usize) -> isize {
+        -1
+    }
+    /// The `PollEvents` the file is ready for, reads and writes of files
+    /// that never block are always ready
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::empty();
+        if self.readable() {
+            events |= PollEvents::IN;
+        }
+        if self.writable() {
+            events |= PollEvents::OUT;
+        }
+        events
+    }
+    /// Queue the polling task to be woken when `poll` may report more,
+    /// only files that can keep poll waiting need it
+    fn poll_wait(&self, _task_id: TaskID) {}
+    /// Take or drop the flock lock of this open file, only files on a
+    /// file system support it
+    fn flock(&self, _op: usize) -> isize {
+        -1
+    }
+    /// fcntl byte-range locking by process `pid`, only regular files
+    /// support it
+    fn lock_range(&self, _pid: usize, _cmd: RangeLockCmd, _flock: &mut Flock) -> isize {
+        -1
+    }
+    /// Process `pid` closed an fd of this file, drop its byte-range locks
+    fn release_locks(&self, _pid: usize) {}
 }
 
-pub use inode::{list_apps, open_file, OSInode, OpenFlags};
-pub use stdio::{Stdin, Stdout};
\ No newline at end of file
+pub use easyfs::{block_flusher, format_easy_fs, EASYFS_MIN_BLOCKS};
+pub use fat::{format_fat, FAT_MIN_BLOCKS};
+pub use fd::FileDescriptor;
+pub use inode::{chmod, chown, list_apps, mkfifo, open, open_file, unlink, OSInode, OpenFlags};
+pub use perm::{permitted, Credentials, MAY_EXEC, S_ISUID};
+pub use lock::{release_process_locks, Flock, RangeLockCmd};
+pub use poll::{poll, PollEvents};
+pub use stdio::{Stdin, Stdout};
+
+/// Bring up the block cache, register every file system type, mount
+/// easy-fs on ROOT_DEVICE as "/", a tmpfs on "/tmp", devices on "/dev"
+/// and processes on "/proc". FAT32 or ext2 on another disk, partition or
+/// RAM disk is mounted by hand by its name, like "vdb", "vdb1" or "ram1",
+/// with type "vfat" or "ext2"
+pub fn init() {
+    easyfs::init_block_cache();
+    vfs::register_filesystem("easyfs", easyfs::mount_easy_fs);
+    vfs::register_filesystem("tmpfs", tmpfs::mount_tmpfs);
+    vfs::register_filesystem("devfs", devfs::mount_devfs);
+    vfs::register_filesystem("procfs", procfs::mount_procfs);
+    vfs::register_filesystem("vfat", fat::mount_fat);
+    vfs::register_filesystem("ext2", ext2fs::mount_ext2);
+    vfs::mount_root(ROOT_DEVICE, easyfs::ROOT_FS.clone());
+    if vfs::mount("tmpfs", "/tmp", "tmpfs") != 0 {
+        println!("no /tmp directory, tmpfs is not mounted");
+    }
+    if vfs::mount("devfs", "/dev", "devfs") != 0 {
+        println!("no /dev directory, devfs is not mounted");
+    }
+    if vfs::mount("procfs", "/proc", "procfs") != 0 {
+        println!("no /proc directory, procfs is not mounted");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,45 @@
+//! Owners and permission bits of files, checked against the credentials
+//! of the process asking
+use super::vfs::Vnode;
+
+///Who a process acts as, the effective uid decides every access
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub euid: u32,
+}
+
+impl Credentials {
+    pub const ROOT: Self = Self { uid: 0, gid: 0, euid: 0 };
+    pub fn is_root(&self) -> bool {
+        self.euid == 0
+    }
+}
+
+///Access asked for, same bits as one rwx triple of a mode
+pub const MAY_EXEC: u32 = 1;
+pub const MAY_WRITE: u32 = 2;
+pub const MAY_READ: u32 = 4;
+///Running the file makes its owner the effective user
+pub const S_ISUID: u32 = 0o4000;
+
+///Whether `cred` may access `vnode` as `mask` asks. The owner is checked
+///against the owner bits, its group against the group bits and everyone
+///else against the last three; root may do anything but run a file
+///without any exec bit
+pub fn permitted(vnode: &dyn Vnode, cred: &Credentials, mask: u32) -> bool {
+    let mode = vnode.mode();
+    if cred.is_root() {
+        return mask & MAY_EXEC == 0 || vnode.is_dir() || mode & 0o111 != 0;
+    }
+    let (uid, gid) = vnode.owner();
+    let bits = if cred.euid == uid {
+        mode >> 6
+    } else if cred.gid == gid {
+        mode >> 3
+    } else {
+        mode
+    };
+    bits & mask == mask
+}
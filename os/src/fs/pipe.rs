@@ -1,8 +1,13 @@
 use crate::mm::memory_set::UserBuffer;
-use alloc::sync::Arc;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use lazy_static::*;
 use spin::Mutex;
-use crate::task::suspend_task_and_run_next;
-use crate::fs::File;
+use crate::task::{block_task_and_run_next, suspend_task_and_run_next};
+use crate::task::process::try_wakeup_task;
+use crate::fs::{File, OpenFlags, PollEvents, ENXIO};
+use crate::sync::{InterruptMask, PollQueue};
+use crate::task::schedule::{get_current_task, TaskID};
 use crate::task::TaskhandleStatus;
 
 //just use a 4k Page for pipe buffer
@@ -16,19 +21,31 @@ pub struct Pipe {
 
 
 impl Pipe {
-    pub fn get_read_pipe(buffer: Arc<Mutex<PipeRingBuffer>>) -> Self {
+    //every end counts itself in the buffer until dropped
+    fn new(readable: bool, writable: bool, buffer: Arc<Mutex<PipeRingBuffer>>) -> Self {
+        {
+            let mut ring = buffer.lock();
+            if readable {
+                ring.readers += 1;
+                ring.read_opens += 1;
+            }
+            if writable {
+                ring.writers += 1;
+                ring.write_opens += 1;
+            }
+            ring.wake_openers();
+        }
         Self {
-            readable: true,
-            writable: false,
+            readable,
+            writable,
             buffer,
         }
     }
+    pub fn get_read_pipe(buffer: Arc<Mutex<PipeRingBuffer>>) -> Self {
+        Self::new(true, false, buffer)
+    }
     pub fn get_write_pipe(buffer: Arc<Mutex<PipeRingBuffer>>) -> Self {
-        Self {
-            readable: false,
-            writable: true,
-            buffer,
-        }
+        Self::new(false, true, buffer)
     }
 }
 
@@ -54,9 +71,17 @@ pub struct PipeRingBuffer {
     arr: [u8; PIPE_RINGBUFFER_SIZE],
     head: usize,
     tail: usize,
-    reader_alive : bool,
-    writer_alive : bool,
+    //ends open now
+    readers : usize,
+    writers : usize,
+    //ends ever opened, an open FIFO waits for the other side to change
+    read_opens : usize,
+    write_opens : usize,
     status: RingBufferStatus,
+    //tasks polling either end, woken on every change
+    pollers : PollQueue,
+    //tasks opening a FIFO until the other side opens, woken by every open
+    openers : VecDeque<TaskID>,
 }
 
 impl PipeRingBuffer {
@@ -65,16 +90,27 @@ impl PipeRingBuffer {
             arr: [0; PIPE_RINGBUFFER_SIZE],
             head: 0,
             tail: 0,
-            reader_alive : true,
-            writer_alive : true,
+            readers : 0,
+            writers : 0,
+            read_opens : 0,
+            write_opens : 0,
             status: RingBufferStatus::EMPTY,
+            pollers : PollQueue::new(),
+            openers : VecDeque::new(),
+        }
+    }
+    fn wake_openers(&mut self) {
+        while let Some(task_id) = self.openers.pop_front() {
+            try_wakeup_task(task_id);
         }
     }
     pub fn writer_dead(&mut self) {
-        self.writer_alive = false;
+        self.writers -= 1;
+        self.pollers.wake_all();
     }
     pub fn reader_dead(&mut self) {
-        self.reader_alive = false;
+        self.readers -= 1;
+        self.pollers.wake_all();
     }
     pub fn avaliable_read(&self) -> usize {
         if self.status == RingBufferStatus::FULL {
@@ -94,7 +130,7 @@ impl PipeRingBuffer {
         //check if we can read
         if self.avaliable_read() == 0 {
             //no read data
-            if self.writer_alive {
+            if self.writers > 0 {
                 return (TaskhandleStatus::DOWAIT, 0);
             } else {
                 return (TaskhandleStatus::DOSTOP, 0);
@@ -119,13 +155,14 @@ impl PipeRingBuffer {
             if self.head == self.tail {
                 self.status = RingBufferStatus::EMPTY;
             }
+            self.pollers.wake_all();
             return (status, read_num);
         }
     }
     pub fn write_data(&mut self, in_buf : &[u8])-> (TaskhandleStatus, usize) {
         if self.avaliable_write() == 0 {
             //can not write, then check read status
-            if self.reader_alive {
+            if self.readers > 0 {
                 return (TaskhandleStatus::DOWAIT, 0);
             } else {
                 return (TaskhandleStatus::DOSTOP, 0);
@@ -150,6 +187,7 @@ impl PipeRingBuffer {
             if self.head == self.tail {
                 self.status = RingBufferStatus::FULL;
             }
+            self.pollers.wake_all();
             return (status, write_num);
         }
     }
@@ -222,6 +260,29 @@ impl File for Pipe {
         }
         len
     }
+    fn poll(&self) -> PollEvents {
+        let ring = self.buffer.lock();
+        let mut events = PollEvents::empty();
+        if self.readable {
+            if ring.avaliable_read() > 0 {
+                events |= PollEvents::IN;
+            }
+            if ring.writers == 0 {
+                events |= PollEvents::HUP;
+            }
+        }
+        if self.writable {
+            if ring.readers == 0 {
+                events |= PollEvents::ERR;
+            } else if ring.avaliable_write() > 0 {
+                events |= PollEvents::OUT;
+            }
+        }
+        events
+    }
+    fn poll_wait(&self, task_id: TaskID) {
+        self.buffer.lock().pollers.register(task_id);
+    }
 }
 
 pub fn get_dual_pipe_file() -> (Arc<Pipe>, Arc<Pipe>) {
@@ -229,4 +290,65 @@ pub fn get_dual_pipe_file() -> (Arc<Pipe>, Arc<Pipe>) {
     let read_pipe = Arc::new(Pipe::get_read_pipe(buffer.clone()));
     let write_pipe = Arc::new(Pipe::get_write_pipe(buffer.clone()));
     (read_pipe, write_pipe)
-}
\ No newline at end of file
+}
+
+lazy_static! {
+    ///Buffers of the FIFOs open now, by file system and inode number
+    static ref FIFOS: Mutex<BTreeMap<(usize, u32), Weak<Mutex<PipeRingBuffer>>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+///Attach to the buffer of the FIFO `id` of the file system `fs`, every
+///opener of the same inode shares it. Opening only for reading waits for
+///a writer and only for writing waits for a reader, unless `flags` holds
+///NONBLOCK: a reader then opens at once and a writer with no reader fails
+///with ENXIO. The buffer and what is left in it go away once the last end
+///is closed
+pub fn open_fifo(fs: usize, id: u32, flags: OpenFlags) -> Result<Arc<Pipe>, isize> {
+    let (readable, writable) = flags.read_write();
+    let nonblock = flags.contains(OpenFlags::NONBLOCK);
+    let buffer = {
+        let mut fifos = FIFOS.lock();
+        fifos.retain(|_, buffer| buffer.strong_count() > 0);
+        match fifos.get(&(fs, id)).and_then(Weak::upgrade) {
+            Some(buffer) => buffer,
+            None => {
+                let buffer = Arc::new(Mutex::new(PipeRingBuffer::new()));
+                fifos.insert((fs, id), Arc::downgrade(&buffer));
+                buffer
+            }
+        }
+    };
+    let since = {
+        let ring = buffer.lock();
+        if nonblock && writable && !readable && ring.readers == 0 {
+            return Err(ENXIO);
+        }
+        if readable { ring.write_opens } else { ring.read_opens }
+    };
+    let pipe = Arc::new(Pipe::new(readable, writable, buffer.clone()));
+    if readable != writable && !nonblock {
+        let mut int_ctrl = InterruptMask::new();
+        loop {
+            //nobody may open between queueing up and going to sleep
+            int_ctrl.mask_interrupt();
+            let mut ring = buffer.lock();
+            //the other side may have come and gone already
+            let peer_came = if readable {
+                ring.writers > 0 || ring.write_opens != since
+            } else {
+                ring.readers > 0 || ring.read_opens != since
+            };
+            if peer_came {
+                drop(ring);
+                int_ctrl.unmask_interrupt();
+                break;
+            }
+            ring.openers.push_back(get_current_task());
+            drop(ring);
+            block_task_and_run_next();
+            int_ctrl.unmask_interrupt();
+        }
+    }
+    Ok(pipe)
+}
@@ -0,0 +1,82 @@
+//! Waiting for any of several files to become ready
+use alloc::sync::Arc;
+use bitflags::*;
+use crate::sync::{sleep_polling, start_polling, stop_polling};
+use crate::task::schedule::get_current_task;
+use crate::timer::{add_poll_timer, get_time_in_ms, remove_timer};
+use super::File;
+
+bitflags! {
+    ///Events asked for and reported by poll
+    pub struct PollEvents: u16 {
+        ///There is data to read, or a read returns at once
+        const IN = 1 << 0;
+        ///A write does not block
+        const OUT = 1 << 2;
+        ///Writing to a pipe nobody reads, always reported
+        const ERR = 1 << 3;
+        ///The other end is closed, always reported
+        const HUP = 1 << 4;
+        ///The fd is not open, always reported
+        const NVAL = 1 << 5;
+    }
+}
+
+///What the file has to report out of `events`, errors and hang ups are
+///reported even if nobody asked for them
+fn ready_events(file: &Option<Arc<dyn File + Send + Sync>>, events: PollEvents) -> PollEvents {
+    match file {
+        Some(file) => file.poll() & (events | PollEvents::ERR | PollEvents::HUP),
+        None => PollEvents::NVAL,
+    }
+}
+
+///Wait until one of `files` reports something, at most `timeout_ms`
+///milliseconds, forever if it is negative and not at all if it is 0.
+///Every file is paired with the events asked for, the reported ones go
+///to `revents`, return how many files reported anything
+pub fn poll(
+    files: &[(Option<Arc<dyn File + Send + Sync>>, PollEvents)],
+    revents: &mut [PollEvents],
+    timeout_ms: isize,
+) -> usize {
+    let task_id = get_current_task();
+    let expire_ms = get_time_in_ms() + timeout_ms.max(0) as usize;
+    let mut waiting = false;
+    let mut timer_added = false;
+    let ready = loop {
+        let mut ready = 0;
+        for (index, (file, events)) in files.iter().enumerate() {
+            revents[index] = ready_events(file, *events);
+            if !revents[index].is_empty() {
+                ready += 1;
+            }
+        }
+        if ready > 0 || timeout_ms == 0 || (timeout_ms > 0 && get_time_in_ms() >= expire_ms) {
+            break ready;
+        }
+        if !waiting {
+            //queue on every file, then look once more, a file may have
+            //become ready before we were queued
+            start_polling(task_id);
+            for (file, _) in files.iter() {
+                if let Some(file) = file {
+                    file.poll_wait(task_id);
+                }
+            }
+            waiting = true;
+            continue;
+        }
+        if timeout_ms > 0 && !timer_added {
+            add_poll_timer(expire_ms, task_id);
+            timer_added = true;
+        }
+        sleep_polling(task_id);
+        waiting = false;
+    };
+    stop_polling(task_id);
+    if timer_added {
+        remove_timer(task_id);
+    }
+    ready
+}
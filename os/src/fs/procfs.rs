@@ -0,0 +1,228 @@
+//! Process file system, every file is text generated when it is read
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use lazy_static::*;
+use crate::board::{irq_count, irq_sources};
+use crate::config::KERNEL_PAGE_SIZE;
+use crate::drivers::block_devices;
+use crate::mm::frame_allocator::frame_stats;
+use crate::mm::heap_allocator::heap_stats;
+use crate::task::process::{process_ids, with_process};
+use crate::timer::get_time_in_ms;
+use easy_fs::BLOCK_SZ;
+use super::vfs::{mounts, SuperBlock, Vnode, VnodeType};
+
+//inode numbers: system files below 1 << PID_ID_SHIFT, then a range of
+//1 << PID_ID_SHIFT for every process holding its directory, its files,
+//its fd directory and from FD_ID_ENTRY on one per fd
+const PID_ID_SHIFT: u32 = 16;
+const FD_DIR_ENTRY: usize = 15;
+const FD_ID_ENTRY: usize = 16;
+
+pub struct ProcfsSuperBlock {
+    root: Arc<ProcNode>,
+}
+
+type TextFn = fn(usize) -> Option<String>;
+
+#[derive(Clone, Copy)]
+enum ProcKind {
+    Root,
+    Pid(usize),
+    Fds(usize),
+    Fd,
+    //generated text of a process, or of the system for pid 0
+    Text(TextFn, usize),
+}
+
+pub struct ProcNode {
+    id: u32,
+    kind: ProcKind,
+}
+
+lazy_static! {
+    static ref PROCFS: Arc<ProcfsSuperBlock> = Arc::new(ProcfsSuperBlock {
+        root: Arc::new(ProcNode { id: 1, kind: ProcKind::Root }),
+    });
+}
+
+///Nothing is stored, every mount shows the same tree
+pub fn mount_procfs(_source: &str) -> Option<Arc<dyn SuperBlock>> {
+    Some(PROCFS.clone())
+}
+
+impl SuperBlock for ProcfsSuperBlock {
+    fn fs_type(&self) -> &'static str {
+        "procfs"
+    }
+    fn root(&self) -> Arc<dyn Vnode> {
+        self.root.clone()
+    }
+}
+
+const SYSTEM_FILES: &[(&str, TextFn)] = &[
+    ("meminfo", meminfo),
+    ("uptime", uptime),
+    ("interrupts", interrupts),
+    ("mounts", mount_list),
+    ("partitions", partitions),
+];
+
+const PROCESS_FILES: &[(&str, TextFn)] = &[
+    ("status", status),
+    ("maps", maps),
+];
+
+fn meminfo(_pid: usize) -> Option<String> {
+    let (total_frames, free_frames) = frame_stats();
+    let (heap_total, heap_used) = heap_stats();
+    let mut text = String::new();
+    let _ = writeln!(text, "FramesTotal:\t{}", total_frames);
+    let _ = writeln!(text, "FramesFree:\t{}", free_frames);
+    let _ = writeln!(text, "MemTotal:\t{} kB", total_frames * KERNEL_PAGE_SIZE / 1024);
+    let _ = writeln!(text, "MemFree:\t{} kB", free_frames * KERNEL_PAGE_SIZE / 1024);
+    let _ = writeln!(text, "HeapTotal:\t{} kB", heap_total / 1024);
+    let _ = writeln!(text, "HeapUsed:\t{} kB", heap_used / 1024);
+    Some(text)
+}
+
+fn uptime(_pid: usize) -> Option<String> {
+    let ms = get_time_in_ms();
+    Some(format!("{}.{:02}\n", ms / 1000, ms % 1000 / 10))
+}
+
+fn interrupts(_pid: usize) -> Option<String> {
+    let mut text = String::new();
+    for (intr_src_id, name) in irq_sources() {
+        let _ = writeln!(text, "{:>3}: {:>10} {}", intr_src_id, irq_count(intr_src_id), name);
+    }
+    Some(text)
+}
+
+fn mount_list(_pid: usize) -> Option<String> {
+    let mut text = String::new();
+    for (source, path, fs_type) in mounts() {
+        let _ = writeln!(text, "{} {} {}", source, path, fs_type);
+    }
+    Some(text)
+}
+
+//sizes in 1K blocks like Linux
+fn partitions(_pid: usize) -> Option<String> {
+    let mut text = String::from("   #blocks name\n");
+    for device in block_devices() {
+        let _ = writeln!(text, "{:>10} {}", device.blocks * BLOCK_SZ / 1024, device.name);
+    }
+    Some(text)
+}
+
+fn status(pid: usize) -> Option<String> {
+    with_process(pid, |process| process.status_text())
+}
+
+fn maps(pid: usize) -> Option<String> {
+    with_process(pid, |process| process.maps_text())
+}
+
+fn fds(pid: usize) -> Vec<usize> {
+    let mut fds = with_process(pid, |process| {
+        process.fd_table.keys().cloned().collect::<Vec<usize>>()
+    })
+    .unwrap_or_default();
+    fds.sort();
+    fds
+}
+
+//the inode number of entry `entry` in the range of process `pid`, None
+//for the pids and fds too large to have one, they are not listed
+fn pid_id(pid: usize, entry: usize) -> Option<u32> {
+    let pid = u32::try_from(pid + 1).ok().filter(|pid| *pid < 1 << (32 - PID_ID_SHIFT))?;
+    let entry = u32::try_from(entry).ok().filter(|entry| *entry < 1 << PID_ID_SHIFT)?;
+    Some(pid << PID_ID_SHIFT | entry)
+}
+
+impl ProcNode {
+    fn new(id: u32, kind: ProcKind) -> Arc<dyn Vnode> {
+        Arc::new(Self { id, kind })
+    }
+    fn children(&self) -> Vec<(String, Arc<dyn Vnode>)> {
+        match self.kind {
+            ProcKind::Root => {
+                let mut children: Vec<(String, Arc<dyn Vnode>)> = SYSTEM_FILES
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (name, text))| {
+                        (name.to_string(), ProcNode::new(2 + i as u32, ProcKind::Text(*text, 0)))
+                    })
+                    .collect();
+                for pid in process_ids() {
+                    if let Some(id) = pid_id(pid, 0) {
+                        children.push((pid.to_string(), ProcNode::new(id, ProcKind::Pid(pid))));
+                    }
+                }
+                children
+            }
+            //the pid was checked when its directory was made
+            ProcKind::Pid(pid) => {
+                let mut children: Vec<(String, Arc<dyn Vnode>)> = PROCESS_FILES
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (name, text))| {
+                        (name.to_string(), ProcNode::new(pid_id(pid, 1 + i).unwrap(), ProcKind::Text(*text, pid)))
+                    })
+                    .collect();
+                children.push((String::from("fd"), ProcNode::new(pid_id(pid, FD_DIR_ENTRY).unwrap(), ProcKind::Fds(pid))));
+                children
+            }
+            ProcKind::Fds(pid) => fds(pid)
+                .into_iter()
+                .filter_map(|fd| Some((fd.to_string(), ProcNode::new(pid_id(pid, FD_ID_ENTRY.checked_add(fd)?)?, ProcKind::Fd))))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+    fn text(&self) -> String {
+        match self.kind {
+            ProcKind::Text(text, pid) => text(pid).unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+}
+
+impl Vnode for ProcNode {
+    fn id(&self) -> u32 {
+        self.id
+    }
+    fn vtype(&self) -> VnodeType {
+        match self.kind {
+            ProcKind::Root | ProcKind::Pid(_) | ProcKind::Fds(_) => VnodeType::Dir,
+            _ => VnodeType::File,
+        }
+    }
+    //the text is generated to be measured, it may change before it is read
+    fn size(&self) -> usize {
+        self.text().len()
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let text = self.text();
+        let bytes = text.as_bytes();
+        if offset >= bytes.len() {
+            return 0;
+        }
+        let len = buf.len().min(bytes.len() - offset);
+        buf[..len].copy_from_slice(&bytes[offset..offset + len]);
+        len
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn Vnode>> {
+        self.children()
+            .into_iter()
+            .find(|(child_name, _)| child_name == name)
+            .map(|(_, node)| node)
+    }
+    fn read_dir(&self) -> Vec<(String, Arc<dyn Vnode>)> {
+        self.children()
+    }
+}
@@ -1,6 +1,7 @@
 
 use crate::mm::memory_set::UserBuffer;
-use crate::fs::File;
+use crate::fs::{File, PollEvents};
+use crate::task::schedule::TaskID;
 use crate::drivers::chardev::UART;
 use crate::drivers::chardev::CharDevice;
 
@@ -26,6 +27,16 @@ impl File for Stdin {
     fn write(&self, _user_buf: &UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
+    fn poll(&self) -> PollEvents {
+        if UART.read_buffer_is_empty() {
+            PollEvents::empty()
+        } else {
+            PollEvents::IN
+        }
+    }
+    fn poll_wait(&self, task_id: TaskID) {
+        UART.poll_wait(task_id);
+    }
 }
 
 impl File for Stdout {
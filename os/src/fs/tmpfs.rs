@@ -0,0 +1,167 @@
+//! RAM file system, file data lives in physical frames and is lost on reboot
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+use crate::config::KERNEL_PAGE_SIZE;
+use crate::mm::address::PhysAddr;
+use crate::mm::frame_allocator::{frame_alloc, FrameWrapper};
+use super::vfs::{SuperBlock, Vnode, VnodeType};
+
+pub struct TmpfsSuperBlock {
+    root: Arc<TmpNode>,
+}
+
+pub struct TmpNode {
+    id: u32,
+    vtype: VnodeType,
+    //inode numbers are shared by all nodes of one mount
+    next_id: Arc<AtomicU32>,
+    inner: Mutex<TmpNodeInner>,
+}
+
+struct TmpNodeInner {
+    size: usize,
+    pages: Vec<FrameWrapper>,
+    children: Vec<(String, Arc<TmpNode>)>,
+}
+
+///Every mount is a new empty file system, the source is ignored
+pub fn mount_tmpfs(_source: &str) -> Option<Arc<dyn SuperBlock>> {
+    let next_id = Arc::new(AtomicU32::new(0));
+    Some(Arc::new(TmpfsSuperBlock {
+        root: TmpNode::new(VnodeType::Dir, next_id),
+    }))
+}
+
+impl SuperBlock for TmpfsSuperBlock {
+    fn fs_type(&self) -> &'static str {
+        "tmpfs"
+    }
+    fn root(&self) -> Arc<dyn Vnode> {
+        self.root.clone()
+    }
+}
+
+fn page_bytes(frame: &FrameWrapper) -> &'static mut [u8] {
+    //physical address is equal to kernel virtual address
+    let page_addr: usize = PhysAddr::from(frame.ppn).into();
+    unsafe { core::slice::from_raw_parts_mut(page_addr as *mut u8, KERNEL_PAGE_SIZE) }
+}
+
+impl TmpNode {
+    fn new(vtype: VnodeType, next_id: Arc<AtomicU32>) -> Arc<Self> {
+        Arc::new(Self {
+            id: next_id.fetch_add(1, Ordering::Relaxed),
+            vtype,
+            next_id,
+            inner: Mutex::new(TmpNodeInner {
+                size: 0,
+                pages: Vec::new(),
+                children: Vec::new(),
+            }),
+        })
+    }
+}
+
+impl Vnode for TmpNode {
+    fn id(&self) -> u32 {
+        self.id
+    }
+    fn vtype(&self) -> VnodeType {
+        self.vtype
+    }
+    fn size(&self) -> usize {
+        self.inner.lock().size
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let inner = self.inner.lock();
+        let end = inner.size.min(offset + buf.len());
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % KERNEL_PAGE_SIZE;
+            let len = (KERNEL_PAGE_SIZE - page_offset).min(end - pos);
+            let page = page_bytes(&inner.pages[pos / KERNEL_PAGE_SIZE]);
+            buf[pos - offset..pos - offset + len]
+                .copy_from_slice(&page[page_offset..page_offset + len]);
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+    //a short write means the frames ran out
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut inner = self.inner.lock();
+        let end = offset + buf.len();
+        while inner.pages.len() * KERNEL_PAGE_SIZE < end {
+            match frame_alloc() {
+                Some(frame) => {
+                    frame.clear_frame();
+                    inner.pages.push(frame);
+                }
+                None => break,
+            }
+        }
+        let end = end.min(inner.pages.len() * KERNEL_PAGE_SIZE);
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % KERNEL_PAGE_SIZE;
+            let len = (KERNEL_PAGE_SIZE - page_offset).min(end - pos);
+            let page = page_bytes(&inner.pages[pos / KERNEL_PAGE_SIZE]);
+            page[page_offset..page_offset + len]
+                .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        if end > inner.size {
+            inner.size = end;
+        }
+        end.saturating_sub(offset)
+    }
+    fn truncate(&self) {
+        let mut inner = self.inner.lock();
+        inner.size = 0;
+        inner.pages.clear();
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn Vnode>> {
+        self.inner
+            .lock()
+            .children
+            .iter()
+            .find(|(child_name, _)| child_name == name)
+            .map(|(_, node)| node.clone() as Arc<dyn Vnode>)
+    }
+    //a FIFO node holds no data, opening it attaches to the pipe of the node
+    fn create(&self, name: &str, vtype: VnodeType) -> Option<Arc<dyn Vnode>> {
+        let mut inner = self.inner.lock();
+        if self.vtype != VnodeType::Dir
+            || !matches!(vtype, VnodeType::File | VnodeType::Dir | VnodeType::Fifo)
+            || inner.children.iter().any(|(child_name, _)| child_name == name)
+        {
+            return None;
+        }
+        let node = TmpNode::new(vtype, self.next_id.clone());
+        inner.children.push((String::from(name), node.clone()));
+        Some(node)
+    }
+    fn unlink(&self, name: &str) -> bool {
+        let mut inner = self.inner.lock();
+        let index = match inner.children.iter().position(|(child_name, _)| child_name == name) {
+            Some(index) => index,
+            None => return false,
+        };
+        if !inner.children[index].1.inner.lock().children.is_empty() {
+            return false;
+        }
+        //frames are released once the last open file drops the node
+        inner.children.remove(index);
+        true
+    }
+    fn read_dir(&self) -> Vec<(String, Arc<dyn Vnode>)> {
+        self.inner
+            .lock()
+            .children
+            .iter()
+            .map(|(name, node)| (name.clone(), node.clone() as Arc<dyn Vnode>))
+            .collect()
+    }
+}
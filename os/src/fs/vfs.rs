@@ -0,0 +1,297 @@
+//! Virtual file system: every file system is a SuperBlock grafted onto a
+//! directory of one tree, paths are resolved through the mount table
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+use super::File;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VnodeType {
+    File,
+    Dir,
+    CharDevice,
+    BlockDevice,
+    Symlink,
+    ///Named pipe, opening it attaches to a buffer shared by its openers
+    Fifo,
+}
+
+/// One mounted instance of a file system
+pub trait SuperBlock: Send + Sync {
+    /// Name the file system type is registered with
+    fn fs_type(&self) -> &'static str;
+    /// Root directory of this file system
+    fn root(&self) -> Arc<dyn Vnode>;
+    /// Write everything cached back to the backing store
+    fn sync(&self) {}
+}
+
+/// A file or directory inside one file system
+pub trait Vnode: Send + Sync {
+    /// Inode number, unique inside the file system
+    fn id(&self) -> u32;
+    fn vtype(&self) -> VnodeType;
+    fn size(&self) -> usize;
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> usize {
+        0
+    }
+    /// Drop all data of a file
+    fn truncate(&self) {}
+    /// Zero `len` bytes from `offset` on and free the blocks entirely
+    /// inside them, false if the file system cannot
+    fn punch_hole(&self, _offset: usize, _len: usize) -> bool {
+        false
+    }
+    /// Find a child of a directory
+    fn lookup(&self, _name: &str) -> Option<Arc<dyn Vnode>> {
+        None
+    }
+    /// Create a child of a directory, fails if the name exists
+    fn create(&self, _name: &str, _vtype: VnodeType) -> Option<Arc<dyn Vnode>> {
+        None
+    }
+    /// Remove a child of a directory, directories must be empty
+    fn unlink(&self, _name: &str) -> bool {
+        false
+    }
+    /// All children of a directory with their names
+    fn read_dir(&self) -> Vec<(String, Arc<dyn Vnode>)> {
+        Vec::new()
+    }
+    fn is_dir(&self) -> bool {
+        self.vtype() == VnodeType::Dir
+    }
+    /// Owner as (uid, gid), file systems without owners give root
+    fn owner(&self) -> (u32, u32) {
+        (0, 0)
+    }
+    /// Permission bits, file systems without them let everyone do everything
+    fn mode(&self) -> u32 {
+        0o777
+    }
+    /// Change the permission bits, false if the file system cannot store them
+    fn chmod(&self, _mode: u32) -> bool {
+        false
+    }
+    /// Change the owner, false if the file system cannot store it
+    fn chown(&self, _uid: u32, _gid: u32) -> bool {
+        false
+    }
+    /// Target of a symbolic link
+    fn read_link(&self) -> Option<String> {
+        None
+    }
+    /// Device nodes hand out their own File, all other vnodes are
+    /// read and written through an OSInode
+    fn open_device(&self, _readable: bool, _writable: bool) -> Option<Arc<dyn File>> {
+        None
+    }
+}
+
+/// A file system grafted onto the directory at `path`
+pub struct Mount {
+    pub path: String,
+    /// What the file system was built from, like "vda" or "tmpfs"
+    pub source: String,
+    pub sb: Arc<dyn SuperBlock>,
+    components: Vec<String>,
+}
+
+/// A resolved path: the vnode it names and the mount it was found in.
+/// Holding a Dentry keeps the mount busy.
+pub struct Dentry {
+    pub path: String,
+    pub vnode: Arc<dyn Vnode>,
+    pub mount: Arc<Mount>,
+}
+
+/// Symbolic links followed while resolving one path
+const MAX_SYMLINK_DEPTH: usize = 8;
+
+/// Build a SuperBlock from a source such as a device name
+pub type MountFn = fn(source: &str) -> Option<Arc<dyn SuperBlock>>;
+
+lazy_static! {
+    static ref FS_TYPES: Mutex<Vec<(&'static str, MountFn)>> = Mutex::new(Vec::new());
+    static ref MOUNTS: Mutex<Vec<Arc<Mount>>> = Mutex::new(Vec::new());
+}
+
+pub fn register_filesystem(name: &'static str, mount_fn: MountFn) {
+    FS_TYPES.lock().push((name, mount_fn));
+}
+
+///Split a path into components, "." and ".." are resolved lexically and
+///relative paths start at the root since there is no working directory
+fn split_path(path: &str) -> Vec<String> {
+    let mut components: Vec<String> = Vec::new();
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(String::from(name)),
+        }
+    }
+    components
+}
+
+fn join_path(components: &[String]) -> String {
+    let mut path = String::new();
+    for name in components {
+        path.push('/');
+        path.push_str(name);
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    path
+}
+
+///Mount the file system every path starts from
+pub fn mount_root(source: &str, sb: Arc<dyn SuperBlock>) {
+    let mut mounts = MOUNTS.lock();
+    assert!(mounts.is_empty(), "root file system mounted twice");
+    mounts.push(Arc::new(Mount {
+        path: String::from("/"),
+        source: String::from(source),
+        sb,
+        components: Vec::new(),
+    }));
+}
+
+///Resolve a path to a dentry, crossing into mounted file systems and
+///following symbolic links
+pub fn lookup(path: &str) -> Option<Dentry> {
+    lookup_components(split_path(path), 0)
+}
+
+fn lookup_components(components: Vec<String>, depth: usize) -> Option<Dentry> {
+    //the deepest mount point on the path wins
+    let mount = MOUNTS
+        .lock()
+        .iter()
+        .filter(|mount| components.starts_with(&mount.components))
+        .max_by_key(|mount| mount.components.len())
+        .cloned()?;
+    let mut vnode = mount.sb.root();
+    for (i, name) in components.iter().enumerate().skip(mount.components.len()) {
+        if !vnode.is_dir() {
+            return None;
+        }
+        vnode = vnode.lookup(name)?;
+        if vnode.vtype() == VnodeType::Symlink {
+            if depth >= MAX_SYMLINK_DEPTH {
+                return None;
+            }
+            //a relative target starts at the directory holding the link,
+            //the rest of the path is resolved below the target
+            let target = vnode.read_link()?;
+            let mut path = if target.starts_with('/') {
+                String::new()
+            } else {
+                join_path(&components[..i])
+            };
+            path.push('/');
+            path.push_str(&target);
+            for rest in &components[i + 1..] {
+                path.push('/');
+                path.push_str(rest);
+            }
+            return lookup_components(split_path(&path), depth + 1);
+        }
+    }
+    Some(Dentry {
+        path: join_path(&components),
+        vnode,
+        mount,
+    })
+}
+
+///Resolve the directory holding the last component of a path,
+///return it with that name
+pub fn lookup_parent(path: &str) -> Option<(Dentry, String)> {
+    let mut components = split_path(path);
+    let name = components.pop()?;
+    let parent = lookup(&join_path(&components))?;
+    if !parent.vnode.is_dir() {
+        return None;
+    }
+    Some((parent, name))
+}
+
+///Graft a new instance of `fs_type` built from `source` onto the directory `target`.
+///Return -1 for an unknown type, a bad source or target, or a busy mount point
+pub fn mount(source: &str, target: &str, fs_type: &str) -> isize {
+    let mount_fn = match FS_TYPES.lock().iter().find(|(name, _)| *name == fs_type) {
+        Some((_, mount_fn)) => *mount_fn,
+        None => return -1,
+    };
+    let dentry = match lookup(target) {
+        Some(dentry) if dentry.vnode.is_dir() => dentry,
+        _ => return -1,
+    };
+    let components = split_path(&dentry.path);
+    let sb = match mount_fn(source) {
+        Some(sb) => sb,
+        None => return -1,
+    };
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|mount| mount.components == components) {
+        return -1;
+    }
+    mounts.push(Arc::new(Mount {
+        path: dentry.path.clone(),
+        source: String::from(source),
+        sb,
+        components,
+    }));
+    0
+}
+
+///Detach the file system mounted on `target` after writing it back.
+///Return -1 if nothing but the root is mounted there, -2 if it is still in use
+pub fn umount(target: &str) -> isize {
+    let components = split_path(target);
+    //syncing writes to the disk and may sleep, not while holding the table
+    let mount = {
+        let mut mounts = MOUNTS.lock();
+        let index = match mounts
+            .iter()
+            .position(|mount| !mount.components.is_empty() && mount.components == components)
+        {
+            Some(index) => index,
+            None => return -1,
+        };
+        //open files hold the mount, so do file systems mounted below it
+        let nested = mounts.iter().any(|mount| {
+            mount.components.len() > components.len() && mount.components.starts_with(&components)
+        });
+        if nested || Arc::strong_count(&mounts[index]) > 1 {
+            return -2;
+        }
+        mounts.remove(index)
+    };
+    mount.sb.sync();
+    0
+}
+
+///Mounted file systems as (source, path, file system type)
+pub fn mounts() -> Vec<(String, String, &'static str)> {
+    MOUNTS
+        .lock()
+        .iter()
+        .map(|mount| (mount.source.clone(), mount.path.clone(), mount.sb.fs_type()))
+        .collect()
+}
+
+///Write every mounted file system back
+pub fn sync_all() {
+    let mounts: Vec<Arc<Mount>> = MOUNTS.lock().clone();
+    for mount in mounts {
+        mount.sb.sync();
+    }
+}
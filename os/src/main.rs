@@ -56,12 +56,15 @@ fn entry_os() {
     println!("enable timer done..");
     board::init_qemu_devices();
     println!("all drivers start-up..");
+    fs::init();
     fs::list_apps();
     println!("All apps have checked..");
     task::process::run_init_process();
     println!("run init process done..");
+    task::process::run_kernel_task(fs::block_flusher);
+    println!("run block flusher done..");
     config::set_file_non_blocking();
-    println!("All file operations switch to non-blocking..");
+    println!("Block devices switch to interrupts..");
     task_schedule();
     panic!("ERROR!!!should never get here");
 }
@@ -36,6 +36,7 @@ trait FrameAllocator {
 }
 
 pub struct StackFrameAllocator {
+    first_ppn : usize,
     cur_ppn : usize,
     last_ppn : usize,
     unused_ppns : Vec<usize>,
@@ -44,15 +45,21 @@ pub struct StackFrameAllocator {
 impl StackFrameAllocator {
     pub fn new()->Self {
         Self {
+            first_ppn : 0,
             cur_ppn : 0,
             last_ppn : 0,
             unused_ppns : Vec::new(),
         }
     }
     pub fn init(&mut self, cur : PhysPageNum,  last : PhysPageNum) {
+        self.first_ppn = cur.0;
         self.cur_ppn = cur.0;
         self.last_ppn = last.0;
     }
+    //(total, free) frames
+    pub fn stats(&self) -> (usize, usize) {
+        (self.last_ppn - self.first_ppn, self.last_ppn - self.cur_ppn + self.unused_ppns.len())
+    }
 }
 
 static mut FRAME_ALLOCATOR: Option<&mut StackFrameAllocator> = None;
@@ -94,6 +101,12 @@ pub fn frame_alloc()->Option<FrameWrapper> {
     }
 }
 
+pub fn frame_stats() -> (usize, usize) {
+    unsafe {
+        FRAME_ALLOCATOR.as_ref().unwrap().stats()
+    }
+}
+
 pub fn frame_dealloc(ppn : PhysPageNum) {
     unsafe {
         FRAME_ALLOCATOR.as_mut().unwrap().dealloc(ppn)
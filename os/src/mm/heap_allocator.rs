@@ -15,6 +15,18 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
 
 static mut OS_HEAP : [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 
+//bytes of kernel heap not allocated yet
+pub fn heap_free_bytes() -> usize {
+    let heap = HEAP_ALLOCATOR.lock();
+    heap.stats_total_bytes() - heap.stats_alloc_actual()
+}
+
+//(total, allocated) bytes of kernel heap
+pub fn heap_stats() -> (usize, usize) {
+    let heap = HEAP_ALLOCATOR.lock();
+    (heap.stats_total_bytes(), heap.stats_alloc_actual())
+}
+
 #[no_mangle]
 #[inline(never)]
 pub fn init_heap() {
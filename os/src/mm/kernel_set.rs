@@ -10,6 +10,7 @@ use crate::alloc::string::ToString;
 use crate::mm::address::USIZE_MAX;
 use crate::config::KERNEL_PAGE_WIDTH_BITS;
 use crate::config::AVALIABLE_MEMORY_END;
+use crate::config::{INITRD_START, INITRD_END};
 use crate::config::KERNEL_PAGE_SIZE;
 use crate::config::KERNEL_STACK_SIZE;
 
@@ -157,6 +158,7 @@ fn set_memory_sets(boot_level : usize)
     let area2 = MemoryStaticArea::new((srodata as usize).into(), (erodata as usize).into(), MapPermission::R);
     let area3 = MemoryStaticArea::new((sdata as usize).into(), (ebss as usize).into(), MapPermission::R | MapPermission::W);
     let area4 = MemoryStaticArea::new((ekernel as usize).into(), (AVALIABLE_MEMORY_END as usize).into(), MapPermission::R | MapPermission::W);
+    let area5 = MemoryStaticArea::new(INITRD_START.into(), INITRD_END.into(), MapPermission::R | MapPermission::W);
     
     match boot_level {
         1 => {
@@ -168,6 +170,7 @@ fn set_memory_sets(boot_level : usize)
             area2.map_area_normal(&kern_table);
             area3.map_area_normal(&kern_table);
             area4.map_area_normal(&kern_table);
+            area5.map_area_normal(&kern_table);
             for (start , size) in MMIO {
                 let cur_area = MemoryStaticArea::new((*start).into(), (*start + *size).into(), MapPermission::R | MapPermission::W);
                 cur_area.map_area_normal(&kern_table);
@@ -186,6 +189,7 @@ fn set_memory_sets(boot_level : usize)
                 KERNEL_MEMSETS.as_mut().unwrap().add_core_map(String::from("rodata"), area2);
                 KERNEL_MEMSETS.as_mut().unwrap().add_core_map(String::from("data_bss"), area3);
                 KERNEL_MEMSETS.as_mut().unwrap().add_core_map(String::from("frames"), area4);
+                KERNEL_MEMSETS.as_mut().unwrap().add_core_map(String::from("initrd"), area5);
                 for i in 0..MMIO.len() {
                     let cur_area = MemoryStaticArea::new(MMIO[i].0.into(), (MMIO[i].0 + MMIO[i].1).into(), MapPermission::R | MapPermission::W);
                     let io_name = String::from("MMIO_") + &(i.to_string());
@@ -15,6 +15,10 @@ use crate::{
 };
 
 use self::{port_table::check_accept, socket::set_s_a_by_index};
+use crate::fs::PollEvents;
+use crate::sync::wake_poller;
+use crate::task::suspend_task_and_run_next;
+use crate::task::schedule::TaskID;
 
 pub struct NetStack(OneCoreCell<LoseStack>);
 
@@ -101,6 +105,26 @@ pub fn net_interrupt_handler() {
     }
 }
 
+// readiness of a socket for poll, sending never blocks
+pub fn socket_poll(socket_index: usize) -> PollEvents {
+    if socket::has_data(socket_index) {
+        PollEvents::IN | PollEvents::OUT
+    } else {
+        PollEvents::OUT
+    }
+}
+
+// a polling task queues on the socket and push_data wakes it when a packet
+// for the socket comes in.
+// NOTICE: the net device has no interrupt handler, packets only come in
+// when a task reads them, so the poller gives the CPU to the others first
+// and then looks at its files again once it is scheduled
+pub fn socket_poll_wait(socket_index: usize, task_id: TaskID) {
+    socket::register_poller(socket_index, task_id);
+    suspend_task_and_run_next();
+    wake_poller(task_id);
+}
+
 #[allow(unused)]
 pub fn hexdump(data: &[u8]) {
     const PRELAND_WIDTH: usize = 70;
@@ -1,9 +1,11 @@
 use alloc::collections::VecDeque;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use lazy_static::lazy_static;
 use lose_net_stack::IPv4;
 
-use crate::sync::OneCoreCell;
+use crate::sync::{OneCoreCell, PollQueue};
+use crate::task::schedule::TaskID;
 
 // TODO: specify the protocol, TCP or UDP
 pub struct Socket {
@@ -13,6 +15,7 @@ pub struct Socket {
     pub buffers: VecDeque<Vec<u8>>, // datas
     pub seq: u32,
     pub ack: u32,
+    pub pollers: Arc<PollQueue>,  // tasks polling for data
 }
 
 lazy_static! {
@@ -81,6 +84,7 @@ pub fn add_socket(raddr: IPv4, lport: u16, rport: u16) -> Option<usize> {
         buffers: VecDeque::new(),
         seq: 0,
         ack: 0,
+        pollers: Arc::new(PollQueue::new()),
     };
 
     if index == usize::MAX {
@@ -106,11 +110,23 @@ pub fn push_data(index: usize, data: Vec<u8>) {
     assert!(socket_table.len() > index);
     assert!(socket_table[index].is_some());
 
-    socket_table[index]
-        .as_mut()
-        .unwrap()
-        .buffers
-        .push_back(data);
+    let sock = socket_table[index].as_mut().unwrap();
+    sock.buffers.push_back(data);
+    let pollers = sock.pollers.clone();
+    drop(socket_table);
+    pollers.wake_all();
+}
+
+/// queue a polling task on the socket, the next data pushed wakes it
+pub fn register_poller(index: usize, task_id: TaskID) {
+    let socket_table = SOCKET_TABLE.exclusive_access();
+
+    assert!(socket_table.len() > index);
+    assert!(socket_table[index].is_some());
+
+    let pollers = socket_table[index].as_ref().unwrap().pollers.clone();
+    drop(socket_table);
+    pollers.register(task_id);
 }
 
 pub fn pop_data(index: usize) -> Option<Vec<u8>> {
@@ -121,3 +137,12 @@ pub fn pop_data(index: usize) -> Option<Vec<u8>> {
 
     socket_table[index].as_mut().unwrap().buffers.pop_front()
 }
+
+pub fn has_data(index: usize) -> bool {
+    let socket_table = SOCKET_TABLE.exclusive_access();
+
+    assert!(socket_table.len() > index);
+    assert!(socket_table[index].is_some());
+
+    !socket_table[index].as_ref().unwrap().buffers.is_empty()
+}
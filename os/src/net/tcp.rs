@@ -5,11 +5,12 @@ use lose_net_stack::MacAddress;
 use lose_net_stack::TcpFlags;
 use crate::mm::memory_set::UserBuffer;
 
-use crate::{drivers::NET_DEVICE, fs::File};
+use crate::{drivers::NET_DEVICE, fs::File, fs::PollEvents};
+use crate::task::schedule::TaskID;
 
 use super::socket::get_s_a_by_index;
 use super::{
-    net_interrupt_handler,
+    net_interrupt_handler, socket_poll, socket_poll_wait,
     socket::{add_socket, pop_data, remove_socket},
     LOSE_NET_STACK,
 };
@@ -60,6 +61,14 @@ impl File for TCP {
         }
     }
 
+    fn poll(&self) -> PollEvents {
+        socket_poll(self.socket_index)
+    }
+
+    fn poll_wait(&self, task_id: TaskID) {
+        socket_poll_wait(self.socket_index, task_id)
+    }
+
     fn write(&self, buf: &UserBuffer) -> usize {
         let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
 
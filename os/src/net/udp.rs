@@ -1,8 +1,9 @@
-use super::net_interrupt_handler;
+use super::{net_interrupt_handler, socket_poll, socket_poll_wait};
 use super::socket::{add_socket, pop_data, remove_socket};
 use super::LOSE_NET_STACK;
 use super::NET_DEVICE;
-use crate::fs::File;
+use crate::fs::{File, PollEvents};
+use crate::task::schedule::TaskID;
 use alloc::vec;
 use lose_net_stack::packets::udp::UDPPacket;
 use lose_net_stack::IPv4;
@@ -50,6 +51,14 @@ impl File for UDP {
         }
     }
 
+    fn poll(&self) -> PollEvents {
+        socket_poll(self.socket_index)
+    }
+
+    fn poll_wait(&self, task_id: TaskID) {
+        socket_poll_wait(self.socket_index, task_id)
+    }
+
     fn write(&self, buf: &UserBuffer) -> usize {
         let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
 
@@ -2,7 +2,9 @@ mod mutex;
 mod semaphore;
 mod condvar;
 mod inner;
+mod poll;
 pub use mutex::{Mutex, SpinLock, MutexLock};
 pub use semaphore::Semaphore;
 pub use condvar::Condvar;
-pub use inner::{InterruptMask, OneCoreCell};
\ No newline at end of file
+pub use inner::{InterruptMask, OneCoreCell};
+pub use poll::{start_polling, stop_polling, sleep_polling, wake_poller, PollQueue};
\ No newline at end of file
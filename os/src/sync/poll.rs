@@ -0,0 +1,87 @@
+//! Wait queues for tasks sleeping in poll
+use alloc::collections::{BTreeSet, VecDeque};
+use lazy_static::*;
+use crate::task::schedule::TaskID;
+use crate::task::process::wakeup_if_blocked;
+use crate::task::block_task_and_run_next;
+use crate::sync::inner::{InterruptMask, OneCoreCell};
+
+lazy_static! {
+    //tasks inside poll that nobody has woken yet
+    static ref POLLING: OneCoreCell<BTreeSet<TaskID>> =
+        unsafe { OneCoreCell::new(BTreeSet::new()) };
+}
+
+/// Tasks polling one source of events, every file that can keep poll
+/// waiting owns one and wakes it whenever its readiness may change
+pub struct PollQueue {
+    waiters: OneCoreCell<VecDeque<TaskID>>,
+}
+
+impl PollQueue {
+    pub fn new() -> Self {
+        Self {
+            waiters: unsafe { OneCoreCell::new(VecDeque::new()) },
+        }
+    }
+    //a task stays queued until the next wake, even if it left poll before
+    pub fn register(&self, task_id: TaskID) {
+        let mut int_ctrl = InterruptMask::new();
+        int_ctrl.mask_interrupt();
+        let mut waiters = self.waiters.exclusive_access();
+        if !waiters.contains(&task_id) {
+            waiters.push_back(task_id);
+        }
+        drop(waiters);
+        int_ctrl.unmask_interrupt();
+    }
+    //called from interrupt handlers as well
+    pub fn wake_all(&self) {
+        let mut int_ctrl = InterruptMask::new();
+        int_ctrl.mask_interrupt();
+        let waiters = core::mem::take(&mut *self.waiters.exclusive_access());
+        for task_id in waiters {
+            wake_poller(task_id);
+        }
+        int_ctrl.unmask_interrupt();
+    }
+}
+
+/// Mark the task as polling, queues it registers on may wake it from now on
+pub fn start_polling(task_id: TaskID) {
+    let mut int_ctrl = InterruptMask::new();
+    int_ctrl.mask_interrupt();
+    POLLING.exclusive_access().insert(task_id);
+    int_ctrl.unmask_interrupt();
+}
+
+/// The task leaves poll, later wakes from its queues are ignored
+pub fn stop_polling(task_id: TaskID) {
+    let mut int_ctrl = InterruptMask::new();
+    int_ctrl.mask_interrupt();
+    POLLING.exclusive_access().remove(&task_id);
+    int_ctrl.unmask_interrupt();
+}
+
+/// Block the polling task unless a queue has woken it since `start_polling`
+pub fn sleep_polling(task_id: TaskID) {
+    let mut int_ctrl = InterruptMask::new();
+    int_ctrl.mask_interrupt();
+    let polling = POLLING.exclusive_access().contains(&task_id);
+    if polling {
+        block_task_and_run_next();
+    }
+    int_ctrl.unmask_interrupt();
+}
+
+/// Wake the task if it is still polling, it looks at its files again
+pub fn wake_poller(task_id: TaskID) {
+    let mut int_ctrl = InterruptMask::new();
+    int_ctrl.mask_interrupt();
+    let polling = POLLING.exclusive_access().remove(&task_id);
+    //the task may not have gone to sleep yet, it will not after this
+    if polling {
+        wakeup_if_blocked(task_id);
+    }
+    int_ctrl.unmask_interrupt();
+}
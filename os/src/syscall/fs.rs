@@ -1,24 +1,55 @@
 use crate::fs::pipe::get_dual_pipe_file;
 use crate::task::process::set_new_fd;
+use crate::task::process::set_new_fd_with_flags;
 use crate::task::process::find_file_by_fd;
+use crate::task::process::find_fd;
+use crate::task::process::set_fd_cloexec;
 use crate::task::process::remove_fd;
-use crate::task::process::dup_fd;
+use crate::task::process::{dup_fd, dup_fd_to};
+use crate::task::process::OS_MAX_FILE_DESCRIPTOR_NUM;
 use crate::task::schedule::get_current_task;
 use crate::mm::memory_set::UserBuffer;
-use crate::fs::open_file;
+use crate::fs::{chmod, chown, mkfifo, open, unlink};
+use crate::task::process::current_credentials;
 use crate::fs::OpenFlags;
+use crate::fs::{Flock, RangeLockCmd, EAGAIN, EPERM};
+use crate::fs::{poll, PollEvents};
+use crate::fs::vfs::{mount, sync_all, umount};
+use crate::fs::{format_easy_fs, format_fat, EASYFS_MIN_BLOCKS, FAT_MIN_BLOCKS};
+use crate::drivers::create_ram_disk;
 use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+//fcntl commands
+const F_DUPFD : usize = 0;
+const F_GETFD : usize = 1;
+const F_SETFD : usize = 2;
+const F_GETFL : usize = 3;
+const F_SETFL : usize = 4;
+const F_GETLK : usize = 5;
+const F_SETLK : usize = 6;
+const F_SETLKW : usize = 7;
+const F_DUPFD_CLOEXEC : usize = 1030;
+
+//ramdisk flags
+const RAMDISK_FAT : usize = 1;
+const RAMDISK_EASYFS : usize = 2;
+//the only flag of an fd itself
+const FD_CLOEXEC : usize = 1;
 
 pub fn syscall_open(path: *const u8, len : usize, flags: u32) -> isize {
     let mut string = String::new();
     let user_buf = UserBuffer::new(path as usize, len);
     user_buf.read_buff_to_kernel_string(&mut string);
-    if let Some(inode) = open_file(&string[0..string.len()-1], OpenFlags::from_bits(flags).unwrap()) {
-        let pid =  get_current_task().to_pid();
-        let fd = set_new_fd(pid, inode);
-        fd as isize
-    } else {
-        -1
+    let flags = OpenFlags::from_bits(flags).unwrap();
+    match open(&string[0..string.len()-1], flags, &current_credentials()) {
+        Ok(file) => {
+            let pid =  get_current_task().to_pid();
+            let fd = set_new_fd_with_flags(pid, file, flags);
+            fd as isize
+        }
+        Err(err) => err,
     }
 }
 
@@ -61,7 +92,67 @@ pub fn syscall_pipe(fd_buf: *mut u8) -> isize {
 
 pub fn syscall_dup(fd : usize) -> isize {
     let pid = get_current_task().to_pid();
-    dup_fd(pid, fd)
+    dup_fd(pid, fd, 0, false)
+}
+
+//new_fd is closed first if it is open, flags may only hold CLOEXEC
+pub fn syscall_dup3(old_fd : usize, new_fd : usize, flags : u32) -> isize {
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) if (flags - OpenFlags::CLOEXEC).is_empty() => flags,
+        _ => return -1,
+    };
+    if old_fd == new_fd {
+        return -1;
+    }
+    let pid = get_current_task().to_pid();
+    dup_fd_to(pid, old_fd, new_fd, flags.contains(OpenFlags::CLOEXEC))
+}
+
+pub fn syscall_fcntl(fd : usize, cmd : usize, arg : usize) -> isize {
+    let pid = get_current_task().to_pid();
+    let fd_desc = match find_fd(pid, fd) {
+        Some(fd_desc) => fd_desc,
+        None => return -1,
+    };
+    match cmd {
+        F_DUPFD => dup_fd(pid, fd, arg, false),
+        F_DUPFD_CLOEXEC => dup_fd(pid, fd, arg, true),
+        F_GETFD => if fd_desc.cloexec { FD_CLOEXEC as isize } else { 0 },
+        F_SETFD => set_fd_cloexec(pid, fd, arg & FD_CLOEXEC != 0),
+        F_GETFL => fd_desc.status_flags().bits() as isize,
+        F_SETFL => {
+            fd_desc.set_status_flags(OpenFlags::from_bits_truncate(arg as u32));
+            0
+        },
+        F_GETLK | F_SETLK | F_SETLKW => {
+            //arg points at a Flock, F_GETLK fills it with the lock in the way
+            let len = core::mem::size_of::<Flock>();
+            let mut flock = Flock { l_type: 0, l_whence: 0, l_start: 0, l_len: 0, l_pid: 0 };
+            let user_buf = UserBuffer::new(arg, len);
+            user_buf.read_buff_to_kernel_slice(&mut flock as *mut Flock as usize, len);
+            let lock_cmd = match cmd {
+                F_GETLK => RangeLockCmd::Test,
+                F_SETLK => RangeLockCmd::Set,
+                _ => RangeLockCmd::SetWait,
+            };
+            let ret = fd_desc.file.lock_range(pid, lock_cmd, &mut flock);
+            if ret == 0 && cmd == F_GETLK {
+                user_buf.write_kernel_slice_to_user(&flock as *const Flock as usize, len);
+            }
+            ret
+        },
+        _ => -1,
+    }
+}
+
+//op is LOCK_SH, LOCK_EX or LOCK_UN, with LOCK_NB a lock in the way gives
+//EAGAIN instead of waiting
+pub fn syscall_flock(fd : usize, op : usize) -> isize {
+    let pid = get_current_task().to_pid();
+    match find_file_by_fd(pid, fd) {
+        Some(file) => file.flock(op),
+        None => -1,
+    }
 }
 
 pub fn syscall_read(fd: usize, buf: *const u8, len: usize) -> isize {
@@ -69,11 +160,15 @@ pub fn syscall_read(fd: usize, buf: *const u8, len: usize) -> isize {
         return -2;
     }
     let pid = get_current_task().to_pid();
-    match find_file_by_fd(pid, fd) {
-        Some(file) => {
+    match find_fd(pid, fd) {
+        Some(fd_desc) => {
+            let file = fd_desc.file.clone();
             if !file.readable() {
                 return -1;
             }
+            if fd_desc.would_block(PollEvents::IN) {
+                return EAGAIN;
+            }
             let user_buf = UserBuffer::new(buf as usize, len);
             file.read(&user_buf) as isize
         },
@@ -88,11 +183,15 @@ pub fn syscall_write(fd : usize, buf : *const u8, len : usize) ->isize {
         return -2;
     }
     let pid = get_current_task().to_pid();
-    match find_file_by_fd(pid, fd) {
-        Some(file) => {
+    match find_fd(pid, fd) {
+        Some(fd_desc) => {
+            let file = fd_desc.file.clone();
             if !file.writable() {
                 return -1;
             }
+            if fd_desc.would_block(PollEvents::OUT) {
+                return EAGAIN;
+            }
             let user_buf = UserBuffer::new(buf as usize, len);
             file.write(&user_buf) as isize
         },
@@ -100,4 +199,181 @@ pub fn syscall_write(fd : usize, buf : *const u8, len : usize) ->isize {
             -1
         }
     }
-}
\ No newline at end of file
+}
+pub fn syscall_getdents(fd: usize, buf: *const u8, len: usize) -> isize {
+    if len == 0 {
+        return -2;
+    }
+    let pid = get_current_task().to_pid();
+    match find_file_by_fd(pid, fd) {
+        Some(file) => {
+            let user_buf = UserBuffer::new(buf as usize, len);
+            file.getdents(&user_buf)
+        },
+        None =>{
+            -1
+        }
+    }
+}
+
+pub fn syscall_sync() -> isize {
+    sync_all();
+    0
+}
+
+//files do not know their file system, so flushing a file flushes everything
+pub fn syscall_fsync(fd: usize) -> isize {
+    let pid = get_current_task().to_pid();
+    match find_file_by_fd(pid, fd) {
+        Some(_) => {
+            sync_all();
+            0
+        },
+        None => -1,
+    }
+}
+
+//args points to (source, target, fs_type) as three (address, length) pairs,
+//every string ends with '\0' just like open; only root may mount
+pub fn syscall_mount(args: usize, len: usize) -> isize {
+    if !current_credentials().is_root() {
+        return EPERM;
+    }
+    if len != 6 * core::mem::size_of::<usize>() {
+        return -1;
+    }
+    let mut args_buf = [0usize; 6];
+    let all_buf = UserBuffer::new(args, len);
+    all_buf.read_buff_to_kernel_slice(args_buf.as_mut_ptr() as usize, len);
+    let mut strings: Vec<String> = Vec::new();
+    for i in 0..3 {
+        let mut string = String::new();
+        let user_buf = UserBuffer::new(args_buf[2 * i], args_buf[2 * i + 1]);
+        user_buf.read_buff_to_kernel_string(&mut string);
+        string.pop();
+        strings.push(string);
+    }
+    mount(&strings[0], &strings[1], &strings[2])
+}
+
+//only root may unmount
+pub fn syscall_umount(path: *const u8, len: usize) -> isize {
+    if !current_credentials().is_root() {
+        return EPERM;
+    }
+    let mut string = String::new();
+    let user_buf = UserBuffer::new(path as usize, len);
+    user_buf.read_buff_to_kernel_string(&mut string);
+    umount(&string[0..string.len()-1])
+}
+
+//a RAM disk of `blocks` blocks to mount as "ramN", FAT32 formatted if
+//asked; return N, -1 if memory is short or the disk is too small for FAT32.
+//Only root may make one, the memory is never given back
+pub fn syscall_ramdisk(blocks: usize, flags: usize) -> isize {
+    if !current_credentials().is_root() {
+        return EPERM;
+    }
+    let fat = flags & RAMDISK_FAT != 0;
+    let easy_fs = flags & RAMDISK_EASYFS != 0;
+    //at most one file system on a disk
+    if flags & !(RAMDISK_FAT | RAMDISK_EASYFS) != 0 || (fat && easy_fs)
+        || (fat && blocks < FAT_MIN_BLOCKS) || (easy_fs && blocks < EASYFS_MIN_BLOCKS) {
+        return -1;
+    }
+    let (number, disk) = match create_ram_disk(blocks) {
+        Some(ram_disk) => ram_disk,
+        None => return -1,
+    };
+    if fat {
+        format_fat(disk, blocks);
+    } else if easy_fs {
+        format_easy_fs(disk, blocks);
+    }
+    number as isize
+}
+
+//the size of the file does not change, reads of the hole return zeros
+pub fn syscall_punch_hole(fd: usize, offset: usize, len: usize) -> isize {
+    let pid = get_current_task().to_pid();
+    match find_file_by_fd(pid, fd) {
+        Some(file) => file.punch_hole(offset, len),
+        None => -1,
+    }
+}
+
+pub fn syscall_unlink(path: *const u8, len: usize) -> isize {
+    let mut string = String::new();
+    let user_buf = UserBuffer::new(path as usize, len);
+    user_buf.read_buff_to_kernel_string(&mut string);
+    unlink(&string[0..string.len()-1], &current_credentials())
+}
+
+pub fn syscall_chmod(path: *const u8, len: usize, mode: u32) -> isize {
+    let mut string = String::new();
+    let user_buf = UserBuffer::new(path as usize, len);
+    user_buf.read_buff_to_kernel_string(&mut string);
+    chmod(&string[0..string.len()-1], mode, &current_credentials())
+}
+
+pub fn syscall_mkfifo(path: *const u8, len: usize, mode: u32) -> isize {
+    let mut string = String::new();
+    let user_buf = UserBuffer::new(path as usize, len);
+    user_buf.read_buff_to_kernel_string(&mut string);
+    mkfifo(&string[0..string.len()-1], mode, &current_credentials())
+}
+
+//uid in the high half of owner, gid in the low half
+pub fn syscall_chown(path: *const u8, len: usize, owner: usize) -> isize {
+    let mut string = String::new();
+    let user_buf = UserBuffer::new(path as usize, len);
+    user_buf.read_buff_to_kernel_string(&mut string);
+    chown(&string[0..string.len()-1], (owner >> 32) as u32, owner as u32, &current_credentials())
+}
+
+//one record of the array given to poll, the same layout as Linux's pollfd
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+//fills in revents of the nfds records at fds and returns how many are set,
+//a negative timeout waits forever. A process has no more fds to poll than
+//its fd table holds
+pub fn syscall_poll(fds: usize, nfds: usize, timeout_ms: isize) -> isize {
+    if nfds > OS_MAX_FILE_DESCRIPTOR_NUM {
+        return -1;
+    }
+    if nfds == 0 {
+        if timeout_ms < 0 {
+            return -1;
+        }
+        poll(&[], &mut [], timeout_ms);
+        return 0;
+    }
+    let len = match nfds.checked_mul(core::mem::size_of::<PollFd>()) {
+        Some(len) => len,
+        None => return -1,
+    };
+    let mut records = vec![PollFd { fd: 0, events: 0, revents: 0 }; nfds];
+    let user_buf = UserBuffer::new(fds, len);
+    user_buf.read_buff_to_kernel_slice(records.as_mut_ptr() as usize, len);
+    let pid = get_current_task().to_pid();
+    let files: Vec<_> = records
+        .iter()
+        .map(|record| {
+            let file = if record.fd < 0 { None } else { find_file_by_fd(pid, record.fd as usize) };
+            (file, PollEvents::from_bits_truncate(record.events as u16))
+        })
+        .collect();
+    let mut revents = vec![PollEvents::empty(); nfds];
+    let ready = poll(&files, &mut revents, timeout_ms);
+    for (record, events) in records.iter_mut().zip(revents) {
+        record.revents = events.bits() as i16;
+    }
+    user_buf.write_kernel_slice_to_user(records.as_ptr() as usize, len);
+    ready as isize
+}
@@ -52,6 +52,23 @@ const SYSCALL_MAP_FRAMEBUFFER : usize = 34;
 const SYSCALL_FLUSH_FRAMEBUFFER : usize = 35;
 const SYSCALL_GET_EVENT : usize = 36;
 const SYSCALL_KEY_PRESSED : usize = 37;
+const SYSCALL_GETDENTS : usize = 38;
+const SYSCALL_SYNC : usize = 39;
+const SYSCALL_FSYNC : usize = 40;
+const SYSCALL_MOUNT : usize = 41;
+const SYSCALL_UMOUNT : usize = 42;
+const SYSCALL_PUNCH_HOLE : usize = 43;
+const SYSCALL_UNLINK : usize = 44;
+const SYSCALL_CHMOD : usize = 45;
+const SYSCALL_CHOWN : usize = 46;
+const SYSCALL_SETUID : usize = 47;
+const SYSCALL_GETUID : usize = 48;
+const SYSCALL_MKFIFO : usize = 49;
+const SYSCALL_POLL : usize = 50;
+const SYSCALL_DUP3 : usize = 51;
+const SYSCALL_FCNTL : usize = 52;
+const SYSCALL_FLOCK : usize = 53;
+const SYSCALL_RAMDISK : usize = 54;
 
 pub fn syscall_fn(syscall_id : usize, args: [usize; 3]) ->isize {
     match syscall_id {
@@ -93,6 +110,23 @@ pub fn syscall_fn(syscall_id : usize, args: [usize; 3]) ->isize {
         SYSCALL_FLUSH_FRAMEBUFFER => syscall_framebuffer_flush(),
         SYSCALL_GET_EVENT => syscall_event_get(),
         SYSCALL_KEY_PRESSED => syscall_key_pressed(),
+        SYSCALL_GETDENTS => syscall_getdents(args[0], args[1] as *const u8, args[2]),
+        SYSCALL_SYNC => syscall_sync(),
+        SYSCALL_FSYNC => syscall_fsync(args[0]),
+        SYSCALL_MOUNT => syscall_mount(args[0], args[1]),
+        SYSCALL_UMOUNT => syscall_umount(args[0] as *const u8, args[1]),
+        SYSCALL_PUNCH_HOLE => syscall_punch_hole(args[0], args[1], args[2]),
+        SYSCALL_UNLINK => syscall_unlink(args[0] as *const u8, args[1]),
+        SYSCALL_CHMOD => syscall_chmod(args[0] as *const u8, args[1], args[2] as u32),
+        SYSCALL_CHOWN => syscall_chown(args[0] as *const u8, args[1], args[2]),
+        SYSCALL_SETUID => syscall_setuid(args[0] as u32),
+        SYSCALL_GETUID => syscall_getuid(),
+        SYSCALL_MKFIFO => syscall_mkfifo(args[0] as *const u8, args[1], args[2] as u32),
+        SYSCALL_POLL => syscall_poll(args[0], args[1], args[2] as isize),
+        SYSCALL_DUP3 => syscall_dup3(args[0], args[1], args[2] as u32),
+        SYSCALL_FCNTL => syscall_fcntl(args[0], args[1], args[2]),
+        SYSCALL_FLOCK => syscall_flock(args[0], args[1]),
+        SYSCALL_RAMDISK => syscall_ramdisk(args[0], args[1]),
         _ => panic!("Unsupported syscall_id: {}", syscall_id),
     }
 }
\ No newline at end of file
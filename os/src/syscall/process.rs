@@ -12,6 +12,9 @@ use alloc::vec::Vec;
 use crate::trap::user_trap_return;
 use crate::fs::open_file;
 use crate::fs::OpenFlags;
+use crate::fs::{permitted, Credentials, MAY_EXEC, S_ISUID};
+use crate::fs::vfs::VnodeType;
+use crate::task::process::{current_credentials, set_current_credentials};
 use crate::task::process::exec_an_app;
 use crate::task::process::set_signal_mask;
 use crate::task::process::set_signal_action;
@@ -67,7 +70,17 @@ pub fn do_exec(args : usize, len : usize, args_num : usize)->isize
     let mut string = String::new();
     let user_buf = UserBuffer::new(args_buf[0], args_buf[1]);
     user_buf.read_buff_to_kernel_string(&mut string);
-    if let Some(app_inode) = open_file(&string[0..string.len()-1], OpenFlags::RDONLY) {
+    //running a file only needs its exec bit, the kernel reads it as root
+    let cred = current_credentials();
+    if let Ok(app_inode) = open_file(&string[0..string.len()-1], OpenFlags::RDONLY, &Credentials::ROOT) {
+        let vnode = app_inode.vnode();
+        if vnode.vtype() != VnodeType::File || !permitted(vnode.as_ref(), &cred, MAY_EXEC) {
+            return -1;
+        }
+        //a setuid file runs as its owner
+        if vnode.mode() & S_ISUID != 0 {
+            set_current_credentials(Credentials { euid: vnode.owner().0, ..cred });
+        }
         let all_data = app_inode.read_all();
         exec_an_app(all_data.as_slice(), args_buf);
         0
@@ -76,6 +89,23 @@ pub fn do_exec(args : usize, len : usize, args_num : usize)->isize
     }
 }
 
+//root sets all user ids, others may only go back to their real uid
+pub fn syscall_setuid(uid : u32) -> isize {
+    let cred = current_credentials();
+    if cred.is_root() {
+        set_current_credentials(Credentials { uid, euid: uid, ..cred });
+    } else if uid == cred.uid {
+        set_current_credentials(Credentials { euid: uid, ..cred });
+    } else {
+        return -1;
+    }
+    0
+}
+
+pub fn syscall_getuid() -> isize {
+    current_credentials().uid as isize
+}
+
 //signal syscalls
 pub fn syscall_setmask(mask : i32) -> isize {
     set_signal_mask(mask)
@@ -10,14 +10,18 @@ use crate::task::schedule::add_schedule_task;
 use alloc::vec::Vec;
 use alloc::boxed::Box;
 use crate::mm::memory_set::UserMemorySets;
+use crate::mm::memory_set::MapPermission;
 use crate::task::id::alloc_pid;
 use crate::task::thread::Thread;
 use crate::task::schedule::get_current_task;
 use crate::fs::open_file;
 use crate::fs::OpenFlags;
+use crate::fs::Credentials;
 use crate::mm::memory_set::UserBuffer;
 use alloc::sync::Arc;
-use crate::fs::{File, Stdin, Stdout};
+use crate::fs::{release_process_locks, File, FileDescriptor, Stdin, Stdout};
+use alloc::string::String;
+use core::fmt::Write;
 use crate::task::action::SignalHandler;
 use crate::task::action::SignalAction;
 use crate::task::signal::SignalFlags;
@@ -39,7 +43,7 @@ pub const FD_STDOUT: usize = 1;
 pub const FD_STDERR: usize = 2;
 
 //now only support 256 opened files for each process
-const OS_MAX_FILE_DESCRIPTOR_NUM : usize = 256;
+pub const OS_MAX_FILE_DESCRIPTOR_NUM : usize = 256;
 //1024 threads for each process should be enough
 const OS_MAX_THREAD_NUM : usize = 1024;
 //64 locks for a process should be enough
@@ -51,7 +55,9 @@ pub struct Process{
     pub childpid : HashMap<usize, usize>,
     pub status : TaskStatus,
     pub exit_code : isize,
-    pub fd_table: HashMap<usize, Arc<dyn File + Send + Sync>>,
+    //who the process acts as, inherited through fork and exec
+    pub cred : Credentials,
+    pub fd_table: HashMap<usize, FileDescriptor>,
     pub sig_handler : SignalHandler,
     pub threads : HashMap<usize, Thread>,
     //base memorys: text&rodata&bss&data......
@@ -70,6 +76,7 @@ impl Process {
             childpid : HashMap::new(),
             status : TaskStatus::READY,
             exit_code : 0,
+            cred : Credentials::ROOT,
             fd_table: HashMap::new(),
             sig_handler : SignalHandler::new(),
             threads : HashMap::new(),
@@ -94,9 +101,47 @@ impl Process {
             thread.dump_thread();
         }
     }
-    fn alloc_an_new_fd(&self)->usize {
+    //text of /proc/<pid>/status
+    pub fn status_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "Pid:\t{}", self.pid.id);
+        let _ = writeln!(text, "PPid:\t{}", self.ppid);
+        let _ = writeln!(text, "State:\t{:?}", self.status);
+        let _ = writeln!(text, "Uid:\t{}\t{}", self.cred.uid, self.cred.euid);
+        let _ = writeln!(text, "Gid:\t{}", self.cred.gid);
+        let _ = writeln!(text, "Threads:\t{}", self.threads.len());
+        let _ = writeln!(text, "SigPnd:\t{:08x}", self.sig_handler.existed_signals.bits());
+        let _ = writeln!(text, "SigBlk:\t{:08x}", self.sig_handler.global_mask.bits());
+        text
+    }
+    //text of /proc/<pid>/maps, shared areas first, then those of every thread
+    pub fn maps_text(&self) -> String {
+        let mut text = String::new();
+        let mut write_sets = |sets: &UserMemorySets, owner: &str| {
+            for (section, map) in &sets.sets {
+                let perm = map.area.perm;
+                let _ = writeln!(text, "{:016x}-{:016x} {}{}{}{} {}{}",
+                    map.area.vaddr_start.0, map.area.vaddr_end.0,
+                    if perm.contains(MapPermission::R) { 'r' } else { '-' },
+                    if perm.contains(MapPermission::W) { 'w' } else { '-' },
+                    if perm.contains(MapPermission::X) { 'x' } else { '-' },
+                    if perm.contains(MapPermission::U) { 'u' } else { '-' },
+                    owner, section);
+            }
+        };
+        for sets in &self.user_memorys {
+            write_sets(sets, "");
+        }
+        for (tid, thread) in &self.threads {
+            for sets in &thread.private_mem {
+                write_sets(sets, &alloc::format!("[tid {}] ", tid));
+            }
+        }
+        text
+    }
+    fn alloc_an_new_fd(&self, lowest : usize)->usize {
         let mut new_fd : usize = OS_MAX_FILE_DESCRIPTOR_NUM;
-        for fd in 0..OS_MAX_FILE_DESCRIPTOR_NUM {
+        for fd in lowest..OS_MAX_FILE_DESCRIPTOR_NUM {
             if !self.fd_table.contains_key(&fd) {
                 new_fd = fd;
                 break;
@@ -120,29 +165,41 @@ impl Process {
         }
         thread_id
     }
-    pub fn set_new_file_descriptor(&mut self, file : Arc<dyn File + Send + Sync>) ->usize {
-        let new_fd = self.alloc_an_new_fd();
-        self.fd_table.insert(new_fd, file);
+    pub fn set_new_file_descriptor(&mut self, file : Arc<dyn File + Send + Sync>, flags : OpenFlags) ->usize {
+        let new_fd = self.alloc_an_new_fd(0);
+        self.fd_table.insert(new_fd, FileDescriptor::new(file, flags));
         new_fd
     }
     pub fn remove_file_descriptor(&mut self, fd : usize) ->isize {
-        if self.fd_table.contains_key(&fd) {
-            self.fd_table.remove(&fd);
+        if let Some(fd_desc) = self.fd_table.remove(&fd) {
+            fd_desc.file.release_locks(self.pid.id);
             0
         } else {
             -1
         }
     }
-    pub fn dup_file_descriptor(&mut self, fd : usize) ->isize {
-        if !self.fd_table.contains_key(&fd) {
+    //the lowest free fd not below lowest
+    pub fn dup_file_descriptor(&mut self, fd : usize, lowest : usize, cloexec : bool) ->isize {
+        if !self.fd_table.contains_key(&fd) || lowest >= OS_MAX_FILE_DESCRIPTOR_NUM {
             -1
         } else {
-            let file = self.fd_table.get(&fd);
-            let new_fd = self.alloc_an_new_fd();
-            self.fd_table.insert(new_fd, file.unwrap().clone());
+            let fd_desc = self.fd_table.get(&fd).unwrap().dup(cloexec);
+            let new_fd = self.alloc_an_new_fd(lowest);
+            self.fd_table.insert(new_fd, fd_desc);
             new_fd as isize
         }
     }
+    //new_fd is closed first if it is open
+    pub fn dup_file_descriptor_to(&mut self, fd : usize, new_fd : usize, cloexec : bool) ->isize {
+        if !self.fd_table.contains_key(&fd) || new_fd >= OS_MAX_FILE_DESCRIPTOR_NUM {
+            return -1;
+        }
+        let fd_desc = self.fd_table.get(&fd).unwrap().dup(cloexec);
+        if let Some(old_desc) = self.fd_table.insert(new_fd, fd_desc) {
+            old_desc.file.release_locks(self.pid.id);
+        }
+        new_fd as isize
+    }
     pub fn mask_signal(&mut self, mask : i32) ->isize {
         let old_mask = self.sig_handler.global_mask.bits();
         if let Some(flags) = SignalFlags::from_bits(mask) {
@@ -283,6 +340,14 @@ impl Process {
         main_thread.init_task_data();
         //remove other thread
         self.remove_other_threads(tid);
+        //close fds marked close-on-exec
+        let pid = self.pid.id;
+        self.fd_table.retain(|_, fd_desc| {
+            if fd_desc.cloexec {
+                fd_desc.file.release_locks(pid);
+            }
+            !fd_desc.cloexec
+        });
         //insert current memory data
         self.user_memorys.pop();
         self.user_memorys.push(new_user_mem);
@@ -294,6 +359,7 @@ impl Process {
             self.exit_code = exit_code;
         }
         self.fd_table.clear();
+        release_process_locks(self.pid.id);
         //remove other thread
         self.remove_other_threads(tid);
         //remove userpace memorys
@@ -305,10 +371,11 @@ impl Process {
     pub fn fork_process(&self, old_tid: usize, new_process : &mut Process){
         //set process info
         new_process.ppid = self.pid.id;
+        new_process.cred = self.cred;
         //copy fd table
         let old_table = &self.fd_table;
-        for (fd, file) in old_table {
-            new_process.fd_table.insert(*fd, file.clone());
+        for (fd, fd_desc) in old_table {
+            new_process.fd_table.insert(*fd, fd_desc.clone());
         }
         //fork user memory
         new_process.user_memorys.push(UserMemorySets::new());
@@ -569,9 +636,9 @@ impl ProcessPool {
         let args : [usize; 2] = [0 ; 2];
         process.replace_process(elf_data,0, &args);
         //add stdin & stdout & stderr
-        process.fd_table.insert(FD_STDIN, Arc::new(Stdin));
-        process.fd_table.insert(FD_STDOUT, Arc::new(Stdout));
-        process.fd_table.insert(FD_STDERR, Arc::new(Stdout));
+        process.fd_table.insert(FD_STDIN, FileDescriptor::new(Arc::new(Stdin), OpenFlags::RDONLY));
+        process.fd_table.insert(FD_STDOUT, FileDescriptor::new(Arc::new(Stdout), OpenFlags::WRONLY));
+        process.fd_table.insert(FD_STDERR, FileDescriptor::new(Arc::new(Stdout), OpenFlags::WRONLY));
         //add task schedule
         add_schedule_task(pid, 0);
         //update instruction cache
@@ -579,6 +646,18 @@ impl ProcessPool {
             asm!("fence.i");
         }
     }
+    //a kernel task has no user memory and never returns to user space,
+    //its only thread starts at entry on its own kernel stack
+    pub fn load_kernel_process(&mut self, entry: usize) {
+        let id = alloc_pid();
+        let pid = id.id;
+        self.processes.insert(pid, Process::new(id));
+        let process = self.processes.get_mut(&pid).unwrap();
+        let mut thread = Thread::new(0);
+        thread.init_kernel_task_data(entry);
+        process.threads.insert(0, thread);
+        add_schedule_task(pid, 0);
+    }
     pub fn fork_app(&mut self) -> usize{
         //get old task first
         let (old_pid, old_tid) = get_current_task().to_pid_tid();
@@ -698,10 +777,31 @@ fn write_exit_code(exit_code_ptr: *mut i32, exit_code : i32)
     user_buf.write_kernel_slice_to_user(code.as_ptr() as usize, result_len);
 }
 
+//pids of all processes, sorted
+pub fn process_ids() -> Vec<usize> {
+    let mut pids: Vec<usize> = unsafe {
+        PROCESSES.as_ref().unwrap().processes.keys().cloned().collect()
+    };
+    pids.sort();
+    pids
+}
+
+//run f on the process with pid, None if there is no such process
+pub fn with_process<V>(pid : usize, f : impl FnOnce(&Process) -> V) -> Option<V> {
+    unsafe {
+        PROCESSES.as_ref().unwrap().processes.get(&pid).map(f)
+    }
+}
+
 pub fn set_new_fd(pid : usize, file : Arc<dyn File + Send + Sync>) -> usize{
+    set_new_fd_with_flags(pid, file, OpenFlags::empty())
+}
+
+//flags may carry NONBLOCK and CLOEXEC
+pub fn set_new_fd_with_flags(pid : usize, file : Arc<dyn File + Send + Sync>, flags : OpenFlags) -> usize{
     unsafe {
         let process = PROCESSES.as_mut().unwrap().processes.get_mut(&pid);
-        process.unwrap().set_new_file_descriptor(file)
+        process.unwrap().set_new_file_descriptor(file, flags)
     }
 }
 
@@ -712,10 +812,30 @@ pub fn remove_fd(pid : usize, fd : usize) -> isize{
     }
 }
 
-pub fn dup_fd(pid : usize, fd : usize) -> isize{
+pub fn dup_fd(pid : usize, fd : usize, lowest : usize, cloexec : bool) -> isize{
+    unsafe {
+        let process = PROCESSES.as_mut().unwrap().processes.get_mut(&pid);
+        process.unwrap().dup_file_descriptor(fd, lowest, cloexec)
+    }
+}
+
+pub fn dup_fd_to(pid : usize, fd : usize, new_fd : usize, cloexec : bool) -> isize{
     unsafe {
         let process = PROCESSES.as_mut().unwrap().processes.get_mut(&pid);
-        process.unwrap().dup_file_descriptor(fd)
+        process.unwrap().dup_file_descriptor_to(fd, new_fd, cloexec)
+    }
+}
+
+pub fn current_credentials() -> Credentials {
+    let cur_pid = get_current_task().to_pid();
+    with_process(cur_pid, |process| process.cred).unwrap()
+}
+
+pub fn set_current_credentials(cred : Credentials) {
+    let cur_pid = get_current_task().to_pid();
+    unsafe {
+        let process = PROCESSES.as_mut().unwrap().processes.get_mut(&cur_pid);
+        process.unwrap().cred = cred;
     }
 }
 
@@ -777,15 +897,27 @@ pub fn return_from_signal()->isize{
 }
 
 pub fn find_file_by_fd(pid : usize, fd : usize)-> Option<Arc<dyn File + Send + Sync>> {
+    find_fd(pid, fd).map(|fd_desc| fd_desc.file)
+}
+
+//the file together with its flags
+pub fn find_fd(pid : usize, fd : usize)-> Option<FileDescriptor> {
     unsafe {
         let process = PROCESSES.as_mut().unwrap().processes.get(&pid);
-        match process.unwrap().fd_table.get(&fd) {
-            Some(file) => {
-                Some(file.clone())
+        process.unwrap().fd_table.get(&fd).cloned()
+    }
+}
+
+//set the close-on-exec flag of one fd
+pub fn set_fd_cloexec(pid : usize, fd : usize, cloexec : bool) -> isize {
+    unsafe {
+        let process = PROCESSES.as_mut().unwrap().processes.get_mut(&pid);
+        match process.unwrap().fd_table.get_mut(&fd) {
+            Some(fd_desc) => {
+                fd_desc.cloexec = cloexec;
+                0
             },
-            None => {
-                None
-            }
+            None => -1,
         }
     }
 }
@@ -843,13 +975,20 @@ pub fn exec_an_app(elf_data: &[u8], args : &[usize]) {
 
 pub fn run_init_process() 
 {
-    let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
+    let inode = open_file("initproc", OpenFlags::RDONLY, &Credentials::ROOT).unwrap();
     let v = inode.read_all();
     unsafe {
         PROCESSES.as_mut().unwrap().load_init_porcess(v.as_slice());
     }
 }
 
+//run after the init process, so that init keeps pid 1
+pub fn run_kernel_task(entry: fn() -> !)
+{
+    unsafe {
+        PROCESSES.as_mut().unwrap().load_kernel_process(entry as usize);
+    }
+}
 
 pub fn create_new_thread(thread_func: usize, start_func: usize, arg_addr: usize)->isize {
     let cur_pid = get_current_task().to_pid();
@@ -906,6 +1045,21 @@ pub fn try_wakeup_task(task_id : TaskID) {
     }
 }
 
+//for wakers racing with the task itself, such as a poll timeout and the
+//files it polls, a task which is not blocked any more is left alone
+pub fn wakeup_if_blocked(task_id : TaskID) {
+    let (pid, tid) = task_id.to_pid_tid();
+    let blocked = unsafe {
+        PROCESSES.as_ref().unwrap().processes.get(&pid)
+            .filter(|process| process.status == TaskStatus::READY)
+            .and_then(|process| process.threads.get(&tid))
+            .map_or(false, |thread| thread.status == TaskStatus::READY)
+    };
+    if blocked {
+        try_wakeup_task(task_id);
+    }
+}
+
 pub fn block_current_task(){
     let (pid, tid) = get_current_task().to_pid_tid();
     unsafe {
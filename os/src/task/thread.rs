@@ -44,7 +44,10 @@ impl Thread {
     }
     pub fn dump_thread(&self) {
         println! ("tid[{}], kern_stack_id:{}", self.tid, self.kern_stack.stack_id);
-        self.private_mem[0].print_maps();
+        //kernel tasks have no private memory
+        if let Some(mem) = self.private_mem.first() {
+            mem.print_maps();
+        }
         println! ("ra:0x{:0x}, sp:0x{:0x}", self.context.ra, self.context.sp);
         println! ("context_addr:0x{:0x}", &self.context as *const TaskContext as usize);
     }
@@ -62,6 +65,10 @@ impl Thread {
         self.context.sp = get_kernel_stack_top(self.kern_stack.stack_id);
         self.context.ra = user_trap_return as usize;
     }
+    pub fn init_kernel_task_data(&mut self, entry : usize) {
+        self.context.sp = get_kernel_stack_top(self.kern_stack.stack_id);
+        self.context.ra = entry;
+    }
     pub fn set_user_trap_context(&self, entry_point : usize, args : &[usize]) {
         extern "C" {
             fn user_trap_handler();
@@ -4,6 +4,8 @@ use crate::config::CLOCK_FREQ;
 use crate::config::SCHEDUL_INTERVAL;
 use crate::task::schedule::TaskID;
 use crate::task::process::try_wakeup_task;
+use crate::sync::wake_poller;
+use crate::sync::InterruptMask;
 use core::cmp::Ordering;
 use spin::Mutex;
 use alloc::collections::BinaryHeap;
@@ -27,6 +29,8 @@ pub fn set_timer_trigger() {
 pub struct TimerCondVar {
     pub expire_ms: usize,
     pub task_id: TaskID,
+    //a poll timeout, it races with the files the task polls
+    pub polling: bool,
 }
 
 impl PartialEq for TimerCondVar {
@@ -54,27 +58,46 @@ lazy_static! {
 }
 
 pub fn add_timer(expire_ms: usize, task_id: TaskID) {
-    TIMERS.lock().push(TimerCondVar { expire_ms, task_id });
+    TIMERS.lock().push(TimerCondVar { expire_ms, task_id, polling: false });
 }
 
-//we do not need to remove timer, since we will check if task is ready,
-//we will not wake up a task that is not ready
+pub fn add_poll_timer(expire_ms: usize, task_id: TaskID) {
+    TIMERS.lock().push(TimerCondVar { expire_ms, task_id, polling: true });
+}
+
+//a task woken before its timeout drops the timer, or it would later
+//wake the task from whatever it blocks on by then
+pub fn remove_timer(task_id: TaskID) {
+    let mut int_ctrl = InterruptMask::new();
+    int_ctrl.mask_interrupt();
+    TIMERS.lock().retain(|timer| timer.task_id != task_id);
+    int_ctrl.unmask_interrupt();
+}
+
+//a sleeping task only waits for its timer, so sleep does not remove it,
+//a polling task is only woken if no file has woken it before
 pub fn check_timer() {
     let current_ms = get_time_in_ms();
     let mut expire_ms : usize;
     let mut task_id : TaskID;
+    let mut polling : bool;
     loop {
         match TIMERS.lock().peek() {
             Some(timer) => {
                 expire_ms = timer.expire_ms;
                 task_id = timer.task_id;
+                polling = timer.polling;
             }
             None => {
                 break;
             }
         }
         if expire_ms <= current_ms {
-            try_wakeup_task(task_id);
+            if polling {
+                wake_poller(task_id);
+            } else {
+                try_wakeup_task(task_id);
+            }
             {
                 TIMERS.lock().pop();
             }
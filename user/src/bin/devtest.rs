@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, read, write, OpenFlags};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [0xffu8; 64];
+    let fd = open("/dev/zero\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    assert_eq!(read(fd as usize, &mut buf), 64);
+    assert!(buf.iter().all(|b| *b == 0));
+    close(fd as usize);
+
+    let fd = open("/dev/null\0", OpenFlags::RDWR);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, &buf), 64);
+    assert_eq!(read(fd as usize, &mut buf), 0);
+    close(fd as usize);
+
+    let fd = open("/dev/random\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    assert_eq!(read(fd as usize, &mut buf), 64);
+    assert!(buf.iter().any(|b| *b != 0));
+    close(fd as usize);
+
+    // disks may hold mounted file systems, so they can only be read
+    assert!(open("/dev/vda\0", OpenFlags::WRONLY) < 0);
+    let fd = open("/dev/vda\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut block = [0u8; 512];
+    assert_eq!(read(fd as usize, &mut block), 512);
+    close(fd as usize);
+
+    // every disk and partition is listed with its size
+    let fd = open("/proc/partitions\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let len = read(fd as usize, &mut buf);
+    assert!(len > 0);
+    assert!(core::str::from_utf8(&buf[..len as usize]).unwrap().contains(" vda\n"));
+    close(fd as usize);
+
+    let fd = open("/dev/ttyS0\0", OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, b"dev test passed!\n");
+    close(fd as usize);
+    0
+}
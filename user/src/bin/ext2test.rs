@@ -0,0 +1,29 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exec, fork, mount, read_dir, umount, waitpid};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    if mount("vdb\0", "/mnt\0", "ext2\0") != 0 {
+        println!("no ext2 disk as vdb, ext2 test skipped");
+        return 0;
+    }
+    let entries = read_dir("/mnt/bin\0").unwrap();
+    assert!(entries.iter().any(|entry| entry.name == "hello_world"));
+    // "/mnt/hello" is a symbolic link to "bin/hello_world"
+    let pid = fork();
+    if pid == 0 {
+        exec("/mnt/hello\0", &[core::ptr::null::<u8>()]);
+        panic!("ext2test: can not run /mnt/hello");
+    }
+    let mut exit_code: i32 = -1;
+    waitpid(pid as usize, &mut exit_code);
+    assert_eq!(exit_code, 0);
+    assert_eq!(umount("/mnt\0"), 0);
+    println!("ext2 test passed!");
+    0
+}
@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, mount, open, read, read_dir, umount, write, OpenFlags};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    if mount("vdb\0", "/mnt\0", "vfat\0") != 0 {
+        println!("no FAT32 disk as vdb, fat test skipped");
+        return 0;
+    }
+    let text = "Long file names are kept on FAT32\n";
+    let fd = open("/mnt/A long file name.txt\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, text.as_bytes()), text.len() as isize);
+    close(fd as usize);
+    // names are found ignoring case
+    let fd = open("/mnt/a LONG file NAME.TXT\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buf = [0u8; 64];
+    let len = read(fd as usize, &mut buf);
+    close(fd as usize);
+    assert_eq!(&buf[..len as usize], text.as_bytes());
+    let entries = read_dir("/mnt\0").unwrap();
+    assert!(entries.iter().any(|entry| entry.name == "A long file name.txt"));
+    assert_eq!(umount("/mnt\0"), 0);
+    println!("fat test passed!");
+    0
+}
@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, dup, dup2, dup3, exec, fcntl, fork, pipe, read, waitpid, write, OpenFlags};
+use user_lib::{EAGAIN, FD_CLOEXEC, F_DUPFD, F_GETFD, F_GETFL, F_SETFD, F_SETFL};
+
+const CLOEXEC_FD: usize = 20;
+const KEPT_FD: usize = 21;
+
+// run again by exec with an argument, only the fd without CLOEXEC is left
+fn after_exec() -> i32 {
+    assert_eq!(fcntl(CLOEXEC_FD, F_GETFD, 0), -1);
+    assert_eq!(fcntl(KEPT_FD, F_GETFD, 0), 0);
+    assert_eq!(write(KEPT_FD, b"kept"), 4);
+    0
+}
+
+#[no_mangle]
+pub fn main(argc: usize, _argv: &[&str]) -> i32 {
+    if argc > 1 {
+        return after_exec();
+    }
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let (read_end, write_end) = (pipe_fd[0], pipe_fd[1]);
+    let mut buffer = [0u8; 16];
+    // NONBLOCK belongs to the open pipe, so a dup sees it as well
+    assert_eq!(fcntl(read_end, F_SETFL, OpenFlags::NONBLOCK.bits() as usize), 0);
+    let read_dup = dup(read_end);
+    assert!(read_dup > 0);
+    assert_eq!(fcntl(read_dup as usize, F_GETFL, 0), OpenFlags::NONBLOCK.bits() as isize);
+    assert_eq!(fcntl(write_end, F_GETFL, 0), OpenFlags::WRONLY.bits() as isize);
+    assert_eq!(read(read_end, &mut buffer), EAGAIN);
+    assert_eq!(write(write_end, b"abc"), 3);
+    assert_eq!(read(read_dup as usize, &mut buffer), 3);
+    assert_eq!(read(read_end, &mut buffer), EAGAIN);
+    close(read_dup as usize);
+    // dup2 to a chosen number, replacing what was there
+    assert_eq!(dup2(write_end, KEPT_FD), KEPT_FD as isize);
+    assert_eq!(dup2(write_end, KEPT_FD), KEPT_FD as isize);
+    assert_eq!(dup2(KEPT_FD, KEPT_FD), KEPT_FD as isize);
+    assert_eq!(dup2(CLOEXEC_FD, CLOEXEC_FD), -1);
+    assert_eq!(dup3(write_end, write_end, OpenFlags::empty()), -1);
+    assert_eq!(dup3(write_end, CLOEXEC_FD, OpenFlags::CLOEXEC), CLOEXEC_FD as isize);
+    assert_eq!(fcntl(CLOEXEC_FD, F_GETFD, 0), FD_CLOEXEC as isize);
+    assert_eq!(fcntl(KEPT_FD, F_GETFD, 0), 0);
+    assert_eq!(fcntl(KEPT_FD, F_SETFD, FD_CLOEXEC), 0);
+    assert_eq!(fcntl(KEPT_FD, F_SETFD, 0), 0);
+    // the lowest free fd not below the argument
+    assert_eq!(fcntl(write_end, F_DUPFD, KEPT_FD), KEPT_FD as isize + 1);
+    close(KEPT_FD + 1);
+    let pid = fork();
+    if pid == 0 {
+        exec("fdflagstest\0", &["fdflagstest\0".as_ptr(), "exec\0".as_ptr(), core::ptr::null::<u8>()]);
+        panic!("unreachable!");
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(read(read_end, &mut buffer), 4);
+    assert_eq!(&buffer[..4], b"kept");
+    println!("fd flags test passed!");
+    0
+}
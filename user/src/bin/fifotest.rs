@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exit, fork, mkfifo, open, read, unlink, waitpid, write, OpenFlags};
+
+static STR: &str = "Hello through a FIFO!";
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mkfifo("fifo_test\0", 0o644), 0);
+    assert_eq!(mkfifo("fifo_test\0", 0o644), -1);
+    // without waiting, a writer needs a reader already there (ENXIO)
+    assert_eq!(open("fifo_test\0", OpenFlags::WRONLY | OpenFlags::NONBLOCK), -6);
+    // while a reader opens at once
+    let fd = open("fifo_test\0", OpenFlags::RDONLY | OpenFlags::NONBLOCK);
+    assert!(fd > 0);
+    close(fd as usize);
+    let pid = fork();
+    if pid == 0 {
+        // nothing inherited, the child finds the pipe by its name
+        let fd = open("fifo_test\0", OpenFlags::WRONLY);
+        assert!(fd > 0);
+        assert_eq!(write(fd as usize, STR.as_bytes()), STR.len() as isize);
+        close(fd as usize);
+        exit(0);
+    }
+    // waits for the child to open the other end
+    let fd = open("fifo_test\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buffer = [0u8; 64];
+    let mut len = 0;
+    loop {
+        let read_len = read(fd as usize, &mut buffer[len..]);
+        // end of file once the writer is gone
+        if read_len <= 0 {
+            break;
+        }
+        len += read_len as usize;
+    }
+    close(fd as usize);
+    assert_eq!(core::str::from_utf8(&buffer[..len]).unwrap(), STR);
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(unlink("fifo_test\0"), 0);
+    // tmpfs holds FIFOs as well
+    assert_eq!(mkfifo("/tmp/fifo_test\0", 0o644), 0);
+    let fd = open("/tmp/fifo_test\0", OpenFlags::RDONLY | OpenFlags::NONBLOCK);
+    assert!(fd > 0);
+    let writer = open("/tmp/fifo_test\0", OpenFlags::WRONLY | OpenFlags::NONBLOCK);
+    assert!(writer > 0);
+    assert_eq!(write(writer as usize, STR.as_bytes()), STR.len() as isize);
+    close(writer as usize);
+    assert_eq!(read(fd as usize, &mut buffer), STR.len() as isize);
+    close(fd as usize);
+    assert_eq!(unlink("/tmp/fifo_test\0"), 0);
+    println!("fifo test passed!");
+    0
+}
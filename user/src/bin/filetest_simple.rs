@@ -4,16 +4,17 @@
 #[macro_use]
 extern crate user_lib;
 
-use user_lib::{close, open, read, write, OpenFlags};
+use user_lib::{close, fsync, open, read, write, OpenFlags};
 
 #[no_mangle]
 pub fn main() -> i32 {
     let test_str = "Hello, world!";
-    let filea = "filea\0";
+    let filea = "/tmp/filea\0";
     let fd = open(filea, OpenFlags::CREATE | OpenFlags::WRONLY);
     assert!(fd > 0);
     let fd = fd as usize;
     write(fd, test_str.as_bytes());
+    assert_eq!(fsync(fd), 0);
     close(fd);
 
     let fd = open(filea, OpenFlags::RDONLY);
@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::read_to_string;
+
+//number in a "Key:\tvalue kB" line of /proc/meminfo
+fn field(meminfo: &str, key: &str) -> usize {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix(key).and_then(|rest| rest.strip_prefix(':')))
+        .and_then(|value| value.split_whitespace().next())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let meminfo = match read_to_string("/proc/meminfo\0") {
+        Some(meminfo) => meminfo,
+        None => {
+            println!("free: /proc is not mounted");
+            return -1;
+        }
+    };
+    let mem_total = field(&meminfo, "MemTotal");
+    let mem_free = field(&meminfo, "MemFree");
+    let heap_total = field(&meminfo, "HeapTotal");
+    let heap_used = field(&meminfo, "HeapUsed");
+    println!("{:>6} {:>10} {:>10} {:>10}", "kB", "total", "used", "free");
+    println!("{:>6} {:>10} {:>10} {:>10}", "Mem:", mem_total, mem_total - mem_free, mem_free);
+    println!("{:>6} {:>10} {:>10} {:>10}", "Heap:", heap_total, heap_used, heap_total - heap_used);
+    0
+}
@@ -5,7 +5,7 @@ extern crate alloc;
 extern crate user_lib;
 
 use user_lib::console::getchar;
-use user_lib::{key_pressed, sleep, Display, VIRTGPU_XRES, VIRTGPU_YRES};
+use user_lib::{poll, Display, PollEvents, PollFd, VIRTGPU_XRES, VIRTGPU_YRES};
 
 use embedded_graphics::pixelcolor::*;
 use embedded_graphics::prelude::{Drawable, Point, RgbColor, Size};
@@ -330,8 +330,10 @@ pub fn main() -> i32 {
     let mut disp = Display::new(Size::new(VIRTGPU_XRES, VIRTGPU_YRES));
     let mut game = SnakeGame::<20, Rgb888>::new(1280, 800, 20, 20, Rgb888::RED, Rgb888::YELLOW, 500);
     let _ = disp.clear(Rgb888::BLACK).unwrap();
+    let mut stdin = [PollFd::new(0, PollEvents::IN)];
     loop {
-        if key_pressed() {
+        // wait for a key at most one frame
+        if poll(&mut stdin, 10) > 0 {
             let c = getchar();
             match c {
                 LF => break,
@@ -345,7 +347,6 @@ pub fn main() -> i32 {
         }
         let _ = disp.clear(Rgb888::BLACK).unwrap();
         game.draw(&mut disp);
-        sleep(10);
     }
     0
 }
@@ -0,0 +1,119 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exit, fcntl, fork, getpid, open, pipe, read, sleep, unlink, waitpid, write, OpenFlags};
+use user_lib::{fcntl_lock, flock, Flock, EAGAIN, EINVAL, F_GETLK, F_RDLCK, F_SETFL, F_SETLK, F_SETLKW, F_UNLCK, F_WRLCK};
+use user_lib::{LOCK_EX, LOCK_NB, LOCK_SH, LOCK_UN};
+
+const FILE: &str = "lock_test\0";
+
+fn open_file() -> usize {
+    let fd = open(FILE, OpenFlags::RDWR);
+    assert!(fd > 0);
+    fd as usize
+}
+
+// the parent writes the pipe right before it lets the lock go, so a child
+// that got the lock too early finds it empty
+fn nonblocking_pipe() -> (usize, usize) {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    assert_eq!(fcntl(pipe_fd[0], F_SETFL, OpenFlags::NONBLOCK.bits() as usize), 0);
+    (pipe_fd[0], pipe_fd[1])
+}
+
+fn wait_child(pid: isize) {
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+}
+
+fn flock_test(fd: usize) {
+    let (read_end, write_end) = nonblocking_pipe();
+    assert_eq!(flock(fd, LOCK_SH), 0);
+    let pid = fork();
+    if pid == 0 {
+        // the inherited fd shares the lock of the parent, another open does not
+        assert_eq!(flock(fd, LOCK_SH | LOCK_NB), 0);
+        let other = open_file();
+        assert_eq!(flock(other, LOCK_SH | LOCK_NB), 0);
+        assert_eq!(flock(other, LOCK_EX | LOCK_NB), EAGAIN);
+        assert_eq!(flock(other, LOCK_UN), 0);
+        // waits for the parent to unlock
+        assert_eq!(flock(other, LOCK_EX), 0);
+        let mut buffer = [0u8; 1];
+        assert_eq!(read(read_end, &mut buffer), 1);
+        close(other);
+        exit(0);
+    }
+    sleep(50);
+    assert_eq!(write(write_end, b"x"), 1);
+    assert_eq!(flock(fd, LOCK_UN), 0);
+    wait_child(pid);
+    close(read_end);
+    close(write_end);
+}
+
+fn range_lock_test(fd: usize) {
+    let (read_end, write_end) = nonblocking_pipe();
+    let parent = getpid();
+    assert_eq!(fcntl_lock(fd, F_SETLK, &mut Flock::new(F_WRLCK, 0, 10)), 0);
+    // unlocking the middle leaves [20, 25) and [35, 40)
+    assert_eq!(fcntl_lock(fd, F_SETLK, &mut Flock::new(F_WRLCK, 20, 20)), 0);
+    assert_eq!(fcntl_lock(fd, F_SETLK, &mut Flock::new(F_UNLCK, 25, 10)), 0);
+    let pid = fork();
+    if pid == 0 {
+        // locks of the parent are not inherited
+        assert_eq!(fcntl_lock(fd, F_SETLK, &mut Flock::new(F_RDLCK, 5, 10)), EAGAIN);
+        let mut lock = Flock::new(F_WRLCK, 5, 10);
+        assert_eq!(fcntl_lock(fd, F_GETLK, &mut lock), 0);
+        assert_eq!((lock.l_type, lock.l_start, lock.l_len, lock.l_pid), (F_WRLCK, 0, 10, parent as i32));
+        let mut lock = Flock::new(F_WRLCK, 25, 10);
+        assert_eq!(fcntl_lock(fd, F_GETLK, &mut lock), 0);
+        assert_eq!(lock.l_type, F_UNLCK);
+        let mut lock = Flock::new(F_RDLCK, 30, 0);
+        assert_eq!(fcntl_lock(fd, F_GETLK, &mut lock), 0);
+        assert_eq!((lock.l_start, lock.l_len), (35, 5));
+        assert_eq!(fcntl_lock(fd, F_SETLK, &mut Flock::new(F_WRLCK, 10, 10)), 0);
+        // waits for the parent to close the file
+        assert_eq!(fcntl_lock(fd, F_SETLKW, &mut Flock::new(F_WRLCK, 0, 0)), 0);
+        let mut buffer = [0u8; 1];
+        assert_eq!(read(read_end, &mut buffer), 1);
+        exit(0);
+    }
+    sleep(50);
+    // closing any fd of the file drops every range lock of the process on it
+    let other = open_file();
+    assert_eq!(write(write_end, b"x"), 1);
+    close(other);
+    wait_child(pid);
+    // the child is gone and its locks with it
+    assert_eq!(fcntl_lock(fd, F_SETLK, &mut Flock::new(F_WRLCK, 0, 0)), 0);
+    close(read_end);
+    close(write_end);
+}
+
+fn bad_range_test(fd: usize) {
+    assert_eq!(fcntl_lock(fd, F_SETLK, &mut Flock { l_len: -1, ..Flock::new(F_WRLCK, 0, 0) }), EINVAL);
+    // past what an offset holds once counted from the current one
+    assert_eq!(write(fd, b"x"), 1);
+    let mut lock = Flock { l_whence: 1, l_start: i64::MAX, ..Flock::new(F_WRLCK, 0, 1) };
+    assert_eq!(fcntl_lock(fd, F_SETLK, &mut lock), EINVAL);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    flock_test(fd);
+    range_lock_test(fd);
+    bad_range_test(fd);
+    close(fd);
+    assert_eq!(unlink(FILE), 0);
+    println!("lock test passed!");
+    0
+}
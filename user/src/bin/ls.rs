@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use user_lib::{read_dir, DT_BLK, DT_CHR, DT_DIR, DT_LNK};
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let path = if argc >= 2 { argv[1] } else { "/\0" };
+    match read_dir(path) {
+        Some(entries) => {
+            for entry in entries.iter() {
+                let kind = match entry.d_type {
+                    DT_DIR => 'd',
+                    DT_CHR => 'c',
+                    DT_BLK => 'b',
+                    DT_LNK => 'l',
+                    _ => '-',
+                };
+                println!("{} {:>5} {:>9} {}", kind, entry.inode, entry.size, entry.name);
+            }
+            0
+        }
+        None => {
+            println!("ls: cannot access {}", path.trim_end_matches('\0'));
+            -1
+        }
+    }
+}
@@ -0,0 +1,20 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::mount;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc != 4 {
+        println!("usage: mount <source> <target> <fs_type>");
+        return -1;
+    }
+    if mount(argv[1], argv[2], argv[3]) != 0 {
+        println!("mount: can not mount {} on {}", argv[1], argv[2]);
+        return -1;
+    }
+    0
+}
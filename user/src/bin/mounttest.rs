@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use user_lib::{close, mount, open, read, umount, OpenFlags};
+
+// the device the root file system was mounted from, like vda or ram0
+fn root_device() -> String {
+    let fd = open("/proc/mounts\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buf = [0u8; 256];
+    let len = read(fd as usize, &mut buf);
+    close(fd as usize);
+    let mounts = core::str::from_utf8(&buf[..len as usize]).unwrap();
+    let root = mounts.lines().find(|line| line.split(' ').nth(1) == Some("/")).unwrap();
+    format!("{}\0", root.split(' ').next().unwrap())
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let root = root_device();
+    let root = root.as_str();
+    assert_eq!(mount(root, "/mnt\0", "nofs\0"), -1);
+    assert_eq!(mount(root, "/filea\0", "easyfs\0"), -1);
+    assert_eq!(mount(root, "/mnt\0", "easyfs\0"), 0);
+    assert_eq!(mount(root, "/mnt\0", "easyfs\0"), -1);
+    // the root file system shows up again below /mnt
+    let fd = open("/mnt/initproc\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    assert_eq!(umount("/mnt\0"), -2);
+    close(fd as usize);
+    assert_eq!(umount("/mnt\0"), 0);
+    assert_eq!(umount("/mnt\0"), -1);
+    assert_eq!(umount("/\0"), -1);
+    assert!(open("/mnt/initproc\0", OpenFlags::RDONLY) < 0);
+    println!("mount test passed!");
+    0
+}
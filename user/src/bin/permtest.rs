@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    chmod, chown, close, exit, fork, getuid, mount, open, ramdisk, setuid, umount, unlink, waitpid,
+    OpenFlags,
+};
+
+const USER: u32 = 1000;
+
+//runs in a child that gave up root
+fn as_user() -> i32 {
+    assert_eq!(setuid(USER), 0);
+    assert_eq!(getuid(), USER as isize);
+    // readable by everyone, writable by root only
+    let fd = open("perm_file\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+    assert_eq!(open("perm_file\0", OpenFlags::WRONLY), -1);
+    assert_eq!(open("perm_file\0", OpenFlags::TRUNC), -1);
+    assert_eq!(open("perm_user\0", OpenFlags::CREATE | OpenFlags::WRONLY), -1);
+    assert_eq!(unlink("perm_file\0"), -2);
+    assert_eq!(chmod("perm_file\0", 0o666), -2);
+    assert_eq!(chown("perm_file\0", USER, USER), -2);
+    assert_eq!(setuid(0), -1);
+    // nothing can be mounted over the files root protects
+    assert_eq!(mount("vdb\0", "/bin\0", "ext2\0"), -1);
+    assert_eq!(umount("/\0"), -1);
+    assert_eq!(ramdisk(16, 0), -1);
+    // the file given away is ours now
+    let fd = open("perm_owned\0", OpenFlags::RDWR);
+    assert!(fd > 0);
+    close(fd as usize);
+    assert_eq!(chmod("perm_owned\0", 0o600), 0);
+    0
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    for name in ["perm_file\0", "perm_owned\0"] {
+        let fd = open(name, OpenFlags::CREATE | OpenFlags::WRONLY);
+        assert!(fd > 0);
+        close(fd as usize);
+    }
+    assert_eq!(chmod("perm_file\0", 0o644), 0);
+    assert_eq!(chown("perm_owned\0", USER, USER), 0);
+    let pid = fork();
+    if pid == 0 {
+        exit(as_user());
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    // root is not bound by the mode of a file
+    assert_eq!(chmod("perm_owned\0", 0o000), 0);
+    let fd = open("perm_owned\0", OpenFlags::RDWR);
+    assert!(fd > 0);
+    close(fd as usize);
+    assert_eq!(unlink("perm_file\0"), 0);
+    assert_eq!(unlink("perm_owned\0"), 0);
+    assert_eq!(getuid(), 0);
+    println!("perm test passed!");
+    0
+}
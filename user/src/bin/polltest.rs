@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exit, fork, get_time_in_ms, pipe, poll, read, sleep, waitpid, write};
+use user_lib::{PollEvents, PollFd};
+
+static STR: &str = "poll me";
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let mut fds = [
+        PollFd::new(pipe_fd[0], PollEvents::IN),
+        PollFd::new(pipe_fd[1], PollEvents::OUT),
+    ];
+    // the empty pipe can only be written
+    assert_eq!(poll(&mut fds, 0), 1);
+    assert!(fds[0].revents.is_empty());
+    assert_eq!(fds[1].revents, PollEvents::OUT);
+    // nothing to read before the timeout
+    let start = get_time_in_ms();
+    assert_eq!(poll(&mut fds[..1], 50), 0);
+    assert!(get_time_in_ms() - start >= 50);
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[0]);
+        sleep(50);
+        assert_eq!(write(pipe_fd[1], STR.as_bytes()), STR.len() as isize);
+        close(pipe_fd[1]);
+        exit(0);
+    }
+    close(pipe_fd[1]);
+    // sleeps until the child writes
+    assert_eq!(poll(&mut fds[..1], -1), 1);
+    assert!(fds[0].revents.contains(PollEvents::IN));
+    let mut buffer = [0u8; 16];
+    let len = read(pipe_fd[0], &mut buffer);
+    assert_eq!(core::str::from_utf8(&buffer[..len as usize]).unwrap(), STR);
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    // the writer is gone
+    assert_eq!(poll(&mut fds[..1], -1), 1);
+    assert_eq!(fds[0].revents, PollEvents::HUP);
+    close(pipe_fd[0]);
+    // closed fds are reported without waiting
+    assert_eq!(poll(&mut fds[..1], -1), 1);
+    assert_eq!(fds[0].revents, PollEvents::NVAL);
+    println!("poll test passed!");
+    0
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use user_lib::{read_dir, read_to_string};
+
+//value of a "Key:\tvalue" line in /proc/<pid>/status
+fn field<'a>(status: &'a str, key: &str) -> &'a str {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(key).and_then(|rest| rest.strip_prefix(':')))
+        .map(|value| value.trim())
+        .unwrap_or("?")
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let entries = match read_dir("/proc\0") {
+        Some(entries) => entries,
+        None => {
+            println!("ps: /proc is not mounted");
+            return -1;
+        }
+    };
+    println!("{:>5} {:>5} {:>8} {:>7}", "PID", "PPID", "STATE", "THREADS");
+    for entry in entries.iter().filter(|entry| entry.is_dir()) {
+        if entry.name.parse::<usize>().is_err() {
+            continue;
+        }
+        //the process may exit before its status is read
+        if let Some(status) = read_to_string(&format!("/proc/{}/status\0", entry.name)) {
+            println!(
+                "{:>5} {:>5} {:>8} {:>7}",
+                field(&status, "Pid"),
+                field(&status, "PPid"),
+                field(&status, "State"),
+                field(&status, "Threads")
+            );
+        }
+    }
+    0
+}
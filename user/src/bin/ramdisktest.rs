@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::format;
+use user_lib::{close, mount, open, ramdisk, read, umount, write, OpenFlags, RAMDISK_EASYFS, RAMDISK_FAT};
+
+const BLOCKS: usize = 2048;
+const EASYFS_BLOCKS: usize = 4096;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(ramdisk(0, 0), -1);
+    // far more than the free memory
+    assert_eq!(ramdisk(usize::MAX / 1024, 0), -1);
+    // FAT32 needs room for its reserved sectors and tables
+    assert_eq!(ramdisk(16, RAMDISK_FAT), -1);
+    assert_eq!(ramdisk(EASYFS_BLOCKS / 2, RAMDISK_EASYFS), -1);
+    // one file system per disk
+    assert_eq!(ramdisk(EASYFS_BLOCKS, RAMDISK_FAT | RAMDISK_EASYFS), -1);
+    let number = ramdisk(BLOCKS, RAMDISK_FAT);
+    assert!(number >= 0);
+    let name = format!("ram{}", number);
+
+    // the new disk is listed with its size and shows up in /dev
+    let mut buf = [0u8; 512];
+    let fd = open("/proc/partitions\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let len = read(fd as usize, &mut buf);
+    close(fd as usize);
+    let line = format!("{:>10} {}\n", BLOCKS / 2, name);
+    assert!(core::str::from_utf8(&buf[..len as usize]).unwrap().contains(line.as_str()));
+    let fd = open(format!("/dev/{}\0", name).as_str(), OpenFlags::RDONLY);
+    assert!(fd > 0);
+    assert_eq!(read(fd as usize, &mut buf), 512);
+    assert_eq!(&buf[510..], &[0x55, 0xAA]);
+    close(fd as usize);
+
+    assert_eq!(mount(format!("{}\0", name).as_str(), "/mnt\0", "vfat\0"), 0);
+    let text = "kept in memory\n";
+    let fd = open("/mnt/scratch.txt\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, text.as_bytes()), text.len() as isize);
+    close(fd as usize);
+    assert_eq!(umount("/mnt\0"), 0);
+    // mounting it again finds the file
+    assert_eq!(mount(format!("{}\0", name).as_str(), "/mnt\0", "vfat\0"), 0);
+    let fd = open("/mnt/scratch.txt\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let len = read(fd as usize, &mut buf);
+    close(fd as usize);
+    assert_eq!(&buf[..len as usize], text.as_bytes());
+    assert_eq!(umount("/mnt\0"), 0);
+
+    // a scratch disk can carry easy-fs as well
+    let number = ramdisk(EASYFS_BLOCKS, RAMDISK_EASYFS);
+    assert!(number >= 0);
+    let name = format!("ram{}\0", number);
+    assert_eq!(mount(name.as_str(), "/mnt\0", "easyfs\0"), 0);
+    let fd = open("/mnt/scratch.txt\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, text.as_bytes()), text.len() as isize);
+    close(fd as usize);
+    assert_eq!(umount("/mnt\0"), 0);
+    assert_eq!(mount(name.as_str(), "/mnt\0", "easyfs\0"), 0);
+    let fd = open("/mnt/scratch.txt\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let len = read(fd as usize, &mut buf);
+    close(fd as usize);
+    assert_eq!(&buf[..len as usize], text.as_bytes());
+    assert_eq!(umount("/mnt\0"), 0);
+    println!("ramdisk test passed!");
+    0
+}
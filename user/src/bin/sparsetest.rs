@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, punch_hole, read, write, OpenFlags};
+
+const BLOCK: usize = 512;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("sparse_file\0", OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let data = [0x5au8; 8 * BLOCK];
+    assert_eq!(write(fd, &data), data.len() as isize);
+    // the middle blocks are freed, the edges of the range zeroed
+    assert_eq!(punch_hole(fd, BLOCK + 100, 5 * BLOCK), 0);
+    close(fd);
+    let fd = open("sparse_file\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(punch_hole(fd, 0, BLOCK), -1);
+    let mut buf = [0u8; 9 * BLOCK];
+    assert_eq!(read(fd, &mut buf), data.len() as isize);
+    close(fd);
+    for (i, byte) in buf[..data.len()].iter().enumerate() {
+        let punched = (BLOCK + 100..6 * BLOCK + 100).contains(&i);
+        assert_eq!(*byte, if punched { 0 } else { 0x5a });
+    }
+    println!("sparse test passed!");
+    0
+}
@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exec, fork, read_to_string, sleep, waitpid};
+
+fn run(app: &str) {
+    let pid = fork();
+    if pid == 0 {
+        exec(app, &[core::ptr::null::<u8>()]);
+        panic!("top: can not run {}", app);
+    }
+    let mut exit_code: i32 = 0;
+    waitpid(pid as usize, &mut exit_code);
+}
+
+//top [rounds], refresh once a second
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let rounds = if argc >= 2 {
+        argv[1].trim_end_matches('\0').parse().unwrap_or(1)
+    } else {
+        3
+    };
+    for round in 0..rounds {
+        if round > 0 {
+            sleep(1000);
+        }
+        let uptime = read_to_string("/proc/uptime\0").unwrap_or_default();
+        println!("up {}s", uptime.trim());
+        run("free\0");
+        run("ps\0");
+    }
+    0
+}
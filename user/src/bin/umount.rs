@@ -0,0 +1,26 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::umount;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc != 2 {
+        println!("usage: umount <target>");
+        return -1;
+    }
+    match umount(argv[1]) {
+        0 => 0,
+        -2 => {
+            println!("umount: {} is busy", argv[1]);
+            -2
+        }
+        _ => {
+            println!("umount: {} is not mounted", argv[1]);
+            -1
+        }
+    }
+}
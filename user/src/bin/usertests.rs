@@ -10,7 +10,23 @@ extern crate user_lib;
 // item of TESTS : app_name(argv_0), argv_1, argv_2, argv_3, exit_code
 static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("filetest_simple\0", "\0", "\0", "\0", 0),
-    ("cat\0", "filea\0", "\0", "\0", 0),
+    ("cat\0", "/tmp/filea\0", "\0", "\0", 0),
+    ("ls\0", "\0", "\0", "\0", 0),
+    ("sync\0", "\0", "\0", "\0", 0),
+    ("mounttest\0", "\0", "\0", "\0", 0),
+    ("devtest\0", "\0", "\0", "\0", 0),
+    ("fattest\0", "\0", "\0", "\0", 0),
+    ("ext2test\0", "\0", "\0", "\0", 0),
+    ("sparsetest\0", "\0", "\0", "\0", 0),
+    ("permtest\0", "\0", "\0", "\0", 0),
+    ("fifotest\0", "\0", "\0", "\0", 0),
+    ("polltest\0", "\0", "\0", "\0", 0),
+    ("fdflagstest\0", "\0", "\0", "\0", 0),
+    ("locktest\0", "\0", "\0", "\0", 0),
+    ("ramdisktest\0", "\0", "\0", "\0", 0),
+    ("ps\0", "\0", "\0", "\0", 0),
+    ("free\0", "\0", "\0", "\0", 0),
+    ("top\0", "1\0", "\0", "\0", 0),
     ("cmdline_args\0", "1\0", "2\0", "3\0", 0),
     ("exit\0", "\0", "\0", "\0", 0),
     ("fantastic_text\0", "\0", "\0", "\0", 0),
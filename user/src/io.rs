@@ -1,4 +1,6 @@
 use super::*;
+use alloc::string::String;
+use alloc::vec::Vec;
 use bitflags::bitflags;
 //This is for I/O devices
 use embedded_graphics::pixelcolor::Rgb888;
@@ -15,6 +17,124 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        const NONBLOCK = 1 << 11;
+        const DIRECTORY = 1 << 16;
+        const CLOEXEC = 1 << 19;
+    }
+}
+
+//returned by reads and writes of a NONBLOCK file that would wait
+pub const EAGAIN: isize = -11;
+//returned for arguments out of range
+pub const EINVAL: isize = -22;
+
+//fcntl commands
+pub const F_DUPFD: usize = 0;
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+pub const F_GETFL: usize = 3;
+pub const F_SETFL: usize = 4;
+pub const F_GETLK: usize = 5;
+pub const F_SETLK: usize = 6;
+pub const F_SETLKW: usize = 7;
+pub const F_DUPFD_CLOEXEC: usize = 1030;
+pub const FD_CLOEXEC: usize = 1;
+
+//flock operations, LOCK_NB can be added to LOCK_SH and LOCK_EX
+pub const LOCK_SH: usize = 1;
+pub const LOCK_EX: usize = 2;
+pub const LOCK_NB: usize = 4;
+pub const LOCK_UN: usize = 8;
+
+//ramdisk flags
+pub const RAMDISK_FAT: usize = 1;
+pub const RAMDISK_EASYFS: usize = 2;
+
+//byte-range lock types
+pub const F_RDLCK: i16 = 0;
+pub const F_WRLCK: i16 = 1;
+pub const F_UNLCK: i16 = 2;
+
+//a byte-range lock for fcntl, l_len 0 reaches the end of the file
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Flock {
+    pub l_type: i16,
+    pub l_whence: i16,
+    pub l_start: i64,
+    pub l_len: i64,
+    pub l_pid: i32,
+}
+
+impl Flock {
+    //len bytes from start, counted from the beginning of the file
+    pub fn new(l_type: i16, start: usize, len: usize) -> Self {
+        Self {
+            l_type,
+            l_whence: 0,
+            l_start: start as i64,
+            l_len: len as i64,
+            l_pid: 0,
+        }
+    }
+}
+
+bitflags! {
+    #[repr(transparent)]
+    pub struct PollEvents: i16 {
+        const IN = 1 << 0;
+        const OUT = 1 << 2;
+        const ERR = 1 << 3;
+        const HUP = 1 << 4;
+        const NVAL = 1 << 5;
+    }
+}
+
+//what poll watches for on one fd, the kernel fills in revents
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: PollEvents,
+    pub revents: PollEvents,
+}
+
+impl PollFd {
+    pub fn new(fd: usize, events: PollEvents) -> Self {
+        Self {
+            fd: fd as i32,
+            events,
+            revents: PollEvents::empty(),
+        }
+    }
+}
+
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_BLK: u8 = 6;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+
+//record header filled by getdents, a NUL terminated name follows it
+#[repr(C)]
+struct DirentHeader {
+    d_ino: u32,
+    d_size: u32,
+    d_reclen: u16,
+    d_namelen: u16,
+    d_type: u8,
+}
+
+pub struct DirEntry {
+    pub inode: u32,
+    pub size: u32,
+    pub d_type: u8,
+    pub name: String,
+}
+
+impl DirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.d_type == DT_DIR
     }
 }
 
@@ -40,6 +160,154 @@ pub fn dup(fd: usize) -> isize {
     syscall_dup(fd)
 }
 
+//make new_fd refer to the file of old_fd, closing new_fd first
+pub fn dup2(old_fd: usize, new_fd: usize) -> isize {
+    if old_fd == new_fd {
+        return if fcntl(old_fd, F_GETFD, 0) < 0 { -1 } else { new_fd as isize };
+    }
+    syscall_dup3(old_fd, new_fd, 0)
+}
+
+//like dup2, flags may be CLOEXEC; return -1 if both fds are the same
+pub fn dup3(old_fd: usize, new_fd: usize, flags: OpenFlags) -> isize {
+    syscall_dup3(old_fd, new_fd, flags.bits)
+}
+
+//F_GETFL and F_SETFL work on NONBLOCK, F_GETFD and F_SETFD on FD_CLOEXEC
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    syscall_fcntl(fd, cmd, arg)
+}
+
+//fcntl with F_GETLK, F_SETLK or F_SETLKW; F_GETLK overwrites lock with
+//the first lock in the way, or sets its type to F_UNLCK
+pub fn fcntl_lock(fd: usize, cmd: usize, lock: &mut Flock) -> isize {
+    syscall_fcntl(fd, cmd, lock as *mut Flock as usize)
+}
+
+//whole-file lock of the open file, shared by its dups and forks; op is
+//LOCK_SH, LOCK_EX or LOCK_UN, LOCK_NB gives EAGAIN instead of waiting
+pub fn flock(fd: usize, op: usize) -> isize {
+    syscall_flock(fd, op)
+}
+
+pub fn getdents(fd: usize, buf: &mut [u8]) -> isize {
+    syscall_getdents(fd, buf)
+}
+
+//write all dirty disk blocks back
+pub fn sync() -> isize {
+    syscall_sync()
+}
+
+pub fn fsync(fd: usize) -> isize {
+    syscall_fsync(fd)
+}
+
+//all strings end with '\0', fs_type is the registered name such as "easyfs"
+pub fn mount(source: &str, target: &str, fs_type: &str) -> isize {
+    syscall_mount(source, target, fs_type)
+}
+
+//return -2 while files below target are still open
+pub fn umount(target: &str) -> isize {
+    syscall_umount(target)
+}
+
+//a RAM disk of blocks 512 byte blocks, FAT32 formatted with RAMDISK_FAT or
+//easy-fs with RAMDISK_EASYFS;
+//return N of its device name "ramN", it stays until the system goes down
+pub fn ramdisk(blocks: usize, flags: usize) -> isize {
+    syscall_ramdisk(blocks, flags)
+}
+
+//free the blocks of len bytes from offset on, they read as zeros afterwards
+pub fn punch_hole(fd: usize, offset: usize, len: usize) -> isize {
+    syscall_punch_hole(fd, offset, len)
+}
+
+//path should end with '\0', return -2 without write permission on its directory
+pub fn unlink(path: &str) -> isize {
+    syscall_unlink(path)
+}
+
+//only the owner and root may change the permission bits
+pub fn chmod(path: &str, mode: u32) -> isize {
+    syscall_chmod(path, mode)
+}
+
+//only root may give a file away
+pub fn chown(path: &str, uid: u32, gid: u32) -> isize {
+    syscall_chown(path, uid, gid)
+}
+
+//a named pipe, opening it for reading waits for a writer and the other
+//way around; return -1 if path exists
+pub fn mkfifo(path: &str, mode: u32) -> isize {
+    syscall_mkfifo(path, mode)
+}
+
+//wait until one of the fds is ready, at most timeout_ms milliseconds or
+//forever if it is negative; return how many fds have revents set
+pub fn poll(fds: &mut [PollFd], timeout_ms: isize) -> isize {
+    syscall_poll(fds, timeout_ms)
+}
+
+//path should end with '\0', just like open
+pub fn read_dir(path: &str) -> Option<Vec<DirEntry>> {
+    let fd = open(path, OpenFlags::RDONLY | OpenFlags::DIRECTORY);
+    if fd < 0 {
+        return None;
+    }
+    let fd = fd as usize;
+    let mut entries: Vec<DirEntry> = Vec::new();
+    //room for a record with the longest FAT name
+    let mut buf = [0u8; 1024];
+    let header_len = core::mem::size_of::<DirentHeader>();
+    loop {
+        let len = getdents(fd, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        let mut pos = 0usize;
+        while pos < len as usize {
+            let header = unsafe {
+                core::ptr::read_unaligned(buf[pos..].as_ptr() as *const DirentHeader)
+            };
+            let name_start = pos + header_len;
+            let name = &buf[name_start..name_start + header.d_namelen as usize];
+            entries.push(DirEntry {
+                inode: header.d_ino,
+                size: header.d_size,
+                d_type: header.d_type,
+                name: String::from(core::str::from_utf8(name).unwrap()),
+            });
+            pos += header.d_reclen as usize;
+        }
+    }
+    close(fd);
+    Some(entries)
+}
+
+//read a whole file as text, path should end with '\0'
+pub fn read_to_string(path: &str) -> Option<String> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let fd = fd as usize;
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let len = read(fd, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        bytes.extend_from_slice(&buf[..len as usize]);
+    }
+    close(fd);
+    String::from_utf8(bytes).ok()
+}
+
 //This is for I/O devices
 pub const VIRTGPU_XRES: u32 = 1280;
 pub const VIRTGPU_YRES: u32 = 800;
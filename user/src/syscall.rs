@@ -1,5 +1,6 @@
 use core::arch::asm;
 use crate::SignalAction;
+use crate::PollFd;
 
 const SYSCALL_EXIT_ID : usize = 0;
 const SYSCALL_WRITE_ID : usize = 1;
@@ -50,6 +51,25 @@ const SYSCALL_MAP_FRAMEBUFFER : usize = 34;
 const SYSCALL_FLUSH_FRAMEBUFFER : usize = 35;
 const SYSCALL_GET_EVENT : usize = 36;
 const SYSCALL_KEY_PRESSED : usize = 37;
+//directory
+const SYSCALL_GETDENTS : usize = 38;
+const SYSCALL_SYNC : usize = 39;
+const SYSCALL_FSYNC : usize = 40;
+//mount
+const SYSCALL_MOUNT : usize = 41;
+const SYSCALL_UMOUNT : usize = 42;
+const SYSCALL_PUNCH_HOLE : usize = 43;
+const SYSCALL_UNLINK : usize = 44;
+const SYSCALL_CHMOD : usize = 45;
+const SYSCALL_CHOWN : usize = 46;
+const SYSCALL_SETUID : usize = 47;
+const SYSCALL_GETUID : usize = 48;
+const SYSCALL_MKFIFO : usize = 49;
+const SYSCALL_POLL : usize = 50;
+const SYSCALL_DUP3 : usize = 51;
+const SYSCALL_FCNTL : usize = 52;
+const SYSCALL_FLOCK : usize = 53;
+const SYSCALL_RAMDISK : usize = 54;
 
 fn syscall_fn(sys_id : usize, args: [usize; 3]) ->isize {
     let mut ret: isize;
@@ -118,6 +138,80 @@ pub fn syscall_dup(fd: usize) -> isize {
     syscall_fn(SYSCALL_DUP,[fd, 0, 0])
 }
 
+pub fn syscall_dup3(old_fd: usize, new_fd: usize, flags: u32) -> isize {
+    syscall_fn(SYSCALL_DUP3,[old_fd, new_fd, flags as usize])
+}
+
+pub fn syscall_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    syscall_fn(SYSCALL_FCNTL,[fd, cmd, arg])
+}
+
+pub fn syscall_flock(fd: usize, op: usize) -> isize {
+    syscall_fn(SYSCALL_FLOCK,[fd, op, 0])
+}
+
+pub fn syscall_ramdisk(blocks: usize, flags: usize) -> isize {
+    syscall_fn(SYSCALL_RAMDISK,[blocks, flags, 0])
+}
+
+pub fn syscall_getdents(fd: usize, buffer: &mut [u8]) -> isize {
+    syscall_fn(SYSCALL_GETDENTS,[fd, buffer.as_mut_ptr() as usize, buffer.len()])
+}
+
+pub fn syscall_sync() -> isize {
+    syscall_fn(SYSCALL_SYNC,[0, 0, 0])
+}
+
+pub fn syscall_fsync(fd: usize) -> isize {
+    syscall_fn(SYSCALL_FSYNC,[fd, 0, 0])
+}
+
+pub fn syscall_mount(source: &str, target: &str, fs_type: &str) -> isize {
+    let args: [usize; 6] = [
+        source.as_ptr() as usize, source.len(),
+        target.as_ptr() as usize, target.len(),
+        fs_type.as_ptr() as usize, fs_type.len(),
+    ];
+    syscall_fn(SYSCALL_MOUNT, [args.as_ptr() as usize, core::mem::size_of_val(&args), 0])
+}
+
+pub fn syscall_umount(target: &str) -> isize {
+    syscall_fn(SYSCALL_UMOUNT, [target.as_ptr() as usize, target.len(), 0])
+}
+
+pub fn syscall_punch_hole(fd: usize, offset: usize, len: usize) -> isize {
+    syscall_fn(SYSCALL_PUNCH_HOLE, [fd, offset, len])
+}
+
+pub fn syscall_unlink(path: &str) -> isize {
+    syscall_fn(SYSCALL_UNLINK, [path.as_ptr() as usize, path.len(), 0])
+}
+
+pub fn syscall_chmod(path: &str, mode: u32) -> isize {
+    syscall_fn(SYSCALL_CHMOD, [path.as_ptr() as usize, path.len(), mode as usize])
+}
+
+pub fn syscall_chown(path: &str, uid: u32, gid: u32) -> isize {
+    let owner = (uid as usize) << 32 | gid as usize;
+    syscall_fn(SYSCALL_CHOWN, [path.as_ptr() as usize, path.len(), owner])
+}
+
+pub fn syscall_setuid(uid: u32) -> isize {
+    syscall_fn(SYSCALL_SETUID, [uid as usize, 0, 0])
+}
+
+pub fn syscall_getuid() -> isize {
+    syscall_fn(SYSCALL_GETUID, [0, 0, 0])
+}
+
+pub fn syscall_mkfifo(path: &str, mode: u32) -> isize {
+    syscall_fn(SYSCALL_MKFIFO, [path.as_ptr() as usize, path.len(), mode as usize])
+}
+
+pub fn syscall_poll(fds: &mut [PollFd], timeout_ms: isize) -> isize {
+    syscall_fn(SYSCALL_POLL, [fds.as_mut_ptr() as usize, fds.len(), timeout_ms as usize])
+}
+
 pub fn syscall_kill(pid: usize, signal: i32) -> isize {
     syscall_fn(SYSCALL_SIGKILL,[pid, signal as usize, 0])
 }
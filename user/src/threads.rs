@@ -21,6 +21,13 @@ pub fn fork() -> isize {
 pub fn getpid() -> isize {
     syscall_getpid()
 }
+//root may become anyone, others only their real uid again
+pub fn setuid(uid: u32) -> isize {
+    syscall_setuid(uid)
+}
+pub fn getuid() -> isize {
+    syscall_getuid()
+}
 pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
     loop {
         match syscall_waitpid(pid as isize, exit_code as _) {